@@ -1,5 +1,7 @@
 use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
-use nostr_crdt::nostr::crdt::{CrdtOperation, CrdtState, GCounter, GSet, GSetAction, LWWRegister};
+use nostr_crdt::nostr::crdt::{
+    CrdtOperation, CrdtOperationRef, CrdtState, GCounter, GSet, GSetAction, LWWRegister,
+};
 
 fn bench_lww_register(c: &mut Criterion) {
     let mut group = c.benchmark_group("LWWRegister");
@@ -7,7 +9,7 @@ fn bench_lww_register(c: &mut Criterion) {
     group.bench_function("single_update", |b| {
         b.iter(|| {
             let mut lww = LWWRegister::default();
-            lww.apply_operation(CrdtOperation::LWWRegister {
+            lww.apply_operation(&CrdtOperation::LWWRegister {
                 key: "test_key".to_string(),
                 value: "test_value".to_string(),
                 timestamp: 100,
@@ -19,14 +21,14 @@ fn bench_lww_register(c: &mut Criterion) {
     group.bench_function("conflict_resolution_newer_wins", |b| {
         b.iter(|| {
             let mut lww = LWWRegister::default();
-            lww.apply_operation(CrdtOperation::LWWRegister {
+            lww.apply_operation(&CrdtOperation::LWWRegister {
                 key: "test_key".to_string(),
                 value: "older_value".to_string(),
                 timestamp: 100,
             })
             .unwrap();
 
-            lww.apply_operation(CrdtOperation::LWWRegister {
+            lww.apply_operation(&CrdtOperation::LWWRegister {
                 key: "test_key".to_string(),
                 value: "newer_value".to_string(),
                 timestamp: 200,
@@ -38,14 +40,14 @@ fn bench_lww_register(c: &mut Criterion) {
     group.bench_function("conflict_resolution_ignore_older", |b| {
         b.iter(|| {
             let mut lww = LWWRegister::default();
-            lww.apply_operation(CrdtOperation::LWWRegister {
+            lww.apply_operation(&CrdtOperation::LWWRegister {
                 key: "test_key".to_string(),
                 value: "newer_value".to_string(),
                 timestamp: 200,
             })
             .unwrap();
 
-            lww.apply_operation(CrdtOperation::LWWRegister {
+            lww.apply_operation(&CrdtOperation::LWWRegister {
                 key: "test_key".to_string(),
                 value: "older_value".to_string(),
                 timestamp: 100,
@@ -63,7 +65,7 @@ fn bench_lww_register(c: &mut Criterion) {
                 b.iter(|| {
                     let mut lww = LWWRegister::default();
                     for i in 0..size {
-                        lww.apply_operation(CrdtOperation::LWWRegister {
+                        lww.apply_operation(&CrdtOperation::LWWRegister {
                             key: format!("key_{}", i),
                             value: format!("value_{}", i),
                             timestamp: i as u64,
@@ -85,7 +87,7 @@ fn bench_g_counter(c: &mut Criterion) {
         b.iter(|| {
             let mut counter = GCounter::default();
             counter
-                .apply_operation(CrdtOperation::GCounter {
+                .apply_operation(&CrdtOperation::GCounter {
                     key: "visitors".to_string(),
                     increment: 1,
                 })
@@ -98,7 +100,7 @@ fn bench_g_counter(c: &mut Criterion) {
             let mut counter = GCounter::default();
             for _ in 0..100 {
                 counter
-                    .apply_operation(CrdtOperation::GCounter {
+                    .apply_operation(&CrdtOperation::GCounter {
                         key: "visitors".to_string(),
                         increment: 1,
                     })
@@ -112,7 +114,7 @@ fn bench_g_counter(c: &mut Criterion) {
             let mut counter = GCounter::default();
             for i in 0..100 {
                 counter
-                    .apply_operation(CrdtOperation::GCounter {
+                    .apply_operation(&CrdtOperation::GCounter {
                         key: format!("counter_{}", i),
                         increment: 1,
                     })
@@ -130,7 +132,7 @@ fn bench_g_set(c: &mut Criterion) {
     group.bench_function("single_add", |b| {
         b.iter(|| {
             let mut set = GSet::default();
-            set.apply_operation(CrdtOperation::GSet {
+            set.apply_operation(&CrdtOperation::GSet {
                 key: "tags".to_string(),
                 value: "tag1".to_string(),
                 action: GSetAction::Add,
@@ -143,7 +145,7 @@ fn bench_g_set(c: &mut Criterion) {
         b.iter(|| {
             let mut set = GSet::default();
             for i in 0..100 {
-                set.apply_operation(CrdtOperation::GSet {
+                set.apply_operation(&CrdtOperation::GSet {
                     key: "tags".to_string(),
                     value: format!("tag_{}", i),
                     action: GSetAction::Add,
@@ -158,7 +160,7 @@ fn bench_g_set(c: &mut Criterion) {
             let mut set = GSet::default();
             for _ in 0..10 {
                 for i in 0..10 {
-                    set.apply_operation(CrdtOperation::GSet {
+                    set.apply_operation(&CrdtOperation::GSet {
                         key: "tags".to_string(),
                         value: format!("tag_{}", i),
                         action: GSetAction::Add,
@@ -194,6 +196,13 @@ fn bench_serialization(c: &mut Criterion) {
         });
     });
 
+    // Received operations are parsed in place, borrowing from the JSON
+    group.bench_function("deserialize_lww_borrowed", |b| {
+        b.iter(|| {
+            let _: CrdtOperationRef = black_box(serde_json::from_str(&lww_json).unwrap());
+        });
+    });
+
     let counter_op = CrdtOperation::GCounter {
         key: "visitors".to_string(),
         increment: 42,
@@ -212,6 +221,12 @@ fn bench_serialization(c: &mut Criterion) {
         });
     });
 
+    group.bench_function("deserialize_counter_borrowed", |b| {
+        b.iter(|| {
+            let _: CrdtOperationRef = black_box(serde_json::from_str(&counter_json).unwrap());
+        });
+    });
+
     group.finish();
 }
 
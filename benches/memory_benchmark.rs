@@ -0,0 +1,98 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use nostr_crdt::nostr::crdt::{CrdtManager, StateBackup};
+use nostr_sdk::{Client, Keys, NostrSigner};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+// System allocator keeping count of the bytes in use
+struct Counting;
+
+static IN_USE: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            IN_USE.fetch_add(layout.size(), Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        IN_USE.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        if !new_ptr.is_null() {
+            IN_USE.fetch_sub(layout.size(), Ordering::Relaxed);
+            IN_USE.fetch_add(new_size, Ordering::Relaxed);
+        }
+        new_ptr
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+fn new_manager() -> CrdtManager {
+    let keys = Keys::generate();
+    let client = Client::new(&keys);
+    CrdtManager::new(Arc::new(client), NostrSigner::Keys(keys))
+}
+
+// A document where every key holds a register, a counter and a set, as
+// when one setting is mirrored into several CRDT types
+fn document(keys: usize) -> StateBackup {
+    let mut backup = StateBackup {
+        version: StateBackup::VERSION,
+        ..Default::default()
+    };
+    for i in 0..keys {
+        let key = format!("settings/section-{}/item-{}", i % 10, i);
+        backup
+            .registers
+            .insert(key.clone(), (format!("value-{i}"), i as u64));
+        backup.counters.insert(key.clone(), i as u64);
+        backup.sets.insert(key, vec![format!("member-{i}")]);
+    }
+    backup
+}
+
+// Heap held by a manager's state once a document is loaded, reported
+// alongside the time the load takes
+fn bench_state_memory(c: &mut Criterion) {
+    let mut group = c.benchmark_group("CRDT_State_Memory");
+
+    for size in [1_000, 10_000] {
+        let backup = document(size);
+
+        let manager = new_manager();
+        let before = IN_USE.load(Ordering::Relaxed);
+        manager.import_state(backup.clone()).unwrap();
+        let held = IN_USE.load(Ordering::Relaxed).saturating_sub(before);
+        println!(
+            "CRDT_State_Memory/{size} keys: {held} bytes, {} per key",
+            held / size
+        );
+        drop(manager);
+
+        group.bench_with_input(
+            BenchmarkId::new("import_state", size),
+            &backup,
+            |b, backup| {
+                b.iter_with_setup(
+                    || (new_manager(), backup.clone()),
+                    |(manager, backup)| manager.import_state(backup).unwrap(),
+                );
+            },
+        );
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_state_memory);
+criterion_main!(benches);
@@ -1,5 +1,6 @@
 use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
 use nostr_crdt::nostr::crdt::{CrdtManager, CrdtOperation};
+use nostr_sdk::nips::nip04;
 use nostr_sdk::{Client, EventBuilder, Keys, Kind, NostrSigner, SecretKey, Tag, TagKind};
 use std::sync::Arc;
 use tokio::runtime::Runtime;
@@ -20,9 +21,9 @@ fn bench_publish_operations(c: &mut Criterion) {
     group.bench_function("publish_lww_register", |b| {
         b.iter_batched(
             || {
-                let (_, keys, client, signer) = setup_client();
+                let (_, _, client, signer) = setup_client();
                 let rt = Runtime::new().unwrap();
-                let crdt_manager = rt.block_on(async { CrdtManager::new(client, signer, keys) });
+                let crdt_manager = rt.block_on(async { CrdtManager::new(client, signer) });
 
                 (rt, crdt_manager)
             },
@@ -40,9 +41,9 @@ fn bench_publish_operations(c: &mut Criterion) {
     group.bench_function("publish_g_counter", |b| {
         b.iter_batched(
             || {
-                let (_, keys, client, signer) = setup_client();
+                let (_, _, client, signer) = setup_client();
                 let rt = Runtime::new().unwrap();
-                let crdt_manager = rt.block_on(async { CrdtManager::new(client, signer, keys) });
+                let crdt_manager = rt.block_on(async { CrdtManager::new(client, signer) });
 
                 (rt, crdt_manager)
             },
@@ -58,9 +59,9 @@ fn bench_publish_operations(c: &mut Criterion) {
     group.bench_function("publish_g_set", |b| {
         b.iter_batched(
             || {
-                let (_, keys, client, signer) = setup_client();
+                let (_, _, client, signer) = setup_client();
                 let rt = Runtime::new().unwrap();
-                let crdt_manager = rt.block_on(async { CrdtManager::new(client, signer, keys) });
+                let crdt_manager = rt.block_on(async { CrdtManager::new(client, signer) });
 
                 (rt, crdt_manager)
             },
@@ -110,9 +111,9 @@ fn bench_process_events(c: &mut Criterion) {
     group.bench_function("process_lww_event", |b| {
         b.iter_batched(
             || {
-                let (_, keys, client, signer) = setup_client();
+                let (_, _, client, signer) = setup_client();
                 let rt = Runtime::new().unwrap();
-                let crdt_manager = rt.block_on(async { CrdtManager::new(client, signer, keys) });
+                let crdt_manager = rt.block_on(async { CrdtManager::new(client, signer) });
                 let event = setup_event();
 
                 (rt, crdt_manager, event)
@@ -129,5 +130,54 @@ fn bench_process_events(c: &mut Criterion) {
     group.finish();
 }
 
-criterion_group!(benches, bench_publish_operations, bench_process_events);
+// Cold start: a backfill of encrypted operations applied in one batch
+fn bench_process_event_batch(c: &mut Criterion) {
+    let mut group = c.benchmark_group("CRDT_Bulk_Processing");
+    group.sample_size(10);
+
+    let (_, keys, _, _) = setup_client();
+    let events: Vec<_> = (0..1000u64)
+        .map(|i| {
+            let op = CrdtOperation::GCounter {
+                key: format!("counter_{}", i % 10),
+                increment: 1,
+            };
+            let content = nip04::encrypt(
+                keys.secret_key().unwrap(),
+                &keys.public_key(),
+                serde_json::to_string(&op).unwrap(),
+            )
+            .unwrap();
+            EventBuilder::new(Kind::TextNote, content, vec![Tag::hashtag("nostr-crdt")])
+                .to_event(&keys)
+                .unwrap()
+        })
+        .collect();
+
+    let rt = Runtime::new().unwrap();
+    group.bench_function("process_1000_encrypted_events", |b| {
+        b.iter_batched(
+            || {
+                let client = Arc::new(Client::new(&keys));
+                let crdt_manager = CrdtManager::new(client, NostrSigner::Keys(keys.clone()));
+                (crdt_manager, events.clone())
+            },
+            |(crdt_manager, events)| {
+                rt.block_on(async {
+                    assert_eq!(crdt_manager.process_events(events).await, 0);
+                });
+            },
+            BatchSize::LargeInput,
+        );
+    });
+
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_publish_operations,
+    bench_process_events,
+    bench_process_event_batch
+);
 criterion_main!(benches);
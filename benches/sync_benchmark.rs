@@ -0,0 +1,259 @@
+use criterion::{criterion_group, criterion_main, Criterion, SamplingMode, Throughput};
+use nostr_crdt::nostr::crdt::{Change, CrdtManager, CrdtType};
+use nostr_crdt::testhelper::MockRelay;
+use nostr_sdk::{Client, Keys, NostrSigner};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+use tokio::sync::mpsc::{self, UnboundedReceiver};
+use tokio::sync::Notify;
+
+// Operations published back to back in a convergence run
+const BURST: usize = 50;
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// Two devices of one user on an in-process relay: the writer publishes,
+// the reader syncs and reports every change it applies
+struct SyncPair {
+    _relay: MockRelay,
+    writer: CrdtManager,
+    changes: UnboundedReceiver<Change>,
+    next: u64,
+}
+
+async fn connect(relay: &MockRelay, keys: &Keys) -> CrdtManager {
+    let client = Client::new(keys);
+    client.add_relay(relay.url()).await.unwrap();
+    client.connect().await;
+    CrdtManager::new(Arc::new(client), NostrSigner::Keys(keys.clone()))
+}
+
+impl SyncPair {
+    async fn new() -> Self {
+        let relay = MockRelay::run().await.unwrap();
+        let keys = Keys::generate();
+        let writer = connect(&relay, &keys).await;
+        let reader = Arc::new(connect(&relay, &keys).await);
+
+        let (sender, changes) = mpsc::unbounded_channel();
+        reader.on_change(move |change| {
+            let _ = sender.send(change.clone());
+        });
+        tokio::spawn(async move { reader.sync().await });
+
+        let mut pair = Self {
+            _relay: relay,
+            writer,
+            changes,
+            next: 0,
+        };
+        // The reader is subscribed once a write makes it across
+        pair.publish(CrdtType::GCounter).await;
+        pair.wait(1).await;
+        pair
+    }
+
+    // Publish one operation that changes a value on the reader. Register
+    // timestamps have a resolution of seconds, so every write goes to a
+    // new key.
+    async fn publish(&mut self, crdt_type: CrdtType) {
+        self.next += 1;
+        let n = self.next;
+        let result = match crdt_type {
+            CrdtType::LWWRegister => {
+                self.writer
+                    .update_lww_register(&format!("register-{n}"), "value")
+                    .await
+            }
+            CrdtType::GCounter => self.writer.increment_counter("counter", 1).await,
+            CrdtType::GSet => self.writer.add_to_set("set", &format!("item-{n}")).await,
+        };
+        result.unwrap();
+    }
+
+    // Wait for the reader to apply `count` operations
+    async fn wait(&mut self, count: usize) {
+        for _ in 0..count {
+            tokio::time::timeout(TIMEOUT, self.changes.recv())
+                .await
+                .expect("reader did not converge")
+                .expect("reader stopped syncing");
+        }
+    }
+}
+
+const TYPES: [(&str, CrdtType); 3] = [
+    ("lww_register", CrdtType::LWWRegister),
+    ("g_counter", CrdtType::GCounter),
+    ("g_set", CrdtType::GSet),
+];
+
+// Publish on one device until the other has applied it, through the relay
+fn bench_round_trip(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("CRDT_Sync_Round_Trip");
+    group.throughput(Throughput::Elements(1));
+
+    for (name, crdt_type) in TYPES {
+        let mut pair = rt.block_on(SyncPair::new());
+        group.bench_function(name, |b| {
+            b.iter_custom(|iters| {
+                rt.block_on(async {
+                    let start = Instant::now();
+                    for _ in 0..iters {
+                        pair.publish(crdt_type).await;
+                        pair.wait(1).await;
+                    }
+                    start.elapsed()
+                })
+            });
+        });
+    }
+
+    group.finish();
+}
+
+// Publish a burst of operations, then wait until the other device has
+// applied all of them
+fn bench_convergence(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("CRDT_Sync_Convergence");
+    group.throughput(Throughput::Elements(BURST as u64));
+    group.sample_size(20);
+
+    for (name, crdt_type) in TYPES {
+        let mut pair = rt.block_on(SyncPair::new());
+        group.bench_function(name, |b| {
+            b.iter_custom(|iters| {
+                rt.block_on(async {
+                    let start = Instant::now();
+                    for _ in 0..iters {
+                        for _ in 0..BURST {
+                            pair.publish(crdt_type).await;
+                        }
+                        pair.wait(BURST).await;
+                    }
+                    start.elapsed()
+                })
+            });
+        });
+    }
+
+    group.finish();
+}
+
+// Devices of one user on an in-process relay, all writing to one counter
+struct Replicas {
+    _relay: MockRelay,
+    managers: Vec<Arc<CrdtManager>>,
+    // Counter value each replica has applied so far
+    seen: Vec<Arc<AtomicU64>>,
+    changed: Arc<Notify>,
+    total: u64,
+}
+
+impl Replicas {
+    async fn new(count: usize) -> Self {
+        let relay = MockRelay::run().await.unwrap();
+        let keys = Keys::generate();
+        let changed = Arc::new(Notify::new());
+        let mut managers = Vec::new();
+        let mut seen = Vec::new();
+        for _ in 0..count {
+            let manager = Arc::new(connect(&relay, &keys).await);
+            let value = Arc::new(AtomicU64::new(0));
+            let (sink, notify) = (Arc::clone(&value), Arc::clone(&changed));
+            manager.on_change(move |change| {
+                if let Ok(total) = change.value.parse() {
+                    sink.store(total, Ordering::Relaxed);
+                    notify.notify_one();
+                }
+            });
+            let syncing = Arc::clone(&manager);
+            tokio::spawn(async move { syncing.sync().await });
+            managers.push(manager);
+            seen.push(value);
+        }
+
+        let mut replicas = Self {
+            _relay: relay,
+            managers,
+            seen,
+            changed,
+            total: 0,
+        };
+        // Every replica is subscribed once a write from each has converged
+        replicas.exchange(count).await;
+        replicas
+    }
+
+    // Split `ops` increments over the replicas, write them concurrently and
+    // wait until every replica has applied all of them
+    async fn exchange(&mut self, ops: usize) {
+        let count = self.managers.len();
+        let writers = self.managers.iter().enumerate().map(|(i, manager)| {
+            let share = ops / count + usize::from(i < ops % count);
+            async move {
+                for _ in 0..share {
+                    manager.increment_counter("counter", 1).await.unwrap();
+                }
+            }
+        });
+        futures::future::join_all(writers).await;
+        self.total += ops as u64;
+
+        tokio::time::timeout(REPLICA_TIMEOUT, async {
+            while self
+                .seen
+                .iter()
+                .any(|seen| seen.load(Ordering::Relaxed) < self.total)
+            {
+                self.changed.notified().await;
+            }
+        })
+        .await
+        .expect("replicas did not converge");
+    }
+}
+
+const REPLICA_TIMEOUT: Duration = Duration::from_secs(300);
+
+// Time for every replica to converge after all of them write a share of a
+// batch of operations. Compare runs with `--save-baseline` and
+// `--baseline` to spot regressions in the sync loop.
+fn bench_replica_convergence(c: &mut Criterion) {
+    let rt = Runtime::new().unwrap();
+    let mut group = c.benchmark_group("CRDT_Sync_Replicas");
+    // Seconds per iteration at 10k operations; keep the sample count fixed
+    group.sample_size(10);
+    group.sampling_mode(SamplingMode::Flat);
+
+    for replicas in [2, 10] {
+        let mut setup = rt.block_on(Replicas::new(replicas));
+        for ops in [1_000, 10_000] {
+            group.throughput(Throughput::Elements(ops as u64));
+            group.bench_function(format!("{replicas}_replicas/{ops}_ops"), |b| {
+                b.iter_custom(|iters| {
+                    rt.block_on(async {
+                        let start = Instant::now();
+                        for _ in 0..iters {
+                            setup.exchange(ops).await;
+                        }
+                        start.elapsed()
+                    })
+                });
+            });
+        }
+    }
+
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_round_trip,
+    bench_convergence,
+    bench_replica_convergence
+);
+criterion_main!(benches);
@@ -0,0 +1,18 @@
+// Turn the backend features into cfgs for the target being built:
+// `native_backend` with the `native` feature off wasm32, `wasm_backend` with
+// the `wasm` feature on wasm32. A feature enabled for the other kind of
+// target selects nothing, so `--all-features` builds everywhere.
+use std::env;
+
+fn main() {
+    println!("cargo::rustc-check-cfg=cfg(native_backend, wasm_backend)");
+
+    let wasm32 = env::var("CARGO_CFG_TARGET_ARCH").is_ok_and(|arch| arch == "wasm32");
+    let enabled = |feature: &str| env::var_os(format!("CARGO_FEATURE_{feature}")).is_some();
+    if enabled("NATIVE") && !wasm32 {
+        println!("cargo::rustc-cfg=native_backend");
+    }
+    if enabled("WASM") && wasm32 {
+        println!("cargo::rustc-cfg=wasm_backend");
+    }
+}
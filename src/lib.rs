@@ -1,2 +1,5 @@
 pub mod nostr;
 pub mod testhelper;
+
+#[cfg(feature = "uniffi")]
+uniffi::setup_scaffolding!();
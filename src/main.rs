@@ -1,368 +1,321 @@
-use nostr_crdt::nostr::crdt::{
-    CrdtManager, CrdtOperation, CrdtState, GCounter, GSet, GSetAction, LWWRegister,
-};
-use nostr_indexeddb::nostr::nips::nip19::ToBech32;
-use nostr_sdk::{Client, Keys, SecretKey};
+use clap::{Parser, Subcommand};
+use nostr_crdt::nostr::crdt::{CheckpointLoad, CrdtManager};
+use nostr_sdk::{Client, FromBech32, Keys, SecretKey, ToBech32};
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
-use tracing::{debug, error, info, warn};
-use tracing_subscriber;
-
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // Initialize tracing subscriber
-    tracing_subscriber::fmt::init();
+use tracing::info;
+
+const DEFAULT_RELAYS: [&str; 3] = ["wss://relay.damus.io", "wss://nos.lol", "wss://nostr.wine"];
+
+#[derive(Debug, Parser)]
+#[command(
+    name = "nostr-crdt",
+    version,
+    about = "Read and write a nostr-crdt document"
+)]
+struct Cli {
+    /// Config file holding the keys and relays
+    #[arg(short, long, env = "NOSTR_CRDT_CONFIG")]
+    config: Option<PathBuf>,
+    /// Named document to work on, the default document when omitted
+    #[arg(short, long)]
+    document: Option<String>,
+    #[command(subcommand)]
+    command: Command,
+}
 
-    info!("CRDT Demo using Nostr");
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Write a config file with fresh keys and the default relays
+    Init {
+        /// Replace an existing config file
+        #[arg(long)]
+        force: bool,
+    },
+    /// Set a register
+    Set { key: String, value: String },
+    /// Increment a counter
+    Incr {
+        key: String,
+        #[arg(default_value_t = 1)]
+        amount: u64,
+    },
+    /// Add a value to a set
+    Add { key: String, value: String },
+    /// Print the current value of a key
+    Get { key: String },
+    /// Print a key, or every key, each time it changes
+    Watch { key: Option<String> },
+    /// Rebuild the document from relays and publish a fresh checkpoint
+    Bootstrap,
+    /// Print the whole document as JSON
+    Export,
+    /// Serve the document to local processes over WebSocket/JSON-RPC
+    #[cfg(feature = "bridge")]
+    Serve {
+        /// Address to listen on, keep it on loopback
+        #[arg(long, default_value = "127.0.0.1:7878")]
+        listen: std::net::SocketAddr,
+    },
+}
 
-    // Generate a new private key, or use an existing one
-    let secret_key = SecretKey::generate();
-    info!("Using private key: {}", secret_key.to_bech32().unwrap());
+// Contents of the config file
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Config {
+    // nsec or hex
+    secret_key: String,
+    #[serde(default = "default_relays")]
+    relays: Vec<String>,
+    // Seconds to wait for relays when loading the document
+    #[serde(default = "default_timeout")]
+    timeout_secs: u64,
+}
 
-    let keys = Keys::new(secret_key);
+fn default_relays() -> Vec<String> {
+    DEFAULT_RELAYS
+        .iter()
+        .map(|relay| relay.to_string())
+        .collect()
+}
 
-    // Create Nostr client
-    let client = Client::new(&keys);
+fn default_timeout() -> u64 {
+    10
+}
 
-    // Add some relay servers
-    client.add_relay("wss://relay.damus.io").await?;
-    client.add_relay("wss://nos.lol").await?;
-    client.add_relay("wss://nostr.wine").await?;
-    client.add_relay("wss://relay.nostr.band").await?;
-    client.add_relay("wss://relay.snort.social").await?;
+impl Config {
+    fn generate() -> Self {
+        Self {
+            secret_key: Keys::generate().secret_key().unwrap().to_bech32().unwrap(),
+            relays: default_relays(),
+            timeout_secs: default_timeout(),
+        }
+    }
 
-    // Connect to relay servers
-    client.connect().await;
-    info!("Connected to relay servers");
+    fn parse(content: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(toml::from_str(content)?)
+    }
 
-    // Wait for connection confirmation
-    tokio::time::sleep(Duration::from_secs(2)).await;
+    fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let content = std::fs::read_to_string(path).map_err(|err| {
+            format!(
+                "Cannot read config {}: {err}, run `nostr-crdt init` first",
+                path.display()
+            )
+        })?;
+        Self::parse(&content)
+    }
 
-    // Get signer
-    let signer = client.signer().await?;
+    fn keys(&self) -> Result<Keys, Box<dyn std::error::Error>> {
+        let secret_key = SecretKey::from_bech32(&self.secret_key)
+            .or_else(|_| SecretKey::from_hex(&self.secret_key))?;
+        Ok(Keys::new(secret_key))
+    }
 
-    // Create CRDT manager
-    let crdt_manager = CrdtManager::new(Arc::new(client.clone()), signer.clone(), keys.clone());
+    fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout_secs)
+    }
+}
 
-    // 1. Demonstrate LWW-Register
-    info!("Demonstrating Last-Writer-Wins Register:");
+// $XDG_CONFIG_HOME/nostr-crdt/config.toml, falling back to ~/.config
+fn default_config_path() -> PathBuf {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .unwrap_or_default();
+    base.join("nostr-crdt").join("config.toml")
+}
 
-    // Update register
-    let event_id = crdt_manager
-        .update_lww_register("username", "capybara")
-        .await?;
-    info!("Published username update event: {}", event_id);
+fn init(path: &Path, force: bool) -> Result<(), Box<dyn std::error::Error>> {
+    if path.exists() && !force {
+        return Err(format!(
+            "{} already exists, pass --force to replace it",
+            path.display()
+        )
+        .into());
+    }
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let config = Config::generate();
+    write_private(path, &toml::to_string(&config)?)?;
+    println!("{}", config.keys()?.public_key().to_bech32()?);
+    Ok(())
+}
 
-    // Get current value
-    let username = crdt_manager.get_register_value("username");
-    info!("Current username: {:?}", username);
+// Write a file only its owner can read, as it holds the secret key. The
+// mode is also set on a file being replaced.
+fn write_private(path: &Path, content: &str) -> std::io::Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    #[cfg(unix)]
+    file.set_permissions(std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+    file.write_all(content.as_bytes())
+}
 
-    // Update to new value
-    let event_id = crdt_manager
-        .update_lww_register("username", "super_capybara")
+async fn connect(config: &Config) -> Result<Client, Box<dyn std::error::Error>> {
+    let client = Client::new(&config.keys()?);
+    client
+        .add_relays(config.relays.iter().map(String::as_str))
         .await?;
-    info!("Published new username update event: {}", event_id);
-
-    // Get updated value
-    let username = crdt_manager.get_register_value("username");
-    info!("Updated username: {:?}", username);
-
-    // 2. Demonstrate G-Counter
-    info!("Demonstrating Grow-only Counter:");
-
-    // Increment counter
-    let event_id = crdt_manager.increment_counter("visitors", 1).await?;
-    info!("Published visitor count increment event: {}", event_id);
-
-    // Increment counter again
-    let event_id = crdt_manager.increment_counter("visitors", 5).await?;
-    info!("Published visitor count increment event: {}", event_id);
-
-    // Get current count
-    let visitors = crdt_manager.get_counter_value("visitors");
-    info!("Current visitor count: {:?}", visitors);
-
-    // 3. Demonstrate G-Set
-    info!("Demonstrating Grow-only Set:");
-
-    // Add to set
-    let event_id = crdt_manager.add_to_set("tags", "nostr").await?;
-    info!("Published tag addition event: {}", event_id);
-
-    // Add more to set
-    let event_id = crdt_manager.add_to_set("tags", "crdt").await?;
-    info!("Published tag addition event: {}", event_id);
-
-    let event_id = crdt_manager.add_to_set("tags", "distributed").await?;
-    info!("Published tag addition event: {}", event_id);
-
-    // Get current set
-    let tags = crdt_manager.get_set_value("tags");
-    info!("Current tag set: {:?}", tags);
+    client.connect_with_timeout(config.timeout()).await;
+    Ok(client)
+}
 
-    // Wait for a while to ensure events have propagated
-    info!("Waiting for event propagation...");
-    tokio::time::sleep(Duration::from_secs(5)).await;
+// Restore the document from its latest checkpoint and the operations after it
+async fn load(
+    manager: &CrdtManager,
+    config: &Config,
+) -> Result<CheckpointLoad, Box<dyn std::error::Error>> {
+    Ok(manager.load_checkpoint(Some(config.timeout())).await?)
+}
 
-    // Create a filter to get CRDT events
-    let filter = crdt_manager.get_filter();
+// Value of `key` in whichever CRDT holds it, registers first
+fn lookup(manager: &CrdtManager, key: &str) -> Option<String> {
+    manager
+        .iter_state()
+        .find(|(entry, _, _)| entry == key)
+        .map(|(_, _, value)| value)
+}
 
-    // Fetch events from network
-    info!("Fetching CRDT events from network:");
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    tracing_subscriber::fmt()
+        .with_writer(std::io::stderr)
+        .init();
+
+    let cli = Cli::parse();
+    let config_path = cli.config.unwrap_or_else(default_config_path);
+    if let Command::Init { force } = cli.command {
+        return init(&config_path, force);
+    }
 
-    // Add error handling
-    let events = match client
-        .get_events_of(vec![filter], Some(Duration::from_secs(10)))
-        .await
-    {
-        Ok(events) => events,
-        Err(err) => {
-            error!("Error fetching events: {}", err);
-            Vec::new() // Return empty array instead of error
-        }
+    let config = Config::load(&config_path)?;
+    let client = Arc::new(connect(&config).await?);
+    let signer = client.signer().await?;
+    let root = Arc::new(CrdtManager::new(Arc::clone(&client), signer));
+    let manager = match &cli.document {
+        Some(name) => root.open_document(name),
+        None => root,
     };
 
-    info!("Found {} CRDT events", events.len());
-    for (i, event) in events.iter().enumerate() {
-        debug!("Event {}: ID: {}", i + 1, event.id);
-
-        // Check if it contains hashtag marker
-        let is_crdt_event = event.tags.iter().any(|tag| {
-            if let Some(values) = tag.as_vec().get(0..2) {
-                return values.len() == 2 && values[0] == "t" && values[1] == "nostr-crdt";
+    match cli.command {
+        Command::Init { .. } => unreachable!("handled before connecting"),
+        Command::Set { key, value } => {
+            println!("{}", manager.update_lww_register(&key, &value).await?);
+        }
+        Command::Incr { key, amount } => {
+            println!("{}", manager.increment_counter(&key, amount).await?);
+        }
+        Command::Add { key, value } => {
+            println!("{}", manager.add_to_set(&key, &value).await?);
+        }
+        Command::Get { key } => {
+            load(&manager, &config).await?;
+            match lookup(&manager, &key) {
+                Some(value) => println!("{value}"),
+                None => return Err(format!("{key} is not set").into()),
             }
-            false
-        });
-
-        if !is_crdt_event {
-            debug!("Not a CRDT event, skipping");
-            continue;
         }
-
-        // Check if content needs decryption
-        let content = if event.content.contains("?iv=") {
-            debug!("Content is encrypted, attempting to decrypt...");
-            // Important: Use sender's public key (event.pubkey) for decryption
-            match signer.nip04_decrypt(event.pubkey, &event.content).await {
-                Ok(decrypted) => {
-                    debug!("Decryption successful");
-                    decrypted
+        Command::Watch { key } => {
+            load(&manager, &config).await?;
+            match &key {
+                Some(key) => {
+                    if let Some(value) = lookup(&manager, key) {
+                        println!("{value}");
+                    }
                 }
-                Err(err) => {
-                    warn!("Decryption failed: {}", err);
-                    event.content.clone()
+                None => {
+                    for (key, _, value) in manager.iter_state() {
+                        println!("{key}={value}");
+                    }
                 }
             }
-        } else {
-            event.content.clone()
-        };
-
-        debug!("Event content: {}", content);
-        match serde_json::from_str::<CrdtOperation>(&content) {
-            Ok(op) => debug!("Operation: {:?}", op),
-            Err(err) => warn!("Could not parse operation: {}", err),
+            manager.on_change(move |change| match &key {
+                Some(key) if *key == change.key => println!("{}", change.value),
+                Some(_) => {}
+                None => println!("{}={}", change.key, change.value),
+            });
+            manager.sync().await?;
+        }
+        Command::Bootstrap => {
+            match load(&manager, &config).await? {
+                CheckpointLoad::Verified(id) => info!("Restored checkpoint {}", id),
+                CheckpointLoad::Missing => info!("No checkpoint yet, replayed operations"),
+                CheckpointLoad::Rejected(id) => {
+                    info!("Checkpoint {} failed verification, replayed operations", id)
+                }
+            }
+            println!("{}", manager.publish_checkpoint().await?);
+        }
+        Command::Export => {
+            load(&manager, &config).await?;
+            println!("{}", serde_json::to_string_pretty(&manager.export_state())?);
+        }
+        #[cfg(feature = "bridge")]
+        Command::Serve { listen } => {
+            load(&manager, &config).await?;
+            let listener = tokio::net::TcpListener::bind(listen).await?;
+            info!("Serving on ws://{}", listener.local_addr()?);
+            let bridge = nostr_crdt::nostr::crdt::Bridge::new(Arc::clone(&manager));
+            tokio::select! {
+                result = manager.sync() => result?,
+                result = bridge.serve(listener) => result?,
+            }
         }
     }
 
-    info!("CRDT demonstration completed");
-
-    // CRDT merge test - Simulate eventual consistency after receiving operations in different orders
-    info!("===== CRDT Merge Test =====");
-
-    info!("1. LWW-Register merge test (last writer wins):");
-
-    // Create a simulated CRDT manager, for local testing only
-    let mut lww_register = LWWRegister::default();
-
-    // Earlier operation
-    let op_a = CrdtOperation::LWWRegister {
-        key: "test_key".to_string(),
-        value: "Value A".to_string(),
-        timestamp: 100,
-    };
-
-    // Later operation
-    let op_b = CrdtOperation::LWWRegister {
-        key: "test_key".to_string(),
-        value: "Value B".to_string(),
-        timestamp: 200,
-    };
-
-    // Simulate Device 1: Apply A then B
-    info!("  Device 1: Apply A (timestamp 100) then B (timestamp 200)");
-    let mut device1 = LWWRegister::default();
-    device1.apply_operation(op_a.clone()).unwrap();
-    debug!(
-        "    Value after applying A: {:?}",
-        device1.get_value("test_key")
-    );
-    device1.apply_operation(op_b.clone()).unwrap();
-    debug!(
-        "    Value after applying B: {:?}",
-        device1.get_value("test_key")
-    );
-
-    // Simulate Device 2: Apply B then A
-    info!("  Device 2: Apply B (timestamp 200) then A (timestamp 100)");
-    let mut device2 = LWWRegister::default();
-    device2.apply_operation(op_b.clone()).unwrap();
-    debug!(
-        "    Value after applying B: {:?}",
-        device2.get_value("test_key")
-    );
-    device2.apply_operation(op_a.clone()).unwrap();
-    debug!(
-        "    Value after applying A: {:?}",
-        device2.get_value("test_key")
-    );
-
-    // Verify final state is consistent
-    info!(
-        "  Final result: Device 1={:?}, Device 2={:?}",
-        device1.get_value("test_key"),
-        device2.get_value("test_key")
-    );
-    info!(
-        "  Merge successful: {}",
-        device1.get_value("test_key") == device2.get_value("test_key")
-    );
-
-    // G-Counter merge test
-    info!("2. G-Counter merge test (grow-only counter):");
-
-    // Simulate Device 1: First +3 then +2
-    info!("  Device 1: First +3 then +2");
-    let mut counter1 = GCounter::default();
-    counter1
-        .apply_operation(CrdtOperation::GCounter {
-            key: "test_counter".to_string(),
-            increment: 3,
-        })
-        .unwrap();
-    debug!(
-        "    Count after +3: {:?}",
-        counter1.get_value("test_counter")
-    );
-
-    counter1
-        .apply_operation(CrdtOperation::GCounter {
-            key: "test_counter".to_string(),
-            increment: 2,
-        })
-        .unwrap();
-    debug!(
-        "    Count after +2: {:?}",
-        counter1.get_value("test_counter")
-    );
-
-    // Simulate Device 2: First +2 then +3
-    info!("  Device 2: First +2 then +3");
-    let mut counter2 = GCounter::default();
-    counter2
-        .apply_operation(CrdtOperation::GCounter {
-            key: "test_counter".to_string(),
-            increment: 2,
-        })
-        .unwrap();
-    debug!(
-        "    Count after +2: {:?}",
-        counter2.get_value("test_counter")
-    );
-
-    counter2
-        .apply_operation(CrdtOperation::GCounter {
-            key: "test_counter".to_string(),
-            increment: 3,
-        })
-        .unwrap();
-    debug!(
-        "    Count after +3: {:?}",
-        counter2.get_value("test_counter")
-    );
-
-    // Verify final count is the same
-    info!(
-        "  Final result: Device 1={:?}, Device 2={:?}",
-        counter1.get_value("test_counter"),
-        counter2.get_value("test_counter")
-    );
-    info!(
-        "  Merge successful: {}",
-        counter1.get_value("test_counter") == counter2.get_value("test_counter")
-    );
-
-    // G-Set merge test
-    info!("3. G-Set merge test (grow-only set):");
-
-    // Simulate Device 1: Add A, B, C
-    info!("  Device 1: Addition order A->B->C");
-    let mut set1 = GSet::default();
-    set1.apply_operation(CrdtOperation::GSet {
-        key: "test_set".to_string(),
-        value: "A".to_string(),
-        action: GSetAction::Add,
-    })
-    .unwrap();
-    debug!("    After adding A: {:?}", set1.get_value("test_set"));
-
-    set1.apply_operation(CrdtOperation::GSet {
-        key: "test_set".to_string(),
-        value: "B".to_string(),
-        action: GSetAction::Add,
-    })
-    .unwrap();
-    debug!("    After adding B: {:?}", set1.get_value("test_set"));
-
-    set1.apply_operation(CrdtOperation::GSet {
-        key: "test_set".to_string(),
-        value: "C".to_string(),
-        action: GSetAction::Add,
-    })
-    .unwrap();
-    debug!("    After adding C: {:?}", set1.get_value("test_set"));
-
-    // Simulate Device 2: Add C, A, B (different order)
-    info!("  Device 2: Addition order C->A->B");
-    let mut set2 = GSet::default();
-    set2.apply_operation(CrdtOperation::GSet {
-        key: "test_set".to_string(),
-        value: "C".to_string(),
-        action: GSetAction::Add,
-    })
-    .unwrap();
-    debug!("    After adding C: {:?}", set2.get_value("test_set"));
+    client.disconnect().await?;
+    Ok(())
+}
 
-    set2.apply_operation(CrdtOperation::GSet {
-        key: "test_set".to_string(),
-        value: "A".to_string(),
-        action: GSetAction::Add,
-    })
-    .unwrap();
-    debug!("    After adding A: {:?}", set2.get_value("test_set"));
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config() {
+        let config = Config::generate();
+        let parsed = Config::parse(&toml::to_string(&config).unwrap()).unwrap();
+        assert_eq!(parsed, config);
+        assert!(parsed.keys().is_ok());
+
+        // Only the key is required
+        let keys = Keys::generate();
+        let hex = keys.secret_key().unwrap().to_secret_hex();
+        let minimal = Config::parse(&format!("secret_key = \"{hex}\"")).unwrap();
+        assert_eq!(minimal.relays, default_relays());
+        assert_eq!(minimal.timeout(), Duration::from_secs(10));
+        assert_eq!(minimal.keys().unwrap().public_key(), keys.public_key());
+
+        assert!(Config::parse("relays = []").is_err());
+    }
 
-    set2.apply_operation(CrdtOperation::GSet {
-        key: "test_set".to_string(),
-        value: "B".to_string(),
-        action: GSetAction::Add,
-    })
-    .unwrap();
-    debug!("    After adding B: {:?}", set2.get_value("test_set"));
+    #[cfg(unix)]
+    #[test]
+    fn test_init_keeps_config_private() {
+        use std::os::unix::fs::PermissionsExt;
 
-    // Verify final sets are the same
-    info!(
-        "  Final result: Device 1={:?}, Device 2={:?}",
-        set1.get_value("test_set"),
-        set2.get_value("test_set")
-    );
-    info!(
-        "  Merge successful: {}",
-        set1.get_value("test_set") == set2.get_value("test_set")
-    );
+        let name = format!("nostr-crdt-{}", Keys::generate().public_key().to_hex());
+        let dir = std::env::temp_dir().join(name);
+        let path = dir.join("config.toml");
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
 
-    info!("CRDT merge test completed - Demonstrated that regardless of operation order, final state converges to the same result");
+        init(&path, false).unwrap();
+        assert_eq!(mode(&path), 0o600);
+        assert!(Config::load(&path).unwrap().keys().is_ok());
+        assert!(init(&path, false).is_err());
 
-    // Disconnect
-    client.disconnect().await?;
+        // A replaced config that had been left readable is locked down
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        init(&path, true).unwrap();
+        assert_eq!(mode(&path), 0o600);
 
-    Ok(())
+        std::fs::remove_dir_all(dir).unwrap();
+    }
 }
@@ -0,0 +1,208 @@
+use std::collections::HashSet;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+
+use nostr_sdk::{Client, EventBuilder, NostrSigner, RelayMessage, RelayPoolNotification, Url};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Client(#[from] nostr_sdk::client::Error),
+    #[error(transparent)]
+    Signer(#[from] nostr_sdk::signer::Error),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Prefix of `OK`/`CLOSED` messages from relays that want NIP-42 auth first
+const AUTH_REQUIRED: &str = "auth-required:";
+
+/// Which relays may receive our signed AUTH events. Authenticating reveals
+/// the public key to the relay, so apps may want to limit it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum AuthPolicy {
+    /// Answer every challenge
+    #[default]
+    Always,
+    /// Ignore every challenge
+    Never,
+    /// Answer only these relays
+    Relays(HashSet<Url>),
+}
+
+impl AuthPolicy {
+    pub fn allows(&self, relay_url: &Url) -> bool {
+        match self {
+            AuthPolicy::Always => true,
+            AuthPolicy::Never => false,
+            AuthPolicy::Relays(relays) => relays.contains(relay_url),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct AuthState {
+    authenticated: HashSet<Url>,
+    /// Relays that refused a request or event until we authenticate
+    auth_required: HashSet<Url>,
+}
+
+/// NIP-42 challenge handler signing with our own signer and policy.
+///
+/// Build the client with `Options::new().automatic_authentication(false)`
+/// so the SDK does not answer challenges on its own, then drive
+/// [`Authenticator::run`] next to the app's other tasks. Fetch and publish
+/// calls that raced a challenge can be wrapped in
+/// [`Authenticator::retry_after_auth`].
+#[derive(Debug, Clone)]
+pub struct Authenticator {
+    signer: NostrSigner,
+    policy: AuthPolicy,
+    state: Arc<Mutex<AuthState>>,
+}
+
+impl Authenticator {
+    pub fn new(signer: NostrSigner, policy: AuthPolicy) -> Self {
+        Self {
+            signer,
+            policy,
+            state: Arc::new(Mutex::new(AuthState::default())),
+        }
+    }
+
+    pub fn policy(&self) -> &AuthPolicy {
+        &self.policy
+    }
+
+    pub fn is_authenticated(&self, relay_url: &Url) -> bool {
+        self.state.lock().unwrap().authenticated.contains(relay_url)
+    }
+
+    /// React to one relay message: answer AUTH challenges the policy allows
+    /// and remember relays that asked for auth. Returns whether an AUTH
+    /// event was sent.
+    pub async fn handle_message(
+        &self,
+        client: &Client,
+        relay_url: &Url,
+        message: &RelayMessage,
+    ) -> Result<bool> {
+        match message {
+            RelayMessage::Auth { challenge } => {
+                if !self.policy.allows(relay_url) {
+                    tracing::debug!("Ignoring AUTH challenge from {}", relay_url);
+                    return Ok(false);
+                }
+                let builder = EventBuilder::auth(challenge, relay_url.clone());
+                let event = self.signer.sign_event_builder(builder).await?;
+                client.send_event_to([relay_url.clone()], event).await?;
+                self.state
+                    .lock()
+                    .unwrap()
+                    .authenticated
+                    .insert(relay_url.clone());
+                tracing::info!("Authenticated to {}", relay_url);
+                Ok(true)
+            }
+            RelayMessage::Ok {
+                status: false,
+                message,
+                ..
+            }
+            | RelayMessage::Closed { message, .. }
+                if message.starts_with(AUTH_REQUIRED) =>
+            {
+                self.state
+                    .lock()
+                    .unwrap()
+                    .auth_required
+                    .insert(relay_url.clone());
+                Ok(false)
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Answer challenges until the client shuts down. A failed answer is
+    /// logged and the loop goes on.
+    pub async fn run(&self, client: &Client) -> Result<()> {
+        client
+            .handle_notifications(|notification| async move {
+                if let RelayPoolNotification::Message { relay_url, message } = notification {
+                    if let Err(err) = self.handle_message(client, &relay_url, &message).await {
+                        tracing::error!("Can't authenticate to {}: {}", relay_url, err);
+                    }
+                }
+                Ok(false)
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Run `op`, and run it once more if a relay refused it for lack of auth
+    /// and has been authenticated since
+    pub async fn retry_after_auth<F, Fut, T>(&self, op: F) -> T
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = T>,
+    {
+        self.state.lock().unwrap().auth_required.clear();
+        let result = op().await;
+        let retry = {
+            let state = self.state.lock().unwrap();
+            state
+                .auth_required
+                .iter()
+                .any(|url| state.authenticated.contains(url))
+        };
+        if retry {
+            tracing::debug!("Retrying after NIP-42 authentication");
+            return op().await;
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr_sdk::{Keys, SubscriptionId};
+
+    #[tokio::test]
+    async fn test_auth_policy() {
+        let keys = Keys::generate();
+        let client = Client::new(keys.clone());
+        let trusted = Url::parse("wss://relay.trusted.example").unwrap();
+        let other = Url::parse("wss://relay.other.example").unwrap();
+        let auth = Authenticator::new(
+            NostrSigner::Keys(keys),
+            AuthPolicy::Relays(HashSet::from([trusted.clone()])),
+        );
+
+        // Challenges from relays outside the policy are not answered
+        let challenge = RelayMessage::Auth {
+            challenge: "challenge".to_string(),
+        };
+        assert!(!auth
+            .handle_message(&client, &other, &challenge)
+            .await
+            .unwrap());
+        assert!(!auth.is_authenticated(&other));
+
+        let closed = RelayMessage::Closed {
+            subscription_id: SubscriptionId::generate(),
+            message: "auth-required: we only serve DMs to their parties".to_string(),
+        };
+        auth.handle_message(&client, &trusted, &closed)
+            .await
+            .unwrap();
+        assert!(auth.state.lock().unwrap().auth_required.contains(&trusted));
+
+        // Nothing was authenticated, so the operation runs once
+        let runs = Mutex::new(0);
+        auth.retry_after_auth(|| async { *runs.lock().unwrap() += 1 })
+            .await;
+        assert_eq!(*runs.lock().unwrap(), 1);
+    }
+}
@@ -1,9 +1,82 @@
-use nostr_sdk::{Event, EventBuilder, EventId, Keys, Kind, NostrSigner, Tag, TagKind, Timestamp};
+use futures::StreamExt;
+use nostr_sdk::{
+    Event, EventBuilder, EventId, Keys, Kind, NostrSigner, PublicKey, SecretKey, Tag, TagKind,
+    Timestamp,
+};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use thiserror::Error;
 
+use super::fetch::DecryptCache;
+
+mod audit;
+mod bookmarks;
+#[cfg(feature = "bridge")]
+mod bridge;
+mod calendar;
+mod change;
+#[cfg(feature = "testkit")]
+mod chaos;
+mod chat;
+mod checkpoint;
+mod conflict;
+mod contacts;
+mod document;
+#[cfg(feature = "uniffi")]
+mod ffi;
+mod guard;
+mod intern;
+#[cfg(wasm_backend)]
+mod js;
+mod kanban;
+mod lazy;
+mod or_set;
+mod pipeline;
+mod processed;
+mod profile;
+#[cfg(feature = "python")]
+mod python;
+mod rate_limit;
+mod relays;
+#[cfg(feature = "yjs")]
+mod yjs;
+
+pub use audit::Divergence;
+pub use bookmarks::{Bookmark, BookmarksDoc};
+#[cfg(feature = "bridge")]
+pub use bridge::Bridge;
+pub use calendar::{CalendarDoc, CalendarEvent, CalendarTime, RsvpStatus};
+pub use change::{Change, ChangeHook};
+#[cfg(feature = "testkit")]
+pub use chaos::ChaosConfig;
+pub use chat::{ChatCursor, ChatDoc, ChatMessage, ChatPage};
+pub use checkpoint::CheckpointLoad;
+pub use conflict::{Conflict, ConflictHook, ConflictOutcome};
+pub use contacts::ContactsDoc;
+#[cfg(feature = "uniffi")]
+pub use ffi::{ChangeListener, FfiCrdtManager, FfiError};
+use guard::WriteGuard;
+use intern::KeyInterner;
+#[cfg(wasm_backend)]
+pub use js::JsCrdtManager;
+pub use kanban::{KanbanCard, KanbanColumn, KanbanDoc};
+use lazy::{key_hash, ColdEvents, KEY_TAG};
+#[cfg(native_backend)]
+pub use processed::FileProcessedStore;
+#[cfg(wasm_backend)]
+pub use processed::IndexedDbProcessedStore;
+pub use processed::ProcessedStore;
+pub use profile::ProfileDoc;
+#[cfg(feature = "python")]
+pub use python::{PyCrdtManager, PyGCounter, PyGSet, PyLWWRegister};
+pub use rate_limit::RateLimit;
+use rate_limit::TokenBucket;
+#[cfg(feature = "yjs")]
+pub use yjs::YjsBridge;
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error(transparent)]
@@ -12,6 +85,8 @@ pub enum Error {
     Signer(#[from] nostr_sdk::signer::Error),
     #[error(transparent)]
     Publish(#[from] super::publish::Error),
+    #[error(transparent)]
+    Fetch(#[from] super::fetch::Error),
     #[error("Invalid CRDT operation")]
     InvalidOperation,
     #[error("Serialization error")]
@@ -20,10 +95,30 @@ pub enum Error {
     KeysNotAvailable,
     #[error(transparent)]
     EventBuilder(#[from] nostr_sdk::event::builder::Error),
+    #[error("Operation superseded by a newer local update")]
+    Coalesced,
+    #[error("CRDT document is read-only")]
+    ReadOnly,
+    #[error("Unsupported state backup version {0}")]
+    UnsupportedBackupVersion(u32),
+    #[error("Processed event store error: {0}")]
+    Store(String),
+    #[error("Not found: {0}")]
+    NotFound(String),
+    #[error("Only the author can change this")]
+    NotAuthor,
+    #[error("Invalid rate limit: burst {burst}, {per_second} per second")]
+    InvalidRateLimit { burst: u32, per_second: f64 },
 }
 
 type Result<T> = std::result::Result<T, Error>;
 
+// How far ahead of its event's `created_at`, in seconds, a register
+// timestamp may be. A write from further in the future would beat every
+// honest write until then. Both values are signed, so every replica judges
+// an operation alike whenever it sees it.
+const MAX_CLOCK_SKEW: u64 = 60 * 60;
+
 // CRDT operation types
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum CrdtOperation {
@@ -46,42 +141,188 @@ pub enum CrdtOperation {
     },
 }
 
+impl CrdtOperation {
+    // `c` tag naming the CRDT type and `key` tag hashing the key, carried
+    // by every operation event
+    fn tags(&self) -> Vec<Tag> {
+        let (crdt_type, key) = self.target();
+        vec![
+            Tag::custom(TagKind::from("c"), ["crdt", crdt_type.tag_name()]),
+            Tag::custom(TagKind::from(KEY_TAG), [key_hash(crdt_type, key)]),
+        ]
+    }
+
+    // View of the operation as a `CrdtOperationRef`, without copying
+    pub fn as_borrowed(&self) -> CrdtOperationRef<'_> {
+        match self {
+            CrdtOperation::LWWRegister {
+                key,
+                value,
+                timestamp,
+            } => CrdtOperationRef::LWWRegister {
+                key: Cow::Borrowed(key),
+                value: Cow::Borrowed(value),
+                timestamp: *timestamp,
+            },
+            CrdtOperation::GCounter { key, increment } => CrdtOperationRef::GCounter {
+                key: Cow::Borrowed(key),
+                increment: *increment,
+            },
+            CrdtOperation::GSet { key, value, action } => CrdtOperationRef::GSet {
+                key: Cow::Borrowed(key),
+                value: Cow::Borrowed(value),
+                action: action.clone(),
+            },
+        }
+    }
+}
+
+// `CrdtOperation` borrowing its strings from the JSON it was parsed from,
+// so applying a received operation does not copy every field first.
+// Strings with JSON escapes are still unescaped into owned copies. Both
+// types read and write the same JSON.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum CrdtOperationRef<'a> {
+    LWWRegister {
+        #[serde(borrow)]
+        key: Cow<'a, str>,
+        #[serde(borrow)]
+        value: Cow<'a, str>,
+        timestamp: u64,
+    },
+    GCounter {
+        #[serde(borrow)]
+        key: Cow<'a, str>,
+        increment: u64,
+    },
+    GSet {
+        #[serde(borrow)]
+        key: Cow<'a, str>,
+        #[serde(borrow)]
+        value: Cow<'a, str>,
+        action: GSetAction,
+    },
+}
+
+impl CrdtOperationRef<'_> {
+    // Copy of the operation that no longer borrows from its JSON
+    pub fn into_owned(self) -> CrdtOperation {
+        match self {
+            CrdtOperationRef::LWWRegister {
+                key,
+                value,
+                timestamp,
+            } => CrdtOperation::LWWRegister {
+                key: key.into_owned(),
+                value: value.into_owned(),
+                timestamp,
+            },
+            CrdtOperationRef::GCounter { key, increment } => CrdtOperation::GCounter {
+                key: key.into_owned(),
+                increment,
+            },
+            CrdtOperationRef::GSet { key, value, action } => CrdtOperation::GSet {
+                key: key.into_owned(),
+                value: value.into_owned(),
+                action,
+            },
+        }
+    }
+
+    pub(super) fn target(&self) -> (CrdtType, &str) {
+        match self {
+            CrdtOperationRef::LWWRegister { key, .. } => (CrdtType::LWWRegister, key),
+            CrdtOperationRef::GCounter { key, .. } => (CrdtType::GCounter, key),
+            CrdtOperationRef::GSet { key, .. } => (CrdtType::GSet, key),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum GSetAction {
     Add,
 }
 
+// CRDT type tags, used when enumerating the document state
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Enum))]
+pub enum CrdtType {
+    LWWRegister,
+    GCounter,
+    GSet,
+}
+
+impl CrdtType {
+    // Name used in the `c` tag of operation events
+    fn tag_name(self) -> &'static str {
+        match self {
+            CrdtType::LWWRegister => "lww",
+            CrdtType::GCounter => "gcounter",
+            CrdtType::GSet => "gset",
+        }
+    }
+}
+
 // CRDT state interface
 pub trait CrdtState: Send + Sync {
-    fn apply_operation(&mut self, op: CrdtOperation) -> Result<()>;
+    // Borrows the operation; states only copy the parts they keep
+    fn apply_operation(&mut self, op: &CrdtOperation) -> Result<()>;
+    // `apply_operation` for an operation parsed in place, as received
+    // events are. The default copies it into a `CrdtOperation` first.
+    fn apply_borrowed(&mut self, op: &CrdtOperationRef<'_>) -> Result<()> {
+        self.apply_operation(&op.clone().into_owned())
+    }
     fn get_value(&self, key: &str) -> Option<String>;
+    // All keys currently holding a value, sorted
+    fn keys(&self) -> Vec<String>;
 }
 
 // Last-Writer-Wins Register implementation
 #[derive(Debug, Clone, Default)]
 pub struct LWWRegister {
-    registers: HashMap<String, (String, u64)>, // key -> (value, timestamp)
+    registers: HashMap<Arc<str>, (String, u64)>, // key -> (value, timestamp)
+    keys: KeyInterner,
+}
+
+impl LWWRegister {
+    // Empty state drawing its keys from a document's pool
+    fn with_keys(keys: KeyInterner) -> Self {
+        Self {
+            keys,
+            ..Default::default()
+        }
+    }
 }
 
 impl CrdtState for LWWRegister {
-    fn apply_operation(&mut self, op: CrdtOperation) -> Result<()> {
+    fn apply_operation(&mut self, op: &CrdtOperation) -> Result<()> {
+        self.apply_borrowed(&op.as_borrowed())
+    }
+
+    fn apply_borrowed(&mut self, op: &CrdtOperationRef<'_>) -> Result<()> {
         match op {
-            CrdtOperation::LWWRegister {
+            CrdtOperationRef::LWWRegister {
                 key,
                 value,
                 timestamp,
             } => {
-                match self.registers.get(&key) {
-                    Some((_, existing_ts)) if *existing_ts >= timestamp => {
-                        // Ignore older or same timestamp updates
-                        Ok(())
+                match self.registers.get_mut(key.as_ref()) {
+                    Some((existing, existing_ts))
+                        if (*existing_ts, existing.as_str()) >= (*timestamp, value.as_ref()) =>
+                    {
+                        // Ignore older updates; equal timestamps go to the
+                        // greater value, whatever the arrival order
                     }
-                    _ => {
+                    Some(register) => {
                         // Apply newer update
-                        self.registers.insert(key, (value, timestamp));
-                        Ok(())
+                        *register = (value.to_string(), *timestamp);
+                    }
+                    None => {
+                        self.registers
+                            .insert(self.keys.intern(key), (value.to_string(), *timestamp));
                     }
                 }
+                Ok(())
             }
             _ => Err(Error::InvalidOperation),
         }
@@ -90,20 +331,45 @@ impl CrdtState for LWWRegister {
     fn get_value(&self, key: &str) -> Option<String> {
         self.registers.get(key).map(|(value, _)| value.clone())
     }
+
+    fn keys(&self) -> Vec<String> {
+        sorted_keys(self.registers.keys())
+    }
 }
 
 // Grow-only Counter implementation
 #[derive(Debug, Clone, Default)]
 pub struct GCounter {
-    counters: HashMap<String, u64>, // key -> count
+    counters: HashMap<Arc<str>, u64>, // key -> count
+    keys: KeyInterner,
+}
+
+impl GCounter {
+    // Empty state drawing its keys from a document's pool
+    fn with_keys(keys: KeyInterner) -> Self {
+        Self {
+            keys,
+            ..Default::default()
+        }
+    }
 }
 
 impl CrdtState for GCounter {
-    fn apply_operation(&mut self, op: CrdtOperation) -> Result<()> {
+    fn apply_operation(&mut self, op: &CrdtOperation) -> Result<()> {
+        self.apply_borrowed(&op.as_borrowed())
+    }
+
+    fn apply_borrowed(&mut self, op: &CrdtOperationRef<'_>) -> Result<()> {
         match op {
-            CrdtOperation::GCounter { key, increment } => {
-                let count = self.counters.entry(key).or_insert(0);
-                *count += increment;
+            CrdtOperationRef::GCounter { key, increment } => {
+                // Look up before inserting, so existing keys are not copied
+                let count = match self.counters.get_mut(key.as_ref()) {
+                    Some(count) => count,
+                    None => self.counters.entry(self.keys.intern(key)).or_insert(0),
+                };
+                // Saturate rather than overflow on hostile increments; the
+                // result still does not depend on the order of operations
+                *count = count.saturating_add(*increment);
                 Ok(())
             }
             _ => Err(Error::InvalidOperation),
@@ -113,25 +379,47 @@ impl CrdtState for GCounter {
     fn get_value(&self, key: &str) -> Option<String> {
         self.counters.get(key).map(|count| count.to_string())
     }
+
+    fn keys(&self) -> Vec<String> {
+        sorted_keys(self.counters.keys())
+    }
 }
 
 // Grow-only Set implementation
 #[derive(Debug, Clone, Default)]
 pub struct GSet {
-    sets: HashMap<String, Vec<String>>, // key -> set of values
+    sets: HashMap<Arc<str>, Vec<String>>, // key -> set of values
+    keys: KeyInterner,
+}
+
+impl GSet {
+    // Empty state drawing its keys from a document's pool
+    fn with_keys(keys: KeyInterner) -> Self {
+        Self {
+            keys,
+            ..Default::default()
+        }
+    }
 }
 
 impl CrdtState for GSet {
-    fn apply_operation(&mut self, op: CrdtOperation) -> Result<()> {
+    fn apply_operation(&mut self, op: &CrdtOperation) -> Result<()> {
+        self.apply_borrowed(&op.as_borrowed())
+    }
+
+    fn apply_borrowed(&mut self, op: &CrdtOperationRef<'_>) -> Result<()> {
         match op {
-            CrdtOperation::GSet {
+            CrdtOperationRef::GSet {
                 key,
                 value,
                 action: GSetAction::Add,
             } => {
-                let set = self.sets.entry(key).or_default();
-                if !set.contains(&value) {
-                    set.push(value);
+                let set = match self.sets.get_mut(key.as_ref()) {
+                    Some(set) => set,
+                    None => self.sets.entry(self.keys.intern(key)).or_default(),
+                };
+                if !set.iter().any(|member| member == value) {
+                    set.push(value.to_string());
                 }
                 Ok(())
             }
@@ -144,150 +432,620 @@ impl CrdtState for GSet {
             .get(key)
             .map(|set| serde_json::to_string(set).unwrap_or_default())
     }
+
+    fn keys(&self) -> Vec<String> {
+        sorted_keys(self.sets.keys())
+    }
+}
+
+// Ordered copy of a state map, for backups
+fn clone_sorted<V: Clone>(map: &HashMap<Arc<str>, V>) -> BTreeMap<String, V> {
+    map.iter()
+        .map(|(key, value)| (key.to_string(), value.clone()))
+        .collect()
+}
+
+// A backup's entries, keyed by pooled keys
+fn intern_all<V>(keys: &KeyInterner, map: BTreeMap<String, V>) -> HashMap<Arc<str>, V> {
+    map.into_iter()
+        .map(|(key, value)| (keys.intern(&key), value))
+        .collect()
+}
+
+fn sorted_keys<'a>(keys: impl Iterator<Item = &'a Arc<str>>) -> Vec<String> {
+    let mut keys: Vec<String> = keys.map(|key| key.to_string()).collect();
+    keys.sort();
+    keys
+}
+
+// Serializable copy of a whole document, used for backups and for moving
+// to a new device without replaying relay history
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateBackup {
+    pub version: u32,
+    // key -> (value, timestamp)
+    pub registers: BTreeMap<String, (String, u64)>,
+    pub counters: BTreeMap<String, u64>,
+    pub sets: BTreeMap<String, Vec<String>>,
+    // Events already folded into the state above
+    pub processed_events: Vec<EventId>,
+}
+
+impl StateBackup {
+    pub const VERSION: u32 = 1;
+}
+
+// Point-in-time view of the sync metrics
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CrdtMetrics {
+    pub ops_applied: u64,
+    pub ops_rejected: u64,
+    pub decryption_failures: u64,
+    pub publish_retries: u64,
+    // Operations applied locally but not yet acknowledged by a relay
+    pub pending_queue_depth: u64,
+    // Unix seconds of the last remote operation applied, if any
+    pub last_sync_timestamp: Option<u64>,
+}
+
+// Live counters behind `CrdtManager::metrics()`. Each update is mirrored to
+// the `metrics` crate facade when the feature is enabled.
+#[derive(Debug, Default)]
+struct SyncMetrics {
+    ops_applied: AtomicU64,
+    ops_rejected: AtomicU64,
+    decryption_failures: AtomicU64,
+    publish_retries: AtomicU64,
+    pending_queue_depth: AtomicU64,
+    last_sync_timestamp: AtomicU64,
+}
+
+impl SyncMetrics {
+    fn incr(counter: &AtomicU64, _name: &'static str) {
+        counter.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        metrics::counter!(_name).increment(1);
+    }
+
+    fn record_applied(&self) {
+        Self::incr(&self.ops_applied, "nostr_crdt_ops_applied");
+    }
+
+    fn record_rejected(&self) {
+        Self::incr(&self.ops_rejected, "nostr_crdt_ops_rejected");
+    }
+
+    fn record_decryption_failure(&self) {
+        Self::incr(&self.decryption_failures, "nostr_crdt_decryption_failures");
+    }
+
+    fn record_publish_retry(&self) {
+        Self::incr(&self.publish_retries, "nostr_crdt_publish_retries");
+    }
+
+    fn record_sync(&self) {
+        let now = Timestamp::now().as_u64();
+        self.last_sync_timestamp.store(now, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        metrics::gauge!("nostr_crdt_last_sync_timestamp").set(now as f64);
+    }
+
+    fn set_pending(&self, depth: u64) {
+        self.pending_queue_depth.store(depth, Ordering::Relaxed);
+        #[cfg(feature = "metrics")]
+        metrics::gauge!("nostr_crdt_pending_queue_depth").set(depth as f64);
+    }
+
+    fn pending_added(&self) {
+        let depth = self.pending_queue_depth.fetch_add(1, Ordering::Relaxed) + 1;
+        self.set_pending(depth);
+    }
+
+    fn pending_removed(&self) {
+        let depth = self.pending_queue_depth.fetch_sub(1, Ordering::Relaxed) - 1;
+        self.set_pending(depth);
+    }
+
+    fn snapshot(&self) -> CrdtMetrics {
+        let last_sync = self.last_sync_timestamp.load(Ordering::Relaxed);
+        CrdtMetrics {
+            ops_applied: self.ops_applied.load(Ordering::Relaxed),
+            ops_rejected: self.ops_rejected.load(Ordering::Relaxed),
+            decryption_failures: self.decryption_failures.load(Ordering::Relaxed),
+            publish_retries: self.publish_retries.load(Ordering::Relaxed),
+            pending_queue_depth: self.pending_queue_depth.load(Ordering::Relaxed),
+            last_sync_timestamp: (last_sync > 0).then_some(last_sync),
+        }
+    }
+}
+
+// How a follower reads another user's operations
+#[derive(Debug, Clone)]
+pub enum FollowAccess {
+    // The publisher posts operations in plaintext
+    Public,
+    // The document's read key, handed out by the publisher from
+    // `CrdtManager::read_key`. Never the publisher's own secret key: that
+    // one signs for their whole identity.
+    SharedSecret(SecretKey),
 }
 
 // Main CRDT manager
 pub struct CrdtManager {
     client: Arc<nostr_sdk::Client>,
+    // Local keys or a NIP-46 remote signer; every event is signed through it
     signer: NostrSigner,
-    keys: Keys,
     lww_registers: Arc<Mutex<LWWRegister>>,
     g_counters: Arc<Mutex<GCounter>>,
     g_sets: Arc<Mutex<GSet>>,
     crdt_kind: Kind,
+    metrics: Arc<SyncMetrics>,
+    limiter: Option<Arc<tokio::sync::Mutex<TokenBucket>>>,
+    // Latest queued publish sequence per register key, for coalescing
+    lww_queue: Arc<Mutex<HashMap<Arc<str>, u64>>>,
+    // Key names of this document, shared by the states and `lww_queue`
+    keys: KeyInterner,
+    publish_seq: Arc<AtomicU64>,
+    encrypt_operations: bool,
+    // Keypair operations are encrypted to instead of the signer's own key,
+    // so that readers can be let in without signing power
+    read_key: Option<Keys>,
+    // Set for read-only managers mirroring another user's document
+    following: Option<(PublicKey, FollowAccess)>,
+    // Events already applied, so re-delivered events are not applied twice
+    processed: Arc<Mutex<HashSet<EventId>>>,
+    // Durable copy of `processed`, loaded into it once on first use
+    processed_store: Option<Arc<dyn ProcessedStore>>,
+    processed_loaded: tokio::sync::OnceCell<()>,
+    // Name of this document, carried in a `d` tag; `None` is the default
+    document: Option<String>,
+    // Accepted authors, any author when empty; set per document
+    members: Arc<Mutex<Vec<PublicKey>>>,
+    // Relays dedicated to this document, the client's relays when empty
+    relays: Vec<nostr_sdk::Url>,
+    relays_ready: tokio::sync::OnceCell<()>,
+    // Callbacks for incoming writes that disagree with the local value
+    conflict_hooks: Arc<Mutex<Vec<ConflictHook>>>,
+    // Callbacks for every value change, local or remote
+    change_hooks: Arc<Mutex<Vec<ChangeHook>>>,
+    // Check on who may make incoming writes, set by document types
+    write_guard: Arc<Mutex<Option<WriteGuard>>>,
+    // Bumped whenever the state changes, for views caching what they read
+    version: Arc<AtomicU64>,
+    // Named documents opened from this manager
+    documents: Arc<Mutex<HashMap<String, Arc<CrdtManager>>>>,
+    // Decrypted operation contents of recent events, shared with documents
+    decrypt_cache: DecryptCache,
+    // Encrypted events not decrypted yet, by key hash; `None` unless lazy
+    // decryption is on
+    cold: Option<Arc<Mutex<ColdEvents>>>,
+    // Faults injected into live events, for testing
+    #[cfg(feature = "testkit")]
+    chaos: Option<ChaosConfig>,
 }
 
 impl CrdtManager {
-    pub fn new(client: Arc<nostr_sdk::Client>, signer: NostrSigner, keys: Keys) -> Self {
+    pub fn new(client: Arc<nostr_sdk::Client>, signer: NostrSigner) -> Self {
+        let keys = KeyInterner::default();
         Self {
             client,
             signer,
-            keys,
-            lww_registers: Arc::new(Mutex::new(LWWRegister::default())),
-            g_counters: Arc::new(Mutex::new(GCounter::default())),
-            g_sets: Arc::new(Mutex::new(GSet::default())),
+            lww_registers: Arc::new(Mutex::new(LWWRegister::with_keys(keys.clone()))),
+            g_counters: Arc::new(Mutex::new(GCounter::with_keys(keys.clone()))),
+            g_sets: Arc::new(Mutex::new(GSet::with_keys(keys.clone()))),
             crdt_kind: Kind::TextNote, // Use standard TextNote Kind instead of custom Kind
+            metrics: Arc::new(SyncMetrics::default()),
+            limiter: None,
+            lww_queue: Arc::new(Mutex::new(HashMap::new())),
+            keys,
+            publish_seq: Arc::new(AtomicU64::new(0)),
+            encrypt_operations: true,
+            read_key: None,
+            following: None,
+            processed: Arc::new(Mutex::new(HashSet::new())),
+            processed_store: None,
+            processed_loaded: tokio::sync::OnceCell::new(),
+            document: None,
+            members: Arc::default(),
+            relays: Vec::new(),
+            relays_ready: tokio::sync::OnceCell::new(),
+            conflict_hooks: Arc::new(Mutex::new(Vec::new())),
+            change_hooks: Arc::new(Mutex::new(Vec::new())),
+            write_guard: Arc::default(),
+            version: Arc::default(),
+            documents: Arc::new(Mutex::new(HashMap::new())),
+            decrypt_cache: DecryptCache::default(),
+            cold: None,
+            #[cfg(feature = "testkit")]
+            chaos: None,
         }
     }
 
+    // Read-only manager that mirrors `publisher`'s document. It never
+    // publishes; update methods fail with `Error::ReadOnly`.
+    pub fn follow(
+        client: Arc<nostr_sdk::Client>,
+        publisher: PublicKey,
+        access: FollowAccess,
+    ) -> Self {
+        // Throwaway keys, a follower never signs anything
+        let mut manager = Self::new(client, NostrSigner::Keys(Keys::generate()));
+        if let FollowAccess::SharedSecret(secret) = &access {
+            manager.read_key = Some(Keys::new(secret.clone()));
+        }
+        manager.following = Some((publisher, access));
+        manager
+    }
+
+    // Encrypt operations to a keypair of the document's own instead of to
+    // the signer. Its secret can then be shared with followers, who read
+    // the document but cannot sign anything. Operations published before
+    // still decrypt with the signer.
+    pub fn with_read_key(mut self, read_key: SecretKey) -> Self {
+        self.read_key = Some(Keys::new(read_key));
+        self
+    }
+
+    // Secret to hand to followers as `FollowAccess::SharedSecret`
+    pub fn read_key(&self) -> Option<&SecretKey> {
+        self.read_key.as_ref()?.secret_key().ok()
+    }
+
+    // Publish operations in plaintext so anyone can follow the document
+    pub fn with_public_operations(mut self) -> Self {
+        self.encrypt_operations = false;
+        self
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.following.is_some()
+    }
+
+    // Throttle publishing with a token bucket. Operations over the limit wait
+    // their turn; queued register updates are coalesced into the newest one.
+    pub fn with_rate_limit(mut self, limit: RateLimit) -> Self {
+        self.limiter = Some(Arc::new(tokio::sync::Mutex::new(TokenBucket::new(limit))));
+        self
+    }
+
+    // Cache decrypted operations in `cache`, e.g. a larger one or one
+    // shared with other managers of the same keys
+    pub fn with_decrypt_cache(mut self, cache: DecryptCache) -> Self {
+        self.decrypt_cache = cache;
+        self
+    }
+
+    // Snapshot of the sync health counters
+    pub fn metrics(&self) -> CrdtMetrics {
+        self.metrics.snapshot()
+    }
+
+    // Route an operation to the state it belongs to, recording the outcome
+    pub(crate) fn apply(&self, op: &CrdtOperation) -> Result<()> {
+        self.apply_borrowed(&op.as_borrowed())
+    }
+
+    fn apply_borrowed(&self, op: &CrdtOperationRef<'_>) -> Result<()> {
+        let (crdt_type, key) = op.target();
+        // Older operations on the key go first
+        self.warm(crdt_type, key);
+        // The previous value is only needed by change hooks
+        let before = self
+            .has_change_hooks()
+            .then(|| self.value_of(crdt_type, key));
+        let result = match op {
+            CrdtOperationRef::LWWRegister { .. } => {
+                self.lww_registers.lock().unwrap().apply_borrowed(op)
+            }
+            CrdtOperationRef::GCounter { .. } => self.g_counters.lock().unwrap().apply_borrowed(op),
+            CrdtOperationRef::GSet { .. } => self.g_sets.lock().unwrap().apply_borrowed(op),
+        };
+        match result {
+            Ok(()) => {
+                self.version.fetch_add(1, Ordering::Relaxed);
+                self.metrics.record_applied();
+                if let Some(before) = before {
+                    self.report_change(key, crdt_type, before);
+                }
+            }
+            Err(_) => self.metrics.record_rejected(),
+        }
+        result
+    }
+
     // Process incoming Nostr events containing CRDT operations
     pub async fn process_event(&self, event: &Event) -> Result<()> {
-        if event.kind != self.crdt_kind {
+        if !self.accepts_event(event) {
             return Ok(());
         }
 
-        let content = if event.content.contains("?iv=") {
-            // Content that needs decryption
-            match self
-                .signer
-                .nip04_decrypt(event.pubkey, &event.content)
-                .await
-            {
-                Ok(decrypted) => decrypted,
-                Err(_) => return Err(Error::SerializationError),
+        self.ensure_processed_loaded().await?;
+        if self.processed.lock().unwrap().contains(&event.id) {
+            return Ok(());
+        }
+
+        let decoder = self.decoder();
+        let content = decoder.content(event).await?;
+        self.apply_decoded(event, &decoder.parse(&content)?).await
+    }
+
+    // Apply the operation carried by `event` and remember the event
+    async fn apply_decoded(&self, event: &Event, op: &CrdtOperationRef<'_>) -> Result<()> {
+        let (crdt_type, key) = op.target();
+        self.warm(crdt_type, key);
+        self.check_incoming(event, op)?;
+        let conflict = self.detect_conflict(op, event);
+        self.apply_borrowed(op)?;
+        self.mark_processed(event.id).await;
+        if let Some(conflict) = conflict {
+            self.report_conflict(&conflict);
+        }
+        self.metrics.record_sync();
+        Ok(())
+    }
+
+    // Refuse an incoming operation whose register timestamp runs too far
+    // ahead of its event, or that the document's write guard turns down
+    pub(super) fn check_incoming(&self, event: &Event, op: &CrdtOperationRef<'_>) -> Result<()> {
+        if let CrdtOperationRef::LWWRegister { timestamp, .. } = op {
+            if *timestamp > event.created_at.as_u64().saturating_add(MAX_CLOCK_SKEW) {
+                self.metrics.record_rejected();
+                return Err(Error::InvalidOperation);
             }
-        } else {
-            event.content.clone()
+        }
+        if !self.admits(event, op) {
+            self.metrics.record_rejected();
+            return Err(Error::NotAuthor);
+        }
+        Ok(())
+    }
+
+    // Whether an event is a CRDT operation of this document from an author
+    // we follow
+    fn accepts_event(&self, event: &Event) -> bool {
+        if event.kind != self.crdt_kind {
+            return false;
+        }
+
+        if let Some((publisher, _)) = &self.following {
+            if event.pubkey != *publisher {
+                return false;
+            }
+        }
+
+        self.owns_event(event)
+    }
+
+    // Decrypt if needed and parse the operation carried by an event
+    async fn decode_event(&self, event: &Event) -> Result<CrdtOperation> {
+        self.decoder().decode(event).await
+    }
+
+    async fn decrypt_content(&self, event: &Event) -> Result<String> {
+        self.decoder().decrypt(event).await
+    }
+
+    // Copy the whole document, including register timestamps and the ids
+    // of every event already applied
+    pub fn export_state(&self) -> StateBackup {
+        self.warm_all();
+        let registers = self.lww_registers.lock().unwrap();
+        let counters = self.g_counters.lock().unwrap();
+        let sets = self.g_sets.lock().unwrap();
+        let mut processed_events: Vec<EventId> =
+            self.processed.lock().unwrap().iter().copied().collect();
+        processed_events.sort();
+
+        StateBackup {
+            version: StateBackup::VERSION,
+            registers: clone_sorted(&registers.registers),
+            counters: clone_sorted(&counters.counters),
+            sets: clone_sorted(&sets.sets),
+            processed_events,
+        }
+    }
+
+    // Replace the local document with a backup. Counters hold totals rather
+    // than per-replica counts, so the backup replaces state instead of
+    // merging into it.
+    pub fn import_state(&self, backup: StateBackup) -> Result<()> {
+        if backup.version != StateBackup::VERSION {
+            return Err(Error::UnsupportedBackupVersion(backup.version));
+        }
+
+        self.lww_registers.lock().unwrap().registers = intern_all(&self.keys, backup.registers);
+        self.g_counters.lock().unwrap().counters = intern_all(&self.keys, backup.counters);
+        self.g_sets.lock().unwrap().sets = intern_all(&self.keys, backup.sets);
+        *self.processed.lock().unwrap() = backup.processed_events.into_iter().collect();
+        // The backup says which events its state includes, skip the store
+        let _ = self.processed_loaded.set(());
+        self.version.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    // Subscribe to the document's events and apply them as they arrive.
+    // Runs until the client shuts down.
+    pub async fn sync(&self) -> Result<()> {
+        let events = self.live_events(vec![self.get_filter()]).await?;
+        #[cfg(feature = "testkit")]
+        let events = match &self.chaos {
+            Some(chaos) => chaos::inject(chaos.clone(), events).left_stream(),
+            None => events.right_stream(),
+        };
+        futures::pin_mut!(events);
+
+        while let Some(event) = events.next().await {
+            if let Err(err) = self.process_event(&event).await {
+                tracing::warn!("Failed to apply CRDT event {}: {}", event.id, err);
+            }
+        }
+        Ok(())
+    }
+
+    // Wait for a publish token. `coalesce` names a register key and the
+    // sequence of this update; a newer queued update for the key wins.
+    async fn throttle(&self, coalesce: Option<(&str, u64)>) -> Result<()> {
+        let Some(limiter) = &self.limiter else {
+            return Ok(());
         };
 
-        let op: CrdtOperation =
-            serde_json::from_str(&content).map_err(|_| Error::SerializationError)?;
+        // The async mutex is fair, so waiters are served in FIFO order
+        let mut bucket = limiter.lock().await;
+        let wait = bucket.wait_time(std::time::Instant::now());
+        if !wait.is_zero() {
+            crate::nostr::runtime::sleep(wait).await;
+        }
 
-        match &op {
-            CrdtOperation::LWWRegister { .. } => {
-                self.lww_registers.lock().unwrap().apply_operation(op)
+        if let Some((key, seq)) = coalesce {
+            let mut queue = self.lww_queue.lock().unwrap();
+            if queue.get(key).is_some_and(|latest| *latest != seq) {
+                return Err(Error::Coalesced);
             }
-            CrdtOperation::GCounter { .. } => self.g_counters.lock().unwrap().apply_operation(op),
-            CrdtOperation::GSet { .. } => self.g_sets.lock().unwrap().apply_operation(op),
+            queue.remove(key);
         }
+
+        // Account for the time spent sleeping before spending the token
+        bucket.wait_time(std::time::Instant::now());
+        bucket.take();
+        Ok(())
     }
 
     // Publish CRDT operation with encryption
     async fn publish_encrypted_crdt_operation(
         &self,
         op: &CrdtOperation,
-        tags: Vec<Tag>,
+        coalesce: Option<(&str, u64)>,
     ) -> Result<EventId> {
+        let event = self.sign_operation(op).await?;
+
+        // Send event with retry logic, once the rate limiter lets it through
+        self.metrics.pending_added();
+        let result = match self.throttle(coalesce).await {
+            Ok(()) => self.send_with_retry(event).await,
+            Err(err) => Err(err),
+        };
+        self.metrics.pending_removed();
+        result
+    }
+
+    // Sign the event carrying an operation already applied locally, and
+    // mark it processed so it is never applied a second time
+    pub(crate) async fn sign_operation(&self, op: &CrdtOperation) -> Result<Event> {
         // Serialize operation
         let content = serde_json::to_string(&op).map_err(|_| Error::SerializationError)?;
 
-        // Get own public key and encrypt content
-        let my_pubkey = self.signer.public_key().await?;
-        let encrypted_content = self.signer.nip04_encrypt(my_pubkey, &content).await?;
+        let encrypted_content = if self.encrypt_operations {
+            self.encrypt_content(&content).await?
+        } else {
+            content
+        };
 
         // Create event - add CRDT specific tags
-        let mut all_tags = tags;
+        let mut all_tags = op.tags();
         // Add hashtag for CRDT operation identification
         all_tags.push(Tag::hashtag("nostr-crdt"));
+        if let Some(document) = &self.document {
+            all_tags.push(Tag::identifier(document));
+        }
 
-        let event =
-            EventBuilder::new(self.crdt_kind, &encrypted_content, all_tags).to_event(&self.keys)?;
+        let event = self
+            .signer
+            .sign_event_builder(EventBuilder::new(
+                self.crdt_kind,
+                &encrypted_content,
+                all_tags,
+            ))
+            .await?;
 
-        // Send event with retry logic
-        let mut retry_count = 0;
-        let max_retries = 3;
-        let mut last_error = None;
+        // Already applied locally, never apply our own event a second time
+        self.ensure_processed_loaded().await?;
+        self.mark_processed(event.id).await;
+        Ok(event)
+    }
 
-        while retry_count < max_retries {
-            match self.client.send_event(event.clone()).await {
-                Ok(_) => {
-                    return Ok(event.id);
-                }
-                Err(err) => {
-                    last_error = Some(err);
-                    retry_count += 1;
-                    if retry_count < max_retries {
-                        // Wait before retrying
-                        tokio::time::sleep(std::time::Duration::from_secs(1)).await;
-                    }
-                }
+    async fn send_with_retry(&self, event: Event) -> Result<EventId> {
+        let id = event.id;
+        let max_attempts = 3;
+
+        // Earlier attempts send a copy, keeping the event for a retry
+        for _ in 1..max_attempts {
+            if self.send_event(event.clone()).await.is_ok() {
+                return Ok(id);
             }
+            self.metrics.record_publish_retry();
+            // Wait before retrying
+            crate::nostr::runtime::sleep(std::time::Duration::from_secs(1)).await;
         }
 
-        // All retries failed
-        Err(Error::Client(last_error.unwrap()))
+        // The last attempt hands the event over; its error is reported
+        self.send_event(event).await.map_err(Error::Client)?;
+        Ok(id)
+    }
+
+    // Timestamp for a local write to register `key`. Timestamps have a
+    // resolution of seconds; a second write within the same second must
+    // still win over the first.
+    fn next_timestamp(&self, key: &str) -> u64 {
+        let now = Timestamp::now().as_u64();
+        match self.register_timestamp(key) {
+            Some(current) if current >= now => current.saturating_add(1),
+            _ => now,
+        }
     }
 
     // Create and publish a LWW-Register update
     pub async fn update_lww_register(&self, key: &str, value: &str) -> Result<EventId> {
-        let now = Timestamp::now().as_u64();
+        if self.is_read_only() {
+            return Err(Error::ReadOnly);
+        }
+
         let op = CrdtOperation::LWWRegister {
             key: key.to_string(),
             value: value.to_string(),
-            timestamp: now,
+            timestamp: self.next_timestamp(key),
         };
 
         // Apply operation locally first
-        self.lww_registers
-            .lock()
-            .unwrap()
-            .apply_operation(op.clone())?;
+        self.apply(&op)?;
+
+        // Remember the newest queued update so older ones can be coalesced
+        let seq = self.publish_seq.fetch_add(1, Ordering::Relaxed);
+        if self.limiter.is_some() {
+            self.lww_queue
+                .lock()
+                .unwrap()
+                .insert(self.keys.intern(key), seq);
+        }
 
         // Then publish to network
-        let tags = vec![Tag::custom(TagKind::from("c"), ["crdt", "lww"])];
-        self.publish_encrypted_crdt_operation(&op, tags).await
+        self.publish_encrypted_crdt_operation(&op, Some((key, seq)))
+            .await
     }
 
     // Create and publish a G-Counter increment
     pub async fn increment_counter(&self, key: &str, increment: u64) -> Result<EventId> {
+        if self.is_read_only() {
+            return Err(Error::ReadOnly);
+        }
+
         let op = CrdtOperation::GCounter {
             key: key.to_string(),
             increment,
         };
 
         // Apply operation locally first
-        self.g_counters
-            .lock()
-            .unwrap()
-            .apply_operation(op.clone())?;
+        self.apply(&op)?;
 
         // Then publish to network
-        let tags = vec![Tag::custom(TagKind::from("c"), ["crdt", "gcounter"])];
-        self.publish_encrypted_crdt_operation(&op, tags).await
+        self.publish_encrypted_crdt_operation(&op, None).await
     }
 
     // Create and publish a G-Set add operation
     pub async fn add_to_set(&self, key: &str, value: &str) -> Result<EventId> {
+        if self.is_read_only() {
+            return Err(Error::ReadOnly);
+        }
+
         let op = CrdtOperation::GSet {
             key: key.to_string(),
             value: value.to_string(),
@@ -295,54 +1053,163 @@ impl CrdtManager {
         };
 
         // Apply operation locally first
-        self.g_sets.lock().unwrap().apply_operation(op.clone())?;
+        self.apply(&op)?;
 
         // Then publish to network
-        let tags = vec![Tag::custom(TagKind::from("c"), ["crdt", "gset"])];
-        self.publish_encrypted_crdt_operation(&op, tags).await
+        self.publish_encrypted_crdt_operation(&op, None).await
     }
 
     // Get value from LWW-Register
     pub fn get_register_value(&self, key: &str) -> Option<String> {
+        self.warm(CrdtType::LWWRegister, key);
         self.lww_registers.lock().unwrap().get_value(key)
     }
 
+    // Timestamp of the write a register holds
+    pub(crate) fn register_timestamp(&self, key: &str) -> Option<u64> {
+        self.warm(CrdtType::LWWRegister, key);
+        self.lww_registers
+            .lock()
+            .unwrap()
+            .registers
+            .get(key)
+            .map(|(_, timestamp)| *timestamp)
+    }
+
     // Get value from G-Counter
     pub fn get_counter_value(&self, key: &str) -> Option<String> {
+        self.warm(CrdtType::GCounter, key);
         self.g_counters.lock().unwrap().get_value(key)
     }
 
     // Get value from G-Set
     pub fn get_set_value(&self, key: &str) -> Option<String> {
+        self.warm(CrdtType::GSet, key);
         self.g_sets.lock().unwrap().get_value(key)
     }
 
+    // List keys held by the LWW-Register
+    pub fn register_keys(&self) -> Vec<String> {
+        self.warm_all();
+        self.lww_registers.lock().unwrap().keys()
+    }
+
+    // List keys held by the G-Counter
+    pub fn counter_keys(&self) -> Vec<String> {
+        self.warm_all();
+        self.g_counters.lock().unwrap().keys()
+    }
+
+    // List keys held by the G-Set
+    pub fn set_keys(&self) -> Vec<String> {
+        self.warm_all();
+        self.g_sets.lock().unwrap().keys()
+    }
+
+    // Count of changes to the state so far, for views that cache what they
+    // read from it. Deferred operations count once fetched.
+    pub(super) fn version(&self) -> u64 {
+        self.version.load(Ordering::Relaxed)
+    }
+
+    // Snapshot the whole document as (key, type, value) entries,
+    // registers first, then counters, then sets
+    pub fn iter_state(&self) -> impl Iterator<Item = (String, CrdtType, String)> {
+        self.warm_all();
+        let mut entries = Vec::new();
+        collect_entries(
+            &*self.lww_registers.lock().unwrap(),
+            CrdtType::LWWRegister,
+            &mut entries,
+        );
+        collect_entries(
+            &*self.g_counters.lock().unwrap(),
+            CrdtType::GCounter,
+            &mut entries,
+        );
+        collect_entries(&*self.g_sets.lock().unwrap(), CrdtType::GSet, &mut entries);
+        entries.into_iter()
+    }
+
+    // Entries of every CRDT type whose key starts with `prefix`,
+    // e.g. `scan_prefix("settings/")` for hierarchical key names
+    pub fn scan_prefix(&self, prefix: &str) -> Vec<(String, CrdtType, String)> {
+        self.iter_state()
+            .filter(|(key, _, _)| key.starts_with(prefix))
+            .collect()
+    }
+
     // Create a filter to subscribe to CRDT events
     pub fn get_filter(&self) -> nostr_sdk::Filter {
         // Update filter to include application-specific tags
-        nostr_sdk::Filter::new()
+        let mut filter = nostr_sdk::Filter::new()
             .kind(self.crdt_kind)
-            .hashtag("nostr-crdt") // Use hashtag as alternative
+            .hashtag("nostr-crdt"); // Use hashtag as alternative
+        if let Some(document) = &self.document {
+            filter = filter.identifier(document);
+        }
+        let members = self.members();
+        match &self.following {
+            Some((publisher, _)) => filter.author(*publisher),
+            None if !members.is_empty() => filter.authors(members),
+            None => filter,
+        }
+    }
+}
+
+// Order events by (created_at, event id). Relays return backfills in
+// arbitrary order; this total order makes replay identical everywhere.
+pub fn sort_for_replay(events: &mut [Event]) {
+    events.sort_by_key(|event| (event.created_at, event.id));
+}
+
+// Random id for a record of a document, e.g. a chat message
+pub(crate) fn new_id() -> String {
+    format!("{:032x}", nostr_sdk::secp256k1::rand::random::<u128>())
+}
+
+fn collect_entries<S: CrdtState>(
+    state: &S,
+    crdt_type: CrdtType,
+    entries: &mut Vec<(String, CrdtType, String)>,
+) {
+    for key in state.keys() {
+        if let Some(value) = state.get_value(&key) {
+            entries.push((key, crdt_type, value));
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use nostr_sdk::nips::nip04;
+
+    fn test_manager() -> CrdtManager {
+        let keys = Keys::generate();
+        let client = Arc::new(nostr_sdk::Client::new(&keys));
+        CrdtManager::new(client, NostrSigner::Keys(keys))
+    }
+
+    fn plain_event(keys: &Keys, content: &str) -> Event {
+        EventBuilder::new(Kind::TextNote, content, [Tag::hashtag("nostr-crdt")])
+            .to_event(keys)
+            .unwrap()
+    }
 
     #[test]
     fn test_lww_register() {
         let mut lww = LWWRegister::default();
 
         // Apply operations in timestamp order
-        lww.apply_operation(CrdtOperation::LWWRegister {
+        lww.apply_operation(&CrdtOperation::LWWRegister {
             key: "test".to_string(),
             value: "value1".to_string(),
             timestamp: 100,
         })
         .unwrap();
 
-        lww.apply_operation(CrdtOperation::LWWRegister {
+        lww.apply_operation(&CrdtOperation::LWWRegister {
             key: "test".to_string(),
             value: "value2".to_string(),
             timestamp: 200,
@@ -350,7 +1217,7 @@ mod tests {
         .unwrap();
 
         // This should be ignored (older timestamp)
-        lww.apply_operation(CrdtOperation::LWWRegister {
+        lww.apply_operation(&CrdtOperation::LWWRegister {
             key: "test".to_string(),
             value: "value3".to_string(),
             timestamp: 150,
@@ -360,26 +1227,46 @@ mod tests {
         assert_eq!(lww.get_value("test"), Some("value2".to_string()));
     }
 
+    #[test]
+    fn test_lww_register_ties() {
+        let write = |value: &str| CrdtOperation::LWWRegister {
+            key: "test".to_string(),
+            value: value.to_string(),
+            timestamp: 100,
+        };
+
+        // Same timestamp, either arrival order ends on the greater value
+        let mut first = LWWRegister::default();
+        first.apply_operation(&write("apple")).unwrap();
+        first.apply_operation(&write("banana")).unwrap();
+        let mut second = LWWRegister::default();
+        second.apply_operation(&write("banana")).unwrap();
+        second.apply_operation(&write("apple")).unwrap();
+
+        assert_eq!(first.get_value("test"), Some("banana".to_string()));
+        assert_eq!(second.get_value("test"), Some("banana".to_string()));
+    }
+
     #[test]
     fn test_g_counter() {
         let mut counter = GCounter::default();
 
         counter
-            .apply_operation(CrdtOperation::GCounter {
+            .apply_operation(&CrdtOperation::GCounter {
                 key: "visitors".to_string(),
                 increment: 1,
             })
             .unwrap();
 
         counter
-            .apply_operation(CrdtOperation::GCounter {
+            .apply_operation(&CrdtOperation::GCounter {
                 key: "visitors".to_string(),
                 increment: 1,
             })
             .unwrap();
 
         counter
-            .apply_operation(CrdtOperation::GCounter {
+            .apply_operation(&CrdtOperation::GCounter {
                 key: "downloads".to_string(),
                 increment: 5,
             })
@@ -393,14 +1280,14 @@ mod tests {
     fn test_g_set() {
         let mut set = GSet::default();
 
-        set.apply_operation(CrdtOperation::GSet {
+        set.apply_operation(&CrdtOperation::GSet {
             key: "users".to_string(),
             value: "alice".to_string(),
             action: GSetAction::Add,
         })
         .unwrap();
 
-        set.apply_operation(CrdtOperation::GSet {
+        set.apply_operation(&CrdtOperation::GSet {
             key: "users".to_string(),
             value: "bob".to_string(),
             action: GSetAction::Add,
@@ -408,7 +1295,7 @@ mod tests {
         .unwrap();
 
         // Duplicate add (should be idempotent)
-        set.apply_operation(CrdtOperation::GSet {
+        set.apply_operation(&CrdtOperation::GSet {
             key: "users".to_string(),
             value: "alice".to_string(),
             action: GSetAction::Add,
@@ -422,4 +1309,423 @@ mod tests {
         assert!(parsed.contains(&"alice".to_string()));
         assert!(parsed.contains(&"bob".to_string()));
     }
+
+    #[test]
+    fn test_iter_state() {
+        let manager = test_manager();
+
+        for (key, value) in [("b", "2"), ("a", "1")] {
+            manager
+                .lww_registers
+                .lock()
+                .unwrap()
+                .apply_operation(&CrdtOperation::LWWRegister {
+                    key: key.to_string(),
+                    value: value.to_string(),
+                    timestamp: 1,
+                })
+                .unwrap();
+        }
+        manager
+            .g_counters
+            .lock()
+            .unwrap()
+            .apply_operation(&CrdtOperation::GCounter {
+                key: "visitors".to_string(),
+                increment: 3,
+            })
+            .unwrap();
+
+        assert_eq!(manager.register_keys(), vec!["a", "b"]);
+        assert_eq!(manager.counter_keys(), vec!["visitors"]);
+        assert!(manager.set_keys().is_empty());
+
+        let state: Vec<_> = manager.iter_state().collect();
+        assert_eq!(
+            state,
+            vec![
+                ("a".to_string(), CrdtType::LWWRegister, "1".to_string()),
+                ("b".to_string(), CrdtType::LWWRegister, "2".to_string()),
+                ("visitors".to_string(), CrdtType::GCounter, "3".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_scan_prefix() {
+        let manager = test_manager();
+
+        manager
+            .lww_registers
+            .lock()
+            .unwrap()
+            .apply_operation(&CrdtOperation::LWWRegister {
+                key: "settings/theme".to_string(),
+                value: "dark".to_string(),
+                timestamp: 1,
+            })
+            .unwrap();
+        manager
+            .g_sets
+            .lock()
+            .unwrap()
+            .apply_operation(&CrdtOperation::GSet {
+                key: "settings/langs".to_string(),
+                value: "en".to_string(),
+                action: GSetAction::Add,
+            })
+            .unwrap();
+        manager
+            .g_counters
+            .lock()
+            .unwrap()
+            .apply_operation(&CrdtOperation::GCounter {
+                key: "stats/visits".to_string(),
+                increment: 1,
+            })
+            .unwrap();
+
+        let settings = manager.scan_prefix("settings/");
+        assert_eq!(settings.len(), 2);
+        assert!(settings
+            .iter()
+            .all(|(key, _, _)| key.starts_with("settings/")));
+        assert_eq!(settings[1].1, CrdtType::GSet);
+        assert!(manager.scan_prefix("missing/").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_metrics() {
+        let manager = test_manager();
+        let keys = Keys::generate();
+        let op = CrdtOperation::GCounter {
+            key: "visitors".to_string(),
+            increment: 2,
+        };
+
+        manager
+            .process_event(&plain_event(&keys, &serde_json::to_string(&op).unwrap()))
+            .await
+            .unwrap();
+        assert!(manager
+            .process_event(&plain_event(&keys, "not an operation"))
+            .await
+            .is_err());
+
+        let metrics = manager.metrics();
+        assert_eq!(metrics.ops_applied, 1);
+        assert_eq!(metrics.ops_rejected, 1);
+        assert_eq!(metrics.decryption_failures, 0);
+        assert_eq!(metrics.pending_queue_depth, 0);
+        assert!(metrics.last_sync_timestamp.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_follow() {
+        let publisher = Keys::generate();
+        let client = Arc::new(nostr_sdk::Client::new(&publisher));
+        let document = CrdtManager::new(client, NostrSigner::Keys(publisher.clone()))
+            .with_read_key(Keys::generate().secret_key().unwrap().clone());
+        // Followers get the read key, never the publisher's own
+        let read_key = document.read_key().unwrap().clone();
+        assert_ne!(&read_key, publisher.secret_key().unwrap());
+        let client = Arc::new(nostr_sdk::Client::new(Keys::generate()));
+        let follower = CrdtManager::follow(
+            client,
+            publisher.public_key(),
+            FollowAccess::SharedSecret(read_key),
+        );
+
+        let op = CrdtOperation::LWWRegister {
+            key: "status".to_string(),
+            value: "live".to_string(),
+            timestamp: 1,
+        };
+        let encrypted = document
+            .encrypt_content(&serde_json::to_string(&op).unwrap())
+            .await
+            .unwrap();
+        follower
+            .process_event(&plain_event(&publisher, &encrypted))
+            .await
+            .unwrap();
+        assert_eq!(
+            follower.get_register_value("status"),
+            Some("live".to_string())
+        );
+
+        // Events from anyone else are ignored
+        let stranger = CrdtOperation::GCounter {
+            key: "visitors".to_string(),
+            increment: 1,
+        };
+        follower
+            .process_event(&plain_event(
+                &Keys::generate(),
+                &serde_json::to_string(&stranger).unwrap(),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(follower.get_counter_value("visitors"), None);
+
+        assert!(matches!(
+            follower.increment_counter("visitors", 1).await,
+            Err(Error::ReadOnly)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_decrypt_cache() {
+        let keys = Keys::generate();
+        let cache = DecryptCache::new(1);
+        let manager = CrdtManager::new(
+            Arc::new(nostr_sdk::Client::new(&keys)),
+            NostrSigner::Keys(keys.clone()),
+        )
+        .with_decrypt_cache(cache.clone());
+        let encrypted = |increment: u64| {
+            let op = CrdtOperation::GCounter {
+                key: "views".to_string(),
+                increment,
+            };
+            let content = nip04::encrypt(
+                keys.secret_key().unwrap(),
+                &keys.public_key(),
+                serde_json::to_string(&op).unwrap(),
+            )
+            .unwrap();
+            plain_event(&keys, &content)
+        };
+
+        let first = encrypted(1);
+        manager.process_event(&first).await.unwrap();
+        assert!(cache.get(&first.id).unwrap().contains("views"));
+
+        // A cached content is used as is, without decrypting the event
+        let second = encrypted(2);
+        let cached = CrdtOperation::GCounter {
+            key: "views".to_string(),
+            increment: 10,
+        };
+        cache.insert(second.id, serde_json::to_string(&cached).unwrap());
+        manager.process_event(&second).await.unwrap();
+        assert_eq!(manager.get_counter_value("views"), Some("11".to_string()));
+        // Bounded, the oldest entry made room
+        assert_eq!(cache.len(), 1);
+        assert!(cache.get(&first.id).is_none());
+
+        // Documents share the cache
+        let document = manager.open_document("notes");
+        assert_eq!(document.decrypt_cache.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_register_timestamps_stay_bounded() {
+        let manager = test_manager();
+        let keys = Keys::generate();
+        let created_at = Timestamp::from(1_000_000);
+        let write = |value: &str, timestamp: u64| {
+            let op = CrdtOperation::LWWRegister {
+                key: "title".to_string(),
+                value: value.to_string(),
+                timestamp,
+            };
+            EventBuilder::new(
+                Kind::TextNote,
+                serde_json::to_string(&op).unwrap(),
+                [Tag::hashtag("nostr-crdt")],
+            )
+            .custom_created_at(created_at)
+            .to_event(&keys)
+            .unwrap()
+        };
+
+        // Judged against the event's own `created_at`, not our clock:
+        // slightly ahead is fine, far ahead is refused
+        let soon = created_at.as_u64() + 60;
+        manager.process_event(&write("soon", soon)).await.unwrap();
+        assert!(matches!(
+            manager.process_event(&write("forever", u64::MAX)).await,
+            Err(Error::InvalidOperation)
+        ));
+        assert_eq!(
+            manager.get_register_value("title"),
+            Some("soon".to_string())
+        );
+        assert_eq!(manager.metrics().ops_rejected, 1);
+
+        // A register already at the end of time is not bumped past it
+        let mut backup = manager.export_state();
+        backup
+            .registers
+            .insert("title".to_string(), ("forever".to_string(), u64::MAX));
+        manager.import_state(backup).unwrap();
+        assert_eq!(manager.next_timestamp("title"), u64::MAX);
+    }
+
+    #[tokio::test]
+    async fn test_export_import_state() {
+        let manager = test_manager();
+        let keys = Keys::generate();
+        let op = CrdtOperation::GCounter {
+            key: "visitors".to_string(),
+            increment: 4,
+        };
+        let event = plain_event(&keys, &serde_json::to_string(&op).unwrap());
+        manager.process_event(&event).await.unwrap();
+        manager
+            .lww_registers
+            .lock()
+            .unwrap()
+            .apply_operation(&CrdtOperation::LWWRegister {
+                key: "username".to_string(),
+                value: "capybara".to_string(),
+                timestamp: 42,
+            })
+            .unwrap();
+
+        let backup = manager.export_state();
+        let json = serde_json::to_string(&backup).unwrap();
+        let restored = test_manager();
+        restored
+            .import_state(serde_json::from_str(&json).unwrap())
+            .unwrap();
+
+        assert_eq!(restored.export_state(), backup);
+        assert_eq!(
+            restored.get_counter_value("visitors"),
+            Some("4".to_string())
+        );
+        assert_eq!(
+            restored.get_register_value("username"),
+            Some("capybara".to_string())
+        );
+
+        // The event is already part of the imported state
+        restored.process_event(&event).await.unwrap();
+        assert_eq!(
+            restored.get_counter_value("visitors"),
+            Some("4".to_string())
+        );
+    }
+
+    #[test]
+    fn test_borrowed_operation() {
+        let json = r#"{"GSet":{"key":"tags","value":"nostr","action":"Add"}}"#;
+        let op: CrdtOperationRef = serde_json::from_str(json).unwrap();
+        let CrdtOperationRef::GSet { key, value, .. } = &op else {
+            panic!("parsed {op:?}");
+        };
+        assert!(matches!(key, Cow::Borrowed("tags")));
+        assert!(matches!(value, Cow::Borrowed("nostr")));
+        assert_eq!(serde_json::to_string(&op).unwrap(), json);
+
+        // Escaped strings cannot be borrowed and are unescaped into copies
+        let json = r#"{"LWWRegister":{"key":"title","value":"say \"hi\"","timestamp":3}}"#;
+        let op: CrdtOperationRef = serde_json::from_str(json).unwrap();
+        let owned = op.clone().into_owned();
+        let CrdtOperation::LWWRegister { value, .. } = &owned else {
+            panic!("parsed {owned:?}");
+        };
+        assert_eq!(value, r#"say "hi""#);
+        assert_eq!(serde_json::to_string(&op).unwrap(), json);
+        assert_eq!(serde_json::to_string(&owned.as_borrowed()).unwrap(), json);
+
+        let mut sets = GSet::default();
+        sets.apply_borrowed(
+            &serde_json::from_str(r#"{"GSet":{"key":"tags","value":"nostr","action":"Add"}}"#)
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(sets.get_value("tags"), Some(r#"["nostr"]"#.to_string()));
+    }
+
+    #[test]
+    fn test_key_interning() {
+        let manager = test_manager();
+        for op in [
+            CrdtOperation::LWWRegister {
+                key: "theme".to_string(),
+                value: "dark".to_string(),
+                timestamp: 1,
+            },
+            CrdtOperation::GCounter {
+                key: "theme".to_string(),
+                increment: 1,
+            },
+            CrdtOperation::GSet {
+                key: "theme".to_string(),
+                value: "dark".to_string(),
+                action: GSetAction::Add,
+            },
+        ] {
+            manager.apply(&op).unwrap();
+        }
+
+        // One copy of the key, whichever CRDT types use it
+        assert_eq!(manager.keys.len(), 1);
+        let registers = manager.lww_registers.lock().unwrap();
+        let counters = manager.g_counters.lock().unwrap();
+        let (register_key, _) = registers.registers.get_key_value("theme").unwrap();
+        let (counter_key, _) = counters.counters.get_key_value("theme").unwrap();
+        assert!(Arc::ptr_eq(register_key, counter_key));
+        drop((registers, counters));
+
+        let restored = test_manager();
+        restored.import_state(manager.export_state()).unwrap();
+        assert_eq!(restored.keys.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_replay_order() {
+        let keys = Keys::generate();
+        let op = |value: &str, timestamp: u64| CrdtOperation::LWWRegister {
+            key: "title".to_string(),
+            value: value.to_string(),
+            timestamp,
+        };
+        // Replica recording every value the register takes
+        let device = || {
+            let manager = test_manager();
+            let seen = Arc::new(Mutex::new(Vec::new()));
+            let record = Arc::clone(&seen);
+            manager.on_change(move |change| record.lock().unwrap().push(change.value.clone()));
+            (manager, seen)
+        };
+
+        // Events arriving out of order are applied by `created_at`
+        let dated = |value: &str, created_at: u64| {
+            let content = serde_json::to_string(&op(value, created_at)).unwrap();
+            EventBuilder::new(Kind::TextNote, content, [Tag::hashtag("nostr-crdt")])
+                .custom_created_at(Timestamp::from(created_at))
+                .to_event(&keys)
+                .unwrap()
+        };
+        let events = vec![dated("final", 30), dated("draft", 10), dated("review", 20)];
+        let mut reversed = events.clone();
+        reversed.reverse();
+        let (device1, seen1) = device();
+        let (device2, seen2) = device();
+        assert_eq!(device1.process_events(events).await, 0);
+        assert_eq!(device2.process_events(reversed).await, 0);
+        assert_eq!(*seen1.lock().unwrap(), ["draft", "review", "final"]);
+        assert_eq!(*seen2.lock().unwrap(), *seen1.lock().unwrap());
+
+        // Same `created_at`, so the event id settles the order
+        let events: Vec<Event> = ["draft", "final"]
+            .iter()
+            .map(|value| plain_event(&keys, &serde_json::to_string(&op(value, 7)).unwrap()))
+            .collect();
+        let mut reversed = events.clone();
+        reversed.reverse();
+        let (device1, seen1) = device();
+        let (device2, seen2) = device();
+        assert_eq!(device1.process_events(events).await, 0);
+        assert_eq!(device2.process_events(reversed).await, 0);
+        assert_eq!(
+            device1.get_register_value("title"),
+            device2.get_register_value("title")
+        );
+        assert_eq!(*seen2.lock().unwrap(), *seen1.lock().unwrap());
+    }
 }
@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use nostr_sdk::{Event, EventId};
+
+use super::{sort_for_replay, CrdtManager, CrdtOperation, CrdtType, Result, SyncMetrics};
+
+// A key whose live value differs from the value rebuilt from relay history
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    pub key: String,
+    pub crdt_type: CrdtType,
+    // Value in the live state, `None` if the key is missing locally
+    pub local: Option<String>,
+    // Value recomputed from the relays, `None` if no relay has it
+    pub relay: Option<String>,
+    // Relay events touching the key, in replay order
+    pub events: Vec<EventId>,
+}
+
+impl CrdtOperation {
+    pub(super) fn target(&self) -> (CrdtType, &str) {
+        match self {
+            CrdtOperation::LWWRegister { key, .. } => (CrdtType::LWWRegister, key),
+            CrdtOperation::GCounter { key, .. } => (CrdtType::GCounter, key),
+            CrdtOperation::GSet { key, .. } => (CrdtType::GSet, key),
+        }
+    }
+}
+
+impl CrdtManager {
+    // Refetch the document's whole operation history, rebuild it in a
+    // scratch manager and report every key whose value differs from the
+    // live state. Local updates that never reached a relay show up here.
+    pub async fn audit(&self, timeout: Option<Duration>) -> Result<Vec<Divergence>> {
+        let events = self.fetch_events(vec![self.get_filter()], timeout).await?;
+        Ok(self.audit_events(events).await)
+    }
+
+    async fn audit_events(&self, mut events: Vec<Event>) -> Vec<Divergence> {
+        // Fresh state, counters and hooks, so the audit leaves no trace on
+        // the live manager's metrics or processed-event store and the app's
+        // hooks never see the replayed history
+        let mut scratch = self.sibling(self.document.clone());
+        scratch.metrics = Arc::new(SyncMetrics::default());
+        scratch.processed_store = None;
+        scratch.change_hooks = Arc::default();
+        scratch.conflict_hooks = Arc::default();
+        scratch.write_guard = Arc::clone(&self.write_guard);
+
+        sort_for_replay(&mut events);
+        let mut touched: HashMap<(CrdtType, String), Vec<EventId>> = HashMap::new();
+        for event in events.iter() {
+            if !scratch.accepts_event(event) || !scratch.processed.lock().unwrap().insert(event.id)
+            {
+                continue;
+            }
+            let Ok(op) = scratch.decode_event(event).await else {
+                continue;
+            };
+            // Writes the live document refuses are not in the relay state
+            if scratch.check_incoming(event, &op.as_borrowed()).is_err() {
+                continue;
+            }
+            let (crdt_type, key) = op.target();
+            let target = (crdt_type, key.to_string());
+            if scratch.apply(&op).is_ok() {
+                touched.entry(target).or_default().push(event.id);
+            }
+        }
+
+        let mut relay: HashMap<(CrdtType, String), String> = scratch
+            .iter_state()
+            .map(|(key, crdt_type, value)| ((crdt_type, key), value))
+            .collect();
+        let mut divergences = Vec::new();
+        for (key, crdt_type, local) in self.iter_state() {
+            let target = (crdt_type, key);
+            let remote = relay.remove(&target);
+            if remote.as_ref() != Some(&local) {
+                divergences.push(Divergence {
+                    events: touched.remove(&target).unwrap_or_default(),
+                    key: target.1,
+                    crdt_type,
+                    local: Some(local),
+                    relay: remote,
+                });
+            }
+        }
+        // Keys the relays know about but the live state lacks
+        let mut missing: Vec<_> = relay.into_iter().collect();
+        missing.sort();
+        for ((crdt_type, key), remote) in missing {
+            divergences.push(Divergence {
+                events: touched
+                    .remove(&(crdt_type, key.clone()))
+                    .unwrap_or_default(),
+                key,
+                crdt_type,
+                local: None,
+                relay: Some(remote),
+            });
+        }
+        divergences
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr_sdk::{EventBuilder, Keys, Kind, NostrSigner, Tag};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn test_audit_reports_divergence() {
+        let keys = Keys::generate();
+        let client = Arc::new(nostr_sdk::Client::new(keys.clone()));
+        let manager = CrdtManager::new(client, NostrSigner::Keys(keys.clone()));
+        let event = |op: &CrdtOperation| {
+            EventBuilder::new(
+                Kind::TextNote,
+                serde_json::to_string(op).unwrap(),
+                [Tag::hashtag("nostr-crdt")],
+            )
+            .to_event(&keys)
+            .unwrap()
+        };
+
+        let published = event(&CrdtOperation::GCounter {
+            key: "visitors".to_string(),
+            increment: 2,
+        });
+        let missed = event(&CrdtOperation::LWWRegister {
+            key: "title".to_string(),
+            value: "draft".to_string(),
+            timestamp: 1,
+        });
+        manager.process_event(&published).await.unwrap();
+        // Applied locally but lost on the way to the relays
+        manager
+            .apply(&CrdtOperation::GCounter {
+                key: "visitors".to_string(),
+                increment: 1,
+            })
+            .unwrap();
+
+        let divergences = manager
+            .audit_events(vec![missed.clone(), published.clone()])
+            .await;
+        assert_eq!(
+            divergences,
+            vec![
+                Divergence {
+                    key: "visitors".to_string(),
+                    crdt_type: CrdtType::GCounter,
+                    local: Some("3".to_string()),
+                    relay: Some("2".to_string()),
+                    events: vec![published.id],
+                },
+                Divergence {
+                    key: "title".to_string(),
+                    crdt_type: CrdtType::LWWRegister,
+                    local: None,
+                    relay: Some("draft".to_string()),
+                    events: vec![missed.id],
+                },
+            ]
+        );
+        assert_eq!(manager.metrics().ops_applied, 2);
+    }
+
+    #[tokio::test]
+    async fn test_audit_leaves_hooks_alone() {
+        let keys = Keys::generate();
+        let client = Arc::new(nostr_sdk::Client::new(keys.clone()));
+        let manager = CrdtManager::new(client, NostrSigner::Keys(keys.clone()));
+        let fired = Arc::new(AtomicUsize::new(0));
+        let changes = Arc::clone(&fired);
+        manager.on_change(move |_| {
+            changes.fetch_add(1, Ordering::SeqCst);
+        });
+        let conflicts = Arc::clone(&fired);
+        manager.on_conflict(move |_| {
+            conflicts.fetch_add(1, Ordering::SeqCst);
+        });
+
+        let event = EventBuilder::new(
+            Kind::TextNote,
+            serde_json::to_string(&CrdtOperation::LWWRegister {
+                key: "title".to_string(),
+                value: "draft".to_string(),
+                timestamp: 1,
+            })
+            .unwrap(),
+            [Tag::hashtag("nostr-crdt")],
+        )
+        .to_event(&keys)
+        .unwrap();
+        let divergences = manager.audit_events(vec![event]).await;
+        assert_eq!(divergences.len(), 1);
+        assert_eq!(fired.load(Ordering::SeqCst), 0);
+    }
+}
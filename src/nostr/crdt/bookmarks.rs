@@ -0,0 +1,250 @@
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use nostr_sdk::{Event, EventBuilder, EventId, Kind, Tag};
+
+use super::or_set::OrSet;
+use super::{CrdtManager, Error, Result};
+
+// Elements of the OR-Set are bookmark tags as JSON: `bookmark/["e","<id>"]`
+const BOOKMARK_PREFIX: &str = "bookmark/";
+// Tags of the list last published or imported, to tell what another client
+// added or removed since
+const PUBLISHED: &str = "published";
+// Encrypted private entries of the last imported list, published again as is
+const PRIVATE: &str = "private";
+
+// An entry of the NIP-51 bookmark list
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Bookmark {
+    Note(EventId),
+    // `<kind>:<pubkey>:<d tag>` of an article
+    Article(String),
+    Hashtag(String),
+    Url(String),
+}
+
+impl Bookmark {
+    fn to_tag(&self) -> Vec<String> {
+        let (kind, value) = match self {
+            Self::Note(id) => ("e", id.to_hex()),
+            Self::Article(address) => ("a", address.clone()),
+            Self::Hashtag(hashtag) => ("t", hashtag.clone()),
+            Self::Url(url) => ("r", url.clone()),
+        };
+        vec![kind.to_string(), value]
+    }
+
+    fn from_tag(tag: &[String]) -> Option<Self> {
+        let bookmark = match tag {
+            [kind, value, ..] if kind == "e" => Self::Note(EventId::from_hex(value).ok()?),
+            [kind, value, ..] if kind == "a" => Self::Article(value.clone()),
+            [kind, value, ..] if kind == "t" => Self::Hashtag(value.clone()),
+            [kind, value, ..] if kind == "r" => Self::Url(value.clone()),
+            _ => return None,
+        };
+        Some(bookmark)
+    }
+
+    fn element(&self) -> String {
+        serde_json::to_string(&self.to_tag()).unwrap_or_default()
+    }
+}
+
+// The user's bookmarks kept in the document `bookmarks`, as an OR-Set so
+// devices merge their edits, and mirrored into the NIP-51 bookmark list
+// (kind 10003) other clients read. `publish` writes the list, best after a
+// `sync()` so it has every device's edits; `import_nip51` brings in what
+// another client changed in the list since. Entries other than notes,
+// articles, hashtags and URLs are not kept.
+pub struct BookmarksDoc {
+    document: Arc<CrdtManager>,
+}
+
+impl BookmarksDoc {
+    pub fn open(manager: &CrdtManager) -> Self {
+        Self {
+            document: manager.open_document("bookmarks"),
+        }
+    }
+
+    // The bookmarks' document; `replay_history` it on a new device before
+    // the first `publish`, or the kind 10003 list loses the other devices'
+    // bookmarks
+    pub fn document(&self) -> &Arc<CrdtManager> {
+        &self.document
+    }
+
+    pub async fn add(&self, bookmark: &Bookmark) -> Result<EventId> {
+        self.set().add(&bookmark.element()).await
+    }
+
+    // Remove a bookmark, `None` if it was not there
+    pub async fn remove(&self, bookmark: &Bookmark) -> Result<Option<EventId>> {
+        self.set().remove(&bookmark.element()).await
+    }
+
+    pub fn contains(&self, bookmark: &Bookmark) -> bool {
+        self.set().contains(&bookmark.element())
+    }
+
+    pub fn bookmarks(&self) -> Vec<Bookmark> {
+        let mut bookmarks: Vec<Bookmark> = self
+            .set()
+            .elements()
+            .iter()
+            .filter_map(|element| parse_tag(element))
+            .filter_map(|tag| Bookmark::from_tag(&tag))
+            .collect();
+        bookmarks.sort();
+        bookmarks
+    }
+
+    // The bookmarks as a NIP-51 bookmark list
+    pub fn to_nip51(&self) -> EventBuilder {
+        let tags = self
+            .bookmarks()
+            .iter()
+            .filter_map(|bookmark| Tag::parse(&bookmark.to_tag()).ok())
+            .collect::<Vec<_>>();
+        let private = self
+            .document
+            .get_register_value(PRIVATE)
+            .unwrap_or_default();
+        EventBuilder::new(Kind::Bookmarks, private, tags)
+    }
+
+    // Publish the bookmark list, replacing the previous one
+    pub async fn publish(&self) -> Result<EventId> {
+        let builder = self.to_nip51();
+        let event = self.document.signer.sign_event_builder(builder).await?;
+        self.remember_published(&event).await?;
+        self.document.send_event(event).await.map_err(Error::Client)
+    }
+
+    // Apply the changes another client made to our bookmark list since it
+    // was last published or imported here
+    pub async fn import_nip51(&self, event: &Event) -> Result<()> {
+        if event.kind != Kind::Bookmarks {
+            return Err(Error::InvalidOperation);
+        }
+        if event.pubkey != self.document.signer.public_key().await? {
+            return Err(Error::NotAuthor);
+        }
+        let before: BTreeSet<Bookmark> = self
+            .document
+            .get_register_value(PUBLISHED)
+            .and_then(|json| serde_json::from_str::<Vec<Vec<String>>>(&json).ok())
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|tag| Bookmark::from_tag(tag))
+            .collect();
+        let after = list_bookmarks(event);
+
+        for bookmark in after.difference(&before) {
+            if !self.contains(bookmark) {
+                self.add(bookmark).await?;
+            }
+        }
+        for bookmark in before.difference(&after) {
+            self.remove(bookmark).await?;
+        }
+        if self
+            .document
+            .get_register_value(PRIVATE)
+            .unwrap_or_default()
+            != event.content
+        {
+            self.document
+                .update_lww_register(PRIVATE, &event.content)
+                .await?;
+        }
+        self.remember_published(event).await
+    }
+
+    async fn remember_published(&self, event: &Event) -> Result<()> {
+        let tags: Vec<Vec<String>> = list_bookmarks(event).iter().map(Bookmark::to_tag).collect();
+        let json = serde_json::to_string(&tags).map_err(|_| Error::SerializationError)?;
+        self.document.update_lww_register(PUBLISHED, &json).await?;
+        Ok(())
+    }
+
+    fn set(&self) -> OrSet<'_> {
+        OrSet::new(&self.document, BOOKMARK_PREFIX)
+    }
+}
+
+fn parse_tag(element: &str) -> Option<Vec<String>> {
+    serde_json::from_str(element).ok()
+}
+
+fn list_bookmarks(event: &Event) -> BTreeSet<Bookmark> {
+    event
+        .tags
+        .iter()
+        .filter_map(|tag| Bookmark::from_tag(tag.as_vec()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testhelper::{connect, MockRelay};
+    use nostr_sdk::{Filter, Keys};
+
+    #[tokio::test]
+    async fn test_bookmarks_doc() {
+        let relay = MockRelay::run().await.unwrap();
+        let keys = Keys::generate();
+        let manager = connect(&relay, &keys).await;
+        let bookmarks = BookmarksDoc::open(&manager);
+
+        let note = Bookmark::Note(EventId::all_zeros());
+        let hashtag = Bookmark::Hashtag("nostr".into());
+        let url = Bookmark::Url("https://example.com/a/b".into());
+        for bookmark in [&note, &hashtag, &url] {
+            bookmarks.add(bookmark).await.unwrap();
+        }
+        bookmarks.remove(&url).await.unwrap();
+        assert_eq!(bookmarks.remove(&url).await.unwrap(), None);
+        assert_eq!(bookmarks.bookmarks(), [note.clone(), hashtag.clone()]);
+
+        bookmarks.publish().await.unwrap();
+        let filter = Filter::new().kind(Kind::Bookmarks);
+        let published = manager.client.get_events_of(vec![filter], None).await;
+        let published = published.unwrap().pop().unwrap();
+        assert_eq!(
+            list_bookmarks(&published),
+            bookmarks.bookmarks().into_iter().collect()
+        );
+
+        // Another client drops the hashtag and adds an article; its private
+        // entries stay, its emoji tag is not kept
+        let article = Bookmark::Article(format!("30023:{}:post", keys.public_key()));
+        let tags = [
+            note.to_tag(),
+            article.to_tag(),
+            vec!["emoji".into(), "x".into()],
+        ];
+        let edited = EventBuilder::new(
+            Kind::Bookmarks,
+            "encrypted",
+            tags.iter().map(|tag| Tag::parse(tag).unwrap()),
+        )
+        .to_event(&keys)
+        .unwrap();
+        // Meanwhile a device adds a URL back
+        bookmarks.add(&url).await.unwrap();
+        bookmarks.import_nip51(&edited).await.unwrap();
+        assert_eq!(bookmarks.bookmarks(), [note, article, url]);
+
+        let republished = bookmarks.to_nip51().to_event(&keys).unwrap();
+        assert_eq!(republished.content, "encrypted");
+
+        let stranger = connect(&relay, &Keys::generate()).await;
+        assert!(matches!(
+            BookmarksDoc::open(&stranger).import_nip51(&edited).await,
+            Err(Error::NotAuthor)
+        ));
+    }
+}
@@ -0,0 +1,361 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use thiserror::Error;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio_tungstenite::tungstenite::Message;
+
+use super::{Change, CrdtManager};
+
+// Changes buffered per connection before a slow client starts missing some
+const CHANGE_BUFFER: usize = 256;
+
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const CRDT_ERROR: i64 = -32000;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    WebSocket(#[from] tokio_tungstenite::tungstenite::Error),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, Deserialize)]
+struct Request {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl ToString) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+        }
+    }
+}
+
+impl From<super::Error> for RpcError {
+    fn from(err: super::Error) -> Self {
+        Self::new(CRDT_ERROR, err)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct KeyParams {
+    key: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct WriteParams {
+    key: String,
+    value: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct IncrementParams {
+    key: String,
+    #[serde(default = "one")]
+    amount: u64,
+}
+
+fn one() -> u64 {
+    1
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct SubscribeParams {
+    // Every key when omitted
+    key: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct UnsubscribeParams {
+    subscription: u64,
+}
+
+fn params<T: for<'de> Deserialize<'de>>(params: Value) -> std::result::Result<T, RpcError> {
+    serde_json::from_value(params).map_err(|err| RpcError::new(INVALID_PARAMS, err))
+}
+
+// Change subscriptions of one connection: id -> key filter
+#[derive(Debug, Default)]
+struct Session {
+    subscriptions: HashMap<u64, Option<String>>,
+    next_id: u64,
+}
+
+/// Serves a `CrdtManager` over a local WebSocket speaking JSON-RPC 2.0, so
+/// Electron apps and scripts can use the document without FFI.
+///
+/// Methods: `get {key}`, `set {key, value}`, `increment {key, amount?}`,
+/// `add {key, value}`, `export`, `subscribe {key?}` and
+/// `unsubscribe {subscription}`. Subscribers receive `change`
+/// notifications with `{subscription, change}` params.
+///
+/// There is no authentication: listen on a loopback address only.
+pub struct Bridge {
+    manager: Arc<CrdtManager>,
+    changes: broadcast::Sender<Change>,
+}
+
+impl Bridge {
+    pub fn new(manager: Arc<CrdtManager>) -> Arc<Self> {
+        let (changes, _) = broadcast::channel(CHANGE_BUFFER);
+        let sender = changes.clone();
+        manager.on_change(move |change| {
+            // No receivers just means nobody is subscribed
+            let _ = sender.send(change.clone());
+        });
+        Arc::new(Self { manager, changes })
+    }
+
+    /// Accepts connections until the listener fails
+    pub async fn serve(self: Arc<Self>, listener: TcpListener) -> Result<()> {
+        loop {
+            let (stream, peer) = listener.accept().await?;
+            let bridge = Arc::clone(&self);
+            tokio::spawn(async move {
+                if let Err(err) = bridge.handle_connection(stream).await {
+                    tracing::warn!("Bridge connection {} failed: {}", peer, err);
+                }
+            });
+        }
+    }
+
+    async fn handle_connection(&self, stream: TcpStream) -> Result<()> {
+        let mut socket = tokio_tungstenite::accept_async(stream).await?;
+        let mut changes = self.changes.subscribe();
+        let mut session = Session::default();
+
+        loop {
+            tokio::select! {
+                message = socket.next() => {
+                    let text = match message {
+                        Some(Ok(Message::Text(text))) => text,
+                        Some(Ok(Message::Close(_))) | None => return Ok(()),
+                        Some(Ok(_)) => continue,
+                        Some(Err(err)) => return Err(err.into()),
+                    };
+                    let response = self.handle_message(&text, &mut session).await;
+                    socket.send(Message::Text(response.to_string())).await?;
+                }
+                change = changes.recv() => {
+                    let change = match change {
+                        Ok(change) => change,
+                        Err(broadcast::error::RecvError::Lagged(missed)) => {
+                            tracing::warn!("Bridge client missed {} changes", missed);
+                            continue;
+                        }
+                        Err(broadcast::error::RecvError::Closed) => return Ok(()),
+                    };
+                    for notification in session.notifications(&change) {
+                        socket.send(Message::Text(notification.to_string())).await?;
+                    }
+                }
+            }
+        }
+    }
+
+    // Answer one JSON-RPC request
+    async fn handle_message(&self, text: &str, session: &mut Session) -> Value {
+        let request: Request = match serde_json::from_str(text) {
+            Ok(request) => request,
+            Err(err) => return response(Value::Null, Err(RpcError::new(PARSE_ERROR, err))),
+        };
+        let result = self.call(&request.method, request.params, session).await;
+        response(request.id, result)
+    }
+
+    async fn call(
+        &self,
+        method: &str,
+        params_value: Value,
+        session: &mut Session,
+    ) -> std::result::Result<Value, RpcError> {
+        match method {
+            "get" => {
+                let KeyParams { key } = params(params_value)?;
+                let value = self
+                    .manager
+                    .iter_state()
+                    .find(|(entry, _, _)| *entry == key)
+                    .map(|(_, crdt_type, value)| json!({ "type": crdt_type, "value": value }));
+                Ok(value.unwrap_or(Value::Null))
+            }
+            "set" => {
+                let WriteParams { key, value } = params(params_value)?;
+                let id = self.manager.update_lww_register(&key, &value).await?;
+                Ok(json!(id.to_hex()))
+            }
+            "increment" => {
+                let IncrementParams { key, amount } = params(params_value)?;
+                let id = self.manager.increment_counter(&key, amount).await?;
+                Ok(json!(id.to_hex()))
+            }
+            "add" => {
+                let WriteParams { key, value } = params(params_value)?;
+                let id = self.manager.add_to_set(&key, &value).await?;
+                Ok(json!(id.to_hex()))
+            }
+            "export" => Ok(json!(self.manager.export_state())),
+            "subscribe" => {
+                let SubscribeParams { key } = if params_value.is_null() {
+                    SubscribeParams::default()
+                } else {
+                    params(params_value)?
+                };
+                Ok(json!(session.subscribe(key)))
+            }
+            "unsubscribe" => {
+                let UnsubscribeParams { subscription } = params(params_value)?;
+                Ok(json!(session.subscriptions.remove(&subscription).is_some()))
+            }
+            _ => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("Unknown method {method}"),
+            )),
+        }
+    }
+}
+
+impl Session {
+    fn subscribe(&mut self, key: Option<String>) -> u64 {
+        self.next_id += 1;
+        self.subscriptions.insert(self.next_id, key);
+        self.next_id
+    }
+
+    // One `change` notification per subscription interested in the change
+    fn notifications(&self, change: &Change) -> Vec<Value> {
+        let mut ids: Vec<u64> = self
+            .subscriptions
+            .iter()
+            .filter(|(_, key)| key.as_ref().is_none_or(|key| *key == change.key))
+            .map(|(id, _)| *id)
+            .collect();
+        ids.sort();
+        ids.into_iter()
+            .map(|subscription| {
+                json!({
+                    "jsonrpc": "2.0",
+                    "method": "change",
+                    "params": { "subscription": subscription, "change": change },
+                })
+            })
+            .collect()
+    }
+}
+
+fn response(id: Value, result: std::result::Result<Value, RpcError>) -> Value {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(error) => json!({ "jsonrpc": "2.0", "id": id, "error": error }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nostr::crdt::CrdtOperation;
+    use nostr_sdk::{Keys, NostrSigner};
+
+    fn manager() -> Arc<CrdtManager> {
+        let keys = Keys::generate();
+        let client = Arc::new(nostr_sdk::Client::new(keys.clone()));
+        Arc::new(CrdtManager::new(client, NostrSigner::Keys(keys)))
+    }
+
+    #[tokio::test]
+    async fn test_bridge_calls() {
+        let manager = manager();
+        let bridge = Bridge::new(Arc::clone(&manager));
+        let mut session = Session::default();
+        manager
+            .apply(&CrdtOperation::GCounter {
+                key: "views".to_string(),
+                increment: 2,
+            })
+            .unwrap();
+
+        let reply = bridge
+            .handle_message(
+                r#"{"jsonrpc":"2.0","id":1,"method":"get","params":{"key":"views"}}"#,
+                &mut session,
+            )
+            .await;
+        assert_eq!(reply["id"], 1);
+        assert_eq!(reply["result"], json!({ "type": "GCounter", "value": "2" }));
+
+        let reply = bridge
+            .handle_message(
+                r#"{"jsonrpc":"2.0","id":2,"method":"get","params":{}}"#,
+                &mut session,
+            )
+            .await;
+        assert_eq!(reply["error"]["code"], INVALID_PARAMS);
+        let reply = bridge
+            .handle_message(r#"{"jsonrpc":"2.0","id":3,"method":"drop"}"#, &mut session)
+            .await;
+        assert_eq!(reply["error"]["code"], METHOD_NOT_FOUND);
+        let reply = bridge.handle_message("not json", &mut session).await;
+        assert_eq!(reply["error"]["code"], PARSE_ERROR);
+    }
+
+    #[tokio::test]
+    async fn test_bridge_subscriptions() {
+        let manager = manager();
+        let bridge = Bridge::new(Arc::clone(&manager));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(Arc::clone(&bridge).serve(listener));
+
+        let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://{addr}"))
+            .await
+            .unwrap();
+        let subscribe = r#"{"jsonrpc":"2.0","id":1,"method":"subscribe","params":{"key":"title"}}"#;
+        socket
+            .send(Message::Text(subscribe.to_string()))
+            .await
+            .unwrap();
+        let reply: Value =
+            serde_json::from_str(&socket.next().await.unwrap().unwrap().into_text().unwrap())
+                .unwrap();
+        let subscription = reply["result"].as_u64().unwrap();
+
+        for key in ["other", "title"] {
+            manager
+                .apply(&CrdtOperation::LWWRegister {
+                    key: key.to_string(),
+                    value: "hello".to_string(),
+                    timestamp: 1,
+                })
+                .unwrap();
+        }
+        let notification: Value =
+            serde_json::from_str(&socket.next().await.unwrap().unwrap().into_text().unwrap())
+                .unwrap();
+        assert_eq!(notification["method"], "change");
+        assert_eq!(notification["params"]["subscription"], subscription);
+        assert_eq!(notification["params"]["change"]["key"], "title");
+        assert_eq!(notification["params"]["change"]["value"], "hello");
+    }
+}
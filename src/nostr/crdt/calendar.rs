@@ -0,0 +1,642 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use nostr_sdk::nips::nip01::Coordinate;
+use nostr_sdk::{
+    Event, EventBuilder, EventId, Kind, PublicKey, Tag, TagKind, TagStandard, Timestamp,
+};
+use serde::{Deserialize, Serialize};
+
+use super::{new_id, CrdtManager, Error, Result};
+
+// Registers per event: `event/<id>` for who created it and whether it is
+// cancelled, `event/<id>/field/<name>` and `event/<id>/rsvp/<pubkey>`
+const EVENT_PREFIX: &str = "event/";
+const FIELD_PREFIX: &str = "field/";
+const RSVP_PREFIX: &str = "rsvp/";
+// Fields with a place in NIP-52 events; any other field stays in the doc
+const TITLE_FIELD: &str = "title";
+const START_FIELD: &str = "start";
+const END_FIELD: &str = "end";
+const LOCATION_FIELD: &str = "location";
+const DESCRIPTION_FIELD: &str = "description";
+
+// NIP-52 kinds
+const DATE_KIND: u16 = 31922;
+const TIME_KIND: u16 = 31923;
+const RSVP_KIND: u16 = 31925;
+
+// Who created an event and whether they cancelled it. Cancelling is a flag,
+// so the event and its fields stay in the document.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StoredEvent {
+    author: PublicKey,
+    cancelled: bool,
+}
+
+// When an event starts or ends
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CalendarTime {
+    // All day, `YYYY-MM-DD`
+    Date(String),
+    Time(Timestamp),
+}
+
+impl CalendarTime {
+    fn encode(&self) -> String {
+        match self {
+            Self::Date(date) => date.clone(),
+            Self::Time(time) => time.as_u64().to_string(),
+        }
+    }
+
+    fn decode(value: &str) -> Option<Self> {
+        match value.parse::<u64>() {
+            Ok(seconds) => Some(Self::Time(Timestamp::from(seconds))),
+            Err(_) => date_seconds(value).map(|_| Self::Date(value.to_string())),
+        }
+    }
+
+    // Seconds since the epoch, dates at the start of their day in UTC
+    fn seconds(&self) -> i64 {
+        match self {
+            Self::Date(date) => date_seconds(date).unwrap_or_default(),
+            Self::Time(time) => time.as_u64() as i64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RsvpStatus {
+    Accepted,
+    Declined,
+    Tentative,
+}
+
+impl RsvpStatus {
+    // The status as NIP-52 spells it
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Accepted => "accepted",
+            Self::Declined => "declined",
+            Self::Tentative => "tentative",
+        }
+    }
+
+    fn parse(status: &str) -> Option<Self> {
+        match status {
+            "accepted" => Some(Self::Accepted),
+            "declined" => Some(Self::Declined),
+            "tentative" => Some(Self::Tentative),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CalendarEvent {
+    pub id: String,
+    pub author: PublicKey,
+    pub start: CalendarTime,
+    pub end: Option<CalendarTime>,
+    // Title, location, description and any other field, by name
+    pub fields: BTreeMap<String, String>,
+    // Answer of each attendee
+    pub rsvps: BTreeMap<PublicKey, RsvpStatus>,
+    pub cancelled: bool,
+}
+
+impl CalendarEvent {
+    pub fn title(&self) -> Option<&str> {
+        self.fields.get(TITLE_FIELD).map(String::as_str)
+    }
+}
+
+// Shared calendar kept in a named document. Every event field and every
+// attendee's RSVP is its own register, so people editing the location and
+// answering at the same time all keep their changes. Events convert to and
+// from NIP-52 calendar events and RSVPs, so calendar clients see them and
+// their answers can be brought in. RSVPs are keyed by attendee but not
+// signed by them: any member of the document can write them.
+pub struct CalendarDoc {
+    document: Arc<CrdtManager>,
+}
+
+impl CalendarDoc {
+    // Open the calendar `name` as the document `calendar/<name>` of `manager`
+    pub fn open(manager: &CrdtManager, name: &str) -> Self {
+        Self {
+            document: manager.open_document(&format!("calendar/{name}")),
+        }
+    }
+
+    // The calendar's document, to `sync()` for RSVPs and edits by others
+    pub fn document(&self) -> &Arc<CrdtManager> {
+        &self.document
+    }
+
+    // Add an event, returning its id
+    pub async fn add_event(
+        &self,
+        title: &str,
+        start: CalendarTime,
+        end: Option<CalendarTime>,
+    ) -> Result<String> {
+        let author = self.document.signer.public_key().await?;
+        let id = new_id();
+        self.write_times(&id, &start, end.as_ref()).await?;
+        self.write_field(&id, TITLE_FIELD, title).await?;
+        self.write_event(
+            &id,
+            &StoredEvent {
+                author,
+                cancelled: false,
+            },
+        )
+        .await?;
+        Ok(id)
+    }
+
+    // Set a field other than the times, e.g. `location` or `description`
+    pub async fn set_field(&self, id: &str, field: &str, value: &str) -> Result<EventId> {
+        if matches!(field, START_FIELD | END_FIELD) {
+            return Err(Error::InvalidOperation);
+        }
+        self.stored(id)?;
+        self.write_field(id, field, value).await
+    }
+
+    pub async fn reschedule(
+        &self,
+        id: &str,
+        start: CalendarTime,
+        end: Option<CalendarTime>,
+    ) -> Result<EventId> {
+        self.stored(id)?;
+        self.write_times(id, &start, end.as_ref()).await
+    }
+
+    // Cancel one of our own events
+    pub async fn cancel_event(&self, id: &str) -> Result<EventId> {
+        let mut event = self.own(id).await?;
+        event.cancelled = true;
+        self.write_event(id, &event).await
+    }
+
+    // Answer an invitation as ourselves
+    pub async fn rsvp(&self, id: &str, status: RsvpStatus) -> Result<EventId> {
+        self.stored(id)?;
+        let attendee = self.document.signer.public_key().await?;
+        self.write_rsvp(id, &attendee, status).await
+    }
+
+    pub fn event(&self, id: &str) -> Option<CalendarEvent> {
+        self.events_where(|event_id| event_id == id).pop()
+    }
+
+    // Events not cancelled, by start time
+    pub fn events(&self) -> Vec<CalendarEvent> {
+        let mut events = self.events_where(|_| true);
+        events.retain(|event| !event.cancelled);
+        events.sort_by(|a, b| (a.start.seconds(), &a.id).cmp(&(b.start.seconds(), &b.id)));
+        events
+    }
+
+    // One of our events as a NIP-52 calendar event, date or time based
+    // after its start, with its attendees as `p` tags
+    pub fn to_nip52(&self, id: &str) -> Option<EventBuilder> {
+        let event = self.event(id)?;
+        let kind = nip52_kind(&event.start);
+        let mut tags = vec![
+            Tag::identifier(&event.id),
+            Tag::from_standardized(TagStandard::Title(
+                event.title().unwrap_or_default().to_string(),
+            )),
+            Tag::custom(TagKind::Custom(START_FIELD.into()), [event.start.encode()]),
+        ];
+        // Both ends of a NIP-52 event are dates, or both times
+        if let Some(end) = event.end.filter(|end| nip52_kind(end) == kind) {
+            tags.push(Tag::custom(
+                TagKind::Custom(END_FIELD.into()),
+                [end.encode()],
+            ));
+        }
+        if let Some(location) = event.fields.get(LOCATION_FIELD) {
+            tags.push(Tag::custom(
+                TagKind::Custom(LOCATION_FIELD.into()),
+                [location.clone()],
+            ));
+        }
+        tags.extend(
+            event
+                .rsvps
+                .keys()
+                .map(|attendee| Tag::public_key(*attendee)),
+        );
+        let description = event
+            .fields
+            .get(DESCRIPTION_FIELD)
+            .cloned()
+            .unwrap_or_default();
+        Some(EventBuilder::new(kind, description, tags))
+    }
+
+    // Publish one of our events as a NIP-52 calendar event. Publish again
+    // after changes; the newer one replaces it.
+    pub async fn publish(&self, id: &str) -> Result<EventId> {
+        self.own(id).await?;
+        let builder = self
+            .to_nip52(id)
+            .ok_or_else(|| Error::NotFound(format!("calendar event {id}")))?;
+        let event = self.document.signer.sign_event_builder(builder).await?;
+        self.document.send_event(event).await.map_err(Error::Client)
+    }
+
+    // Publish our answer to an event as a NIP-52 RSVP
+    pub async fn publish_rsvp(&self, id: &str) -> Result<EventId> {
+        let event = self
+            .event(id)
+            .ok_or_else(|| Error::NotFound(format!("calendar event {id}")))?;
+        let attendee = self.document.signer.public_key().await?;
+        let status = event
+            .rsvps
+            .get(&attendee)
+            .ok_or_else(|| Error::NotFound(format!("RSVP to {id}")))?;
+        let coordinate =
+            Coordinate::new(nip52_kind(&event.start), event.author).identifier(&event.id);
+        let tags = [
+            Tag::coordinate(coordinate),
+            Tag::identifier(&event.id),
+            Tag::custom(TagKind::Custom("status".into()), [status.as_str()]),
+            Tag::public_key(event.author),
+        ];
+        let builder = EventBuilder::new(Kind::from(RSVP_KIND), "", tags);
+        let rsvp = self.document.signer.sign_event_builder(builder).await?;
+        self.document.send_event(rsvp).await.map_err(Error::Client)
+    }
+
+    // Bring in a NIP-52 calendar event, keeping its `d` tag as id, or a
+    // NIP-52 RSVP to an event already here. An event only updates one here
+    // by the same author, and an RSVP must point at the event's kind and
+    // author as well as its id. Returns the event id.
+    pub async fn import_nip52(&self, event: &Event) -> Result<String> {
+        match event.kind.as_u16() {
+            DATE_KIND | TIME_KIND => self.import_event(event).await,
+            RSVP_KIND => self.import_rsvp(event).await,
+            _ => Err(Error::InvalidOperation),
+        }
+    }
+
+    async fn import_event(&self, event: &Event) -> Result<String> {
+        // Ids are part of register keys
+        let id = event
+            .identifier()
+            .filter(|id| !id.is_empty() && !id.contains('/'))
+            .ok_or(Error::InvalidOperation)?
+            .to_string();
+        // Someone else's event with the same `d` tag is a different event
+        match self.stored(&id) {
+            Ok(stored) if stored.author != event.pubkey => return Err(Error::NotAuthor),
+            _ => {}
+        }
+        let time = |name: &str| tag_value(event, name).and_then(CalendarTime::decode);
+        let start = time(START_FIELD).ok_or(Error::InvalidOperation)?;
+        self.write_times(&id, &start, time(END_FIELD).as_ref())
+            .await?;
+        for field in [TITLE_FIELD, LOCATION_FIELD] {
+            if let Some(value) = tag_value(event, field) {
+                self.write_field(&id, field, value).await?;
+            }
+        }
+        if !event.content.is_empty() {
+            self.write_field(&id, DESCRIPTION_FIELD, &event.content)
+                .await?;
+        }
+        self.write_event(
+            &id,
+            &StoredEvent {
+                author: event.pubkey,
+                cancelled: false,
+            },
+        )
+        .await?;
+        Ok(id)
+    }
+
+    async fn import_rsvp(&self, event: &Event) -> Result<String> {
+        let coordinate = tag_value(event, "a")
+            .and_then(|coordinate| Coordinate::parse(coordinate).ok())
+            .ok_or(Error::InvalidOperation)?;
+        let status = tag_value(event, "status")
+            .and_then(RsvpStatus::parse)
+            .ok_or(Error::InvalidOperation)?;
+        let id = coordinate.identifier;
+        let answered = self
+            .event(&id)
+            .ok_or_else(|| Error::NotFound(format!("calendar event {id}")))?;
+        if coordinate.kind != nip52_kind(&answered.start)
+            || coordinate.public_key != answered.author
+        {
+            return Err(Error::InvalidOperation);
+        }
+        self.write_rsvp(&id, &event.pubkey, status).await?;
+        Ok(id)
+    }
+
+    fn stored(&self, id: &str) -> Result<StoredEvent> {
+        self.document
+            .get_register_value(&format!("{EVENT_PREFIX}{id}"))
+            .and_then(|value| serde_json::from_str(&value).ok())
+            .ok_or_else(|| Error::NotFound(format!("calendar event {id}")))
+    }
+
+    // An event we created
+    async fn own(&self, id: &str) -> Result<StoredEvent> {
+        let event = self.stored(id)?;
+        if event.author != self.document.signer.public_key().await? {
+            return Err(Error::NotAuthor);
+        }
+        Ok(event)
+    }
+
+    // Events whose id passes `wanted`, including cancelled ones. Events
+    // whose start has not synced yet are left out.
+    fn events_where(&self, wanted: impl Fn(&str) -> bool) -> Vec<CalendarEvent> {
+        let mut stored = HashMap::new();
+        let mut fields: HashMap<String, BTreeMap<String, String>> = HashMap::new();
+        let mut rsvps: HashMap<String, BTreeMap<PublicKey, RsvpStatus>> = HashMap::new();
+        for key in self.document.register_keys() {
+            let Some(rest) = key.strip_prefix(EVENT_PREFIX) else {
+                continue;
+            };
+            let (id, rest) = rest.split_once('/').unwrap_or((rest, ""));
+            if !wanted(id) {
+                continue;
+            }
+            let Some(value) = self.document.get_register_value(&key) else {
+                continue;
+            };
+            if rest.is_empty() {
+                if let Ok(event) = serde_json::from_str::<StoredEvent>(&value) {
+                    stored.insert(id.to_string(), event);
+                }
+            } else if let Some(field) = rest.strip_prefix(FIELD_PREFIX) {
+                fields
+                    .entry(id.to_string())
+                    .or_default()
+                    .insert(field.to_string(), value);
+            } else if let Some(attendee) = rest.strip_prefix(RSVP_PREFIX) {
+                let answer = PublicKey::from_hex(attendee)
+                    .ok()
+                    .zip(RsvpStatus::parse(&value));
+                if let Some((attendee, status)) = answer {
+                    rsvps
+                        .entry(id.to_string())
+                        .or_default()
+                        .insert(attendee, status);
+                }
+            }
+        }
+
+        stored
+            .into_iter()
+            .filter_map(|(id, event)| {
+                let mut fields = fields.remove(&id).unwrap_or_default();
+                let start = CalendarTime::decode(&fields.remove(START_FIELD)?)?;
+                let end = fields
+                    .remove(END_FIELD)
+                    .and_then(|end| CalendarTime::decode(&end));
+                Some(CalendarEvent {
+                    rsvps: rsvps.remove(&id).unwrap_or_default(),
+                    id,
+                    author: event.author,
+                    start,
+                    end,
+                    fields,
+                    cancelled: event.cancelled,
+                })
+            })
+            .collect()
+    }
+
+    async fn write_event(&self, id: &str, event: &StoredEvent) -> Result<EventId> {
+        let value = serde_json::to_string(event).map_err(|_| Error::SerializationError)?;
+        self.document
+            .update_lww_register(&format!("{EVENT_PREFIX}{id}"), &value)
+            .await
+    }
+
+    async fn write_field(&self, id: &str, field: &str, value: &str) -> Result<EventId> {
+        self.document
+            .update_lww_register(&format!("{EVENT_PREFIX}{id}/{FIELD_PREFIX}{field}"), value)
+            .await
+    }
+
+    // An event without an end has its `end` register cleared to ""
+    async fn write_times(
+        &self,
+        id: &str,
+        start: &CalendarTime,
+        end: Option<&CalendarTime>,
+    ) -> Result<EventId> {
+        let end = end.map(CalendarTime::encode).unwrap_or_default();
+        self.write_field(id, END_FIELD, &end).await?;
+        self.write_field(id, START_FIELD, &start.encode()).await
+    }
+
+    async fn write_rsvp(
+        &self,
+        id: &str,
+        attendee: &PublicKey,
+        status: RsvpStatus,
+    ) -> Result<EventId> {
+        self.document
+            .update_lww_register(
+                &format!("{EVENT_PREFIX}{id}/{RSVP_PREFIX}{}", attendee.to_hex()),
+                status.as_str(),
+            )
+            .await
+    }
+}
+
+fn nip52_kind(time: &CalendarTime) -> Kind {
+    match time {
+        CalendarTime::Date(_) => Kind::from(DATE_KIND),
+        CalendarTime::Time(_) => Kind::from(TIME_KIND),
+    }
+}
+
+// Value of the first tag named `name`
+fn tag_value<'a>(event: &'a Event, name: &str) -> Option<&'a str> {
+    event.tags.iter().find_map(|tag| match tag.as_vec() {
+        [kind, value, ..] if kind == name => Some(value.as_str()),
+        _ => None,
+    })
+}
+
+// Seconds since the epoch at the start of a `YYYY-MM-DD` day in UTC, after
+// Howard Hinnant's days-from-civil algorithm
+fn date_seconds(date: &str) -> Option<i64> {
+    let mut parts = date.splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: i64 = parts.next()?.parse().ok()?;
+    let day: i64 = parts.next()?.parse().ok()?;
+    let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+    let days_in_month = match month {
+        2 if leap => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        1..=12 => 31,
+        _ => return None,
+    };
+    if !(1..=days_in_month).contains(&day) {
+        return None;
+    }
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    Some((era * 146_097 + day_of_era - 719_468) * 86_400)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testhelper::{connect, MockRelay};
+    use nostr_sdk::{Filter, Keys};
+
+    async fn fetch(manager: &CrdtManager, kind: u16) -> Event {
+        let filter = Filter::new().kind(Kind::from(kind));
+        let events = manager.client.get_events_of(vec![filter], None).await;
+        events.unwrap().pop().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_calendar_doc() {
+        let relay = MockRelay::run().await.unwrap();
+        let alice = Keys::generate();
+        let bob = Keys::generate();
+        let alice_manager = connect(&relay, &alice).await;
+        let bob_manager = connect(&relay, &bob).await;
+        let calendar = CalendarDoc::open(&alice_manager, "team");
+        let bob_calendar = CalendarDoc::open(&bob_manager, "meetups");
+
+        let start = Timestamp::from(1_800_000_000);
+        let meetup = calendar
+            .add_event(
+                "Meetup",
+                CalendarTime::Time(start),
+                Some(CalendarTime::Time(start + 3_600)),
+            )
+            .await
+            .unwrap();
+        let retreat = calendar
+            .add_event("Retreat", CalendarTime::Date("2026-10-16".into()), None)
+            .await
+            .unwrap();
+        calendar
+            .set_field(&meetup, LOCATION_FIELD, "Room 1")
+            .await
+            .unwrap();
+        calendar.rsvp(&meetup, RsvpStatus::Accepted).await.unwrap();
+        let order: Vec<String> = calendar.events().into_iter().map(|e| e.id).collect();
+        assert_eq!(order, [retreat.clone(), meetup.clone()]);
+
+        // Calendar clients see a time based NIP-52 event
+        calendar.publish(&meetup).await.unwrap();
+        let published = fetch(&alice_manager, TIME_KIND).await;
+        assert_eq!(published.identifier(), Some(meetup.as_str()));
+        assert_eq!(tag_value(&published, LOCATION_FIELD), Some("Room 1"));
+        let all_day = calendar.to_nip52(&retreat).unwrap().to_event(&alice);
+        assert_eq!(all_day.unwrap().kind.as_u16(), DATE_KIND);
+
+        // Bob brings it into his own calendar and answers it
+        assert_eq!(bob_calendar.import_nip52(&published).await.unwrap(), meetup);
+        bob_calendar
+            .rsvp(&meetup, RsvpStatus::Tentative)
+            .await
+            .unwrap();
+        assert!(matches!(
+            bob_calendar.publish(&meetup).await,
+            Err(Error::NotAuthor)
+        ));
+        bob_calendar.publish_rsvp(&meetup).await.unwrap();
+        let answer = fetch(&bob_manager, RSVP_KIND).await;
+        calendar.import_nip52(&answer).await.unwrap();
+
+        let event = calendar.event(&meetup).unwrap();
+        assert_eq!(event.title(), Some("Meetup"));
+        assert_eq!(event.end, Some(CalendarTime::Time(start + 3_600)));
+        assert_eq!(
+            event.rsvps,
+            BTreeMap::from([
+                (alice.public_key(), RsvpStatus::Accepted),
+                (bob.public_key(), RsvpStatus::Tentative),
+            ])
+        );
+        assert_eq!(
+            bob_calendar.event(&meetup).unwrap().author,
+            alice.public_key()
+        );
+
+        calendar.cancel_event(&retreat).await.unwrap();
+        assert_eq!(calendar.events().len(), 1);
+        assert!(calendar.event(&retreat).unwrap().cancelled);
+        assert_eq!(date_seconds("1970-01-01"), Some(0));
+        assert_eq!(date_seconds("2000-03-01"), Some(951_868_800));
+        assert_eq!(date_seconds("2024-02-29"), Some(1_709_164_800));
+        assert_eq!(date_seconds("2023-02-29"), None);
+        assert_eq!(date_seconds("2024-02-31"), None);
+        assert_eq!(date_seconds("2024-04-31"), None);
+    }
+
+    #[tokio::test]
+    async fn test_calendar_imports_check_authors() {
+        let relay = MockRelay::run().await.unwrap();
+        let alice = Keys::generate();
+        let mallory = Keys::generate();
+        let manager = connect(&relay, &alice).await;
+        let calendar = CalendarDoc::open(&manager, "team");
+        let start = CalendarTime::Time(Timestamp::from(1_800_000_000));
+        let meetup = calendar.add_event("Meetup", start, None).await.unwrap();
+
+        // The same `d` tag under another author is not our event
+        let hijack = calendar.to_nip52(&meetup).unwrap().to_event(&mallory);
+        assert!(matches!(
+            calendar.import_nip52(&hijack.unwrap()).await,
+            Err(Error::NotAuthor)
+        ));
+        assert_eq!(calendar.event(&meetup).unwrap().author, alice.public_key());
+
+        // RSVPs whose coordinate names another kind or author
+        let rsvp = |kind: u16, author: PublicKey| {
+            let coordinate = Coordinate::new(Kind::from(kind), author).identifier(&meetup);
+            let tags = [
+                Tag::coordinate(coordinate),
+                Tag::custom(TagKind::Custom("status".into()), ["accepted"]),
+            ];
+            EventBuilder::new(Kind::from(RSVP_KIND), "", tags)
+                .to_event(&mallory)
+                .unwrap()
+        };
+        for answer in [
+            rsvp(DATE_KIND, alice.public_key()),
+            rsvp(TIME_KIND, mallory.public_key()),
+        ] {
+            assert!(matches!(
+                calendar.import_nip52(&answer).await,
+                Err(Error::InvalidOperation)
+            ));
+        }
+        assert!(calendar.event(&meetup).unwrap().rsvps.is_empty());
+        calendar
+            .import_nip52(&rsvp(TIME_KIND, alice.public_key()))
+            .await
+            .unwrap();
+        assert_eq!(
+            calendar.event(&meetup).unwrap().rsvps,
+            BTreeMap::from([(mallory.public_key(), RsvpStatus::Accepted)])
+        );
+    }
+}
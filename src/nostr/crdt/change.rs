@@ -0,0 +1,118 @@
+use std::sync::Arc;
+
+use serde::Serialize;
+
+use super::{CrdtManager, CrdtState, CrdtType};
+
+// Callback run after an operation changes a value, local or remote
+pub type ChangeHook = Arc<dyn Fn(&Change) + Send + Sync>;
+
+// A key whose value changed, with the value it holds now
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[cfg_attr(feature = "uniffi", derive(uniffi::Record))]
+pub struct Change {
+    pub document: Option<String>,
+    pub key: String,
+    pub crdt_type: CrdtType,
+    pub value: String,
+}
+
+impl CrdtManager {
+    // Register a callback for every key whose value changes, e.g. to
+    // re-render a view. Operations that leave the value as it was, such as
+    // a stale register write, do not fire it. Hooks are shared with
+    // documents opened from this manager.
+    pub fn on_change<F>(&self, hook: F)
+    where
+        F: Fn(&Change) + Send + Sync + 'static,
+    {
+        self.change_hooks.lock().unwrap().push(Arc::new(hook));
+    }
+
+    // Current value of `key` in the CRDT of type `crdt_type`
+    pub(super) fn value_of(&self, crdt_type: CrdtType, key: &str) -> Option<String> {
+        match crdt_type {
+            CrdtType::LWWRegister => self.lww_registers.lock().unwrap().get_value(key),
+            CrdtType::GCounter => self.g_counters.lock().unwrap().get_value(key),
+            CrdtType::GSet => self.g_sets.lock().unwrap().get_value(key),
+        }
+    }
+
+    // Whether anyone listens, so changes need to be worked out at all
+    pub(super) fn has_change_hooks(&self) -> bool {
+        !self.change_hooks.lock().unwrap().is_empty()
+    }
+
+    // Fire the change hooks if `key` moved away from `before`. Must be
+    // called after the operation is applied.
+    pub(super) fn report_change(&self, key: &str, crdt_type: CrdtType, before: Option<String>) {
+        let Some(value) = self.value_of(crdt_type, key) else {
+            return;
+        };
+        if before.as_ref() == Some(&value) {
+            return;
+        }
+
+        let change = Change {
+            document: self.document.clone(),
+            key: key.to_string(),
+            crdt_type,
+            value,
+        };
+        // Clone the hooks so a hook may register another without deadlocking
+        let hooks: Vec<ChangeHook> = self.change_hooks.lock().unwrap().clone();
+        for hook in hooks.iter() {
+            hook(&change);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nostr::crdt::{CrdtOperation, GSetAction};
+    use nostr_sdk::{Keys, NostrSigner};
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_change_hooks() {
+        let keys = Keys::generate();
+        let client = Arc::new(nostr_sdk::Client::new(keys.clone()));
+        let manager = CrdtManager::new(client, NostrSigner::Keys(keys));
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&seen);
+        manager.on_change(move |change| sink.lock().unwrap().push(change.clone()));
+
+        let register = |value: &str, timestamp: u64| CrdtOperation::LWWRegister {
+            key: "title".to_string(),
+            value: value.to_string(),
+            timestamp,
+        };
+        let tag = |value: &str| CrdtOperation::GSet {
+            key: "tags".to_string(),
+            value: value.to_string(),
+            action: GSetAction::Add,
+        };
+        manager.apply(&register("first", 10)).unwrap();
+        // Stale write, the value stays "first"
+        manager.apply(&register("stale", 5)).unwrap();
+        manager
+            .apply(&CrdtOperation::GCounter {
+                key: "views".to_string(),
+                increment: 2,
+            })
+            .unwrap();
+        manager.apply(&tag("rust")).unwrap();
+        // Already in the set
+        manager.apply(&tag("rust")).unwrap();
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 3);
+        assert_eq!(seen[0].key, "title");
+        assert_eq!(seen[0].value, "first");
+        assert_eq!(seen[1].crdt_type, CrdtType::GCounter);
+        assert_eq!(seen[1].value, "2");
+        assert_eq!(seen[2].value, r#"["rust"]"#);
+        assert_eq!(seen[2].document, None);
+    }
+}
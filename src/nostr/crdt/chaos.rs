@@ -0,0 +1,245 @@
+use std::collections::VecDeque;
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+use futures::future::{self, Either};
+use futures::stream::FuturesUnordered;
+use futures::{Stream, StreamExt};
+use nostr_sdk::Event;
+
+use super::CrdtManager;
+use crate::nostr::runtime;
+use crate::testhelper::Rng;
+
+// Faults injected into the events a syncing manager receives, before they
+// reach `process_event`, so applications can test their views under
+// realistic sync conditions. The default injects nothing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChaosConfig {
+    // Chance (0 to 1) of an event being lost
+    pub drop: f64,
+    // Chance (0 to 1) of an event being delivered a second time
+    pub duplicate: f64,
+    // Time an event is held back, drawn uniformly from the range. A wide
+    // range reorders events.
+    pub delay: RangeInclusive<Duration>,
+    // Chance (0 to 1) of an event being held back until the next one has
+    // been delivered
+    pub reorder: f64,
+    // Every random choice comes from the seed
+    pub seed: u64,
+}
+
+impl Default for ChaosConfig {
+    fn default() -> Self {
+        Self {
+            drop: 0.0,
+            duplicate: 0.0,
+            delay: Duration::ZERO..=Duration::ZERO,
+            reorder: 0.0,
+            seed: 0,
+        }
+    }
+}
+
+impl CrdtManager {
+    // Test only: pass the events of `sync()` through `chaos` first.
+    // Catching up through `process_events` is left untouched.
+    pub fn with_chaos(mut self, chaos: ChaosConfig) -> Self {
+        self.chaos = Some(chaos);
+        self
+    }
+}
+
+// Resolves to `event` once `delay` has passed
+async fn delayed(event: Event, delay: Duration) -> Event {
+    runtime::sleep(delay).await;
+    event
+}
+
+// Browser timers are not `Send`
+#[cfg(target_arch = "wasm32")]
+type Delayed = future::LocalBoxFuture<'static, Event>;
+#[cfg(not(target_arch = "wasm32"))]
+type Delayed = future::BoxFuture<'static, Event>;
+
+struct Injector<S> {
+    events: std::pin::Pin<Box<S>>,
+    ended: bool,
+    config: ChaosConfig,
+    rng: Rng,
+    delayed: FuturesUnordered<Delayed>,
+    held: Option<Event>,
+    ready: VecDeque<Event>,
+}
+
+impl<S> Injector<S> {
+    // An event came in from the relays
+    fn receive(&mut self, event: Event) {
+        if self.rng.chance(self.config.drop) {
+            return;
+        }
+        let copies = if self.rng.chance(self.config.duplicate) {
+            2
+        } else {
+            1
+        };
+        let millis = self.config.delay.start().as_millis() as u64
+            ..=self.config.delay.end().as_millis() as u64;
+        for _ in 0..copies {
+            let delay = Duration::from_millis(self.rng.range(&millis));
+            if delay.is_zero() {
+                self.release(event.clone());
+            } else {
+                self.delayed.push(Box::pin(delayed(event.clone(), delay)));
+            }
+        }
+    }
+
+    // An event is due for delivery
+    fn release(&mut self, event: Event) {
+        if self.held.is_none() && self.rng.chance(self.config.reorder) {
+            self.held = Some(event);
+            return;
+        }
+        self.ready.push_back(event);
+        if let Some(held) = self.held.take() {
+            self.ready.push_back(held);
+        }
+    }
+}
+
+// `events` with the faults of `config` injected
+pub(super) fn inject<S>(config: ChaosConfig, events: S) -> impl Stream<Item = Event>
+where
+    S: Stream<Item = Event>,
+{
+    let injector = Injector {
+        events: Box::pin(events),
+        ended: false,
+        rng: Rng(config.seed),
+        config,
+        delayed: FuturesUnordered::new(),
+        held: None,
+        ready: VecDeque::new(),
+    };
+    futures::stream::unfold(injector, |mut injector| async move {
+        loop {
+            if let Some(event) = injector.ready.pop_front() {
+                return Some((event, injector));
+            }
+            if injector.ended {
+                // Drain the delayed events, then whatever is still held
+                match injector.delayed.next().await {
+                    Some(event) => injector.release(event),
+                    None => return injector.held.take().map(|event| (event, injector)),
+                }
+                continue;
+            }
+            if injector.delayed.is_empty() {
+                match injector.events.next().await {
+                    Some(event) => injector.receive(event),
+                    None => injector.ended = true,
+                }
+                continue;
+            }
+            match future::select(injector.events.next(), injector.delayed.next()).await {
+                Either::Left((Some(event), _)) => injector.receive(event),
+                Either::Left((None, _)) => injector.ended = true,
+                Either::Right((Some(event), _)) => injector.release(event),
+                Either::Right((None, _)) => {}
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testhelper::{connect, MockRelay};
+    use nostr_sdk::{EventBuilder, Keys};
+    use std::collections::HashSet;
+    use std::sync::Arc;
+
+    fn notes(count: usize) -> Vec<Event> {
+        let keys = Keys::generate();
+        (0..count)
+            .map(|i| {
+                EventBuilder::text_note(i.to_string(), [])
+                    .to_event(&keys)
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_chaos_injection() {
+        let events = notes(40);
+        let sent: Vec<_> = events.iter().map(|event| event.id).collect();
+
+        // The default changes nothing
+        let received: Vec<_> = inject(
+            ChaosConfig::default(),
+            futures::stream::iter(events.clone()),
+        )
+        .map(|event| event.id)
+        .collect()
+        .await;
+        assert_eq!(received, sent);
+
+        let chaos = ChaosConfig {
+            drop: 0.2,
+            duplicate: 0.3,
+            delay: Duration::ZERO..=Duration::from_millis(5),
+            reorder: 0.3,
+            seed: 7,
+        };
+        let received: Vec<_> = inject(chaos, futures::stream::iter(events))
+            .map(|event| event.id)
+            .collect()
+            .await;
+        let distinct: HashSet<_> = received.iter().collect();
+        assert!(distinct.iter().all(|id| sent.contains(id)));
+        assert!(distinct.len() < sent.len(), "nothing dropped");
+        assert!(received.len() > distinct.len(), "nothing duplicated");
+        let order: Vec<_> = sent.iter().filter(|id| distinct.contains(id)).collect();
+        let mut seen = HashSet::new();
+        let first: Vec<_> = received.iter().filter(|id| seen.insert(*id)).collect();
+        assert_ne!(first, order, "nothing reordered");
+    }
+
+    #[tokio::test]
+    async fn test_sync_under_chaos() {
+        let relay = MockRelay::run().await.unwrap();
+        let keys = Keys::generate();
+        let writer = connect(&relay, &keys).await;
+        let reader = Arc::new(connect(&relay, &keys).await.with_chaos(ChaosConfig {
+            duplicate: 0.5,
+            delay: Duration::ZERO..=Duration::from_millis(20),
+            reorder: 0.5,
+            seed: 3,
+            ..Default::default()
+        }));
+        for i in 0..10 {
+            writer.increment_counter("views", 1).await.unwrap();
+            writer.add_to_set("tags", &i.to_string()).await.unwrap();
+        }
+
+        let syncing = Arc::clone(&reader);
+        let task = tokio::spawn(async move { syncing.sync().await });
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while reader.get_counter_value("views").as_deref() != Some("10") {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("reader did not catch up");
+        // Duplicates are not applied twice, whatever order they arrive in
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(reader.get_counter_value("views"), Some("10".to_string()));
+        let tags: Vec<String> =
+            serde_json::from_str(&reader.get_set_value("tags").unwrap()).unwrap();
+        assert_eq!(tags.len(), 10);
+        task.abort();
+    }
+}
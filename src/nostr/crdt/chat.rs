@@ -0,0 +1,411 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use nostr_sdk::{EventId, PublicKey, Timestamp};
+use serde::{Deserialize, Serialize};
+
+use super::{new_id, CrdtManager, CrdtOperationRef, Error, Result};
+
+// Register holding each message: `message/<id>`, the id being
+// `<author pubkey>:<random>`
+const MESSAGE_PREFIX: &str = "message/";
+// Register per message, emoji and reacting member:
+// `reaction/<id>/<emoji>/<reactor pubkey>`
+const REACTION_PREFIX: &str = "reaction/";
+
+// A message as stored in its register
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StoredMessage {
+    author: PublicKey,
+    text: String,
+    // Place in the room, one past the last message its author had seen
+    position: u64,
+    sent_at: u64,
+    edited_at: Option<u64>,
+}
+
+// A chat message with the reactions it has collected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChatMessage {
+    pub id: String,
+    pub author: PublicKey,
+    pub text: String,
+    // Seconds since the epoch when the message was sent
+    pub sent_at: u64,
+    // When it was last edited, if ever
+    pub edited_at: Option<u64>,
+    // Emoji -> number of members who reacted with it
+    pub reactions: BTreeMap<String, u64>,
+}
+
+// Position in the room's history, pages are read backwards from it
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ChatCursor {
+    position: u64,
+    id: String,
+}
+
+// A page of messages, oldest first
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChatPage {
+    pub messages: Vec<ChatMessage>,
+    // Pass to `messages` for the page before this one, `None` on the first
+    // page of the room
+    pub before: Option<ChatCursor>,
+}
+
+// Chat room kept in a named document. Messages form an append-only
+// sequence rather than a general sequence CRDT: each takes the position
+// after the last message its author had seen, like a Lamport clock, so a
+// reply always lists after what it answers whatever the clocks say, and
+// messages sent at once are ordered by id on every device. Nothing can be
+// inserted between existing messages. Each message is a register only its
+// author may write, as checked against the signer of incoming events;
+// edits are later writes to it. A reaction is a register of the member
+// making it, so each member counts once per emoji. For a room shared by
+// several people, their managers publish public operations and accept each
+// other as members.
+pub struct ChatDoc {
+    document: Arc<CrdtManager>,
+    // Messages in room order, rebuilt when the document changes
+    index: Mutex<ChatIndex>,
+}
+
+#[derive(Default)]
+struct ChatIndex {
+    // Document version the index was built from
+    version: Option<u64>,
+    messages: BTreeMap<ChatCursor, StoredMessage>,
+    // Message id -> emoji -> reactors
+    reactions: HashMap<String, BTreeMap<String, u64>>,
+}
+
+impl ChatDoc {
+    // Open the room `name` as the document `chat/<name>` of `manager`
+    pub fn open(manager: &CrdtManager, name: &str) -> Self {
+        let document = manager.open_document(&format!("chat/{name}"));
+        document.guard_writes(admits);
+        Self {
+            document,
+            index: Mutex::default(),
+        }
+    }
+
+    // The room's document, to `sync()` for messages as they are sent
+    pub fn document(&self) -> &Arc<CrdtManager> {
+        &self.document
+    }
+
+    // Post a message, returning its id
+    pub async fn send_message(&self, text: &str) -> Result<String> {
+        let author = self.document.signer.public_key().await?;
+        let id = format!("{}:{}", author.to_hex(), new_id());
+        let message = StoredMessage {
+            author,
+            text: text.to_string(),
+            position: self.next_position(),
+            sent_at: Timestamp::now().as_u64(),
+            edited_at: None,
+        };
+        self.write(&id, &message).await?;
+        Ok(id)
+    }
+
+    // Replace the text of one of our own messages
+    pub async fn edit_message(&self, id: &str, text: &str) -> Result<EventId> {
+        let mut message = self
+            .stored(id)
+            .ok_or_else(|| Error::NotFound(format!("chat message {id}")))?;
+        if message.author != self.document.signer.public_key().await? {
+            return Err(Error::NotAuthor);
+        }
+        message.text = text.to_string();
+        message.edited_at = Some(Timestamp::now().as_u64());
+        self.write(id, &message).await
+    }
+
+    // React to a message with `emoji`; reacting twice with the same one
+    // still counts once
+    pub async fn react(&self, id: &str, emoji: &str) -> Result<EventId> {
+        if self.stored(id).is_none() {
+            return Err(Error::NotFound(format!("chat message {id}")));
+        }
+        let reactor = self.document.signer.public_key().await?;
+        self.document
+            .update_lww_register(
+                &format!("{REACTION_PREFIX}{id}/{emoji}/{}", reactor.to_hex()),
+                "1",
+            )
+            .await
+    }
+
+    pub fn message(&self, id: &str) -> Option<ChatMessage> {
+        let stored = self.stored(id)?;
+        let reactions = self.index().reactions.get(id).cloned().unwrap_or_default();
+        Some(into_message(id.to_string(), stored, reactions))
+    }
+
+    // Up to `limit` messages listed before `before`, or the latest ones
+    pub fn messages(&self, before: Option<&ChatCursor>, limit: usize) -> ChatPage {
+        let index = self.index();
+        let mut older = match before {
+            Some(before) => index.messages.range(..before),
+            None => index.messages.range::<ChatCursor, _>(..),
+        };
+        let mut page: Vec<(&ChatCursor, &StoredMessage)> =
+            older.by_ref().rev().take(limit).collect();
+        page.reverse();
+        // Only a page with older messages left has one before it
+        let before = page
+            .first()
+            .filter(|_| older.next_back().is_some())
+            .map(|(cursor, _)| (*cursor).clone());
+        let messages = page
+            .into_iter()
+            .map(|(cursor, stored)| {
+                let counts = index.reactions.get(&cursor.id).cloned().unwrap_or_default();
+                into_message(cursor.id.clone(), stored.clone(), counts)
+            })
+            .collect();
+        ChatPage { messages, before }
+    }
+
+    // The room's messages and reactions, read again if the document has
+    // changed since
+    fn index(&self) -> MutexGuard<'_, ChatIndex> {
+        // Fetched operations are applied first, so they are in this version
+        self.document.warm_all();
+        let version = self.document.version();
+        let mut index = self.index.lock().unwrap();
+        if index.version == Some(version) {
+            return index;
+        }
+
+        let mut messages = BTreeMap::new();
+        let mut reactions: HashMap<String, BTreeMap<String, u64>> = HashMap::new();
+        for key in self.document.register_keys() {
+            if let Some(id) = key.strip_prefix(MESSAGE_PREFIX) {
+                let Some(stored) = self.stored(id) else {
+                    continue;
+                };
+                let cursor = ChatCursor {
+                    position: stored.position,
+                    id: id.to_string(),
+                };
+                messages.insert(cursor, stored);
+            } else if let Some((id, emoji, _)) = reaction_key(&key) {
+                *reactions
+                    .entry(id.to_string())
+                    .or_default()
+                    .entry(emoji.to_string())
+                    .or_default() += 1;
+            }
+        }
+        *index = ChatIndex {
+            version: Some(version),
+            messages,
+            reactions,
+        };
+        index
+    }
+
+    // Position after every message seen so far
+    fn next_position(&self) -> u64 {
+        self.index()
+            .messages
+            .last_key_value()
+            .map_or(0, |(cursor, _)| cursor.position.saturating_add(1))
+    }
+
+    fn stored(&self, id: &str) -> Option<StoredMessage> {
+        let value = self
+            .document
+            .get_register_value(&format!("{MESSAGE_PREFIX}{id}"))?;
+        serde_json::from_str(&value).ok()
+    }
+
+    async fn write(&self, id: &str, message: &StoredMessage) -> Result<EventId> {
+        let value = serde_json::to_string(message).map_err(|_| Error::SerializationError)?;
+        self.document
+            .update_lww_register(&format!("{MESSAGE_PREFIX}{id}"), &value)
+            .await
+    }
+}
+
+// Message id, emoji and reactor of a reaction register's key. Message ids
+// hold no `/`, and the reactor is the last segment whatever the emoji.
+fn reaction_key(key: &str) -> Option<(&str, &str, &str)> {
+    let (id, rest) = key.strip_prefix(REACTION_PREFIX)?.split_once('/')?;
+    let (emoji, reactor) = rest.rsplit_once('/')?;
+    Some((id, emoji, reactor))
+}
+
+// Write guard of a room: a message register is only written by the author
+// its id and its value name, a reaction only by the member it names, and
+// neither kind of key by other CRDT types
+fn admits(signer: &PublicKey, op: &CrdtOperationRef<'_>) -> bool {
+    let CrdtOperationRef::LWWRegister { key, value, .. } = op else {
+        let (_, key) = op.target();
+        return !key.starts_with(MESSAGE_PREFIX) && !key.starts_with(REACTION_PREFIX);
+    };
+    if let Some(id) = key.strip_prefix(MESSAGE_PREFIX) {
+        return id
+            .split_once(':')
+            .is_some_and(|(author, _)| author == signer.to_hex())
+            && serde_json::from_str::<StoredMessage>(value)
+                .is_ok_and(|message| message.author == *signer);
+    }
+    if key.starts_with(REACTION_PREFIX) {
+        return reaction_key(key).is_some_and(|(_, _, reactor)| reactor == signer.to_hex());
+    }
+    true
+}
+
+fn into_message(
+    id: String,
+    stored: StoredMessage,
+    reactions: BTreeMap<String, u64>,
+) -> ChatMessage {
+    ChatMessage {
+        id,
+        author: stored.author,
+        text: stored.text,
+        sent_at: stored.sent_at,
+        edited_at: stored.edited_at,
+        reactions,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testhelper::{connect, MockRelay};
+    use nostr_sdk::Keys;
+
+    #[tokio::test]
+    async fn test_chat_doc() {
+        let relay = MockRelay::run().await.unwrap();
+        let alice = Keys::generate();
+        let manager = connect(&relay, &alice).await;
+        let room = ChatDoc::open(&manager, "general");
+
+        let mut ids = Vec::new();
+        for i in 0..5 {
+            ids.push(room.send_message(&format!("message {i}")).await.unwrap());
+        }
+        room.edit_message(&ids[0], "first!").await.unwrap();
+        room.react(&ids[0], "🤙").await.unwrap();
+        room.react(&ids[0], "🤙").await.unwrap();
+        room.react(&ids[1], "👀").await.unwrap();
+        assert!(matches!(
+            room.react("missing", "👀").await,
+            Err(Error::NotFound(_))
+        ));
+
+        // Pages go back in time, each oldest first
+        let latest = room.messages(None, 3);
+        assert_eq!(latest.messages.len(), 3);
+        let older = room.messages(latest.before.as_ref(), 3);
+        assert_eq!(older.messages.len(), 2);
+        assert_eq!(older.before, None);
+        let all: Vec<String> = older
+            .messages
+            .into_iter()
+            .chain(latest.messages)
+            .map(|message| message.id)
+            .collect();
+        assert_eq!(all, ids);
+
+        let first = room.message(&ids[0]).unwrap();
+        assert_eq!(first.text, "first!");
+        assert!(first.edited_at.is_some());
+        // Reacting again changes nothing
+        assert_eq!(first.reactions.get("🤙"), Some(&1));
+
+        // Another device of the same user sees the same room
+        let other = connect(&relay, &alice).await;
+        let mirror = ChatDoc::open(&other, "general");
+        mirror.document().replay_history(None, None).await.unwrap();
+        assert_eq!(mirror.messages(None, 10), room.messages(None, 10));
+
+        // Only the author edits a message
+        let bob = connect(&relay, &Keys::generate()).await;
+        let bob_room = ChatDoc::open(&bob, "general");
+        bob_room
+            .document()
+            .import_state(room.document().export_state())
+            .unwrap();
+        assert!(matches!(
+            bob_room.edit_message(&ids[1], "mine now").await,
+            Err(Error::NotAuthor)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_chat_authors() {
+        let relay = MockRelay::run().await.unwrap();
+        let alice = Keys::generate();
+        let manager = connect(&relay, &alice).await.with_public_operations();
+        let room = ChatDoc::open(&manager, "general");
+        let hello = room.send_message("hello").await.unwrap();
+
+        let bob = Keys::generate();
+        let bob_manager = connect(&relay, &bob).await.with_public_operations();
+        let bob_room = ChatDoc::open(&bob_manager, "general");
+        bob_room
+            .document()
+            .replay_history(None, None)
+            .await
+            .unwrap();
+        // Bob's clock is behind, his answer still lists after the question
+        let answer = format!("{}:{}", bob.public_key().to_hex(), new_id());
+        let stored = StoredMessage {
+            author: bob.public_key(),
+            text: "hi".to_string(),
+            position: bob_room.next_position(),
+            sent_at: 0,
+            edited_at: None,
+        };
+        bob_room.write(&answer, &stored).await.unwrap();
+
+        // Writes in Alice's name, bypassing the local author check
+        let mut forged = room.stored(&hello).unwrap();
+        forged.text = "rewritten".to_string();
+        bob_room.write(&hello, &forged).await.unwrap();
+        let claimed = format!("{}:{}", alice.public_key().to_hex(), new_id());
+        bob_room.write(&claimed, &forged).await.unwrap();
+        forged.author = bob.public_key();
+        bob_room.write(&hello, &forged).await.unwrap();
+
+        // One reaction each, however often or in whose name Bob reacts
+        room.react(&hello, "👍").await.unwrap();
+        bob_room.react(&hello, "👍").await.unwrap();
+        bob_room.react(&hello, "👍").await.unwrap();
+        let in_alices_name = format!(
+            "{REACTION_PREFIX}{hello}/👍/{}",
+            alice.public_key().to_hex()
+        );
+        bob_room
+            .document()
+            .update_lww_register(&in_alices_name, "1")
+            .await
+            .unwrap();
+        bob_room
+            .document()
+            .increment_counter(&format!("{REACTION_PREFIX}{hello}/👍"), 100)
+            .await
+            .unwrap();
+
+        room.document().replay_history(None, None).await.unwrap();
+        let page = room.messages(None, 10);
+        let texts: Vec<&str> = page
+            .messages
+            .iter()
+            .map(|message| message.text.as_str())
+            .collect();
+        assert_eq!(texts, ["hello", "hi"]);
+        assert_eq!(page.messages[1].id, answer);
+        assert_eq!(page.messages[0].reactions.get("👍"), Some(&2));
+        assert_eq!(room.document().metrics().ops_rejected, 5);
+    }
+}
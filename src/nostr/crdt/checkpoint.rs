@@ -0,0 +1,270 @@
+use std::time::Duration;
+
+use nostr_sdk::hashes::sha256::Hash as Sha256Hash;
+use nostr_sdk::hashes::Hash;
+use nostr_sdk::{Event, EventBuilder, EventId, Filter, Tag, TagKind};
+
+use super::{CrdtManager, Error, Result, StateBackup};
+
+const CHECKPOINT_HASHTAG: &str = "nostr-crdt-checkpoint";
+const DIGEST_TAG: &str = "digest";
+// How far before a checkpoint operations are fetched again when loading it.
+// An operation can reach the relays after the checkpoint while dated
+// earlier, e.g. one written on a device that was offline; those dated
+// further back are left to a full `replay_history`.
+const REPLAY_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+// Result of bootstrapping from the latest checkpoint
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckpointLoad {
+    // Checkpoint verified and restored, the operations it lacks replayed on
+    // top
+    Verified(EventId),
+    // No checkpoint published yet, state rebuilt from operations
+    Missing,
+    // Checkpoint failed verification and was ignored, state rebuilt from
+    // operations
+    Rejected(EventId),
+}
+
+impl StateBackup {
+    // Hex SHA-256 of the canonical JSON encoding. Maps are ordered, so equal
+    // states always produce the same digest.
+    pub fn digest(&self) -> String {
+        let json = serde_json::to_vec(self).unwrap_or_default();
+        Sha256Hash::hash(&json).to_string()
+    }
+}
+
+impl CrdtManager {
+    // Publish the full state as a signed checkpoint event carrying the
+    // state digest, so later loads can skip replaying every operation
+    pub async fn publish_checkpoint(&self) -> Result<EventId> {
+        if self.is_read_only() {
+            return Err(Error::ReadOnly);
+        }
+
+        let backup = self.export_state();
+        let content = serde_json::to_string(&backup).map_err(|_| Error::SerializationError)?;
+        let content = if self.encrypt_operations {
+            self.encrypt_content(&content).await?
+        } else {
+            content
+        };
+
+        let mut tags = vec![
+            Tag::hashtag(CHECKPOINT_HASHTAG),
+            Tag::custom(TagKind::from(DIGEST_TAG), [backup.digest()]),
+        ];
+        if let Some(document) = &self.document {
+            tags.push(Tag::identifier(document));
+        }
+        let event = self
+            .signer
+            .sign_event_builder(EventBuilder::new(self.crdt_kind, content, tags))
+            .await?;
+        self.throttle(None).await?;
+        self.send_with_retry(event).await
+    }
+
+    // Restore from the newest checkpoint of the document's author, then
+    // apply the operations dated from `REPLAY_WINDOW` before it. A
+    // checkpoint whose signature or digest does not check out is discarded
+    // with a warning and the state is rebuilt from operations instead.
+    pub async fn load_checkpoint(&self, timeout: Option<Duration>) -> Result<CheckpointLoad> {
+        let author = match &self.following {
+            Some((publisher, _)) => *publisher,
+            None => self.signer.public_key().await?,
+        };
+        let mut filter = Filter::new()
+            .kind(self.crdt_kind)
+            .author(author)
+            .hashtag(CHECKPOINT_HASHTAG);
+        if let Some(document) = &self.document {
+            filter = filter.identifier(document);
+        }
+        let events = self.fetch_events(vec![filter], timeout).await?;
+
+        let Some(checkpoint) = events
+            .into_iter()
+            .filter(|event| event.identifier() == self.document.as_deref())
+            .max_by_key(|event| event.created_at)
+        else {
+            self.replay_history(None, timeout).await?;
+            return Ok(CheckpointLoad::Missing);
+        };
+
+        match self.verify_checkpoint(&checkpoint).await {
+            Some(backup) => {
+                self.import_state(backup)?;
+                // The checkpoint lists the events it includes; those within
+                // the window are skipped before being decrypted
+                let since = checkpoint.created_at - REPLAY_WINDOW;
+                self.replay_history(Some(since), timeout).await?;
+                Ok(CheckpointLoad::Verified(checkpoint.id))
+            }
+            None => {
+                tracing::warn!(
+                    "Checkpoint {} failed verification, replaying operations",
+                    checkpoint.id
+                );
+                self.replay_history(None, timeout).await?;
+                Ok(CheckpointLoad::Rejected(checkpoint.id))
+            }
+        }
+    }
+
+    // The checkpoint's state, if its signature and digest both verify
+    async fn verify_checkpoint(&self, event: &Event) -> Option<StateBackup> {
+        event.verify().ok()?;
+        let expected = event.tags.iter().find_map(|tag| {
+            let values = tag.as_vec();
+            (values.len() == 2 && values[0] == DIGEST_TAG).then(|| values[1].clone())
+        })?;
+
+        let content = if event.content.contains("?iv=") {
+            self.decrypt_content(event).await.ok()?
+        } else {
+            event.content.clone()
+        };
+        let backup: StateBackup = serde_json::from_str(&content).ok()?;
+        (backup.digest() == expected).then_some(backup)
+    }
+
+    // Fetch and apply every operation of the document in replay order,
+    // optionally only those newer than `since`
+    pub async fn replay_history(
+        &self,
+        since: Option<nostr_sdk::Timestamp>,
+        timeout: Option<Duration>,
+    ) -> Result<()> {
+        let mut filter = self.get_filter();
+        if let Some(since) = since {
+            filter = filter.since(since);
+        }
+        let events = self.fetch_events(vec![filter], timeout).await?;
+        self.process_events(events).await;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nostr::crdt::CrdtOperation;
+    use crate::testhelper::{connect, MockRelay};
+    use nostr_sdk::{Keys, Kind, NostrSigner, Timestamp};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_verify_checkpoint() {
+        let keys = Keys::generate();
+        let client = Arc::new(nostr_sdk::Client::new(keys.clone()));
+        let manager = CrdtManager::new(client, NostrSigner::Keys(keys.clone()));
+        manager
+            .apply(&CrdtOperation::GCounter {
+                key: "visitors".to_string(),
+                increment: 3,
+            })
+            .unwrap();
+
+        let backup = manager.export_state();
+        let content = serde_json::to_string(&backup).unwrap();
+        let signed = |digest: String| {
+            EventBuilder::new(
+                Kind::TextNote,
+                &content,
+                [
+                    Tag::hashtag(CHECKPOINT_HASHTAG),
+                    Tag::custom(TagKind::from(DIGEST_TAG), [digest]),
+                ],
+            )
+            .to_event(&keys)
+            .unwrap()
+        };
+
+        let valid = signed(backup.digest());
+        assert_eq!(manager.verify_checkpoint(&valid).await, Some(backup));
+
+        let tampered = signed("00".repeat(32));
+        assert_eq!(manager.verify_checkpoint(&tampered).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_keeps_late_operations() {
+        let relay = MockRelay::run().await.unwrap();
+        let keys = Keys::generate();
+        let timeout = Some(Duration::from_secs(2));
+
+        let writer = connect(&relay, &keys).await.with_public_operations();
+        writer.increment_counter("visitors", 1).await.unwrap();
+        writer.publish_checkpoint().await.unwrap();
+
+        // Another device's operation, reaching the relay only after the
+        // checkpoint but dated an hour before it
+        let op = CrdtOperation::GCounter {
+            key: "visitors".to_string(),
+            increment: 2,
+        };
+        let mut tags = op.tags();
+        tags.push(Tag::hashtag("nostr-crdt"));
+        let late = EventBuilder::new(Kind::TextNote, serde_json::to_string(&op).unwrap(), tags)
+            .custom_created_at(Timestamp::now() - Duration::from_secs(3600))
+            .to_event(&keys)
+            .unwrap();
+        relay.add_event(late);
+
+        let reader = connect(&relay, &keys).await.with_public_operations();
+        assert!(matches!(
+            reader.load_checkpoint(timeout).await.unwrap(),
+            CheckpointLoad::Verified(_)
+        ));
+        assert_eq!(reader.get_counter_value("visitors").as_deref(), Some("3"));
+        // Operations included in the checkpoint are not applied twice
+        assert_eq!(reader.metrics().ops_applied, 1);
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_replays_recent_operations() {
+        let relay = MockRelay::run().await.unwrap();
+        let keys = Keys::generate();
+        let timeout = Some(Duration::from_secs(2));
+        let increment = |increment: u64, age: Duration| {
+            let op = CrdtOperation::GCounter {
+                key: "visitors".to_string(),
+                increment,
+            };
+            let mut tags = op.tags();
+            tags.push(Tag::hashtag("nostr-crdt"));
+            EventBuilder::new(Kind::TextNote, serde_json::to_string(&op).unwrap(), tags)
+                .custom_created_at(Timestamp::now() - age)
+                .to_event(&keys)
+                .unwrap()
+        };
+
+        // History from well before the checkpoint, all included in it
+        let writer = connect(&relay, &keys).await.with_public_operations();
+        let old = increment(1, REPLAY_WINDOW * 3);
+        relay.add_event(old.clone());
+        writer.process_event(&old).await.unwrap();
+        writer.publish_checkpoint().await.unwrap();
+
+        // Published after the checkpoint: one dated inside the window, one
+        // dated before it
+        relay.add_event(increment(2, REPLAY_WINDOW / 2));
+        relay.add_event(increment(4, REPLAY_WINDOW * 2));
+
+        let reader = connect(&relay, &keys).await.with_public_operations();
+        assert!(matches!(
+            reader.load_checkpoint(timeout).await.unwrap(),
+            CheckpointLoad::Verified(_)
+        ));
+        // Only the operation dated inside the window is applied
+        assert_eq!(reader.get_counter_value("visitors").as_deref(), Some("3"));
+        assert_eq!(reader.metrics().ops_applied, 1);
+
+        // A full replay still finds it
+        reader.replay_history(None, timeout).await.unwrap();
+        assert_eq!(reader.get_counter_value("visitors").as_deref(), Some("7"));
+    }
+}
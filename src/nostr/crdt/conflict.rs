@@ -0,0 +1,148 @@
+use std::sync::Arc;
+
+use nostr_sdk::{Event, EventId, PublicKey};
+
+use super::{CrdtManager, CrdtOperationRef};
+
+// Callback run for every conflict between an incoming and a local value
+pub type ConflictHook = Arc<dyn Fn(&Conflict) + Send + Sync>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictOutcome {
+    // The incoming value replaced the local one
+    Overridden,
+    // The incoming value was older and was dropped
+    Discarded,
+}
+
+// An incoming register write that disagreed with the local value
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    pub document: Option<String>,
+    pub key: String,
+    // Value and timestamp held before the incoming operation
+    pub local: (String, u64),
+    pub incoming: (String, u64),
+    pub outcome: ConflictOutcome,
+    pub event_id: EventId,
+    pub author: PublicKey,
+}
+
+impl CrdtManager {
+    // Register a callback for incoming operations that override or lose to
+    // a local value, e.g. to show "your edit was overridden" notices. Hooks
+    // are shared with documents opened from this manager.
+    pub fn on_conflict<F>(&self, hook: F)
+    where
+        F: Fn(&Conflict) + Send + Sync + 'static,
+    {
+        self.conflict_hooks.lock().unwrap().push(Arc::new(hook));
+    }
+
+    // Conflict the operation would cause against the current state, if any.
+    // Must be called before the operation is applied.
+    pub(super) fn detect_conflict(
+        &self,
+        op: &CrdtOperationRef<'_>,
+        event: &Event,
+    ) -> Option<Conflict> {
+        // Conflicts are only worked out for someone to report them to
+        if self.conflict_hooks.lock().unwrap().is_empty() {
+            return None;
+        }
+        let CrdtOperationRef::LWWRegister {
+            key,
+            value,
+            timestamp,
+        } = op
+        else {
+            return None;
+        };
+        let registers = self.lww_registers.lock().unwrap();
+        let (local_value, local_timestamp) = registers.registers.get(key.as_ref())?;
+        if local_value == value {
+            return None;
+        }
+
+        // Mirrors the register's rule: ties go to the greater value
+        let outcome = if (*timestamp, value.as_ref()) > (*local_timestamp, local_value.as_str()) {
+            ConflictOutcome::Overridden
+        } else {
+            ConflictOutcome::Discarded
+        };
+        Some(Conflict {
+            document: self.document.clone(),
+            key: key.to_string(),
+            local: (local_value.clone(), *local_timestamp),
+            incoming: (value.to_string(), *timestamp),
+            outcome,
+            event_id: event.id,
+            author: event.pubkey,
+        })
+    }
+
+    pub(super) fn report_conflict(&self, conflict: &Conflict) {
+        // Clone the hooks so a hook may register another without deadlocking
+        let hooks: Vec<ConflictHook> = self.conflict_hooks.lock().unwrap().clone();
+        for hook in hooks.iter() {
+            hook(conflict);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nostr::crdt::CrdtOperation;
+    use nostr_sdk::{EventBuilder, Keys, Kind, NostrSigner, Tag};
+    use std::sync::Mutex;
+
+    #[tokio::test]
+    async fn test_conflict_hooks() {
+        let keys = Keys::generate();
+        let client = Arc::new(nostr_sdk::Client::new(keys.clone()));
+        let manager = CrdtManager::new(client, NostrSigner::Keys(keys));
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&seen);
+        manager.on_conflict(move |conflict| sink.lock().unwrap().push(conflict.clone()));
+
+        let remote = Keys::generate();
+        let write = |value: &str, timestamp: u64| {
+            let op = CrdtOperation::LWWRegister {
+                key: "title".to_string(),
+                value: value.to_string(),
+                timestamp,
+            };
+            EventBuilder::new(
+                Kind::TextNote,
+                serde_json::to_string(&op).unwrap(),
+                [Tag::hashtag("nostr-crdt")],
+            )
+            .to_event(&remote)
+            .unwrap()
+        };
+
+        manager
+            .apply(&CrdtOperation::LWWRegister {
+                key: "title".to_string(),
+                value: "mine".to_string(),
+                timestamp: 10,
+            })
+            .unwrap();
+        let stale = write("stale", 5);
+        let newer = write("theirs", 20);
+        manager.process_event(&stale).await.unwrap();
+        manager.process_event(&newer).await.unwrap();
+        // Same value again is not a conflict
+        manager.process_event(&write("theirs", 30)).await.unwrap();
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 2);
+        assert_eq!(seen[0].outcome, ConflictOutcome::Discarded);
+        assert_eq!(seen[0].incoming, ("stale".to_string(), 5));
+        assert_eq!(seen[1].outcome, ConflictOutcome::Overridden);
+        assert_eq!(seen[1].local, ("mine".to_string(), 10));
+        assert_eq!(seen[1].event_id, newer.id);
+        assert_eq!(seen[1].author, remote.public_key());
+    }
+}
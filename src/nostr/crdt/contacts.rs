@@ -0,0 +1,296 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use nostr_sdk::{Contact, Event, EventBuilder, EventId, Kind, PublicKey, UncheckedUrl};
+use serde::{Deserialize, Serialize};
+
+use super::or_set::OrSet;
+use super::{CrdtManager, Error, Result};
+use crate::nostr::fetch::ContactListCache;
+use crate::nostr::publish::contact_tag;
+
+// Elements of the OR-Set are followed public keys in hex: `follow/<pubkey>`
+const FOLLOW_PREFIX: &str = "follow/";
+// Register per contact with its relay hint and petname: `contact/<pubkey>`
+const CONTACT_PREFIX: &str = "contact/";
+// Follows of the list last published or imported, to tell what another
+// client changed since
+const PUBLISHED: &str = "published";
+// Content of the last imported list, the legacy relay list some clients
+// still read, published again as is
+const CONTENT: &str = "content";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct ContactDetails {
+    relay_url: Option<String>,
+    alias: Option<String>,
+}
+
+impl ContactDetails {
+    fn of(contact: &Contact) -> Self {
+        Self {
+            relay_url: contact.relay_url.as_ref().map(ToString::to_string),
+            alias: contact.alias.clone(),
+        }
+    }
+}
+
+// The user's follows kept in the document `contacts`, as an OR-Set so a
+// follow on one device and an unfollow on another never undo each other
+// unseen: a follow concurrent with an unfollow of the same key wins. Relay
+// hints and petnames are registers per contact. `publish` projects the set
+// onto the kind 3 contact list, best after a `sync()`; `import_contact_list`
+// brings in follows another client changed.
+pub struct ContactsDoc {
+    document: Arc<CrdtManager>,
+}
+
+impl ContactsDoc {
+    pub fn open(manager: &CrdtManager) -> Self {
+        Self {
+            document: manager.open_document("contacts"),
+        }
+    }
+
+    // The follow list's document; `replay_history` it before `publish` so
+    // the kind 3 list keeps every device's follows
+    pub fn document(&self) -> &Arc<CrdtManager> {
+        &self.document
+    }
+
+    // Follow `contact`. Its relay hint and petname, when `None`, keep the
+    // ones already set.
+    pub async fn follow(&self, contact: Contact) -> Result<EventId> {
+        let current = self.details(&contact.public_key);
+        let details = ContactDetails {
+            relay_url: ContactDetails::of(&contact)
+                .relay_url
+                .or(current.relay_url.clone()),
+            alias: contact.alias.clone().or(current.alias.clone()),
+        };
+        if details != current {
+            let json = serde_json::to_string(&details).map_err(|_| Error::SerializationError)?;
+            self.document
+                .update_lww_register(&details_key(&contact.public_key), &json)
+                .await?;
+        }
+        self.set().add(&contact.public_key.to_hex()).await
+    }
+
+    // Unfollow, `None` if not followed
+    pub async fn unfollow(&self, public_key: &PublicKey) -> Result<Option<EventId>> {
+        self.set().remove(&public_key.to_hex()).await
+    }
+
+    pub fn is_following(&self, public_key: &PublicKey) -> bool {
+        self.set().contains(&public_key.to_hex())
+    }
+
+    // Followed contacts, by public key
+    pub fn contacts(&self) -> Vec<Contact> {
+        self.set()
+            .elements()
+            .iter()
+            .filter_map(|hex| PublicKey::from_hex(hex).ok())
+            .map(|public_key| {
+                let details = self.details(&public_key);
+                Contact::new(
+                    public_key,
+                    details.relay_url.map(UncheckedUrl::from),
+                    details.alias,
+                )
+            })
+            .collect()
+    }
+
+    // The follows as a kind 3 contact list
+    pub fn to_contact_list(&self) -> EventBuilder {
+        let content = self
+            .document
+            .get_register_value(CONTENT)
+            .unwrap_or_default();
+        let tags = self.contacts().into_iter().map(contact_tag);
+        EventBuilder::new(Kind::ContactList, content, tags)
+    }
+
+    // Publish the contact list, replacing the previous one
+    pub async fn publish(&self) -> Result<EventId> {
+        let builder = self.to_contact_list();
+        let event = self.document.signer.sign_event_builder(builder).await?;
+        self.remember_published(&event).await?;
+        ContactListCache::global().observe(&event);
+        self.document.send_event(event).await.map_err(Error::Client)
+    }
+
+    // Apply the follows, unfollows, relay hints and petnames another client
+    // changed in our contact list since it was last published or imported
+    // here
+    pub async fn import_contact_list(&self, event: &Event) -> Result<()> {
+        if event.kind != Kind::ContactList {
+            return Err(Error::InvalidOperation);
+        }
+        if event.pubkey != self.document.signer.public_key().await? {
+            return Err(Error::NotAuthor);
+        }
+        let before: BTreeMap<PublicKey, ContactDetails> = self
+            .document
+            .get_register_value(PUBLISHED)
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        let after = list_contacts(event);
+
+        for (public_key, details) in &after {
+            if before.get(public_key) != Some(details) {
+                let contact = Contact::new(
+                    *public_key,
+                    details.relay_url.clone().map(UncheckedUrl::from),
+                    details.alias.clone(),
+                );
+                self.follow(contact).await?;
+            }
+        }
+        for public_key in before.keys().filter(|key| !after.contains_key(key)) {
+            self.unfollow(public_key).await?;
+        }
+        if self
+            .document
+            .get_register_value(CONTENT)
+            .unwrap_or_default()
+            != event.content
+        {
+            self.document
+                .update_lww_register(CONTENT, &event.content)
+                .await?;
+        }
+        self.remember_published(event).await
+    }
+
+    fn details(&self, public_key: &PublicKey) -> ContactDetails {
+        self.document
+            .get_register_value(&details_key(public_key))
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    async fn remember_published(&self, event: &Event) -> Result<()> {
+        let json =
+            serde_json::to_string(&list_contacts(event)).map_err(|_| Error::SerializationError)?;
+        self.document.update_lww_register(PUBLISHED, &json).await?;
+        Ok(())
+    }
+
+    fn set(&self) -> OrSet<'_> {
+        OrSet::new(&self.document, FOLLOW_PREFIX)
+    }
+}
+
+fn details_key(public_key: &PublicKey) -> String {
+    format!("{CONTACT_PREFIX}{}", public_key.to_hex())
+}
+
+// The follows of a contact list with their relay hints and petnames
+fn list_contacts(event: &Event) -> BTreeMap<PublicKey, ContactDetails> {
+    event
+        .tags
+        .iter()
+        .filter_map(|tag| match tag.as_vec() {
+            [kind, public_key, rest @ ..] if kind == "p" => {
+                let public_key = PublicKey::from_hex(public_key).ok()?;
+                let non_empty = |value: Option<&String>| value.filter(|v| !v.is_empty()).cloned();
+                let details = ContactDetails {
+                    relay_url: non_empty(rest.first()),
+                    alias: non_empty(rest.get(1)),
+                };
+                Some((public_key, details))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testhelper::{connect, MockRelay};
+    use nostr_sdk::{Filter, Keys};
+
+    #[tokio::test]
+    async fn test_contacts_doc() {
+        let relay = MockRelay::run().await.unwrap();
+        let keys = Keys::generate();
+        let phone_manager = connect(&relay, &keys).await;
+        let phone = ContactsDoc::open(&phone_manager);
+        let desktop = ContactsDoc::open(&connect(&relay, &keys).await);
+        let [carol, dave, erin] = [(); 3].map(|_| Keys::generate().public_key());
+
+        phone
+            .follow(Contact::new(carol, None, Some("carol")))
+            .await
+            .unwrap();
+        phone
+            .follow(Contact::new(dave, None, None::<String>))
+            .await
+            .unwrap();
+        desktop.document().replay_history(None, None).await.unwrap();
+
+        // The phone unfollows and follows while the desktop does the same
+        phone.unfollow(&dave).await.unwrap();
+        desktop.unfollow(&dave).await.unwrap();
+        desktop
+            .follow(Contact::new(dave, None, None::<String>))
+            .await
+            .unwrap();
+        desktop
+            .follow(Contact::new(erin, None, None::<String>))
+            .await
+            .unwrap();
+        phone.unfollow(&carol).await.unwrap();
+        phone.document().replay_history(None, None).await.unwrap();
+        desktop.document().replay_history(None, None).await.unwrap();
+
+        let followed: Vec<PublicKey> = phone.contacts().iter().map(|c| c.public_key).collect();
+        let mut expected = vec![dave, erin];
+        expected.sort();
+        assert_eq!(followed, expected);
+        assert_eq!(phone.contacts(), desktop.contacts());
+
+        phone.publish().await.unwrap();
+        let filter = Filter::new().kind(Kind::ContactList);
+        let published = phone_manager.client.get_events_of(vec![filter], None).await;
+        let published = published.unwrap().pop().unwrap();
+        assert_eq!(
+            list_contacts(&published).into_keys().collect::<Vec<_>>(),
+            expected
+        );
+
+        // Another client follows carol again with a relay hint and drops erin
+        let contacts = [
+            Contact::new(
+                carol,
+                Some(UncheckedUrl::from("wss://relay.example")),
+                None::<String>,
+            ),
+            Contact::new(dave, None, None::<String>),
+        ];
+        let edited = EventBuilder::new(
+            Kind::ContactList,
+            "{}",
+            contacts.into_iter().map(contact_tag),
+        )
+        .to_event(&keys)
+        .unwrap();
+        phone.import_contact_list(&edited).await.unwrap();
+        let contacts = phone.contacts();
+        assert!(!phone.is_following(&erin));
+        let carol = contacts.iter().find(|c| c.public_key == carol).unwrap();
+        assert_eq!(carol.alias.as_deref(), Some("carol"));
+        assert_eq!(
+            carol.relay_url,
+            Some(UncheckedUrl::from("wss://relay.example"))
+        );
+        assert_eq!(
+            phone.to_contact_list().to_event(&keys).unwrap().content,
+            "{}"
+        );
+    }
+}
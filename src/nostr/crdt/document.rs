@@ -0,0 +1,192 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::atomic::AtomicU64;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use nostr_sdk::{Event, PublicKey};
+
+use super::{CrdtManager, GCounter, GSet, KeyInterner, LWWRegister, Result};
+
+impl CrdtManager {
+    // Open (or reuse) a named document hosted by this manager. Documents
+    // share the client, keys and rate limit but keep their own state,
+    // filter and sync loop; call `sync()` on the handle to follow it live.
+    pub fn open_document(&self, name: &str) -> Arc<CrdtManager> {
+        let mut documents = self.documents.lock().unwrap();
+        if let Some(document) = documents.get(name) {
+            return Arc::clone(document);
+        }
+
+        let document = Arc::new(self.sibling(Some(name.to_string())));
+        documents.insert(name.to_string(), Arc::clone(&document));
+        document
+    }
+
+    // Manager sharing this one's client, keys and configuration, with fresh
+    // state for `document`
+    pub(super) fn sibling(&self, document: Option<String>) -> CrdtManager {
+        let keys = KeyInterner::default();
+        CrdtManager {
+            client: Arc::clone(&self.client),
+            signer: self.signer.clone(),
+            lww_registers: Arc::new(Mutex::new(LWWRegister::with_keys(keys.clone()))),
+            g_counters: Arc::new(Mutex::new(GCounter::with_keys(keys.clone()))),
+            g_sets: Arc::new(Mutex::new(GSet::with_keys(keys.clone()))),
+            crdt_kind: self.crdt_kind,
+            metrics: Arc::clone(&self.metrics),
+            limiter: self.limiter.clone(),
+            lww_queue: Arc::new(Mutex::new(HashMap::new())),
+            keys,
+            publish_seq: Arc::new(AtomicU64::new(0)),
+            encrypt_operations: self.encrypt_operations,
+            read_key: self.read_key.clone(),
+            following: self.following.clone(),
+            processed: Arc::new(Mutex::new(HashSet::new())),
+            processed_store: self.processed_store.clone(),
+            processed_loaded: tokio::sync::OnceCell::new(),
+            document,
+            members: Arc::new(Mutex::new(self.members())),
+            relays: self.relays.clone(),
+            relays_ready: tokio::sync::OnceCell::new(),
+            conflict_hooks: Arc::clone(&self.conflict_hooks),
+            change_hooks: Arc::clone(&self.change_hooks),
+            write_guard: Arc::default(),
+            version: Arc::default(),
+            documents: Arc::new(Mutex::new(HashMap::new())),
+            decrypt_cache: self.decrypt_cache.clone(),
+            cold: self.cold.as_ref().map(|_| Arc::default()),
+            #[cfg(feature = "testkit")]
+            chaos: self.chaos.clone(),
+        }
+    }
+
+    // Name of this document, `None` for the manager's default document
+    pub fn document_name(&self) -> Option<&str> {
+        self.document.as_deref()
+    }
+
+    // Only accept operations authored by these pubkeys
+    pub fn with_members(self, members: Vec<PublicKey>) -> Self {
+        self.set_members(members);
+        self
+    }
+
+    // `with_members` for a document already shared, e.g. one returned by
+    // `open_document`, which starts with the members of its manager. A
+    // running `sync()` keeps the filter it subscribed with, but operations
+    // by authors no longer accepted are still dropped.
+    pub fn set_members(&self, members: Vec<PublicKey>) {
+        *self.members.lock().unwrap() = members;
+    }
+
+    pub fn members(&self) -> Vec<PublicKey> {
+        self.members.lock().unwrap().clone()
+    }
+
+    // Names of every document opened here or found in the author's CRDT
+    // events on relays, sorted
+    pub async fn list_documents(&self, timeout: Option<Duration>) -> Result<Vec<String>> {
+        let mut names: BTreeSet<String> = self.documents.lock().unwrap().keys().cloned().collect();
+
+        let author = match &self.following {
+            Some((publisher, _)) => *publisher,
+            None => self.signer.public_key().await?,
+        };
+        let filter = nostr_sdk::Filter::new()
+            .kind(self.crdt_kind)
+            .author(author)
+            .hashtag("nostr-crdt");
+        let events = self.fetch_events(vec![filter], timeout).await?;
+        names.extend(
+            events
+                .iter()
+                .filter_map(|event| event.identifier().map(String::from)),
+        );
+
+        Ok(names.into_iter().collect())
+    }
+
+    // Whether an event belongs to this document and comes from an accepted
+    // author
+    pub(super) fn owns_event(&self, event: &Event) -> bool {
+        let members = self.members.lock().unwrap();
+        if !members.is_empty() && !members.contains(&event.pubkey) {
+            return false;
+        }
+        event.identifier() == self.document.as_deref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nostr::crdt::CrdtOperation;
+    use nostr_sdk::{EventBuilder, Keys, Kind, NostrSigner, Tag};
+
+    #[tokio::test]
+    async fn test_documents_are_isolated() {
+        let keys = Keys::generate();
+        let client = Arc::new(nostr_sdk::Client::new(keys.clone()));
+        let manager = CrdtManager::new(client, NostrSigner::Keys(keys.clone()));
+        let shopping = manager.open_document("shopping-list");
+        assert!(Arc::ptr_eq(
+            &shopping,
+            &manager.open_document("shopping-list")
+        ));
+        assert_eq!(shopping.document_name(), Some("shopping-list"));
+
+        let op = CrdtOperation::GCounter {
+            key: "eggs".to_string(),
+            increment: 12,
+        };
+        let event = EventBuilder::new(
+            Kind::TextNote,
+            serde_json::to_string(&op).unwrap(),
+            [Tag::hashtag("nostr-crdt"), Tag::identifier("shopping-list")],
+        )
+        .to_event(&keys)
+        .unwrap();
+
+        shopping.process_event(&event).await.unwrap();
+        manager.process_event(&event).await.unwrap();
+        assert_eq!(shopping.get_counter_value("eggs"), Some("12".to_string()));
+        assert_eq!(manager.get_counter_value("eggs"), None);
+    }
+
+    #[tokio::test]
+    async fn test_document_members() {
+        let (alice, bob) = (Keys::generate(), Keys::generate());
+        let client = Arc::new(nostr_sdk::Client::new(alice.clone()));
+        let manager = CrdtManager::new(client, NostrSigner::Keys(alice.clone()))
+            .with_members(vec![alice.public_key()]);
+        let shared = manager.open_document("shared");
+        assert_eq!(shared.members(), vec![alice.public_key()]);
+        shared.set_members(vec![alice.public_key(), bob.public_key()]);
+        assert_eq!(manager.members(), vec![alice.public_key()]);
+        assert_eq!(
+            shared.get_filter().authors,
+            Some([alice.public_key(), bob.public_key()].into())
+        );
+
+        let increment = |document: &str| {
+            let op = CrdtOperation::GCounter {
+                key: "visits".to_string(),
+                increment: 1,
+            };
+            EventBuilder::new(
+                Kind::TextNote,
+                serde_json::to_string(&op).unwrap(),
+                [Tag::hashtag("nostr-crdt"), Tag::identifier(document)],
+            )
+            .to_event(&bob)
+            .unwrap()
+        };
+        shared.process_event(&increment("shared")).await.unwrap();
+        assert_eq!(shared.get_counter_value("visits"), Some("1".to_string()));
+
+        // Other documents keep the manager's members
+        let private = manager.open_document("private");
+        private.process_event(&increment("private")).await.unwrap();
+        assert_eq!(private.get_counter_value("visits"), None);
+    }
+}
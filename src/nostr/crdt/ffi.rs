@@ -0,0 +1,201 @@
+use std::sync::Arc;
+
+use nostr_sdk::{Client, FromBech32, Keys, NostrSigner, SecretKey, ToBech32};
+use thiserror::Error;
+
+use super::{Change, CrdtManager};
+
+#[derive(Debug, Error, uniffi::Error)]
+#[uniffi(flat_error)]
+pub enum FfiError {
+    #[error(transparent)]
+    Crdt(#[from] super::Error),
+    #[error(transparent)]
+    Client(#[from] nostr_sdk::client::Error),
+    #[error("Invalid secret key: {0}")]
+    InvalidKey(String),
+    #[error("Invalid JSON: {0}")]
+    Json(String),
+}
+
+type Result<T> = std::result::Result<T, FfiError>;
+
+/// Implemented in Swift/Kotlin to hear about value changes
+#[uniffi::export(callback_interface)]
+pub trait ChangeListener: Send + Sync {
+    fn on_change(&self, change: Change);
+}
+
+/// `CrdtManager` for Swift and Kotlin. Async methods map to `async`
+/// functions and suspend functions; they run on a Tokio runtime owned by
+/// the bindings.
+#[derive(uniffi::Object)]
+pub struct FfiCrdtManager {
+    client: Arc<Client>,
+    keys: Keys,
+    inner: Arc<CrdtManager>,
+}
+
+#[uniffi::export(async_runtime = "tokio")]
+impl FfiCrdtManager {
+    /// Manager signing with `secret_key` (nsec or hex), or with fresh keys
+    #[uniffi::constructor]
+    pub fn new(secret_key: Option<String>) -> Result<Arc<Self>> {
+        let keys = match secret_key {
+            Some(secret_key) => {
+                let secret_key = SecretKey::from_bech32(&secret_key)
+                    .or_else(|_| SecretKey::from_hex(&secret_key))
+                    .map_err(|err| FfiError::InvalidKey(err.to_string()))?;
+                Keys::new(secret_key)
+            }
+            None => Keys::generate(),
+        };
+        let client = Arc::new(Client::new(&keys));
+        let inner = Arc::new(CrdtManager::new(
+            Arc::clone(&client),
+            NostrSigner::Keys(keys.clone()),
+        ));
+        Ok(Arc::new(Self {
+            client,
+            keys,
+            inner,
+        }))
+    }
+
+    /// npub of the keys signing the operations
+    pub fn public_key(&self) -> String {
+        self.keys.public_key().to_bech32().unwrap_or_default()
+    }
+
+    /// Adds the relays and waits until they are connected
+    pub async fn connect(&self, relays: Vec<String>) -> Result<()> {
+        self.client
+            .add_relays(relays.iter().map(String::as_str))
+            .await?;
+        self.client.connect().await;
+        Ok(())
+    }
+
+    /// Handle on a named document sharing this manager's connection
+    pub fn open_document(&self, name: String) -> Arc<Self> {
+        Arc::new(Self {
+            client: Arc::clone(&self.client),
+            keys: self.keys.clone(),
+            inner: self.inner.open_document(&name),
+        })
+    }
+
+    /// Loads the document from its latest checkpoint and the operations
+    /// published after it, e.g. on app start
+    pub async fn load(&self) -> Result<()> {
+        self.inner.load_checkpoint(None).await?;
+        Ok(())
+    }
+
+    /// Applies remote operations as they arrive. Only returns once the
+    /// client disconnects.
+    pub async fn sync(&self) -> Result<()> {
+        Ok(self.inner.sync().await?)
+    }
+
+    /// Sets a last-writer-wins register, returning the event id
+    pub async fn update(&self, key: String, value: String) -> Result<String> {
+        let id = self.inner.update_lww_register(&key, &value).await?;
+        Ok(id.to_hex())
+    }
+
+    /// Adds `amount` to a grow-only counter, returning the event id
+    pub async fn increment(&self, key: String, amount: u64) -> Result<String> {
+        let id = self.inner.increment_counter(&key, amount).await?;
+        Ok(id.to_hex())
+    }
+
+    /// Adds `value` to a grow-only set, returning the event id
+    pub async fn add(&self, key: String, value: String) -> Result<String> {
+        let id = self.inner.add_to_set(&key, &value).await?;
+        Ok(id.to_hex())
+    }
+
+    pub fn get_register(&self, key: String) -> Option<String> {
+        self.inner.get_register_value(&key)
+    }
+
+    pub fn get_counter(&self, key: String) -> Option<u64> {
+        self.inner
+            .get_counter_value(&key)
+            .and_then(|count| count.parse().ok())
+    }
+
+    pub fn get_set(&self, key: String) -> Option<Vec<String>> {
+        self.inner
+            .get_set_value(&key)
+            .and_then(|set| serde_json::from_str(&set).ok())
+    }
+
+    /// Full state backup as JSON, to persist between app launches
+    pub fn export_state(&self) -> Result<String> {
+        serde_json::to_string(&self.inner.export_state())
+            .map_err(|err| FfiError::Json(err.to_string()))
+    }
+
+    pub fn import_state(&self, json: String) -> Result<()> {
+        let backup = serde_json::from_str(&json).map_err(|err| FfiError::Json(err.to_string()))?;
+        Ok(self.inner.import_state(backup)?)
+    }
+
+    /// Calls `listener` whenever a value changes, locally or from a relay
+    pub fn on_change(&self, listener: Box<dyn ChangeListener>) {
+        self.inner
+            .on_change(move |change| listener.on_change(change.clone()));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nostr::crdt::{CrdtOperation, CrdtType};
+    use std::sync::Mutex;
+
+    struct Recorder(Arc<Mutex<Vec<Change>>>);
+
+    impl ChangeListener for Recorder {
+        fn on_change(&self, change: Change) {
+            self.0.lock().unwrap().push(change);
+        }
+    }
+
+    #[test]
+    fn test_ffi_manager() {
+        let manager = FfiCrdtManager::new(None).unwrap();
+        assert!(manager.public_key().starts_with("npub1"));
+        assert!(matches!(
+            FfiCrdtManager::new(Some("not a key".to_string())),
+            Err(FfiError::InvalidKey(_))
+        ));
+
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        manager.on_change(Box::new(Recorder(Arc::clone(&seen))));
+        manager
+            .inner
+            .apply(&CrdtOperation::GCounter {
+                key: "views".to_string(),
+                increment: 4,
+            })
+            .unwrap();
+        assert_eq!(manager.get_counter("views".to_string()), Some(4));
+        assert_eq!(seen.lock().unwrap()[0].crdt_type, CrdtType::GCounter);
+
+        // State survives an export/import round trip into another manager
+        let copy = FfiCrdtManager::new(None).unwrap();
+        copy.import_state(manager.export_state().unwrap()).unwrap();
+        assert_eq!(copy.get_counter("views".to_string()), Some(4));
+        assert!(matches!(
+            copy.import_state("{".to_string()),
+            Err(FfiError::Json(_))
+        ));
+
+        // Documents have their own state
+        let notes = manager.open_document("notes".to_string());
+        assert_eq!(notes.get_counter("views".to_string()), None);
+    }
+}
@@ -0,0 +1,27 @@
+use std::sync::Arc;
+
+use nostr_sdk::{Event, PublicKey};
+
+use super::{CrdtManager, CrdtOperationRef};
+
+// Whether the author of an incoming operation may make it
+pub(super) type WriteGuard = Arc<dyn Fn(&PublicKey, &CrdtOperationRef<'_>) -> bool + Send + Sync>;
+
+impl CrdtManager {
+    // Drop incoming operations `guard` refuses, e.g. writes to another
+    // member's entries. It is asked with the pubkey that signed the event,
+    // so a writer cannot claim to be someone else. Unlike hooks, a guard
+    // belongs to this document alone; setting another replaces it.
+    pub(super) fn guard_writes<F>(&self, guard: F)
+    where
+        F: Fn(&PublicKey, &CrdtOperationRef<'_>) -> bool + Send + Sync + 'static,
+    {
+        *self.write_guard.lock().unwrap() = Some(Arc::new(guard));
+    }
+
+    // Whether the operation carried by `event` passes the guard, if any
+    pub(super) fn admits(&self, event: &Event, op: &CrdtOperationRef<'_>) -> bool {
+        let guard = self.write_guard.lock().unwrap().clone();
+        guard.is_none_or(|guard| guard(&event.pubkey, op))
+    }
+}
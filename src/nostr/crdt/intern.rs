@@ -0,0 +1,30 @@
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+
+// Pool of the key names of one document. The states and the publish queue
+// share it, so a key used by several CRDT types, or queued for publishing,
+// is stored once. Keys are never removed; no CRDT here deletes a key.
+#[derive(Debug, Clone, Default)]
+pub(super) struct KeyInterner {
+    keys: Arc<Mutex<HashSet<Arc<str>>>>,
+}
+
+impl KeyInterner {
+    // The pooled copy of `key`, added on first use
+    pub(super) fn intern(&self, key: &str) -> Arc<str> {
+        let mut keys = self.keys.lock().unwrap();
+        match keys.get(key) {
+            Some(interned) => Arc::clone(interned),
+            None => {
+                let interned: Arc<str> = Arc::from(key);
+                keys.insert(Arc::clone(&interned));
+                interned
+            }
+        }
+    }
+
+    #[cfg(test)]
+    pub(super) fn len(&self) -> usize {
+        self.keys.lock().unwrap().len()
+    }
+}
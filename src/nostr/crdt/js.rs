@@ -0,0 +1,243 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use js_sys::{Function, Promise};
+use nostr_sdk::{Client, FromBech32, Keys, NostrSigner, SecretKey, ToBech32};
+use serde::Serialize;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen_futures::future_to_promise;
+
+use super::{Change, CrdtManager, CrdtType, Error};
+
+impl From<Error> for JsValue {
+    fn from(err: Error) -> Self {
+        JsError::new(&err.to_string()).into()
+    }
+}
+
+// Whole document as handed to JS: set values are arrays, counters numbers
+#[derive(Debug, Default, Serialize)]
+struct JsState {
+    registers: BTreeMap<String, String>,
+    counters: BTreeMap<String, u64>,
+    sets: BTreeMap<String, Vec<String>>,
+}
+
+// A change callback only ever runs on the browser's single thread
+struct JsCallback(Function);
+
+unsafe impl Send for JsCallback {}
+unsafe impl Sync for JsCallback {}
+
+impl JsCallback {
+    fn call(&self, change: &Change) {
+        let Ok(change) = serde_wasm_bindgen::to_value(change) else {
+            return;
+        };
+        if let Err(err) = self.0.call1(&JsValue::NULL, &change) {
+            tracing::warn!("Change callback threw: {:?}", err);
+        }
+    }
+}
+
+/// `CrdtManager` for JavaScript and TypeScript. Writes return Promises
+/// resolving to the id of the published event; getters return plain JS
+/// values.
+///
+/// ```js
+/// const doc = new CrdtManager(nsec);
+/// await doc.addRelay("wss://relay.damus.io");
+/// await doc.connect();
+/// doc.onChange((change) => render(change.key, change.value));
+/// doc.sync();
+/// await doc.update("title", "Groceries");
+/// ```
+#[wasm_bindgen(js_name = CrdtManager)]
+pub struct JsCrdtManager {
+    client: Arc<Client>,
+    keys: Keys,
+    inner: Arc<CrdtManager>,
+}
+
+#[wasm_bindgen(js_class = CrdtManager)]
+impl JsCrdtManager {
+    /// Manager signing with `secretKey` (nsec or hex), or with fresh keys
+    #[wasm_bindgen(constructor)]
+    pub fn new(secret_key: Option<String>) -> Result<JsCrdtManager, JsError> {
+        let keys = match secret_key {
+            Some(secret_key) => {
+                let secret_key = SecretKey::from_bech32(&secret_key)
+                    .or_else(|_| SecretKey::from_hex(&secret_key))
+                    .map_err(|err| JsError::new(&err.to_string()))?;
+                Keys::new(secret_key)
+            }
+            None => Keys::generate(),
+        };
+        let client = Arc::new(Client::new(&keys));
+        let inner = Arc::new(CrdtManager::new(
+            Arc::clone(&client),
+            NostrSigner::Keys(keys.clone()),
+        ));
+        Ok(Self {
+            client,
+            keys,
+            inner,
+        })
+    }
+
+    /// npub of the keys signing the operations
+    #[wasm_bindgen(js_name = publicKey)]
+    pub fn public_key(&self) -> String {
+        self.keys.public_key().to_bech32().unwrap_or_default()
+    }
+
+    #[wasm_bindgen(js_name = addRelay)]
+    pub fn add_relay(&self, url: String) -> Promise {
+        let client = Arc::clone(&self.client);
+        future_to_promise(async move {
+            let added = client
+                .add_relay(url.as_str())
+                .await
+                .map_err(|err| JsError::new(&err.to_string()))?;
+            Ok(JsValue::from_bool(added))
+        })
+    }
+
+    pub fn connect(&self) -> Promise {
+        let client = Arc::clone(&self.client);
+        future_to_promise(async move {
+            client.connect().await;
+            Ok(JsValue::UNDEFINED)
+        })
+    }
+
+    /// Applies remote operations as they arrive. The Promise only settles
+    /// once the client disconnects.
+    pub fn sync(&self) -> Promise {
+        let inner = Arc::clone(&self.inner);
+        future_to_promise(async move {
+            inner.sync().await?;
+            Ok(JsValue::UNDEFINED)
+        })
+    }
+
+    /// Sets a last-writer-wins register
+    pub fn update(&self, key: String, value: String) -> Promise {
+        let inner = Arc::clone(&self.inner);
+        future_to_promise(async move {
+            let id = inner.update_lww_register(&key, &value).await?;
+            Ok(JsValue::from_str(&id.to_hex()))
+        })
+    }
+
+    /// Adds `amount` to a grow-only counter
+    pub fn increment(&self, key: String, amount: u32) -> Promise {
+        let inner = Arc::clone(&self.inner);
+        future_to_promise(async move {
+            let id = inner.increment_counter(&key, amount as u64).await?;
+            Ok(JsValue::from_str(&id.to_hex()))
+        })
+    }
+
+    /// Adds `value` to a grow-only set
+    pub fn add(&self, key: String, value: String) -> Promise {
+        let inner = Arc::clone(&self.inner);
+        future_to_promise(async move {
+            let id = inner.add_to_set(&key, &value).await?;
+            Ok(JsValue::from_str(&id.to_hex()))
+        })
+    }
+
+    /// Register value, `undefined` if unset
+    #[wasm_bindgen(js_name = getRegister)]
+    pub fn get_register(&self, key: &str) -> Option<String> {
+        self.inner.get_register_value(key)
+    }
+
+    /// Counter total, `undefined` if never incremented
+    #[wasm_bindgen(js_name = getCounter)]
+    pub fn get_counter(&self, key: &str) -> Option<f64> {
+        self.inner
+            .get_counter_value(key)
+            .and_then(|count| count.parse::<u64>().ok())
+            .map(|count| count as f64)
+    }
+
+    /// Set members as an array, `undefined` if empty
+    #[wasm_bindgen(js_name = getSet)]
+    pub fn get_set(&self, key: &str) -> Option<Vec<String>> {
+        self.inner
+            .get_set_value(key)
+            .and_then(|set| serde_json::from_str(&set).ok())
+    }
+
+    /// The whole document as `{ registers, counters, sets }`
+    #[wasm_bindgen(js_name = toJSON)]
+    pub fn to_json(&self) -> Result<JsValue, JsError> {
+        let mut state = JsState::default();
+        for (key, crdt_type, value) in self.inner.iter_state() {
+            match crdt_type {
+                CrdtType::LWWRegister => {
+                    state.registers.insert(key, value);
+                }
+                CrdtType::GCounter => {
+                    state
+                        .counters
+                        .insert(key, value.parse().unwrap_or_default());
+                }
+                CrdtType::GSet => {
+                    state
+                        .sets
+                        .insert(key, serde_json::from_str(&value).unwrap_or_default());
+                }
+            }
+        }
+        Ok(serde_wasm_bindgen::to_value(&state)?)
+    }
+
+    /// Calls `callback` with `{ document, key, crdt_type, value }` whenever
+    /// a value changes, whether written here or received from a relay
+    #[wasm_bindgen(js_name = onChange)]
+    pub fn on_change(&self, callback: Function) {
+        let callback = JsCallback(callback);
+        self.inner.on_change(move |change| callback.call(change));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nostr::crdt::{CrdtOperation, GSetAction};
+    use wasm_bindgen_test::*;
+
+    #[wasm_bindgen_test]
+    fn test_js_getters() {
+        let manager = JsCrdtManager::new(None).unwrap();
+        assert!(manager.public_key().starts_with("npub1"));
+        assert_eq!(manager.get_register("title"), None);
+
+        manager
+            .inner
+            .apply(&CrdtOperation::GCounter {
+                key: "views".to_string(),
+                increment: 3,
+            })
+            .unwrap();
+        for value in ["a", "b"] {
+            manager
+                .inner
+                .apply(&CrdtOperation::GSet {
+                    key: "tags".to_string(),
+                    value: value.to_string(),
+                    action: GSetAction::Add,
+                })
+                .unwrap();
+        }
+        assert_eq!(manager.get_counter("views"), Some(3.0));
+        assert_eq!(
+            manager.get_set("tags"),
+            Some(vec!["a".to_string(), "b".to_string()])
+        );
+        assert!(JsCrdtManager::new(Some("not a key".to_string())).is_err());
+    }
+}
@@ -0,0 +1,399 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+use nostr_sdk::EventId;
+use serde::{Deserialize, Serialize};
+
+use super::or_set::OrSet;
+use super::{new_id, CrdtManager, Error, Result};
+
+// Column ids, an OR-Set: `column/<id>/added` and `/removed`. Registers per
+// column: `column/<id>/title` and `column/<id>/rank`.
+const COLUMN_PREFIX: &str = "column/";
+const COLUMN_TITLE: &str = "/title";
+const COLUMN_RANK: &str = "/rank";
+// Registers per card: `card/<id>/place` and `card/<id>/field/<name>`
+const CARD_PREFIX: &str = "card/";
+const PLACE: &str = "place";
+const FIELD_PREFIX: &str = "field/";
+// Field set by `add_card`
+const TITLE_FIELD: &str = "title";
+
+// A column's entry in the map of columns
+#[derive(Debug, Clone)]
+struct StoredColumn {
+    title: String,
+    rank: String,
+}
+
+// Where a card sits: the column and its rank among that column's cards
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Placement {
+    column: String,
+    rank: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KanbanCard {
+    pub id: String,
+    pub column: String,
+    // Field name -> value, `title` among them
+    pub fields: BTreeMap<String, String>,
+}
+
+impl KanbanCard {
+    pub fn title(&self) -> Option<&str> {
+        self.fields.get(TITLE_FIELD).map(String::as_str)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KanbanColumn {
+    pub id: String,
+    pub title: String,
+    // Cards in board order
+    pub cards: Vec<KanbanCard>,
+}
+
+// Kanban board kept in a named document. Columns form an observed-remove
+// map: their ids are an OR-Set and each holds a title and rank register, so
+// a column renamed on one device while removed on another stays. The cards
+// of a column are a sequence ordered by fractional ranks, with ties broken
+// by card id. A card's column and rank are one register, so when two
+// people move the same card at once, every device ends up with the card in
+// the one place of the later move, never in both. Each card field is its
+// own register, so edits to different fields all survive. Cards of a
+// removed column leave the board.
+pub struct KanbanDoc {
+    document: Arc<CrdtManager>,
+}
+
+impl KanbanDoc {
+    // Open the board `name` as the document `kanban/<name>` of `manager`
+    pub fn open(manager: &CrdtManager, name: &str) -> Self {
+        Self {
+            document: manager.open_document(&format!("kanban/{name}")),
+        }
+    }
+
+    // The board's document, to `sync()` for cards moved on other devices
+    pub fn document(&self) -> &Arc<CrdtManager> {
+        &self.document
+    }
+
+    // Add a column after the others, returning its id
+    pub async fn add_column(&self, title: &str) -> Result<String> {
+        let last = self.columns().pop().map(|(_, column)| column.rank);
+        let id = new_id();
+        let rank = rank_between(last.as_deref(), None);
+        self.write_column(&id, COLUMN_RANK, &rank).await?;
+        self.write_column(&id, COLUMN_TITLE, title).await?;
+        self.column_ids().add(&id).await?;
+        Ok(id)
+    }
+
+    // Rename a column. Like any update of a map entry, this keeps the
+    // column on the board when another device removes it concurrently.
+    pub async fn rename_column(&self, id: &str, title: &str) -> Result<EventId> {
+        self.column(id)?;
+        self.write_column(id, COLUMN_TITLE, title).await?;
+        self.column_ids().add(id).await
+    }
+
+    pub async fn remove_column(&self, id: &str) -> Result<EventId> {
+        self.column_ids()
+            .remove(id)
+            .await?
+            .ok_or_else(|| Error::NotFound(format!("kanban column {id}")))
+    }
+
+    // Add a card at the bottom of `column`, returning its id
+    pub async fn add_card(&self, column: &str, title: &str) -> Result<String> {
+        self.column(column)?;
+        let id = new_id();
+        self.document
+            .update_lww_register(&field_key(&id, TITLE_FIELD), title)
+            .await?;
+        self.move_card(&id, column, usize::MAX).await?;
+        Ok(id)
+    }
+
+    // Set one field of a card, e.g. its title or description
+    pub async fn set_card_field(&self, card: &str, field: &str, value: &str) -> Result<EventId> {
+        self.placement(card)
+            .ok_or_else(|| Error::NotFound(format!("kanban card {card}")))?;
+        self.document
+            .update_lww_register(&field_key(card, field), value)
+            .await
+    }
+
+    // Put a card at `index` of `column`, at the bottom if the column has
+    // fewer cards
+    pub async fn move_card(&self, card: &str, column: &str, index: usize) -> Result<EventId> {
+        self.column(column)?;
+        let ranks: Vec<String> = self
+            .placements()
+            .into_iter()
+            .filter(|(id, placement)| id != card && placement.column == column)
+            .map(|(_, placement)| placement.rank)
+            .collect();
+        let index = index.min(ranks.len());
+        let low = index.checked_sub(1).map(|before| ranks[before].as_str());
+        let high = ranks.get(index).map(String::as_str);
+        let placement = Placement {
+            column: column.to_string(),
+            rank: rank_between(low, high),
+        };
+        let value = serde_json::to_string(&placement).map_err(|_| Error::SerializationError)?;
+        self.document
+            .update_lww_register(&place_key(card), &value)
+            .await
+    }
+
+    pub fn card(&self, id: &str) -> Option<KanbanCard> {
+        let placement = self.placement(id)?;
+        let fields = self.fields().remove(id).unwrap_or_default();
+        Some(KanbanCard {
+            id: id.to_string(),
+            column: placement.column,
+            fields,
+        })
+    }
+
+    // The columns left to right, each with its cards top to bottom
+    pub fn board(&self) -> Vec<KanbanColumn> {
+        let mut fields = self.fields();
+        let mut cards: HashMap<String, Vec<KanbanCard>> = HashMap::new();
+        for (id, placement) in self.placements() {
+            let fields = fields.remove(&id).unwrap_or_default();
+            cards
+                .entry(placement.column.clone())
+                .or_default()
+                .push(KanbanCard {
+                    id,
+                    column: placement.column,
+                    fields,
+                });
+        }
+        self.columns()
+            .into_iter()
+            .map(|(id, column)| KanbanColumn {
+                cards: cards.remove(&id).unwrap_or_default(),
+                id,
+                title: column.title,
+            })
+            .collect()
+    }
+
+    fn column_ids(&self) -> OrSet<'_> {
+        OrSet::new(&self.document, COLUMN_PREFIX)
+    }
+
+    fn column(&self, id: &str) -> Result<StoredColumn> {
+        let not_found = || Error::NotFound(format!("kanban column {id}"));
+        if !self.column_ids().contains(id) {
+            return Err(not_found());
+        }
+        let read = |field| {
+            self.document
+                .get_register_value(&format!("{COLUMN_PREFIX}{id}{field}"))
+                .ok_or_else(not_found)
+        };
+        Ok(StoredColumn {
+            title: read(COLUMN_TITLE)?,
+            rank: read(COLUMN_RANK)?,
+        })
+    }
+
+    // Columns on the board, in board order
+    fn columns(&self) -> Vec<(String, StoredColumn)> {
+        let mut columns: Vec<(String, StoredColumn)> = self
+            .column_ids()
+            .elements()
+            .into_iter()
+            .filter_map(|id| {
+                let column = self.column(&id).ok()?;
+                Some((id, column))
+            })
+            .collect();
+        columns.sort_by(|(a_id, a), (b_id, b)| (&a.rank, a_id).cmp(&(&b.rank, b_id)));
+        columns
+    }
+
+    fn placement(&self, card: &str) -> Option<Placement> {
+        let value = self.document.get_register_value(&place_key(card))?;
+        serde_json::from_str(&value).ok()
+    }
+
+    // Every placed card in board order, ties between equal ranks placed at
+    // the same time on two devices broken by card id
+    fn placements(&self) -> Vec<(String, Placement)> {
+        let mut placements: Vec<(String, Placement)> = self
+            .document
+            .register_keys()
+            .into_iter()
+            .filter_map(|key| {
+                let (id, rest) = key.strip_prefix(CARD_PREFIX)?.split_once('/')?;
+                let placement = (rest == PLACE).then(|| self.placement(id))??;
+                Some((id.to_string(), placement))
+            })
+            .collect();
+        placements.sort_by(|(a_id, a), (b_id, b)| (&a.rank, a_id).cmp(&(&b.rank, b_id)));
+        placements
+    }
+
+    // Field values of every card, by card id
+    fn fields(&self) -> HashMap<String, BTreeMap<String, String>> {
+        let mut fields: HashMap<String, BTreeMap<String, String>> = HashMap::new();
+        for key in self.document.register_keys() {
+            let Some((id, field)) = key
+                .strip_prefix(CARD_PREFIX)
+                .and_then(|rest| rest.split_once('/'))
+                .and_then(|(id, rest)| Some((id, rest.strip_prefix(FIELD_PREFIX)?)))
+            else {
+                continue;
+            };
+            if let Some(value) = self.document.get_register_value(&key) {
+                fields
+                    .entry(id.to_string())
+                    .or_default()
+                    .insert(field.to_string(), value);
+            }
+        }
+        fields
+    }
+
+    async fn write_column(&self, id: &str, field: &str, value: &str) -> Result<EventId> {
+        self.document
+            .update_lww_register(&format!("{COLUMN_PREFIX}{id}{field}"), value)
+            .await
+    }
+}
+
+fn place_key(card: &str) -> String {
+    format!("{CARD_PREFIX}{card}/{PLACE}")
+}
+
+fn field_key(card: &str, field: &str) -> String {
+    format!("{CARD_PREFIX}{card}/{FIELD_PREFIX}{field}")
+}
+
+// A rank sorting strictly between `low` and `high`, either end open. Ranks
+// are strings of `a` to `z` that never end in `a`, so there is always room
+// for another between two of them. Out of order bounds, as a malformed
+// remote rank could give, still yield a rank above `low`.
+fn rank_between(low: Option<&str>, high: Option<&str>) -> String {
+    let low = low.unwrap_or_default().as_bytes();
+    let mut high = high.map(str::as_bytes);
+    let digit = |rank: &[u8], i: usize| rank.get(i).map_or(0, |d| d.saturating_sub(b'a').min(25));
+
+    let mut rank = String::new();
+    for i in 0.. {
+        // Past the end of `high`, it no longer bounds what follows
+        if high.is_some_and(|high| i >= high.len()) {
+            high = None;
+        }
+        let l = digit(low, i);
+        let h = high.map_or(26, |high| digit(high, i));
+        if h > l + 1 {
+            rank.push(char::from(b'a' + (l + h) / 2));
+            break;
+        }
+        rank.push(char::from(b'a' + l));
+        // Below `high` from here on, only `low` is left to stay above
+        if h == l + 1 {
+            high = None;
+        }
+    }
+    rank
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testhelper::{connect, MockRelay};
+    use nostr_sdk::Keys;
+
+    fn titles(column: &KanbanColumn) -> Vec<&str> {
+        column.cards.iter().filter_map(KanbanCard::title).collect()
+    }
+
+    #[test]
+    fn test_rank_between() {
+        let mut ranks = vec![rank_between(None, None)];
+        for _ in 0..50 {
+            // Always squeezing in at the front and right after it
+            let first = rank_between(None, Some(&ranks[0]));
+            let second = rank_between(Some(&first), Some(&ranks[0]));
+            ranks.insert(0, second);
+            ranks.insert(0, first);
+        }
+        let mut sorted = ranks.clone();
+        sorted.sort();
+        sorted.dedup();
+        assert_eq!(sorted, ranks);
+        assert!(ranks.iter().all(|rank| !rank.ends_with('a')));
+        assert!(rank_between(Some("n"), Some("b")).as_str() > "n");
+    }
+
+    #[tokio::test]
+    async fn test_kanban_doc() {
+        let relay = MockRelay::run().await.unwrap();
+        let keys = Keys::generate();
+        let laptop = connect(&relay, &keys).await;
+        let board = KanbanDoc::open(&laptop, "project");
+
+        let todo = board.add_column("To do").await.unwrap();
+        let doing = board.add_column("Doing").await.unwrap();
+        let done = board.add_column("Done").await.unwrap();
+        let mut cards = Vec::new();
+        for title in ["write", "review", "ship"] {
+            cards.push(board.add_card(&todo, title).await.unwrap());
+        }
+        board.move_card(&cards[2], &todo, 0).await.unwrap();
+        board
+            .set_card_field(&cards[0], "description", "draft the docs")
+            .await
+            .unwrap();
+        board.remove_column(&done).await.unwrap();
+        assert!(matches!(
+            board.move_card(&cards[0], &done, 0).await,
+            Err(Error::NotFound(_))
+        ));
+
+        let columns = board.board();
+        assert_eq!(columns.len(), 2);
+        assert_eq!(titles(&columns[0]), ["ship", "write", "review"]);
+        assert_eq!(
+            board.card(&cards[0]).unwrap().fields["description"],
+            "draft the docs"
+        );
+
+        // Both devices move the same card before hearing of the other's move
+        let phone = connect(&relay, &keys).await;
+        let mirror = KanbanDoc::open(&phone, "project");
+        mirror.document().replay_history(None, None).await.unwrap();
+        assert_eq!(mirror.board(), columns);
+        mirror.move_card(&cards[1], &doing, 0).await.unwrap();
+        board.move_card(&cards[1], &todo, 0).await.unwrap();
+
+        mirror.document().replay_history(None, None).await.unwrap();
+        board.document().replay_history(None, None).await.unwrap();
+        let columns = board.board();
+        assert_eq!(mirror.board(), columns);
+        let places = columns
+            .iter()
+            .flat_map(|column| &column.cards)
+            .filter(|card| card.id == cards[1])
+            .count();
+        assert_eq!(places, 1);
+
+        // A column renamed on one device while removed on the other stays
+        board.remove_column(&doing).await.unwrap();
+        mirror.rename_column(&doing, "In progress").await.unwrap();
+        board.document().replay_history(None, None).await.unwrap();
+        mirror.document().replay_history(None, None).await.unwrap();
+        assert_eq!(mirror.board(), board.board());
+        assert_eq!(board.board()[1].title, "In progress");
+    }
+}
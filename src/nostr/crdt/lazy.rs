@@ -0,0 +1,191 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+use nostr_sdk::hashes::sha256::Hash as Sha256Hash;
+use nostr_sdk::hashes::Hash;
+use nostr_sdk::{Event, EventId, Timestamp};
+
+use super::{sort_for_replay, CrdtManager, CrdtType};
+
+// Deferred events by key hash, each key's in replay order and once only
+pub(super) type ColdEvents = HashMap<String, BTreeMap<(Timestamp, EventId), Event>>;
+
+// Tag carrying `key_hash` on every operation event
+pub(super) const KEY_TAG: &str = "key";
+
+// Hash of the CRDT type and key an operation writes, so encrypted history
+// can be indexed by key without decrypting it. Relays learn which
+// operations touch the same key, and common key names can be guessed.
+pub(super) fn key_hash(crdt_type: CrdtType, key: &str) -> String {
+    Sha256Hash::hash(format!("{}:{}", crdt_type.tag_name(), key).as_bytes()).to_string()
+}
+
+// The `key` tag of an operation event, if it has one
+fn key_tag(event: &Event) -> Option<&str> {
+    event.tags.iter().find_map(|tag| match tag.as_vec() {
+        [kind, hash] if kind == KEY_TAG => Some(hash.as_str()),
+        _ => None,
+    })
+}
+
+impl CrdtManager {
+    // Only index backfilled operations by key, and decrypt those of a key
+    // when it is first read or written. Enumerating the document, e.g.
+    // `iter_state` or `export_state`, decrypts everything. Change and
+    // conflict hooks fire for an operation once it is decrypted. Needs the
+    // keys at hand: with a remote signer, events are still decrypted as
+    // they arrive.
+    pub fn with_lazy_decryption(mut self) -> Self {
+        self.cold = Some(Arc::default());
+        self
+    }
+
+    // Operations fetched but not decrypted yet
+    pub fn pending_decryption(&self) -> usize {
+        self.cold.as_ref().map_or(0, |cold| {
+            cold.lock().unwrap().values().map(BTreeMap::len).sum()
+        })
+    }
+
+    // Set aside the encrypted events that can be decrypted on first read,
+    // returning those to apply now
+    pub(super) fn defer_events(&self, events: Vec<Event>) -> Vec<Event> {
+        let Some(cold) = &self.cold else {
+            return events;
+        };
+        if !self.decoder().decrypts_locally() {
+            return events;
+        }
+
+        let mut cold = cold.lock().unwrap();
+        let mut deferred = false;
+        let mut now = Vec::new();
+        for event in events {
+            match key_tag(&event) {
+                Some(hash) if event.content.contains("?iv=") => {
+                    cold.entry(hash.to_string())
+                        .or_default()
+                        .insert((event.created_at, event.id), event);
+                    deferred = true;
+                }
+                _ => now.push(event),
+            }
+        }
+        if deferred {
+            self.version.fetch_add(1, Ordering::Relaxed);
+        }
+        now
+    }
+
+    // Decrypt and apply the deferred operations on `key`
+    pub(super) fn warm(&self, crdt_type: CrdtType, key: &str) {
+        let Some(cold) = &self.cold else {
+            return;
+        };
+        let events = cold.lock().unwrap().remove(&key_hash(crdt_type, key));
+        if let Some(events) = events {
+            self.apply_deferred(events.into_values().collect());
+        }
+    }
+
+    // Decrypt and apply every deferred operation
+    pub(super) fn warm_all(&self) {
+        let Some(cold) = &self.cold else {
+            return;
+        };
+        let events: Vec<Event> = cold
+            .lock()
+            .unwrap()
+            .drain()
+            .flat_map(|(_, events)| events.into_values())
+            .collect();
+        self.apply_deferred(events);
+    }
+
+    // `process_events` for deferred events, which decrypt without awaiting
+    fn apply_deferred(&self, mut events: Vec<Event>) {
+        sort_for_replay(&mut events);
+        let decoder = self.decoder();
+        for event in events {
+            // Also applied through a live subscription, or fetched twice
+            if self.processed.lock().unwrap().contains(&event.id) {
+                continue;
+            }
+            let applied = decoder.content_local(&event).and_then(|content| {
+                let op = decoder.parse(&content)?;
+                self.check_incoming(&event, &op)?;
+                let conflict = self.detect_conflict(&op, &event);
+                self.apply_borrowed(&op)?;
+                Ok(conflict)
+            });
+            let conflict = match applied {
+                Ok(conflict) => conflict,
+                Err(err) => {
+                    tracing::warn!("Failed to replay CRDT event {}: {}", event.id, err);
+                    continue;
+                }
+            };
+            self.mark_processed_detached(event.id);
+            if let Some(conflict) = conflict {
+                self.report_conflict(&conflict);
+            }
+            self.metrics.record_sync();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nostr::crdt::CrdtOperation;
+    use nostr_sdk::{Keys, NostrSigner};
+
+    fn manager(keys: &Keys) -> CrdtManager {
+        let client = Arc::new(nostr_sdk::Client::new(keys));
+        CrdtManager::new(client, NostrSigner::Keys(keys.clone()))
+    }
+
+    #[tokio::test]
+    async fn test_lazy_decryption() {
+        let keys = Keys::generate();
+        let writer = manager(&keys);
+        let mut events = Vec::new();
+        for i in 0..5u64 {
+            let ops = [
+                CrdtOperation::GCounter {
+                    key: "views".to_string(),
+                    increment: i,
+                },
+                CrdtOperation::LWWRegister {
+                    key: "title".to_string(),
+                    value: format!("title-{i}"),
+                    timestamp: i,
+                },
+            ];
+            for op in ops.iter() {
+                events.push(writer.sign_operation(op).await.unwrap());
+            }
+        }
+
+        let eager = manager(&keys);
+        assert_eq!(eager.process_events(events.clone()).await, 0);
+
+        let lazy = manager(&keys).with_lazy_decryption();
+        assert_eq!(lazy.process_events(events.clone()).await, 0);
+        assert_eq!(lazy.pending_decryption(), 10);
+        assert_eq!(lazy.metrics().ops_applied, 0);
+
+        // Reading a key decrypts that key only
+        assert_eq!(lazy.get_counter_value("views"), Some("10".to_string()));
+        assert_eq!(lazy.pending_decryption(), 5);
+        assert_eq!(lazy.metrics().ops_applied, 5);
+
+        // Nothing is applied twice when the history is fetched again
+        assert_eq!(lazy.process_events(events).await, 0);
+        assert_eq!(lazy.pending_decryption(), 5);
+        assert_eq!(lazy.get_counter_value("views"), Some("10".to_string()));
+        assert_eq!(lazy.export_state(), eager.export_state());
+        assert_eq!(lazy.pending_decryption(), 0);
+    }
+}
@@ -0,0 +1,117 @@
+use std::collections::HashSet;
+
+use nostr_sdk::EventId;
+
+use super::{new_id, CrdtManager, Result};
+
+// Grow-only sets per element: `<prefix><element>/added` and `/removed`
+const ADDED: &str = "/added";
+const REMOVED: &str = "/removed";
+
+// Observed-remove set over the grow-only sets of a document. Each add puts
+// a fresh tag in the element's `added` set; a remove copies the tags it has
+// seen into `removed`. An element is in the set while one of its tags is
+// not removed, so an add concurrent with a remove wins and a removed
+// element can be added again.
+pub(super) struct OrSet<'a> {
+    document: &'a CrdtManager,
+    prefix: &'a str,
+}
+
+impl<'a> OrSet<'a> {
+    pub(super) fn new(document: &'a CrdtManager, prefix: &'a str) -> Self {
+        Self { document, prefix }
+    }
+
+    pub(super) async fn add(&self, element: &str) -> Result<EventId> {
+        self.document
+            .add_to_set(&self.key(element, ADDED), &new_id())
+            .await
+    }
+
+    // Remove `element` as far as adds seen here go; `None` if it was not in
+    // the set
+    pub(super) async fn remove(&self, element: &str) -> Result<Option<EventId>> {
+        let mut last = None;
+        for tag in self.live_tags(element) {
+            let id = self
+                .document
+                .add_to_set(&self.key(element, REMOVED), &tag)
+                .await?;
+            last = Some(id);
+        }
+        Ok(last)
+    }
+
+    pub(super) fn contains(&self, element: &str) -> bool {
+        !self.live_tags(element).is_empty()
+    }
+
+    // Elements in the set, sorted
+    pub(super) fn elements(&self) -> Vec<String> {
+        let mut elements: Vec<String> = self
+            .document
+            .set_keys()
+            .into_iter()
+            .filter_map(|key| {
+                let element = key.strip_prefix(self.prefix)?.strip_suffix(ADDED)?;
+                Some(element.to_string())
+            })
+            .filter(|element| self.contains(element))
+            .collect();
+        elements.sort();
+        elements
+    }
+
+    fn live_tags(&self, element: &str) -> Vec<String> {
+        let removed: HashSet<String> = self.tags(element, REMOVED).into_iter().collect();
+        self.tags(element, ADDED)
+            .into_iter()
+            .filter(|tag| !removed.contains(tag))
+            .collect()
+    }
+
+    fn tags(&self, element: &str, suffix: &str) -> Vec<String> {
+        self.document
+            .get_set_value(&self.key(element, suffix))
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default()
+    }
+
+    fn key(&self, element: &str, suffix: &str) -> String {
+        format!("{}{element}{suffix}", self.prefix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testhelper::{connect, MockRelay};
+    use nostr_sdk::Keys;
+
+    #[tokio::test]
+    async fn test_or_set() {
+        let relay = MockRelay::run().await.unwrap();
+        let keys = Keys::generate();
+        let laptop = connect(&relay, &keys).await;
+        let phone = connect(&relay, &keys).await;
+        let set = OrSet::new(&laptop, "tags/");
+        set.add("a/b").await.unwrap();
+        set.add("c").await.unwrap();
+        assert!(set.remove("c").await.unwrap().is_some());
+        assert_eq!(set.remove("c").await.unwrap(), None);
+        assert_eq!(set.elements(), ["a/b"]);
+
+        // The phone removes what it has seen while the laptop adds it again
+        phone.replay_history(None, None).await.unwrap();
+        let mirror = OrSet::new(&phone, "tags/");
+        assert_eq!(mirror.elements(), ["a/b"]);
+        mirror.remove("a/b").await.unwrap();
+        set.add("a/b").await.unwrap();
+
+        laptop.replay_history(None, None).await.unwrap();
+        phone.replay_history(None, None).await.unwrap();
+        assert_eq!(set.elements(), ["a/b"]);
+        assert_eq!(mirror.elements(), ["a/b"]);
+    }
+}
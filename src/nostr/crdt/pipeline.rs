@@ -0,0 +1,295 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use futures::StreamExt;
+use nostr_sdk::nips::nip04;
+use nostr_sdk::{Event, Keys, NostrSigner, PublicKey};
+
+use super::{
+    sort_for_replay, CrdtManager, CrdtOperation, CrdtOperationRef, Error, FollowAccess, Result,
+    SyncMetrics,
+};
+use crate::nostr::fetch::DecryptCache;
+use crate::nostr::runtime;
+use crate::nostr::utils::is_nip04_payload;
+
+// Events decoded ahead of the one being applied by `process_events`
+const DECODE_AHEAD: usize = 32;
+
+// What decoding an operation event takes, owned so that events can be
+// decoded in tasks of their own
+#[derive(Clone)]
+pub(super) struct Decoder {
+    signer: NostrSigner,
+    following: Option<(PublicKey, FollowAccess)>,
+    read_key: Option<Keys>,
+    cache: DecryptCache,
+    metrics: Arc<SyncMetrics>,
+}
+
+impl Decoder {
+    // Decrypt if needed and parse the operation carried by an event
+    pub(super) async fn decode(&self, event: &Event) -> Result<CrdtOperation> {
+        let content = self.content(event).await?;
+        Ok(self.parse(&content)?.into_owned())
+    }
+
+    // The operation JSON of an event, decrypted if needed
+    pub(super) async fn content<'e>(&self, event: &'e Event) -> Result<Cow<'e, str>> {
+        if event.content.contains("?iv=") {
+            // Content that needs decryption, unless seen recently
+            match self
+                .cache
+                .get_or_decrypt(event.id, self.decrypt(event))
+                .await
+            {
+                Ok(decrypted) => Ok(Cow::Owned(decrypted)),
+                Err(_) => {
+                    self.metrics.record_decryption_failure();
+                    Err(Error::SerializationError)
+                }
+            }
+        } else {
+            Ok(Cow::Borrowed(event.content.as_str()))
+        }
+    }
+
+    // `content` of encrypted events, without awaiting a signer. Only for
+    // decoders that `decrypt_locally`.
+    pub(super) fn content_local(&self, event: &Event) -> Result<String> {
+        if let Some(content) = self.cache.get(&event.id) {
+            return Ok(content);
+        }
+        match self.decrypt_local(event) {
+            Ok(content) => {
+                self.cache.insert(event.id, content.clone());
+                Ok(content)
+            }
+            Err(err) => {
+                self.metrics.record_decryption_failure();
+                Err(err)
+            }
+        }
+    }
+
+    // Parse an operation, borrowing its strings from `content`
+    pub(super) fn parse<'c>(&self, content: &'c str) -> Result<CrdtOperationRef<'c>> {
+        serde_json::from_str(content).map_err(|_| {
+            self.metrics.record_rejected();
+            Error::SerializationError
+        })
+    }
+
+    // Whether events are decrypted with a key at hand rather than by a
+    // remote signer
+    pub(super) fn decrypts_locally(&self) -> bool {
+        match &self.following {
+            Some(_) => self.read_key.is_some(),
+            None => matches!(self.signer, NostrSigner::Keys(_)),
+        }
+    }
+
+    fn decrypt_local(&self, event: &Event) -> Result<String> {
+        if !is_nip04_payload(&event.content) {
+            return Err(Error::SerializationError);
+        }
+        if let Some(content) = self.decrypt_read_key(event) {
+            return Ok(content);
+        }
+        // Our own operations, encrypted to ourselves
+        match (&self.following, &self.signer) {
+            (None, NostrSigner::Keys(keys)) => {
+                let secret = keys.secret_key().map_err(|_| Error::KeysNotAvailable)?;
+                nip04::decrypt(secret, &event.pubkey, &event.content)
+                    .map_err(|_| Error::SerializationError)
+            }
+            _ => Err(Error::KeysNotAvailable),
+        }
+    }
+
+    pub(super) async fn decrypt(&self, event: &Event) -> Result<String> {
+        // Relay data; malformed payloads would panic inside nip04
+        if !is_nip04_payload(&event.content) {
+            return Err(Error::SerializationError);
+        }
+        if let Some(content) = self.decrypt_read_key(event) {
+            return Ok(content);
+        }
+        match &self.following {
+            Some(_) => Err(Error::SerializationError),
+            None => Ok(self
+                .signer
+                .nip04_decrypt(event.pubkey, &event.content)
+                .await?),
+        }
+    }
+
+    // Content encrypted to the document's read key, if there is one and it
+    // fits
+    fn decrypt_read_key(&self, event: &Event) -> Option<String> {
+        let read_key = self.read_key.as_ref()?;
+        let secret = read_key.secret_key().ok()?;
+        nip04::decrypt(secret, &read_key.public_key(), &event.content).ok()
+    }
+}
+
+impl CrdtManager {
+    // Encrypt an operation or checkpoint for the document's readers: to the
+    // read key if there is one, else to ourselves
+    pub(super) async fn encrypt_content(&self, content: &str) -> Result<String> {
+        match &self.read_key {
+            Some(read_key) => {
+                let secret = read_key.secret_key().map_err(|_| Error::KeysNotAvailable)?;
+                nip04::encrypt(secret, &read_key.public_key(), content)
+                    .map_err(|_| Error::SerializationError)
+            }
+            None => {
+                let my_pubkey = self.signer.public_key().await?;
+                Ok(self.signer.nip04_encrypt(my_pubkey, content).await?)
+            }
+        }
+    }
+
+    pub(super) fn decoder(&self) -> Decoder {
+        Decoder {
+            signer: self.signer.clone(),
+            following: self.following.clone(),
+            read_key: self.read_key.clone(),
+            cache: self.decrypt_cache.clone(),
+            metrics: Arc::clone(&self.metrics),
+        }
+    }
+
+    // Apply a batch of events, e.g. a relay backfill, in replay order so
+    // every device folds them in the same sequence. Decryption and parsing
+    // run ahead concurrently, in tasks spread over the runtime's threads;
+    // operations are still applied one at a time, in order. With lazy
+    // decryption, encrypted operations are only indexed by key. Returns how
+    // many failed.
+    pub async fn process_events(&self, mut events: Vec<Event>) -> usize {
+        sort_for_replay(&mut events);
+        let mut events: Vec<Event> = events
+            .into_iter()
+            .filter(|event| self.accepts_event(event))
+            .collect();
+        if let Err(err) = self.ensure_processed_loaded().await {
+            tracing::warn!("Failed to replay {} CRDT events: {}", events.len(), err);
+            return events.len();
+        }
+        // Nothing to decode for events already applied or repeated
+        let mut seen = HashSet::new();
+        {
+            let processed = self.processed.lock().unwrap();
+            events.retain(|event| !processed.contains(&event.id) && seen.insert(event.id));
+        }
+        let events = self.defer_events(events);
+
+        let decoder = self.decoder();
+        let mut decoded = futures::stream::iter(events)
+            .map(|event| {
+                let decoder = decoder.clone();
+                runtime::run(async move {
+                    // Only decrypted contents need carrying over; the
+                    // operation is parsed in place when it is applied
+                    let decrypted = decoder.content(&event).await.map(|content| match content {
+                        Cow::Owned(decrypted) => Some(decrypted),
+                        Cow::Borrowed(_) => None,
+                    });
+                    (event, decrypted)
+                })
+            })
+            .buffered(DECODE_AHEAD);
+
+        let mut failed = 0;
+        while let Some((event, decrypted)) = decoded.next().await {
+            // A live subscription may have applied it in the meantime
+            if self.processed.lock().unwrap().contains(&event.id) {
+                continue;
+            }
+            let result = match decrypted {
+                Ok(decrypted) => {
+                    let content = decrypted.as_deref().unwrap_or(&event.content);
+                    match decoder.parse(content) {
+                        Ok(op) => self.apply_decoded(&event, &op).await,
+                        Err(err) => Err(err),
+                    }
+                }
+                Err(err) => Err(err),
+            };
+            if let Err(err) = result {
+                tracing::warn!("Failed to replay CRDT event {}: {}", event.id, err);
+                failed += 1;
+            }
+        }
+        failed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nostr::crdt::GSetAction;
+    use nostr_sdk::{EventBuilder, Keys, Kind, Tag, Timestamp};
+
+    fn manager(keys: &Keys) -> CrdtManager {
+        let client = Arc::new(nostr_sdk::Client::new(keys));
+        CrdtManager::new(client, NostrSigner::Keys(keys.clone()))
+    }
+
+    fn event(keys: &Keys, content: String, created_at: u64) -> Event {
+        EventBuilder::new(Kind::TextNote, content, [Tag::hashtag("nostr-crdt")])
+            .custom_created_at(Timestamp::from(created_at))
+            .to_event(keys)
+            .unwrap()
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn test_process_events_matches_sequential() {
+        let keys = Keys::generate();
+        let secret_key = keys.secret_key().unwrap();
+        let mut events = Vec::new();
+        for i in 0..200u64 {
+            let op = match i % 3 {
+                0 => CrdtOperation::LWWRegister {
+                    key: format!("register-{}", i % 5),
+                    value: format!("value-{i}"),
+                    timestamp: i / 10,
+                },
+                1 => CrdtOperation::GCounter {
+                    key: "views".to_string(),
+                    increment: i,
+                },
+                _ => CrdtOperation::GSet {
+                    key: "tags".to_string(),
+                    value: format!("tag-{}", i % 7),
+                    action: GSetAction::Add,
+                },
+            };
+            let json = serde_json::to_string(&op).unwrap();
+            let content = nip04::encrypt(secret_key, &keys.public_key(), json).unwrap();
+            events.push(event(&keys, content, i / 4));
+        }
+        // Repeats are applied once, broken events fail
+        events.extend(events[..20].to_vec());
+        events.push(event(&keys, "not an operation".to_string(), 3));
+        events.push(event(&keys, "garbage?iv=".to_string(), 4));
+        events.reverse();
+
+        let sequential = manager(&keys);
+        let mut replay = events.clone();
+        sort_for_replay(&mut replay);
+        let mut failed = 0;
+        for event in replay.iter() {
+            if sequential.process_event(event).await.is_err() {
+                failed += 1;
+            }
+        }
+
+        let pipelined = manager(&keys);
+        assert_eq!(pipelined.process_events(events).await, failed);
+        assert_eq!(failed, 2);
+        assert_eq!(pipelined.export_state(), sequential.export_state());
+        assert_eq!(pipelined.metrics().ops_applied, 200);
+    }
+}
@@ -0,0 +1,274 @@
+use nostr_sdk::database::async_trait;
+use nostr_sdk::EventId;
+
+use super::{CrdtManager, Error, Result};
+use crate::nostr::runtime;
+
+// Durable record of the CRDT events already applied, one namespace per
+// document (`None` is the default document). Ids are loaded lazily on first
+// use, so an app that keeps its own view of the state only ever sees new
+// operations after a restart. `import_state` takes precedence: a restored
+// backup carries its own processed ids and the store is not merged in.
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait ProcessedStore: Send + Sync {
+    async fn load(&self, document: Option<&str>) -> Result<Vec<EventId>>;
+    async fn record(&self, document: Option<&str>, id: EventId) -> Result<()>;
+}
+
+impl CrdtManager {
+    // Remember applied event ids in `store` so they are never applied again,
+    // even after a restart. Documents opened later share the store.
+    pub fn with_processed_store(mut self, store: std::sync::Arc<dyn ProcessedStore>) -> Self {
+        self.processed_store = Some(store);
+        self
+    }
+
+    // Merge the stored ids into the in-memory set, once per manager
+    pub(super) async fn ensure_processed_loaded(&self) -> Result<()> {
+        let Some(store) = &self.processed_store else {
+            return Ok(());
+        };
+        self.processed_loaded
+            .get_or_try_init(|| async {
+                let ids = store.load(self.document.as_deref()).await?;
+                self.processed.lock().unwrap().extend(ids);
+                Ok::<(), Error>(())
+            })
+            .await?;
+        Ok(())
+    }
+
+    // Mark an event as applied, in memory and in the store if there is one.
+    // A store failure only costs durability, so it is logged, not returned.
+    pub(super) async fn mark_processed(&self, id: EventId) {
+        self.processed.lock().unwrap().insert(id);
+        if let Some(store) = &self.processed_store {
+            if let Err(err) = store.record(self.document.as_deref(), id).await {
+                tracing::warn!("Failed to persist processed CRDT event {}: {}", id, err);
+            }
+        }
+    }
+
+    // `mark_processed` for callers that cannot wait, the store write runs
+    // in the background
+    pub(super) fn mark_processed_detached(&self, id: EventId) {
+        self.processed.lock().unwrap().insert(id);
+        if let Some(store) = self.processed_store.clone() {
+            let document = self.document.clone();
+            runtime::detach(async move {
+                if let Err(err) = store.record(document.as_deref(), id).await {
+                    tracing::warn!("Failed to persist processed CRDT event {}: {}", id, err);
+                }
+            });
+        }
+    }
+}
+
+#[cfg(native_backend)]
+pub use file::FileProcessedStore;
+
+#[cfg(native_backend)]
+mod file {
+    use std::fs::{self, OpenOptions};
+    use std::io::{ErrorKind, Write};
+    use std::path::PathBuf;
+    use std::sync::Mutex;
+
+    use nostr_sdk::hashes::sha256::Hash as Sha256Hash;
+    use nostr_sdk::hashes::Hash;
+
+    use super::*;
+
+    // Append-only log of event ids, one file per document under `dir`
+    #[derive(Debug)]
+    pub struct FileProcessedStore {
+        dir: PathBuf,
+        // Serializes appends so lines never interleave
+        write_lock: Mutex<()>,
+    }
+
+    impl FileProcessedStore {
+        pub fn new(dir: impl Into<PathBuf>) -> Result<Self> {
+            let dir = dir.into();
+            fs::create_dir_all(&dir).map_err(|err| Error::Store(err.to_string()))?;
+            Ok(Self {
+                dir,
+                write_lock: Mutex::new(()),
+            })
+        }
+
+        // Document names are free-form, so hash them into a safe file name
+        fn path(&self, document: Option<&str>) -> PathBuf {
+            match document {
+                None => self.dir.join("processed.log"),
+                Some(name) => {
+                    let hash = Sha256Hash::hash(name.as_bytes());
+                    self.dir.join(format!("processed-{hash}.log"))
+                }
+            }
+        }
+    }
+
+    #[async_trait]
+    impl ProcessedStore for FileProcessedStore {
+        async fn load(&self, document: Option<&str>) -> Result<Vec<EventId>> {
+            let contents = match fs::read_to_string(self.path(document)) {
+                Ok(contents) => contents,
+                Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+                Err(err) => return Err(Error::Store(err.to_string())),
+            };
+            // A torn last line from a crash simply fails to parse and is dropped
+            Ok(contents
+                .lines()
+                .filter_map(|line| EventId::from_hex(line).ok())
+                .collect())
+        }
+
+        async fn record(&self, document: Option<&str>, id: EventId) -> Result<()> {
+            let _guard = self.write_lock.lock().unwrap();
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.path(document))
+                .map_err(|err| Error::Store(err.to_string()))?;
+            writeln!(file, "{}", id.to_hex()).map_err(|err| Error::Store(err.to_string()))
+        }
+    }
+}
+
+#[cfg(wasm_backend)]
+pub use indexed_db::IndexedDbProcessedStore;
+
+#[cfg(wasm_backend)]
+mod indexed_db {
+    use indexed_db_futures::prelude::*;
+    use wasm_bindgen::JsValue;
+
+    use super::*;
+
+    const STORE_NAME: &str = "processed_events";
+
+    // IndexedDB-backed store for the browser. Keys are `<document>/<event id>`
+    // in a single object store; the database is opened per call, so the
+    // store itself holds no JS handles.
+    #[derive(Debug, Clone)]
+    pub struct IndexedDbProcessedStore {
+        db_name: String,
+    }
+
+    impl IndexedDbProcessedStore {
+        pub fn new(db_name: &str) -> Self {
+            Self {
+                db_name: db_name.to_string(),
+            }
+        }
+
+        async fn open(&self) -> Result<IdbDatabase> {
+            let mut request = IdbDatabase::open_u32(&self.db_name, 1).map_err(dom_error)?;
+            request.set_on_upgrade_needed(Some(
+                |evt: &IdbVersionChangeEvent| -> std::result::Result<(), JsValue> {
+                    if !evt.db().object_store_names().any(|name| name == STORE_NAME) {
+                        evt.db().create_object_store(STORE_NAME)?;
+                    }
+                    Ok(())
+                },
+            ));
+            request.into_future().await.map_err(dom_error)
+        }
+    }
+
+    fn key(document: Option<&str>, id: &EventId) -> String {
+        format!("{}/{}", document.unwrap_or_default(), id.to_hex())
+    }
+
+    fn dom_error(err: web_sys::DomException) -> Error {
+        Error::Store(err.message())
+    }
+
+    #[async_trait(?Send)]
+    impl ProcessedStore for IndexedDbProcessedStore {
+        async fn load(&self, document: Option<&str>) -> Result<Vec<EventId>> {
+            let db = self.open().await?;
+            let tx = db
+                .transaction_on_one_with_mode(STORE_NAME, IdbTransactionMode::Readonly)
+                .map_err(dom_error)?;
+            let store = tx.object_store(STORE_NAME).map_err(dom_error)?;
+            let keys = store
+                .get_all_keys()
+                .map_err(dom_error)?
+                .await
+                .map_err(dom_error)?;
+
+            let document = document.unwrap_or_default();
+            Ok(keys
+                .iter()
+                .filter_map(|key| key.as_string())
+                .filter_map(|key| {
+                    let (doc, id) = key.rsplit_once('/')?;
+                    if doc != document {
+                        return None;
+                    }
+                    EventId::from_hex(id).ok()
+                })
+                .collect())
+        }
+
+        async fn record(&self, document: Option<&str>, id: EventId) -> Result<()> {
+            let db = self.open().await?;
+            let tx = db
+                .transaction_on_one_with_mode(STORE_NAME, IdbTransactionMode::Readwrite)
+                .map_err(dom_error)?;
+            let store = tx.object_store(STORE_NAME).map_err(dom_error)?;
+            store
+                .put_key_val_owned(key(document, &id), &JsValue::TRUE)
+                .map_err(dom_error)?;
+            tx.await.into_result().map_err(dom_error)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nostr::crdt::CrdtOperation;
+    use nostr_sdk::{EventBuilder, Keys, Kind, NostrSigner, Tag};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_processed_survives_restart() {
+        let dir =
+            std::env::temp_dir().join(format!("nostr-crdt-{}", Keys::generate().public_key()));
+        let keys = Keys::generate();
+        let op = CrdtOperation::GCounter {
+            key: "visitors".to_string(),
+            increment: 1,
+        };
+        let event = EventBuilder::new(
+            Kind::TextNote,
+            serde_json::to_string(&op).unwrap(),
+            [Tag::hashtag("nostr-crdt")],
+        )
+        .to_event(&keys)
+        .unwrap();
+
+        let restart = || {
+            let client = Arc::new(nostr_sdk::Client::new(keys.clone()));
+            let store = Arc::new(FileProcessedStore::new(&dir).unwrap());
+            CrdtManager::new(client, NostrSigner::Keys(keys.clone())).with_processed_store(store)
+        };
+
+        let manager = restart();
+        manager.process_event(&event).await.unwrap();
+        assert_eq!(manager.metrics().ops_applied, 1);
+        drop(manager);
+
+        // Re-bootstrapping delivers the same event again
+        let manager = restart();
+        manager.process_event(&event).await.unwrap();
+        assert_eq!(manager.metrics().ops_applied, 0);
+        assert_eq!(manager.get_counter_value("visitors"), None);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
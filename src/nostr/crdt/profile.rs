@@ -0,0 +1,280 @@
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use nostr_sdk::{Event, EventBuilder, EventId, Kind, Metadata};
+use serde_json::{Map, Value};
+
+use super::{CrdtManager, Error, Result};
+use crate::nostr::publish::ProfilePatch;
+
+// Register per profile field, holding its JSON value: `field/<name>`. A
+// removed field holds `null`.
+const FIELD_PREFIX: &str = "field/";
+// Fields of the profile last published or imported, to tell what another
+// client changed since
+const PUBLISHED: &str = "published";
+
+// The user's kind 0 profile kept in the document `profile`, one register
+// per field: editing `about` on one device and `picture` on another keeps
+// both, where two kind 0 events would have the newer wipe the other's
+// change. `publish` writes the merged profile, best after a `sync()`;
+// `import_metadata` brings in fields another client changed.
+pub struct ProfileDoc {
+    document: Arc<CrdtManager>,
+}
+
+impl ProfileDoc {
+    pub fn open(manager: &CrdtManager) -> Self {
+        Self {
+            document: manager.open_document("profile"),
+        }
+    }
+
+    // The profile's document; `replay_history` it before `publish` so the
+    // kind 0 carries the fields edited on every device
+    pub fn document(&self) -> &Arc<CrdtManager> {
+        &self.document
+    }
+
+    // Set the fields of `patch`, each on its own
+    pub async fn update(&self, patch: ProfilePatch) -> Result<()> {
+        for (field, value) in patch_fields(patch) {
+            self.write(&field, &value).await?;
+        }
+        Ok(())
+    }
+
+    // The merged profile, field by field: a standard field holding
+    // something other than a string, as a misbehaving client may write, is
+    // left out and the rest of the profile kept
+    pub fn metadata(&self) -> Metadata {
+        let mut metadata = Metadata::default();
+        for (name, value) in self.fields() {
+            let field = match name.as_str() {
+                "name" => &mut metadata.name,
+                "display_name" => &mut metadata.display_name,
+                "about" => &mut metadata.about,
+                "website" => &mut metadata.website,
+                "picture" => &mut metadata.picture,
+                "banner" => &mut metadata.banner,
+                "nip05" => &mut metadata.nip05,
+                "lud06" => &mut metadata.lud06,
+                "lud16" => &mut metadata.lud16,
+                _ => {
+                    if !value.is_null() {
+                        metadata.custom.insert(name, value);
+                    }
+                    continue;
+                }
+            };
+            match value {
+                Value::Null => {}
+                Value::String(value) => *field = Some(value),
+                value => tracing::warn!("Skipping profile field {name}: {value} is not a string"),
+            }
+        }
+        metadata
+    }
+
+    // Publish the profile as a kind 0 event, replacing the previous one
+    pub async fn publish(&self) -> Result<EventId> {
+        let metadata = self.metadata();
+        let builder = EventBuilder::metadata(&metadata);
+        let event = self.document.signer.sign_event_builder(builder).await?;
+        self.remember_published(&metadata_fields(&metadata)).await?;
+        self.document.send_event(event).await.map_err(Error::Client)
+    }
+
+    // Apply the fields another client changed in our kind 0 profile since it
+    // was last published or imported here. The first import only fills in
+    // fields not set here.
+    pub async fn import_metadata(&self, event: &Event) -> Result<()> {
+        if event.kind != Kind::Metadata {
+            return Err(Error::InvalidOperation);
+        }
+        if event.pubkey != self.document.signer.public_key().await? {
+            return Err(Error::NotAuthor);
+        }
+        let after: Map<String, Value> =
+            serde_json::from_str(&event.content).map_err(|_| Error::InvalidOperation)?;
+        let before: Option<Map<String, Value>> = self
+            .document
+            .get_register_value(PUBLISHED)
+            .and_then(|json| serde_json::from_str(&json).ok());
+        let current = self.fields();
+
+        let names: BTreeSet<&String> = after
+            .keys()
+            .chain(before.iter().flat_map(|before| before.keys()))
+            .collect();
+        for name in names {
+            let value = after.get(name).cloned().unwrap_or(Value::Null);
+            let changed = match &before {
+                Some(before) => before.get(name).unwrap_or(&Value::Null) != &value,
+                None => !current.contains_key(name),
+            };
+            if changed && current.get(name) != Some(&value) {
+                self.write(name, &value).await?;
+            }
+        }
+        self.remember_published(&after).await
+    }
+
+    // Every field with a register, removed ones as `null`
+    fn fields(&self) -> Map<String, Value> {
+        self.document
+            .register_keys()
+            .into_iter()
+            .filter_map(|key| {
+                let name = key.strip_prefix(FIELD_PREFIX)?;
+                let value = self.document.get_register_value(&key)?;
+                Some((name.to_string(), serde_json::from_str(&value).ok()?))
+            })
+            .collect()
+    }
+
+    async fn write(&self, field: &str, value: &Value) -> Result<EventId> {
+        self.document
+            .update_lww_register(&format!("{FIELD_PREFIX}{field}"), &value.to_string())
+            .await
+    }
+
+    async fn remember_published(&self, fields: &Map<String, Value>) -> Result<()> {
+        let json = serde_json::to_string(fields).map_err(|_| Error::SerializationError)?;
+        self.document.update_lww_register(PUBLISHED, &json).await?;
+        Ok(())
+    }
+}
+
+// The fields a patch sets, `null` for those it removes
+fn patch_fields(patch: ProfilePatch) -> Vec<(String, Value)> {
+    let standard = [
+        ("name", patch.name),
+        ("display_name", patch.display_name),
+        ("about", patch.about),
+        ("website", patch.website),
+        ("picture", patch.picture),
+        ("banner", patch.banner),
+        ("nip05", patch.nip05),
+        ("lud06", patch.lud06),
+        ("lud16", patch.lud16),
+    ];
+    standard
+        .into_iter()
+        .filter_map(|(field, value)| {
+            let value = value?;
+            let value = if value.is_empty() {
+                Value::Null
+            } else {
+                Value::String(value)
+            };
+            Some((field.to_string(), value))
+        })
+        .chain(patch.custom)
+        .collect()
+}
+
+fn metadata_fields(metadata: &Metadata) -> Map<String, Value> {
+    match serde_json::to_value(metadata) {
+        Ok(Value::Object(fields)) => fields,
+        _ => Map::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testhelper::{connect, MockRelay};
+    use nostr_sdk::{JsonUtil, Keys};
+
+    #[tokio::test]
+    async fn test_profile_doc() {
+        let relay = MockRelay::run().await.unwrap();
+        let keys = Keys::generate();
+        let phone = ProfileDoc::open(&connect(&relay, &keys).await);
+        let desktop = ProfileDoc::open(&connect(&relay, &keys).await);
+        phone
+            .update(ProfilePatch {
+                name: Some("alice".into()),
+                website: Some("https://old.example".into()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        desktop.document().replay_history(None, None).await.unwrap();
+
+        // Both devices edit a different field before syncing
+        phone
+            .update(ProfilePatch {
+                about: Some("on the phone".into()),
+                website: Some(String::new()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        desktop
+            .update(ProfilePatch {
+                picture: Some("https://example.com/me.png".into()),
+                custom: [("pronouns".to_string(), Value::from("they/them"))].into(),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        phone.document().replay_history(None, None).await.unwrap();
+        desktop.document().replay_history(None, None).await.unwrap();
+
+        let merged = phone.metadata();
+        assert_eq!(merged, desktop.metadata());
+        assert_eq!(merged.name.as_deref(), Some("alice"));
+        assert_eq!(merged.about.as_deref(), Some("on the phone"));
+        assert_eq!(
+            merged.picture.as_deref(),
+            Some("https://example.com/me.png")
+        );
+        assert_eq!(merged.website, None);
+        assert_eq!(merged.custom["pronouns"], "they/them");
+
+        // Another client renames the profile from what was published
+        phone.publish().await.unwrap();
+        let renamed = Metadata {
+            name: Some("alice2".into()),
+            ..merged.clone()
+        };
+        let event = EventBuilder::metadata(&renamed).to_event(&keys).unwrap();
+        desktop.document().replay_history(None, None).await.unwrap();
+        desktop
+            .update(ProfilePatch {
+                about: Some("from the desktop".into()),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        desktop.import_metadata(&event).await.unwrap();
+        let imported = desktop.metadata();
+        assert_eq!(imported.name.as_deref(), Some("alice2"));
+        assert_eq!(imported.about.as_deref(), Some("from the desktop"));
+    }
+
+    #[tokio::test]
+    async fn test_profile_skips_bad_fields() {
+        let relay = MockRelay::run().await.unwrap();
+        let keys = Keys::generate();
+        let profile = ProfileDoc::open(&connect(&relay, &keys).await);
+        let content = r#"{"name":42,"about":"still here","lud16":null}"#;
+        let event = EventBuilder::new(Kind::Metadata, content, [])
+            .to_event(&keys)
+            .unwrap();
+        profile.import_metadata(&event).await.unwrap();
+
+        let metadata = profile.metadata();
+        assert_eq!(metadata.name, None);
+        assert_eq!(metadata.about.as_deref(), Some("still here"));
+
+        // The published profile keeps the good fields
+        profile.publish().await.unwrap();
+        let filter = nostr_sdk::Filter::new().kind(Kind::Metadata);
+        let events = profile.document().client.get_events_of(vec![filter], None);
+        let published = Metadata::from_json(&events.await.unwrap()[0].content).unwrap();
+        assert_eq!(published, metadata);
+    }
+}
@@ -0,0 +1,294 @@
+// The #[pymethods] expansion converts PyErr into itself
+#![allow(clippy::useless_conversion)]
+
+use std::sync::{Arc, OnceLock};
+
+use nostr_sdk::{Client, FromBech32, Keys, NostrSigner, SecretKey, ToBech32};
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use tokio::runtime::Runtime;
+
+use super::{CrdtManager, CrdtOperation, CrdtState, GCounter, GSet, GSetAction, LWWRegister};
+
+impl From<super::Error> for PyErr {
+    fn from(err: super::Error) -> Self {
+        PyRuntimeError::new_err(err.to_string())
+    }
+}
+
+// Runtime driving every manager of the process
+fn runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| Runtime::new().expect("failed to start the Tokio runtime"))
+}
+
+/// `CrdtManager` for Python. Network calls release the GIL while they run,
+/// so asyncio code can await them with `asyncio.to_thread` and other
+/// threads keep running.
+#[pyclass(name = "CrdtManager", module = "nostr_crdt")]
+pub struct PyCrdtManager {
+    client: Arc<Client>,
+    keys: Keys,
+    inner: Arc<CrdtManager>,
+}
+
+#[pymethods]
+impl PyCrdtManager {
+    /// Manager signing with `secret_key` (nsec or hex), or with fresh keys
+    #[new]
+    #[pyo3(signature = (secret_key=None))]
+    fn new(secret_key: Option<String>) -> PyResult<Self> {
+        let keys = match secret_key {
+            Some(secret_key) => {
+                let secret_key = SecretKey::from_bech32(&secret_key)
+                    .or_else(|_| SecretKey::from_hex(&secret_key))
+                    .map_err(|err| PyValueError::new_err(err.to_string()))?;
+                Keys::new(secret_key)
+            }
+            None => Keys::generate(),
+        };
+        let client = Arc::new(Client::new(&keys));
+        let inner = Arc::new(CrdtManager::new(
+            Arc::clone(&client),
+            NostrSigner::Keys(keys.clone()),
+        ));
+        Ok(Self {
+            client,
+            keys,
+            inner,
+        })
+    }
+
+    /// npub of the keys signing the operations
+    fn public_key(&self) -> String {
+        self.keys.public_key().to_bech32().unwrap_or_default()
+    }
+
+    fn connect(&self, py: Python<'_>, relays: Vec<String>) -> PyResult<()> {
+        py.allow_threads(|| {
+            runtime().block_on(async {
+                self.client
+                    .add_relays(relays.iter().map(String::as_str))
+                    .await
+                    .map_err(|err| PyRuntimeError::new_err(err.to_string()))?;
+                self.client.connect().await;
+                Ok(())
+            })
+        })
+    }
+
+    /// Handle on a named document sharing this manager's connection
+    fn open_document(&self, name: &str) -> Self {
+        Self {
+            client: Arc::clone(&self.client),
+            keys: self.keys.clone(),
+            inner: self.inner.open_document(name),
+        }
+    }
+
+    /// Loads the document from its latest checkpoint and the operations
+    /// published after it
+    #[pyo3(signature = (timeout_secs=10.0))]
+    fn load(&self, py: Python<'_>, timeout_secs: f64) -> PyResult<()> {
+        let timeout = std::time::Duration::from_secs_f64(timeout_secs);
+        py.allow_threads(|| runtime().block_on(self.inner.load_checkpoint(Some(timeout))))?;
+        Ok(())
+    }
+
+    /// Sets a last-writer-wins register, returning the event id
+    fn update(&self, py: Python<'_>, key: &str, value: &str) -> PyResult<String> {
+        let id =
+            py.allow_threads(|| runtime().block_on(self.inner.update_lww_register(key, value)))?;
+        Ok(id.to_hex())
+    }
+
+    /// Adds `amount` to a grow-only counter, returning the event id
+    #[pyo3(signature = (key, amount=1))]
+    fn increment(&self, py: Python<'_>, key: &str, amount: u64) -> PyResult<String> {
+        let id =
+            py.allow_threads(|| runtime().block_on(self.inner.increment_counter(key, amount)))?;
+        Ok(id.to_hex())
+    }
+
+    /// Adds `value` to a grow-only set, returning the event id
+    fn add(&self, py: Python<'_>, key: &str, value: &str) -> PyResult<String> {
+        let id = py.allow_threads(|| runtime().block_on(self.inner.add_to_set(key, value)))?;
+        Ok(id.to_hex())
+    }
+
+    fn get_register(&self, key: &str) -> Option<String> {
+        self.inner.get_register_value(key)
+    }
+
+    fn get_counter(&self, key: &str) -> Option<u64> {
+        self.inner
+            .get_counter_value(key)
+            .and_then(|count| count.parse().ok())
+    }
+
+    fn get_set(&self, key: &str) -> Option<Vec<String>> {
+        self.inner
+            .get_set_value(key)
+            .and_then(|set| serde_json::from_str(&set).ok())
+    }
+
+    /// Full state backup as a JSON string
+    fn export_state(&self) -> PyResult<String> {
+        serde_json::to_string(&self.inner.export_state())
+            .map_err(|err| PyValueError::new_err(err.to_string()))
+    }
+
+    fn import_state(&self, json: &str) -> PyResult<()> {
+        let backup =
+            serde_json::from_str(json).map_err(|err| PyValueError::new_err(err.to_string()))?;
+        Ok(self.inner.import_state(backup)?)
+    }
+}
+
+/// Standalone last-writer-wins registers, merged locally
+#[pyclass(name = "LWWRegister", module = "nostr_crdt")]
+#[derive(Default)]
+pub struct PyLWWRegister(LWWRegister);
+
+#[pymethods]
+impl PyLWWRegister {
+    #[new]
+    fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keeps `value` unless the key already holds a write at least as new
+    fn set(&mut self, key: String, value: String, timestamp: u64) -> PyResult<()> {
+        Ok(self.0.apply_operation(&CrdtOperation::LWWRegister {
+            key,
+            value,
+            timestamp,
+        })?)
+    }
+
+    fn get(&self, key: &str) -> Option<String> {
+        self.0.get_value(key)
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.0.keys()
+    }
+}
+
+/// Standalone grow-only counters
+#[pyclass(name = "GCounter", module = "nostr_crdt")]
+#[derive(Default)]
+pub struct PyGCounter(GCounter);
+
+#[pymethods]
+impl PyGCounter {
+    #[new]
+    fn new() -> Self {
+        Self::default()
+    }
+
+    #[pyo3(signature = (key, amount=1))]
+    fn increment(&mut self, key: String, amount: u64) -> PyResult<()> {
+        Ok(self.0.apply_operation(&CrdtOperation::GCounter {
+            key,
+            increment: amount,
+        })?)
+    }
+
+    fn get(&self, key: &str) -> Option<u64> {
+        self.0.get_value(key).and_then(|count| count.parse().ok())
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.0.keys()
+    }
+}
+
+/// Standalone grow-only sets
+#[pyclass(name = "GSet", module = "nostr_crdt")]
+#[derive(Default)]
+pub struct PyGSet(GSet);
+
+#[pymethods]
+impl PyGSet {
+    #[new]
+    fn new() -> Self {
+        Self::default()
+    }
+
+    fn add(&mut self, key: String, value: String) -> PyResult<()> {
+        Ok(self.0.apply_operation(&CrdtOperation::GSet {
+            key,
+            value,
+            action: GSetAction::Add,
+        })?)
+    }
+
+    fn get(&self, key: &str) -> Option<Vec<String>> {
+        self.0
+            .get_value(key)
+            .and_then(|set| serde_json::from_str(&set).ok())
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.0.keys()
+    }
+}
+
+#[pymodule]
+fn nostr_crdt(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<PyCrdtManager>()?;
+    module.add_class::<PyLWWRegister>()?;
+    module.add_class::<PyGCounter>()?;
+    module.add_class::<PyGSet>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pyo3::types::PyDict;
+
+    #[test]
+    fn test_python_module() {
+        pyo3::append_to_inittab!(nostr_crdt);
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let locals = PyDict::new_bound(py);
+            py.run_bound(
+                r#"
+import nostr_crdt
+
+register = nostr_crdt.LWWRegister()
+register.set("title", "new", 20)
+register.set("title", "old", 10)
+assert register.get("title") == "new"
+
+counter = nostr_crdt.GCounter()
+counter.increment("views")
+counter.increment("views", 4)
+assert counter.get("views") == 5
+
+tags = nostr_crdt.GSet()
+tags.add("tags", "rust")
+tags.add("tags", "rust")
+assert tags.get("tags") == ["rust"]
+
+doc = nostr_crdt.CrdtManager()
+assert doc.public_key().startswith("npub1")
+assert doc.get_register("title") is None
+copy = nostr_crdt.CrdtManager()
+copy.import_state(doc.export_state())
+try:
+    nostr_crdt.CrdtManager("not a key")
+    raise AssertionError("expected ValueError")
+except ValueError:
+    pass
+"#,
+                None,
+                Some(&locals),
+            )
+            .unwrap();
+        });
+    }
+}
@@ -0,0 +1,103 @@
+use std::time::{Duration, Instant};
+
+use super::{Error, Result};
+
+// Token-bucket settings for publishing CRDT operations. The fields are
+// private so every limit goes through the check in `new`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    burst: u32,
+    per_second: f64,
+}
+
+impl RateLimit {
+    // A limit that lets at least one operation through and refills at a
+    // finite, positive rate; anything else would stall publishing for good
+    pub fn new(burst: u32, per_second: f64) -> Result<Self> {
+        if burst == 0 || !per_second.is_finite() || per_second <= 0.0 {
+            return Err(Error::InvalidRateLimit { burst, per_second });
+        }
+        Ok(Self { burst, per_second })
+    }
+
+    // Operations that may be published back to back
+    pub fn burst(&self) -> u32 {
+        self.burst
+    }
+
+    // Sustained operations per second once the burst is spent, always positive
+    pub fn per_second(&self) -> f64 {
+        self.per_second
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    pub(crate) fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            tokens: limit.burst as f64,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.per_second).min(self.limit.burst as f64);
+        self.last_refill = now;
+    }
+
+    // Time until a token is available, zero if one can be taken now
+    pub(crate) fn wait_time(&mut self, now: Instant) -> Duration {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64((1.0 - self.tokens) / self.limit.per_second)
+    }
+
+    pub(crate) fn take(&mut self) {
+        self.tokens = (self.tokens - 1.0).max(0.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_burst_then_refill() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(RateLimit::new(2, 4.0).unwrap());
+
+        for _ in 0..2 {
+            assert_eq!(bucket.wait_time(start), Duration::ZERO);
+            bucket.take();
+        }
+        assert_eq!(bucket.wait_time(start), Duration::from_millis(250));
+
+        // A quarter second later one more token has accrued
+        let later = start + Duration::from_millis(250);
+        assert_eq!(bucket.wait_time(later), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_invalid_rate_limits() {
+        for per_second in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(matches!(
+                RateLimit::new(1, per_second),
+                Err(Error::InvalidRateLimit { .. })
+            ));
+        }
+        assert!(RateLimit::new(0, 1.0).is_err());
+        assert_eq!(RateLimit::new(1, 0.5).unwrap().per_second(), 0.5);
+    }
+}
@@ -0,0 +1,130 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::{Stream, StreamExt};
+use nostr_sdk::{Event, EventId, Filter, Url};
+
+use super::{CrdtManager, Result};
+use crate::nostr::fetch::{get_write_relays, subscribe_stream, subscribe_stream_to};
+
+impl CrdtManager {
+    // Publish and subscribe only on these relays instead of every relay of
+    // the client, e.g. to keep a team document off big public relays. The
+    // relays are added to the client's pool and connected on first use.
+    pub fn with_relays(mut self, relays: Vec<Url>) -> Self {
+        self.relays = relays;
+        self
+    }
+
+    // Pin the manager to the NIP-65 write relays of the document's author:
+    // the followed publisher, or our own key. Operations are then read
+    // where the author actually publishes them. Without a relay list the
+    // current relays are kept.
+    pub async fn with_outbox_relays(mut self, timeout: Option<Duration>) -> Result<Self> {
+        let author = match &self.following {
+            Some((publisher, _)) => *publisher,
+            None => self.signer.public_key().await?,
+        };
+        let outbox = get_write_relays(&self.client, &author, timeout).await?;
+        if !outbox.is_empty() {
+            self.relays = outbox;
+        }
+        Ok(self)
+    }
+
+    // Relays dedicated to this document, empty when it uses the client's
+    pub fn relays(&self) -> &[Url] {
+        &self.relays
+    }
+
+    // Like `open_document`, but pinned to its own relays. An already open
+    // document is returned as is.
+    pub fn open_document_with_relays(&self, name: &str, relays: Vec<Url>) -> Arc<CrdtManager> {
+        let mut documents = self.documents.lock().unwrap();
+        let document = documents.entry(name.to_string()).or_insert_with(|| {
+            let mut document = self.sibling(Some(name.to_string()));
+            document.relays = relays;
+            Arc::new(document)
+        });
+        Arc::clone(document)
+    }
+
+    async fn ensure_relays(&self) -> std::result::Result<(), nostr_sdk::client::Error> {
+        self.relays_ready
+            .get_or_try_init(|| async {
+                for url in self.relays.iter() {
+                    self.client.add_relay(url.clone()).await?;
+                    self.client.connect_relay(url.clone()).await?;
+                }
+                Ok::<(), nostr_sdk::client::Error>(())
+            })
+            .await?;
+        Ok(())
+    }
+
+    pub(super) async fn fetch_events(
+        &self,
+        filters: Vec<Filter>,
+        timeout: Option<Duration>,
+    ) -> Result<Vec<Event>> {
+        if self.relays.is_empty() {
+            return Ok(self.client.get_events_of(filters, timeout).await?);
+        }
+        self.ensure_relays().await?;
+        Ok(self
+            .client
+            .get_events_from(self.relays.clone(), filters, timeout)
+            .await?)
+    }
+
+    pub(super) async fn send_event(
+        &self,
+        event: Event,
+    ) -> std::result::Result<EventId, nostr_sdk::client::Error> {
+        if self.relays.is_empty() {
+            return self.client.send_event(event).await;
+        }
+        self.ensure_relays().await?;
+        self.client.send_event_to(self.relays.clone(), event).await
+    }
+
+    // Live stream of events matching `filters` on this document's relays
+    pub(super) async fn live_events(
+        &self,
+        filters: Vec<Filter>,
+    ) -> Result<impl Stream<Item = Event>> {
+        if self.relays.is_empty() {
+            let events = subscribe_stream(Arc::clone(&self.client), filters).await;
+            return Ok(events.left_stream());
+        }
+        self.ensure_relays().await?;
+        let events =
+            subscribe_stream_to(Arc::clone(&self.client), self.relays.clone(), filters).await?;
+        Ok(events.right_stream())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr_sdk::{Keys, NostrSigner};
+
+    #[test]
+    fn test_document_relays() {
+        let keys = Keys::generate();
+        let client = Arc::new(nostr_sdk::Client::new(keys.clone()));
+        let team = Url::parse("wss://relay.team.example").unwrap();
+        let manager = CrdtManager::new(client, NostrSigner::Keys(keys.clone()))
+            .with_relays(vec![team.clone()]);
+
+        // Documents inherit the manager's relays unless given their own
+        assert_eq!(manager.open_document("notes").relays(), &[team]);
+        let private = Url::parse("wss://relay.private.example").unwrap();
+        let roadmap = manager.open_document_with_relays("roadmap", vec![private.clone()]);
+        assert_eq!(roadmap.relays(), &[private]);
+        assert!(Arc::ptr_eq(
+            &roadmap,
+            &manager.open_document_with_relays("roadmap", Vec::new())
+        ));
+    }
+}
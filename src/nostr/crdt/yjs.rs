@@ -0,0 +1,311 @@
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+
+use nostr_sdk::{EventId, Timestamp};
+use yrs::updates::decoder::Decode;
+use yrs::{
+    Any, Array, ArrayPrelim, ArrayRef, Doc, Map, MapRef, Out, ReadTxn, StateVector, Transact,
+    TransactionMut, Update,
+};
+
+use super::{CrdtManager, CrdtOperation, Error, GSetAction, Result, StateBackup};
+
+const REGISTERS: &str = "registers";
+const COUNTERS: &str = "counters";
+const SETS: &str = "sets";
+
+// Mirror of a document as a Yjs doc, for web apps that already sync through
+// Yjs. Root maps are `registers` (key -> {value, timestamp}), `counters`
+// (key -> array of increments) and `sets` (key -> array of members).
+pub struct YjsBridge {
+    doc: Doc,
+    registers: MapRef,
+    counters: MapRef,
+    sets: MapRef,
+}
+
+impl Default for YjsBridge {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl YjsBridge {
+    pub fn new() -> Self {
+        let doc = Doc::new();
+        let registers = doc.get_or_insert_map(REGISTERS);
+        let counters = doc.get_or_insert_map(COUNTERS);
+        let sets = doc.get_or_insert_map(SETS);
+        Self {
+            doc,
+            registers,
+            counters,
+            sets,
+        }
+    }
+
+    // Yjs update (v1 encoding) carrying a single operation
+    pub fn encode_operation(&self, op: &CrdtOperation) -> Vec<u8> {
+        let mut txn = self.doc.transact_mut();
+        match op {
+            CrdtOperation::LWWRegister {
+                key,
+                value,
+                timestamp,
+            } => {
+                let newer = match read_register(self.registers.get(&txn, key)) {
+                    Some((existing, existing_ts)) => {
+                        (*timestamp, value.as_str()) > (existing_ts, existing.as_str())
+                    }
+                    None => true,
+                };
+                if newer {
+                    let entry = HashMap::from([
+                        ("value".to_string(), Any::from(value.as_str())),
+                        ("timestamp".to_string(), Any::from(*timestamp as f64)),
+                    ]);
+                    self.registers
+                        .insert(&mut txn, key.as_str(), Any::Map(Arc::new(entry)));
+                }
+            }
+            CrdtOperation::GCounter { key, increment } => {
+                let array = nested_array(&self.counters, &mut txn, key);
+                array.push_back(&mut txn, Any::from(*increment as f64));
+            }
+            CrdtOperation::GSet { key, value, .. } => {
+                let array = nested_array(&self.sets, &mut txn, key);
+                let present = array
+                    .iter(&txn)
+                    .any(|member| matches!(member, Out::Any(Any::String(s)) if *s == **value));
+                if !present {
+                    array.push_back(&mut txn, Any::from(value.as_str()));
+                }
+            }
+        }
+        txn.encode_update_v1()
+    }
+
+    // Apply an update produced by a Yjs peer and return the operations it
+    // adds to the document
+    pub fn decode_update(&self, update: &[u8]) -> Result<Vec<CrdtOperation>> {
+        let update = Update::decode_v1(update).map_err(|_| Error::SerializationError)?;
+        let before = self.snapshot();
+        self.doc
+            .transact_mut()
+            .apply_update(update)
+            .map_err(|_| Error::InvalidOperation)?;
+        let after = self.snapshot();
+
+        let mut ops = Vec::new();
+        for (key, (value, timestamp)) in after.registers {
+            if before.registers.get(&key) != Some(&(value.clone(), timestamp)) {
+                // Untimestamped Yjs writes count as written now
+                let timestamp = match timestamp {
+                    0 => Timestamp::now().as_u64(),
+                    timestamp => timestamp,
+                };
+                ops.push(CrdtOperation::LWWRegister {
+                    key,
+                    value,
+                    timestamp,
+                });
+            }
+        }
+        for (key, total) in after.counters {
+            let previous = before.counters.get(&key).copied().unwrap_or(0);
+            if total > previous {
+                ops.push(CrdtOperation::GCounter {
+                    key,
+                    increment: total - previous,
+                });
+            }
+        }
+        for (key, members) in after.sets {
+            let previous = before.sets.get(&key);
+            for value in members {
+                if !previous.is_some_and(|set| set.contains(&value)) {
+                    ops.push(CrdtOperation::GSet {
+                        key: key.clone(),
+                        value,
+                        action: GSetAction::Add,
+                    });
+                }
+            }
+        }
+        Ok(ops)
+    }
+
+    // The whole Yjs doc as one update, to bring a new Yjs peer up to date
+    pub fn state_update(&self) -> Vec<u8> {
+        self.doc
+            .transact()
+            .encode_state_as_update_v1(&StateVector::default())
+    }
+
+    // Current content in the crate's own shape
+    pub fn snapshot(&self) -> StateBackup {
+        let txn = self.doc.transact();
+        let mut backup = StateBackup {
+            version: StateBackup::VERSION,
+            ..Default::default()
+        };
+        for (key, value) in self.registers.iter(&txn) {
+            if let Some(register) = read_register(Some(value)) {
+                backup.registers.insert(key.to_string(), register);
+            }
+        }
+        for (key, value) in self.counters.iter(&txn) {
+            if let Out::YArray(array) = value {
+                let total = array
+                    .iter(&txn)
+                    .filter_map(|item| match item {
+                        Out::Any(any) => any.cast::<u64>().ok(),
+                        _ => None,
+                    })
+                    .sum();
+                backup.counters.insert(key.to_string(), total);
+            }
+        }
+        for (key, value) in self.sets.iter(&txn) {
+            if let Out::YArray(array) = value {
+                let members: BTreeSet<String> = array
+                    .iter(&txn)
+                    .filter_map(|item| match item {
+                        Out::Any(Any::String(s)) => Some(s.to_string()),
+                        _ => None,
+                    })
+                    .collect();
+                backup
+                    .sets
+                    .insert(key.to_string(), members.into_iter().collect());
+            }
+        }
+        backup
+    }
+}
+
+// Registers written by this bridge are {value, timestamp} maps. A plain
+// string written by a Yjs app has no timestamp and reads as 0.
+fn read_register(value: Option<Out>) -> Option<(String, u64)> {
+    match value? {
+        Out::Any(Any::String(value)) => Some((value.to_string(), 0)),
+        Out::Any(Any::Map(entry)) => {
+            let value = match entry.get("value")? {
+                Any::String(value) => value.to_string(),
+                _ => return None,
+            };
+            let timestamp = entry.get("timestamp")?.clone().cast::<u64>().ok()?;
+            Some((value, timestamp))
+        }
+        _ => None,
+    }
+}
+
+fn nested_array(map: &MapRef, txn: &mut TransactionMut, key: &str) -> ArrayRef {
+    match map.get(txn, key) {
+        Some(Out::YArray(array)) => array,
+        _ => map.insert(txn, key, ArrayPrelim::default()),
+    }
+}
+
+impl CrdtManager {
+    // Yjs bridge seeded with the current state
+    pub fn yjs_bridge(&self) -> YjsBridge {
+        let bridge = YjsBridge::new();
+        for op in self.export_state().into_operations() {
+            bridge.encode_operation(&op);
+        }
+        bridge
+    }
+
+    // Publish the operations contained in a Yjs update, so peers that speak
+    // only this crate's format see the change too
+    pub async fn apply_yjs_update(
+        &self,
+        bridge: &YjsBridge,
+        update: &[u8],
+    ) -> Result<Vec<EventId>> {
+        let mut published = Vec::new();
+        for op in bridge.decode_update(update)? {
+            let id = match op {
+                CrdtOperation::LWWRegister { key, value, .. } => {
+                    self.update_lww_register(&key, &value).await?
+                }
+                CrdtOperation::GCounter { key, increment } => {
+                    self.increment_counter(&key, increment).await?
+                }
+                CrdtOperation::GSet { key, value, .. } => self.add_to_set(&key, &value).await?,
+            };
+            published.push(id);
+        }
+        Ok(published)
+    }
+}
+
+impl StateBackup {
+    // Operations that rebuild this state from scratch
+    fn into_operations(self) -> Vec<CrdtOperation> {
+        let registers = self.registers.into_iter().map(|(key, (value, timestamp))| {
+            CrdtOperation::LWWRegister {
+                key,
+                value,
+                timestamp,
+            }
+        });
+        let counters = self
+            .counters
+            .into_iter()
+            .map(|(key, increment)| CrdtOperation::GCounter { key, increment });
+        let sets = self.sets.into_iter().flat_map(|(key, values)| {
+            values.into_iter().map(move |value| CrdtOperation::GSet {
+                key: key.clone(),
+                value,
+                action: GSetAction::Add,
+            })
+        });
+        registers.chain(counters).chain(sets).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_yjs_round_trip() {
+        let local = YjsBridge::new();
+        let remote = YjsBridge::new();
+        let ops = vec![
+            CrdtOperation::LWWRegister {
+                key: "title".to_string(),
+                value: "draft".to_string(),
+                timestamp: 5,
+            },
+            CrdtOperation::GCounter {
+                key: "visitors".to_string(),
+                increment: 2,
+            },
+            CrdtOperation::GSet {
+                key: "tags".to_string(),
+                value: "rust".to_string(),
+                action: GSetAction::Add,
+            },
+        ];
+
+        let mut decoded = Vec::new();
+        for op in ops.iter() {
+            decoded.extend(remote.decode_update(&local.encode_operation(op)).unwrap());
+        }
+        assert_eq!(
+            serde_json::to_string(&decoded).unwrap(),
+            serde_json::to_string(&ops).unwrap()
+        );
+        assert_eq!(remote.snapshot(), local.snapshot());
+
+        // Replaying the full state adds nothing new
+        assert!(remote
+            .decode_update(&local.state_update())
+            .unwrap()
+            .is_empty());
+    }
+}
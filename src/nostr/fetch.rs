@@ -1,22 +1,33 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::future::Future;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use std::time::Duration;
 
-use futures::{Future, StreamExt};
-use gloo_timers::future::TimeoutFuture;
-use nostr_indexeddb::database::Order;
+use cached::{Cached, SizedCache};
+use futures::StreamExt;
+use nostr_sdk::database::Order;
+use nostr_sdk::nips::nip65::RelayMetadata;
+use nostr_sdk::pool::relay::Error as RelayError;
+use nostr_sdk::types::time::Instant;
 use nostr_sdk::{
-    Client, Event, EventId, Filter, JsonUtil, Kind, Metadata, NostrSigner, PublicKey, Tag,
-    TagStandard, Timestamp,
+    Alphabet, Client, Event, EventId, Filter, FilterOptions, JsonUtil, Kind, Metadata, NostrSigner,
+    PublicKey, Relay, RelayMessage, RelayPoolNotification, RelayStatus, SingleLetterTag,
+    SubscriptionId, Tag, TagStandard, Timestamp, UnsignedEvent, Url,
 };
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
-use tokio::sync::{mpsc, Mutex};
+use tokio::sync::{broadcast, mpsc, Mutex};
 use tokio_stream::wrappers::UnboundedReceiverStream;
 use tokio_stream::Stream;
-use wasm_bindgen_futures::spawn_local;
 
-use super::utils::{get_newest_event, get_oldest_event};
+use super::note::{LongFormNote, Poll, TextNote};
+use super::relay_info::{RelayCapabilities, RelayCapabilitiesCache};
+use super::runtime;
+use super::utils::{
+    custom_emoji, get_newest_event, get_oldest_event, is_nip04_payload, parse_bolt11_msats,
+    CustomEmoji,
+};
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -24,6 +35,7 @@ pub enum Error {
     Client(#[from] nostr_sdk::client::Error),
     #[error(transparent)]
     Metadata(#[from] nostr_sdk::types::metadata::Error),
+    #[cfg(wasm_backend)]
     #[error(transparent)]
     IndexDb(#[from] nostr_indexeddb::IndexedDBError),
     #[error(transparent)]
@@ -31,14 +43,102 @@ pub enum Error {
     #[error(transparent)]
     Signer(#[from] nostr_sdk::signer::Error),
     #[error(transparent)]
-    Database(#[from] nostr_indexeddb::database::DatabaseError),
+    Database(#[from] nostr_sdk::database::DatabaseError),
     #[error(transparent)]
     ChannelSend(#[from] tokio::sync::mpsc::error::TrySendError<String>),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
     #[error("Event not found")]
     EventNotFound,
 }
 type Result<T> = std::result::Result<T, Error>;
 
+/// NIP-17 list of the relays to send someone direct messages to
+const KIND_DM_RELAYS: u16 = 10050;
+
+/// Same default as the nostr-sdk client
+const DEFAULT_RELAY_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Where paginators and getters read events from. Whatever the relays
+/// return is written back to the client database (IndexedDB in the
+/// browser), so a later cached read sees it.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FetchPolicy {
+    /// Only the database; nothing goes over the network
+    CacheOnly,
+    /// The database first, then the relays for what it is missing: the
+    /// rest of a page for paginators, newer events for getters
+    CacheThenRelay,
+    /// Only the relays
+    #[default]
+    RelayOnly,
+}
+
+impl FetchPolicy {
+    pub fn reads_cache(self) -> bool {
+        self != FetchPolicy::RelayOnly
+    }
+
+    pub fn reads_relays(self) -> bool {
+        self != FetchPolicy::CacheOnly
+    }
+}
+
+/// Events matching `filters` according to `policy`, newest first
+pub async fn fetch_events(
+    client: &Client,
+    filters: Vec<Filter>,
+    policy: FetchPolicy,
+    timeout: Option<Duration>,
+) -> Result<Vec<Event>> {
+    let mut events = Vec::new();
+    let mut since = None;
+    if policy.reads_cache() {
+        events = client
+            .database()
+            .query(filters.clone(), Order::Desc)
+            .await?;
+        since = get_newest_event(&events).map(|event| event.created_at() + 1);
+    }
+    if policy.reads_relays() {
+        let filters = match since {
+            Some(since) => filters.into_iter().map(|f| f.since(since)).collect(),
+            None => filters,
+        };
+        let relay_events = match client.get_events_of(filters, timeout).await {
+            Ok(relay_events) => relay_events,
+            // Offline, the cached events are still worth showing
+            Err(err) if !events.is_empty() => {
+                tracing::warn!("Relay fetch failed, using cached events: {:?}", err);
+                return Ok(events);
+            }
+            Err(err) => return Err(err.into()),
+        };
+        store_events(client, &relay_events).await;
+        events = merge_events(events, relay_events);
+    }
+    Ok(events)
+}
+
+/// Write relay results back to the database. A failed write only costs a
+/// refetch later, so it is logged rather than returned.
+async fn store_events(client: &Client, events: &[Event]) {
+    let database = client.database();
+    for event in events {
+        if let Err(err) = database.save_event(event).await {
+            tracing::warn!("Failed to save event {}: {:?}", event.id, err);
+        }
+    }
+}
+
+/// Union of two event lists, deduplicated by id, newest first
+fn merge_events(mut events: Vec<Event>, other: Vec<Event>) -> Vec<Event> {
+    let mut seen: HashSet<EventId> = events.iter().map(|event| event.id).collect();
+    events.extend(other.into_iter().filter(|event| seen.insert(event.id)));
+    events.sort_by_key(|event| std::cmp::Reverse((event.created_at(), event.id)));
+    events
+}
+
 macro_rules! create_encrypted_filters {
     ($kind:expr, $author:expr, $public_key:expr) => {{
         (
@@ -48,8 +148,8 @@ macro_rules! create_encrypted_filters {
                 .pubkey($public_key),
             Filter::new()
                 .kind($kind)
-                .author($author)
-                .pubkey($public_key),
+                .author($public_key)
+                .pubkey($author),
         )
     }};
 }
@@ -84,7 +184,6 @@ impl From<Event> for DecryptedMsg {
 }
 
 #[derive(Debug, Clone)]
-#[allow(clippy::arc_with_non_send_sync)]
 pub struct EventPaginator {
     client: Arc<Client>,
     filters: Vec<Filter>,
@@ -93,11 +192,40 @@ pub struct EventPaginator {
     timeout: Option<Duration>,
     page_size: usize,
     last_event_ids: HashSet<EventId>,
-    from_db: bool,
+    policy: FetchPolicy,
+    /// Walk towards newer events instead of older ones
+    forward: bool,
+    /// Newest `created_at` handed out so far, for forward mode
+    newest_timestamp: Option<Timestamp>,
+    /// Query every relay separately instead of one pooled query
+    per_relay: bool,
+    /// Relays that returned each event of the last page
+    sources: HashMap<Url, Vec<EventId>>,
+    /// Order of the events within a page, `None` for the mode's default
+    order: Option<Order>,
+}
+
+/// Serializable position of an [`EventPaginator`], to resume pagination in
+/// a later session without refetching pages already shown
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PaginationCursor {
+    /// Oldest `created_at` returned so far (backward mode)
+    pub oldest_timestamp: Option<Timestamp>,
+    /// Newest `created_at` returned so far (forward mode)
+    pub newest_timestamp: Option<Timestamp>,
+    /// Ids of the last page, to drop repeats at the boundary timestamp
+    pub seen_ids: Vec<EventId>,
+    /// Backward pagination reached the end of history
+    pub done: bool,
 }
 
-unsafe impl Send for EventPaginator {}
-unsafe impl Sync for EventPaginator {}
+// The paginator only holds owned data and an `Arc<Client>`, so it is Send
+// and Sync wherever the client is; fail the build if that ever regresses
+#[cfg(not(target_arch = "wasm32"))]
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<EventPaginator>();
+};
 
 impl EventPaginator {
     pub fn new(
@@ -105,7 +233,7 @@ impl EventPaginator {
         filters: Vec<Filter>,
         timeout: Option<Duration>,
         page_size: usize,
-        from_db: bool,
+        policy: FetchPolicy,
     ) -> Self {
         Self {
             client,
@@ -115,17 +243,207 @@ impl EventPaginator {
             timeout,
             page_size,
             last_event_ids: HashSet::new(),
-            from_db,
+            policy,
+            forward: false,
+            newest_timestamp: None,
+            per_relay: false,
+            sources: HashMap::new(),
+            order: None,
+        }
+    }
+
+    /// Order of the events within each page. Defaults to newest first when
+    /// walking back and oldest first in forward mode; a chat view walking
+    /// back through history would pick `Order::Asc`.
+    pub fn order(mut self, order: Order) -> Self {
+        self.order = Some(order);
+        self
+    }
+
+    /// Walks back through history (the default), starting with events
+    /// created at or before `until`, or with the newest events when `None`
+    pub fn backward(mut self, until: Option<Timestamp>) -> Self {
+        self.forward = false;
+        self.oldest_timestamp = until.map(|until| until + 1);
+        self
+    }
+
+    fn sort_page(&self, events: &mut [Event]) {
+        let default = if self.forward {
+            Order::Asc
+        } else {
+            Order::Desc
+        };
+        match self.order.unwrap_or(default) {
+            Order::Asc => events.sort_by_key(|event| (event.created_at(), event.id)),
+            Order::Desc => {
+                events.sort_by_key(|event| std::cmp::Reverse((event.created_at(), event.id)))
+            }
+        }
+    }
+
+    /// Queries each connected relay concurrently and merges the results,
+    /// deduplicated by event id. A slow or failing relay no longer holds back
+    /// or empties the page, and [`EventPaginator::page_sources`] tells which
+    /// relay returned what. Only applies to the relay side of the
+    /// [`FetchPolicy`].
+    ///
+    /// In either mode, relays quarantined after repeated failures (see
+    /// [`relay_stats`]) are left out.
+    pub fn per_relay(mut self) -> Self {
+        self.per_relay = true;
+        self
+    }
+
+    /// Relays that returned each event of the last page, in per-relay mode
+    pub fn page_sources(&self) -> &HashMap<Url, Vec<EventId>> {
+        &self.sources
+    }
+
+    /// Switches to forward (live tail) mode: each page holds only events
+    /// newer than the previous page, oldest first, starting after `since`
+    /// (or from the beginning of history when `None`). An empty result means
+    /// "nothing new yet" rather than the end, so `next_page` can be polled
+    /// again later to catch up.
+    pub fn forward(mut self, since: Option<Timestamp>) -> Self {
+        self.forward = true;
+        self.newest_timestamp = since;
+        self
+    }
+
+    /// Current position, see [`PaginationCursor`]
+    pub fn cursor(&self) -> PaginationCursor {
+        let mut seen_ids: Vec<EventId> = self.last_event_ids.iter().copied().collect();
+        seen_ids.sort();
+        PaginationCursor {
+            oldest_timestamp: self.oldest_timestamp,
+            newest_timestamp: self.newest_timestamp,
+            seen_ids,
+            done: self.done,
         }
     }
 
+    /// Resumes from a cursor saved with [`EventPaginator::cursor`]. The
+    /// paginator must be built with the same filters and mode.
+    pub fn with_cursor(mut self, cursor: PaginationCursor) -> Self {
+        self.oldest_timestamp = cursor.oldest_timestamp;
+        self.newest_timestamp = cursor.newest_timestamp;
+        self.last_event_ids = cursor.seen_ids.into_iter().collect();
+        self.done = cursor.done;
+        self
+    }
+
     pub fn are_all_event_ids_present(&self, events: &[Event]) -> bool {
         events
             .iter()
             .all(|event| self.last_event_ids.contains(&event.id))
     }
 
+    async fn query(&mut self, filters: Vec<Filter>) -> Option<Vec<Event>> {
+        match self.policy {
+            FetchPolicy::CacheOnly => self.query_db(filters).await,
+            FetchPolicy::RelayOnly => self.query_relay(filters).await,
+            FetchPolicy::CacheThenRelay => {
+                let cached = self.query_db(filters.clone()).await.unwrap_or_default();
+                if cached.len() >= self.page_size {
+                    return Some(cached);
+                }
+                match self.query_relay(filters).await {
+                    Some(events) => {
+                        let mut events = merge_events(cached, events);
+                        events.truncate(self.page_size);
+                        Some(events)
+                    }
+                    None if cached.is_empty() => None,
+                    None => Some(cached),
+                }
+            }
+        }
+    }
+
+    async fn query_db(&self, filters: Vec<Filter>) -> Option<Vec<Event>> {
+        match self.client.database().query(filters, Order::Desc).await {
+            Ok(events) => Some(events),
+            Err(err) => {
+                tracing::error!("Database query failed: {:?}", err);
+                None
+            }
+        }
+    }
+
+    async fn query_relay(&mut self, filters: Vec<Filter>) -> Option<Vec<Event>> {
+        let events = if self.per_relay {
+            self.query_relays(filters).await?
+        } else {
+            // Leave quarantined relays out of the pooled query too
+            let relays = self.client.relays().await;
+            let total = relays.len();
+            let healthy: Vec<Url> = healthy_relays(relays).into_keys().collect();
+            let result = if healthy.len() < total {
+                self.client
+                    .get_events_from(healthy, filters, self.timeout)
+                    .await
+            } else {
+                self.client.get_events_of(filters, self.timeout).await
+            };
+            match result {
+                Ok(events) => events,
+                Err(err) => {
+                    tracing::error!("Relay fetch failed: {:?}", err);
+                    return None;
+                }
+            }
+        };
+        store_events(&self.client, &events).await;
+        Some(events)
+    }
+
+    async fn query_relays(&mut self, filters: Vec<Filter>) -> Option<Vec<Event>> {
+        let relays = self.client.relays().await;
+        let results = fetch_from_each(relays, filters, self.timeout).await;
+
+        let mut events: Vec<Event> = Vec::new();
+        let mut sources: HashMap<Url, Vec<EventId>> = HashMap::new();
+        let mut seen = HashSet::new();
+        let mut any_succeeded = false;
+        for (url, result) in results {
+            match result {
+                Ok(relay_events) => {
+                    any_succeeded = true;
+                    let ids = sources.entry(url).or_default();
+                    for event in relay_events {
+                        ids.push(event.id);
+                        if seen.insert(event.id) {
+                            events.push(event);
+                        }
+                    }
+                }
+                Err(err) => tracing::warn!("Relay {} fetch failed: {:?}", url, err),
+            }
+        }
+        if !any_succeeded {
+            return None;
+        }
+
+        // Each relay honours the limit on its own; keeping only the newest
+        // `page_size` of the merge leaves no gaps before the next page
+        events.sort_by_key(|event| std::cmp::Reverse(event.created_at()));
+        events.truncate(self.page_size);
+        let kept: HashSet<EventId> = events.iter().map(|event| event.id).collect();
+        for ids in sources.values_mut() {
+            ids.retain(|id| kept.contains(id));
+        }
+        for (url, ids) in sources {
+            self.sources.entry(url).or_default().extend(ids);
+        }
+        Some(events)
+    }
+
     pub async fn next_page(&mut self) -> Option<Vec<Event>> {
+        self.sources.clear();
+        if self.forward {
+            return self.next_forward_page().await;
+        }
         if self.done {
             return None;
         }
@@ -144,36 +462,9 @@ impl EventPaginator {
             })
             .collect();
 
-        let events = if self.from_db {
-            // Attempt to fetch from the database first
-            match self
-                .client
-                .database()
-                .query(updated_filters.clone(), Order::Desc)
-                .await
-            {
-                Ok(events) => events,
-                // If database query fails, fall back to fetching from the relay
-                Err(err) => {
-                    tracing::error!("Database query failed: {:?}", err);
-                    self.done = true;
-                    return None;
-                }
-            }
-        } else {
-            // Directly fetch from the relay
-            match self
-                .client
-                .get_events_of(updated_filters.clone(), self.timeout)
-                .await
-            {
-                Ok(events) => events,
-                Err(err) => {
-                    tracing::error!("Relay fetch failed: {:?}", err);
-                    self.done = true;
-                    return None;
-                }
-            }
+        let Some(mut events) = self.query(updated_filters.clone()).await else {
+            self.done = true;
+            return None;
         };
 
         if events.is_empty() || self.are_all_event_ids_present(&events) {
@@ -192,32 +483,268 @@ impl EventPaginator {
         // Update the filters
         self.filters = updated_filters;
         self.last_event_ids = events.iter().map(|event| event.id).collect();
+        self.sort_page(&mut events);
+        Some(events)
+    }
+
+    /// Pages as a stream, ending where [`EventPaginator::next_page`] would
+    /// return `None`. The in-flight page is kept across polls, so a slow
+    /// relay never restarts the query.
+    pub fn into_stream(self) -> impl Stream<Item = Vec<Event>> {
+        futures::stream::unfold(self, |mut paginator| async move {
+            let page = paginator.next_page().await?;
+            Some((page, paginator))
+        })
+    }
+
+    async fn next_forward_page(&mut self) -> Option<Vec<Event>> {
+        let since = self.newest_timestamp;
+        let mut events: Vec<Event> = Vec::new();
+        let mut until = None;
+
+        // Relays return the newest matches first, so when a window is full
+        // keep walking back until it meets the previous page
+        loop {
+            let filters: Vec<Filter> = self
+                .filters
+                .iter()
+                .map(|f| {
+                    let mut f = f.clone().limit(self.page_size);
+                    if let Some(since) = since {
+                        f = f.since(since);
+                    }
+                    if let Some(until) = until {
+                        f = f.until(until);
+                    }
+                    f
+                })
+                .collect();
+            let window = self.query(filters).await?;
+            let full = window.len() >= self.page_size;
+            let oldest = get_oldest_event(&window).map(|event| event.created_at());
+            events.extend(window);
+
+            let Some(oldest) = oldest.filter(|_| full) else {
+                break;
+            };
+            // The limit may have cut the window's oldest second short
+            let boundary: Vec<Filter> = self
+                .filters
+                .iter()
+                .map(|f| f.clone().since(oldest).until(oldest))
+                .collect();
+            events.extend(self.query(boundary).await?);
+            if since.is_some_and(|since| oldest <= since) {
+                break;
+            }
+            until = Some(oldest - 1);
+        }
+
+        // Events sharing the previous newest timestamp were already returned
+        let mut seen = std::mem::take(&mut self.last_event_ids);
+        events.retain(|event| seen.insert(event.id));
+        events.sort_by_key(|event| (event.created_at(), event.id));
+
+        let Some(newest) = events.last().map(|event| event.created_at()) else {
+            self.last_event_ids = seen;
+            return None;
+        };
+        self.newest_timestamp = Some(newest);
+        self.last_event_ids = events
+            .iter()
+            .filter(|event| event.created_at() == newest)
+            .map(|event| event.id)
+            .collect();
+        self.sort_page(&mut events);
         Some(events)
     }
 }
 
-impl Stream for EventPaginator {
-    type Item = Option<Vec<Event>>;
+/// Several feeds paginated together as one timeline, e.g. the following
+/// feed, our own notes and reposts of them. Each filter gets its own
+/// [`EventPaginator`] and the pages are merged, so every page is strictly
+/// newest first across all feeds and an event matched by several filters
+/// shows up once.
+pub struct TimelinePaginator {
+    sources: Vec<TimelineSource>,
+    page_size: usize,
+    seen: HashSet<EventId>,
+}
+
+struct TimelineSource {
+    paginator: EventPaginator,
+    /// Fetched events not handed out yet, newest first
+    buffer: VecDeque<Event>,
+}
+
+impl TimelineSource {
+    fn needs_refill(&self) -> bool {
+        self.buffer.is_empty() && !self.paginator.done
+    }
+
+    async fn refill(&mut self) {
+        if let Some(events) = self.paginator.next_page().await {
+            self.buffer.extend(events);
+        }
+    }
+}
+
+impl TimelinePaginator {
+    pub fn new(
+        client: Arc<Client>,
+        filters: Vec<Filter>,
+        timeout: Option<Duration>,
+        page_size: usize,
+        policy: FetchPolicy,
+    ) -> Self {
+        let sources = filters
+            .into_iter()
+            .map(|filter| TimelineSource {
+                paginator: EventPaginator::new(
+                    Arc::clone(&client),
+                    vec![filter],
+                    timeout,
+                    page_size,
+                    policy,
+                ),
+                buffer: VecDeque::new(),
+            })
+            .collect();
+        Self {
+            sources,
+            page_size,
+            seen: HashSet::new(),
+        }
+    }
+
+    pub async fn next_page(&mut self) -> Option<Vec<Event>> {
+        let mut page = Vec::new();
+        while page.len() < self.page_size {
+            // A feed with an empty buffer may hold the next newest event,
+            // so it is refilled before anything is picked
+            futures::future::join_all(
+                self.sources
+                    .iter_mut()
+                    .filter(|source| source.needs_refill())
+                    .map(TimelineSource::refill),
+            )
+            .await;
+
+            let newest = self
+                .sources
+                .iter_mut()
+                .filter(|source| !source.buffer.is_empty())
+                .max_by_key(|source| {
+                    let event = &source.buffer[0];
+                    (event.created_at(), event.id)
+                });
+            let Some(event) = newest.and_then(|source| source.buffer.pop_front()) else {
+                break;
+            };
+            if self.seen.insert(event.id) {
+                page.push(event);
+            }
+        }
+        (!page.is_empty()).then_some(page)
+    }
+
+    /// Pages as a stream, see [`EventPaginator::into_stream`]
+    pub fn into_stream(self) -> impl Stream<Item = Vec<Event>> {
+        futures::stream::unfold(self, |mut paginator| async move {
+            let page = paginator.next_page().await?;
+            Some((page, paginator))
+        })
+    }
+}
+
+/// Bounded LRU of decrypted contents keyed by event id, so events fetched
+/// again (paginator re-fetches, audits) are not decrypted again. Clones
+/// share the same entries.
+///
+/// Entries are plaintext: only share a cache between readers holding the
+/// same keys.
+#[derive(Clone)]
+pub struct DecryptCache {
+    entries: Arc<std::sync::Mutex<SizedCache<EventId, String>>>,
+}
+
+impl DecryptCache {
+    pub const DEFAULT_CAPACITY: usize = 1024;
+
+    /// Panics if `capacity` is zero
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: Arc::new(std::sync::Mutex::new(SizedCache::with_size(capacity))),
+        }
+    }
+
+    pub fn get(&self, id: &EventId) -> Option<String> {
+        self.entries.lock().unwrap().cache_get(id).cloned()
+    }
+
+    pub fn insert(&self, id: EventId, content: String) {
+        self.entries.lock().unwrap().cache_set(id, content);
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().cache_size()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().cache_clear();
+    }
 
-    fn poll_next(
-        mut self: std::pin::Pin<&mut Self>,
-        cx: &mut std::task::Context<'_>,
-    ) -> std::task::Poll<Option<Self::Item>> {
-        let fut = self.next_page();
-        futures::pin_mut!(fut);
-        match fut.poll(cx) {
-            std::task::Poll::Ready(res) => std::task::Poll::Ready(Some(res)),
-            std::task::Poll::Pending => std::task::Poll::Pending,
+    /// The cached content of `id`, or `decrypt`'s result, which is cached
+    /// when it succeeds
+    pub(crate) async fn get_or_decrypt<F, E>(
+        &self,
+        id: EventId,
+        decrypt: F,
+    ) -> std::result::Result<String, E>
+    where
+        F: Future<Output = std::result::Result<String, E>>,
+    {
+        if let Some(content) = self.get(&id) {
+            return Ok(content);
         }
+        let content = decrypt.await?;
+        self.insert(id, content.clone());
+        Ok(content)
+    }
+}
+
+impl Default for DecryptCache {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_CAPACITY)
+    }
+}
+
+// Entries are plaintext, keep them out of logs
+impl std::fmt::Debug for DecryptCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DecryptCache")
+            .field("len", &self.len())
+            .finish()
     }
 }
 
+/// Direct messages with one peer, newest first: NIP-04 kind 4 messages
+/// (NIP-04 or NIP-44 payloads) and NIP-17 private messages, which arrive
+/// gift-wrapped (kind 1059) around a sealed (kind 13) kind 14 rumor.
+///
+/// Gift wraps carry randomized timestamps, so pages are cut on the outer
+/// timestamp but ordered by the rumor's.
 pub struct DecryptedMsgPaginator<'a> {
     signer: &'a NostrSigner,
+    public_key: PublicKey,
     target_pub_key: PublicKey,
     paginator: EventPaginator,
+    cache: DecryptCache,
 }
-
 impl<'a> DecryptedMsgPaginator<'a> {
     pub async fn new(
         signer: &'a NostrSigner,
@@ -225,28 +752,79 @@ impl<'a> DecryptedMsgPaginator<'a> {
         target_pub_key: PublicKey,
         timeout: Option<Duration>,
         page_size: usize,
-        from_db: bool,
+        policy: FetchPolicy,
     ) -> Result<DecryptedMsgPaginator<'a>> {
         let public_key = signer.public_key().await?;
 
         let (me, target) =
             create_encrypted_filters!(Kind::EncryptedDirectMessage, target_pub_key, public_key);
-        let filters = vec![me, target];
+        // Gift wraps hide the sender; every one addressed to us is fetched
+        // and those from other conversations are dropped once unwrapped
+        let gift_wraps = Filter::new().kind(Kind::GiftWrap).pubkey(public_key);
+        let filters = vec![me, target, gift_wraps];
 
-        let paginator = EventPaginator::new(client, filters, timeout, page_size, from_db);
+        let paginator = EventPaginator::new(client, filters, timeout, page_size, policy);
         Ok(DecryptedMsgPaginator {
             signer,
+            public_key,
             target_pub_key,
             paginator,
+            cache: DecryptCache::default(),
         })
     }
 
+    /// Share `cache` with other paginators of the same signer, so reopening
+    /// a conversation does not decrypt it all over again
+    pub fn with_decrypt_cache(mut self, cache: DecryptCache) -> Self {
+        self.cache = cache;
+        self
+    }
+
     async fn decrypt_dm_event(&self, event: &Event) -> Result<String> {
-        let msg = self
-            .signer
-            .nip04_decrypt(self.target_pub_key, &event.content)
-            .await?;
-        Ok(msg)
+        self.cache
+            .get_or_decrypt(
+                event.id,
+                decrypt_dm(self.signer, self.target_pub_key, &event.content),
+            )
+            .await
+    }
+
+    /// The kind 14 rumor inside a gift wrap, if it belongs to this
+    /// conversation
+    async fn unwrap_private_msg(&self, gift_wrap: &Event) -> Option<DecryptedMsg> {
+        // Cached as the rumor's JSON, both layers are skipped on a hit
+        let rumor = match self.cache.get(&gift_wrap.id) {
+            Some(json) => UnsignedEvent::from_json(json).ok()?,
+            None => {
+                let rumor = unwrap_private_msg(self.signer, gift_wrap).await?;
+                self.cache.insert(gift_wrap.id, rumor.as_json());
+                rumor
+            }
+        };
+        let tags_peer = |peer: &PublicKey| rumor_recipients(&rumor).any(|p| p == *peer);
+        let in_conversation = (rumor.pubkey == self.target_pub_key && tags_peer(&self.public_key))
+            || (rumor.pubkey == self.public_key && tags_peer(&self.target_pub_key));
+        if !in_conversation {
+            return None;
+        }
+
+        let id = rumor.id.unwrap_or_else(|| {
+            EventId::new(
+                &rumor.pubkey,
+                &rumor.created_at,
+                &rumor.kind,
+                &rumor.tags,
+                &rumor.content,
+            )
+        });
+        Some(DecryptedMsg {
+            id,
+            pubkey: rumor.pubkey,
+            created_at: rumor.created_at,
+            kind: rumor.kind,
+            tags: rumor.tags,
+            content: Some(rumor.content),
+        })
     }
 
     async fn convert_events(&self, events: Vec<Event>) -> Result<Vec<DecryptedMsg>> {
@@ -255,15 +833,24 @@ impl<'a> DecryptedMsgPaginator<'a> {
             .map(|event| {
                 let self_ref = self;
                 async move {
+                    if event.kind == Kind::GiftWrap {
+                        return Ok::<_, Error>(self_ref.unwrap_private_msg(&event).await);
+                    }
                     let msg = self_ref.decrypt_dm_event(&event).await?;
                     let mut decrypted_msg: DecryptedMsg = event.into();
                     decrypted_msg.content = Some(msg);
-                    Ok(decrypted_msg)
+                    Ok(Some(decrypted_msg))
                 }
             })
             .collect();
 
-        futures::future::try_join_all(futures).await
+        let mut msgs: Vec<DecryptedMsg> = futures::future::try_join_all(futures)
+            .await?
+            .into_iter()
+            .flatten()
+            .collect();
+        msgs.sort_by_key(|msg| std::cmp::Reverse((msg.created_at, msg.id)));
+        Ok(msgs)
     }
 
     pub async fn next_page(&mut self) -> Option<Vec<DecryptedMsg>> {
@@ -280,114 +867,1145 @@ impl<'a> DecryptedMsgPaginator<'a> {
     }
 }
 
-pub async fn get_event_by_id(
-    client: &Client,
-    event_id: &EventId,
-    timeout: Option<std::time::Duration>,
-) -> Result<Option<Event>> {
-    let filter = Filter::new().id(*event_id).limit(1);
-    let events = client.get_events_of(vec![filter], timeout).await?;
-    Ok(events.into_iter().next())
+/// NIP-17 kind 14 rumor inside a gift wrap. Anyone can gift-wrap to us, so
+/// anything invalid is skipped rather than reported.
+async fn unwrap_private_msg(signer: &NostrSigner, gift_wrap: &Event) -> Option<UnsignedEvent> {
+    let seal = signer
+        .nip44_decrypt(gift_wrap.pubkey, &gift_wrap.content)
+        .await
+        .ok()?;
+    let seal = Event::from_json(seal).ok()?;
+    if seal.kind != Kind::Seal || seal.verify().is_err() {
+        tracing::debug!("Dropping gift wrap {} with an invalid seal", gift_wrap.id);
+        return None;
+    }
+    let rumor = signer
+        .nip44_decrypt(seal.pubkey, &seal.content)
+        .await
+        .ok()?;
+    let rumor = UnsignedEvent::from_json(rumor).ok()?;
+    // The seal signer is the real sender; a rumor claiming someone else is
+    // forged
+    if rumor.kind != Kind::PrivateDirectMessage || rumor.pubkey != seal.pubkey {
+        return None;
+    }
+    Some(rumor)
 }
 
-pub async fn get_events_by_ids(
-    client: &Client,
-    event_ids: &[EventId],
-    timeout: Option<std::time::Duration>,
-) -> Result<Vec<Event>> {
-    let filters: Vec<Filter> = event_ids.iter().map(|id| Filter::new().id(*id)).collect();
-    let events = client.get_events_of(filters, timeout).await?;
-    Ok(events)
+fn rumor_recipients(rumor: &UnsignedEvent) -> impl Iterator<Item = PublicKey> + '_ {
+    rumor
+        .tags
+        .iter()
+        .filter_map(|tag| match tag.as_standardized() {
+            Some(TagStandard::PublicKey { public_key, .. }) => Some(*public_key),
+            _ => None,
+        })
 }
 
-pub async fn get_metadata(
+/// One direct message conversation, for a chat list
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conversation {
+    pub peer: PublicKey,
+    /// Newest message, `None` if it could not be decrypted
+    pub last_message: Option<String>,
+    pub last_message_at: Timestamp,
+    /// Newest message from the peer; compare with a stored "read up to"
+    /// time to show the conversation as unread
+    pub last_received_at: Option<Timestamp>,
+    pub message_count: usize,
+}
+
+/// Direct message conversations of the signer, in both directions and
+/// across NIP-04 and NIP-17 messages, most recently active first
+pub async fn get_conversations(
     client: &Client,
-    public_key: &PublicKey,
+    signer: &NostrSigner,
     timeout: Option<Duration>,
-) -> Result<Metadata> {
-    let filter = Filter::new().author(*public_key).kind(Kind::Metadata);
-    let events = client.get_events_of(vec![filter], timeout).await?;
+) -> Result<Vec<Conversation>> {
+    let me = signer.public_key().await?;
+    let filters = vec![
+        Filter::new().kind(Kind::EncryptedDirectMessage).author(me),
+        Filter::new().kind(Kind::EncryptedDirectMessage).pubkey(me),
+        Filter::new().kind(Kind::GiftWrap).pubkey(me),
+    ];
+    let events = client.get_events_of(filters, timeout).await?;
+    Ok(summarize_conversations(signer, me, &events).await)
+}
 
-    if let Some(event) = get_newest_event(&events) {
-        let metadata = Metadata::from_json(&event.content)?;
-        client.database().save_event(event).await?;
-        Ok(metadata)
-    } else {
-        Err(Error::EventNotFound)
+async fn summarize_conversations(
+    signer: &NostrSigner,
+    me: PublicKey,
+    events: &[Event],
+) -> Vec<Conversation> {
+    // (peer, sender, created_at, plaintext if known, kind 4 event to decrypt)
+    let mut messages = Vec::new();
+    let mut seen = HashSet::new();
+    for event in events.iter().filter(|event| seen.insert(event.id)) {
+        if event.kind == Kind::GiftWrap {
+            let Some(rumor) = unwrap_private_msg(signer, event).await else {
+                continue;
+            };
+            let peer = if rumor.pubkey == me {
+                rumor_recipients(&rumor).find(|p| *p != me).unwrap_or(me)
+            } else {
+                rumor.pubkey
+            };
+            messages.push((
+                peer,
+                rumor.pubkey,
+                rumor.created_at,
+                Some(rumor.content),
+                None,
+            ));
+        } else {
+            let peer = if event.pubkey == me {
+                match event.public_keys().next() {
+                    Some(peer) => *peer,
+                    None => continue,
+                }
+            } else {
+                event.pubkey
+            };
+            messages.push((peer, event.pubkey, event.created_at, None, Some(event)));
+        }
+    }
+    messages.sort_by_key(|(_, _, created_at, _, _)| std::cmp::Reverse(*created_at));
+
+    let mut conversations: Vec<Conversation> = Vec::new();
+    let mut index: HashMap<PublicKey, usize> = HashMap::new();
+    for (peer, sender, created_at, content, event) in messages {
+        if let Some(&i) = index.get(&peer) {
+            let conversation = &mut conversations[i];
+            conversation.message_count += 1;
+            if sender == peer && conversation.last_received_at.is_none() {
+                conversation.last_received_at = Some(created_at);
+            }
+            continue;
+        }
+        // Only the newest message of each conversation is decrypted
+        let last_message = match (content, event) {
+            (Some(content), _) => Some(content),
+            (None, Some(event)) => decrypt_dm(signer, peer, &event.content).await.ok(),
+            (None, None) => None,
+        };
+        index.insert(peer, conversations.len());
+        conversations.push(Conversation {
+            peer,
+            last_message,
+            last_message_at: created_at,
+            last_received_at: (sender == peer).then_some(created_at),
+            message_count: 1,
+        });
+    }
+    conversations
+}
+
+/// Kind 4 content: NIP-04 payloads carry the IV after `?iv=`, anything
+/// else is NIP-44
+async fn decrypt_dm(signer: &NostrSigner, peer: PublicKey, content: &str) -> Result<String> {
+    let msg = if content.contains("?iv=") {
+        if !is_nip04_payload(content) {
+            return Err(nostr_sdk::nips::nip04::Error::InvalidContentFormat.into());
+        }
+        signer.nip04_decrypt(peer, content).await?
+    } else {
+        signer.nip44_decrypt(peer, content).await?
+    };
+    Ok(msg)
+}
+
+/// Consecutive failures after which a relay is quarantined
+const QUARANTINE_AFTER: u32 = 3;
+/// First quarantine period, doubled on every further failure
+const QUARANTINE_BASE: Duration = Duration::from_secs(60);
+const QUARANTINE_MAX: Duration = Duration::from_secs(30 * 60);
+
+/// Health of one relay as seen by the per-relay fetch helpers
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct RelayStats {
+    pub successes: u32,
+    /// Failed queries, timeouts included
+    pub failures: u32,
+    pub timeouts: u32,
+    /// Failures since the last success
+    pub consecutive_failures: u32,
+    /// Moving average over successful queries
+    pub avg_latency: Option<Duration>,
+    /// Skipped by the fetch helpers until then
+    pub quarantined_until: Option<Timestamp>,
+}
+
+impl RelayStats {
+    /// Share of successful queries, 1 for a relay never queried
+    pub fn success_rate(&self) -> f64 {
+        let total = self.successes + self.failures;
+        if total == 0 {
+            return 1.0;
+        }
+        f64::from(self.successes) / f64::from(total)
+    }
+
+    pub fn is_quarantined(&self) -> bool {
+        self.quarantined_until
+            .is_some_and(|until| until > Timestamp::now())
+    }
+
+    fn record_success(&mut self, latency: Duration) {
+        self.successes += 1;
+        self.consecutive_failures = 0;
+        self.quarantined_until = None;
+        // Weigh the new sample by 1/4 so one slow answer does not dominate
+        self.avg_latency = Some(match self.avg_latency {
+            Some(avg) => (avg * 3 + latency) / 4,
+            None => latency,
+        });
+    }
+
+    fn record_failure(&mut self, timed_out: bool) {
+        self.failures += 1;
+        self.consecutive_failures += 1;
+        if timed_out {
+            self.timeouts += 1;
+        }
+        if self.consecutive_failures >= QUARANTINE_AFTER {
+            let doublings = (self.consecutive_failures - QUARANTINE_AFTER).min(16);
+            let period = (QUARANTINE_BASE * 2u32.pow(doublings)).min(QUARANTINE_MAX);
+            self.quarantined_until = Some(Timestamp::now() + period);
+        }
+    }
+}
+
+fn relay_health() -> &'static std::sync::Mutex<HashMap<Url, RelayStats>> {
+    static HEALTH: std::sync::OnceLock<std::sync::Mutex<HashMap<Url, RelayStats>>> =
+        std::sync::OnceLock::new();
+    HEALTH.get_or_init(Default::default)
+}
+
+/// Health of every relay queried through the per-relay fetch helpers
+pub fn relay_stats() -> HashMap<Url, RelayStats> {
+    relay_health().lock().unwrap().clone()
+}
+
+/// `relays` without the quarantined ones. When every relay is quarantined
+/// they are all kept, so a flaky network never leaves nothing to ask.
+fn healthy_relays(mut relays: HashMap<Url, Relay>) -> HashMap<Url, Relay> {
+    let health = relay_health().lock().unwrap();
+    let quarantined = |url: &Url| health.get(url).is_some_and(RelayStats::is_quarantined);
+    if relays.keys().all(quarantined) {
+        return relays;
+    }
+    relays.retain(|url, _| !quarantined(url));
+    relays
+}
+
+/// Runs the same query on every healthy relay concurrently, recording the
+/// outcome of each in [`relay_stats`]
+async fn fetch_from_each(
+    relays: HashMap<Url, Relay>,
+    filters: Vec<Filter>,
+    timeout: Option<Duration>,
+) -> Vec<(
+    Url,
+    std::result::Result<Vec<Event>, nostr_sdk::pool::relay::Error>,
+)> {
+    let timeout = timeout.unwrap_or(DEFAULT_RELAY_TIMEOUT);
+    futures::future::join_all(healthy_relays(relays).into_iter().map(|(url, relay)| {
+        let filters = filters.clone();
+        async move {
+            let started = Instant::now();
+            let result = relay
+                .get_events_of(filters, timeout, FilterOptions::ExitOnEOSE)
+                .await;
+            let mut health = relay_health().lock().unwrap();
+            let stats = health.entry(url.clone()).or_default();
+            match &result {
+                Ok(_) => stats.record_success(started.elapsed()),
+                Err(err) => stats.record_failure(matches!(
+                    err,
+                    RelayError::Timeout | RelayError::RecvTimeout | RelayError::WebSocketTimeout
+                )),
+            }
+            drop(health);
+            (url, result)
+        }
+    }))
+    .await
+}
+
+/// How many relays must signal end of stored events before
+/// [`get_events_until_eose`] returns
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EoseQuorum {
+    /// Every connected relay
+    #[default]
+    All,
+    /// At least this many relays, or all of them when fewer are connected
+    AtLeast(usize),
+}
+
+impl EoseQuorum {
+    fn required(self, relays: usize) -> usize {
+        match self {
+            EoseQuorum::All => relays,
+            EoseQuorum::AtLeast(count) => count.clamp(1, relays.max(1)),
+        }
+    }
+}
+
+/// Events of one subscription, and the relays done sending stored events
+struct EoseTally {
+    id: SubscriptionId,
+    required: usize,
+    done: HashSet<Url>,
+    events: HashMap<EventId, Event>,
+}
+
+impl EoseTally {
+    fn new(id: SubscriptionId, required: usize) -> Self {
+        Self {
+            id,
+            required,
+            done: HashSet::new(),
+            events: HashMap::new(),
+        }
+    }
+
+    /// Record one notification; returns whether the quorum is reached
+    fn observe(&mut self, notification: RelayPoolNotification) -> bool {
+        let RelayPoolNotification::Message { relay_url, message } = notification else {
+            return self.is_complete();
+        };
+        match message {
+            RelayMessage::Event {
+                subscription_id,
+                event,
+            } if subscription_id == self.id => {
+                self.events.entry(event.id).or_insert(*event);
+            }
+            // A relay refusing the subscription has nothing more to send
+            RelayMessage::EndOfStoredEvents(subscription_id)
+            | RelayMessage::Closed {
+                subscription_id, ..
+            } if subscription_id == self.id => {
+                self.done.insert(relay_url);
+            }
+            _ => {}
+        }
+        self.is_complete()
+    }
+
+    fn is_complete(&self) -> bool {
+        self.done.len() >= self.required
+    }
+
+    async fn collect(&mut self, notifications: &mut broadcast::Receiver<RelayPoolNotification>) {
+        loop {
+            match notifications.recv().await {
+                Ok(notification) => {
+                    if self.observe(notification) {
+                        return;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    tracing::warn!("EOSE fetch lagged, {} notifications dropped", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => return,
+            }
+        }
+    }
+}
+
+/// Like `Client::get_events_of`, but returns as soon as `quorum` of the
+/// connected relays sent EOSE instead of waiting for the slowest one. A
+/// relay closing the subscription counts as done. When the timeout hits
+/// first, the events received so far are returned. Newest first.
+pub async fn get_events_until_eose(
+    client: &Client,
+    filters: Vec<Filter>,
+    quorum: EoseQuorum,
+    timeout: Option<Duration>,
+) -> Result<Vec<Event>> {
+    let mut relays = Vec::new();
+    for (url, relay) in client.relays().await {
+        if relay.status().await == RelayStatus::Connected {
+            relays.push(url);
+        }
+    }
+    // The pool does not deliver events it already saved, so start from those
+    let stored = client
+        .database()
+        .query(filters.clone(), Order::Desc)
+        .await?;
+    if relays.is_empty() {
+        return Ok(stored);
+    }
+
+    let id = SubscriptionId::generate();
+    let mut tally = EoseTally::new(id.clone(), quorum.required(relays.len()));
+    // Listen before subscribing so no early event is missed
+    let mut notifications = client.notifications();
+    client
+        .subscribe_with_id_to(relays, id.clone(), filters, None)
+        .await?;
+
+    let timeout = timeout.unwrap_or(DEFAULT_RELAY_TIMEOUT);
+    let collect = Box::pin(tally.collect(&mut notifications));
+    if let futures::future::Either::Right(_) =
+        futures::future::select(collect, Box::pin(runtime::sleep(timeout))).await
+    {
+        tracing::debug!("EOSE fetch timed out, returning partial results");
+    }
+    client.unsubscribe(id).await;
+
+    Ok(merge_events(stored, tally.events.into_values().collect()))
+}
+
+/// NIP-50 full-text search. Only relays whose NIP-11 document lists NIP-50
+/// are asked; the others would ignore the `search` field and return
+/// unrelated events. Results are merged, deduplicated and newest first.
+pub async fn search_events(
+    client: &Client,
+    query: &str,
+    filters: Vec<Filter>,
+    timeout: Option<Duration>,
+) -> Result<Vec<Event>> {
+    let relays = RelayCapabilitiesCache::global()
+        .relays_where(client, RelayCapabilities::supports_search)
+        .await;
+    if relays.is_empty() {
+        tracing::debug!("No connected relay supports NIP-50 search");
+    }
+
+    let filters = if filters.is_empty() {
+        vec![Filter::new()]
+    } else {
+        filters
+    };
+    let filters: Vec<Filter> = filters.into_iter().map(|f| f.search(query)).collect();
+
+    let mut seen = HashSet::new();
+    let mut events: Vec<Event> = Vec::new();
+    for (url, result) in fetch_from_each(relays, filters, timeout).await {
+        match result {
+            Ok(relay_events) => events.extend(
+                relay_events
+                    .into_iter()
+                    .filter(|event| seen.insert(event.id)),
+            ),
+            Err(err) => tracing::warn!("Search on {} failed: {:?}", url, err),
+        }
+    }
+    events.sort_by_key(|event| std::cmp::Reverse(event.created_at()));
+    Ok(events)
+}
+
+pub async fn get_event_by_id(
+    client: &Client,
+    event_id: &EventId,
+    timeout: Option<std::time::Duration>,
+) -> Result<Option<Event>> {
+    let filter = Filter::new().id(*event_id).limit(1);
+    let events = client.get_events_of(vec![filter], timeout).await?;
+    Ok(events.into_iter().next())
+}
+
+pub async fn get_events_by_ids(
+    client: &Client,
+    event_ids: &[EventId],
+    timeout: Option<std::time::Duration>,
+) -> Result<Vec<Event>> {
+    let filters: Vec<Filter> = event_ids.iter().map(|id| Filter::new().id(*id)).collect();
+    let events = client.get_events_of(filters, timeout).await?;
+    Ok(events)
+}
+
+pub async fn get_metadata(
+    client: &Client,
+    public_key: &PublicKey,
+    timeout: Option<Duration>,
+) -> Result<Metadata> {
+    let event = get_metadata_event(client, public_key, timeout).await?;
+    Ok(Metadata::from_json(&event.content)?)
+}
+
+/// Newest kind 0 event of `public_key` on the relays, saved to the database
+async fn get_metadata_event(
+    client: &Client,
+    public_key: &PublicKey,
+    timeout: Option<Duration>,
+) -> Result<Event> {
+    let filter = Filter::new().author(*public_key).kind(Kind::Metadata);
+    let events = client.get_events_of(vec![filter], timeout).await?;
+
+    if let Some(event) = get_newest_event(&events) {
+        client.database().save_event(event).await?;
+        Ok(event.clone())
+    } else {
+        Err(Error::EventNotFound)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct CachedMetadata {
+    metadata: Metadata,
+    /// `created_at` of the kind 0 event the metadata came from
+    created_at: Timestamp,
+    /// When the entry was last confirmed against the relays
+    fetched_at: Timestamp,
+}
+
+/// In-memory cache in front of [`get_metadata`], keyed by public key.
+///
+/// Entries younger than the TTL are served as is. Stale entries are still
+/// served, and refreshed from the relays in the background
+/// (stale-while-revalidate). When the [`FetchPolicy`] reads the cache, a
+/// miss is first looked up in the client database (IndexedDB in the
+/// browser) and treated as stale; `CacheOnly` never asks the relays.
+/// Clones share the same entries.
+#[derive(Debug, Clone)]
+pub struct MetadataCache {
+    client: Arc<Client>,
+    ttl: Duration,
+    timeout: Option<Duration>,
+    policy: FetchPolicy,
+    entries: Arc<std::sync::Mutex<HashMap<PublicKey, CachedMetadata>>>,
+    /// Keys with a background refresh in flight
+    refreshing: Arc<std::sync::Mutex<HashSet<PublicKey>>>,
+}
+
+impl MetadataCache {
+    pub fn new(client: Arc<Client>, ttl: Duration, timeout: Option<Duration>) -> Self {
+        Self {
+            client,
+            ttl,
+            timeout,
+            policy: FetchPolicy::RelayOnly,
+            entries: Arc::new(std::sync::Mutex::new(HashMap::new())),
+            refreshing: Arc::new(std::sync::Mutex::new(HashSet::new())),
+        }
+    }
+
+    pub fn policy(mut self, policy: FetchPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub async fn get(&self, public_key: &PublicKey) -> Result<Metadata> {
+        let cached = self.entries.lock().unwrap().get(public_key).cloned();
+        if let Some(cached) = cached {
+            if !self.is_stale(&cached) {
+                return Ok(cached.metadata);
+            }
+            self.revalidate(*public_key);
+            return Ok(cached.metadata);
+        }
+
+        if self.policy.reads_cache() {
+            let filter = Filter::new().author(*public_key).kind(Kind::Metadata);
+            let events = self
+                .client
+                .database()
+                .query(vec![filter], Order::Desc)
+                .await?;
+            if let Some(event) = get_newest_event(&events) {
+                let metadata = Metadata::from_json(&event.content)?;
+                self.store(event, metadata.clone(), Timestamp::from(0));
+                self.revalidate(*public_key);
+                return Ok(metadata);
+            }
+        }
+        if !self.policy.reads_relays() {
+            return Err(Error::EventNotFound);
+        }
+
+        self.refresh(public_key).await
+    }
+
+    /// Fetch from the relays now, regardless of the TTL
+    pub async fn refresh(&self, public_key: &PublicKey) -> Result<Metadata> {
+        let event = get_metadata_event(&self.client, public_key, self.timeout).await?;
+        let metadata = Metadata::from_json(&event.content)?;
+        self.store(&event, metadata.clone(), Timestamp::now());
+        Ok(metadata)
+    }
+
+    /// Feed a kind 0 event seen elsewhere, e.g. on a live subscription.
+    /// It replaces the cached entry if it is newer.
+    pub fn observe(&self, event: &Event) {
+        if event.kind != Kind::Metadata {
+            return;
+        }
+        match Metadata::from_json(&event.content) {
+            Ok(metadata) => self.store(event, metadata, Timestamp::now()),
+            Err(err) => tracing::debug!("Ignoring invalid metadata {}: {}", event.id, err),
+        }
+    }
+
+    pub fn invalidate(&self, public_key: &PublicKey) {
+        self.entries.lock().unwrap().remove(public_key);
+    }
+
+    fn is_stale(&self, cached: &CachedMetadata) -> bool {
+        cached.fetched_at.as_u64() + self.ttl.as_secs() <= Timestamp::now().as_u64()
+    }
+
+    /// Keep the entry with the newest event; an older event only refreshes
+    /// `fetched_at` when it is the one already cached
+    fn store(&self, event: &Event, metadata: Metadata, fetched_at: Timestamp) {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get_mut(&event.pubkey) {
+            Some(cached) if cached.created_at > event.created_at => {}
+            Some(cached) if cached.created_at == event.created_at => {
+                cached.fetched_at = cached.fetched_at.max(fetched_at);
+            }
+            _ => {
+                entries.insert(
+                    event.pubkey,
+                    CachedMetadata {
+                        metadata,
+                        created_at: event.created_at,
+                        fetched_at,
+                    },
+                );
+            }
+        }
+    }
+
+    fn revalidate(&self, public_key: PublicKey) {
+        if !self.policy.reads_relays() || !self.refreshing.lock().unwrap().insert(public_key) {
+            return;
+        }
+        let cache = self.clone();
+        runtime::spawn(async move {
+            if let Err(err) = cache.refresh(&public_key).await {
+                tracing::warn!("Failed to refresh metadata of {}: {}", public_key, err);
+            }
+            cache.refreshing.lock().unwrap().remove(&public_key);
+        });
+    }
+}
+
+/// NIP-65 relay list of `public_key`, from its newest kind 10002 event.
+/// A relay without a marker is used for both reading and writing.
+pub async fn get_relay_list(
+    client: &Client,
+    public_key: &PublicKey,
+    timeout: Option<Duration>,
+) -> Result<Vec<(Url, Option<RelayMetadata>)>> {
+    let filter = Filter::new().author(*public_key).kind(Kind::RelayList);
+    let events = client.get_events_of(vec![filter], timeout).await?;
+    let Some(event) = get_newest_event(&events) else {
+        return Ok(Vec::new());
+    };
+    Ok(nostr_sdk::nips::nip65::extract_relay_list(event)
+        .into_iter()
+        .map(|(url, metadata)| (url.clone(), metadata.clone()))
+        .collect())
+}
+
+/// Our NIP-78 settings for `app_name`, `None` when never set. Content
+/// written encrypted by `set_app_data` is decrypted with `signer`.
+pub async fn get_app_data(
+    client: &Client,
+    signer: &NostrSigner,
+    app_name: &str,
+    timeout: Option<Duration>,
+) -> Result<Option<serde_json::Value>> {
+    let public_key = signer.public_key().await?;
+    let filter = Filter::new()
+        .author(public_key)
+        .kind(Kind::ApplicationSpecificData)
+        .identifier(app_name);
+    let events = client.get_events_of(vec![filter], timeout).await?;
+    match get_newest_event(&events) {
+        Some(event) => Ok(Some(decode_app_data(signer, event).await?)),
+        None => Ok(None),
+    }
+}
+
+/// Plain content is json; anything else is a NIP-44 payload, whose base64
+/// never parses as json
+async fn decode_app_data(signer: &NostrSigner, event: &Event) -> Result<serde_json::Value> {
+    if let Ok(data) = serde_json::from_str(&event.content) {
+        return Ok(data);
+    }
+    let plaintext = signer.nip44_decrypt(event.author(), &event.content).await?;
+    Ok(serde_json::from_str(&plaintext)?)
+}
+
+/// Relays `public_key` reads NIP-17 direct messages from (kind 10050)
+pub async fn get_dm_relays(
+    client: &Client,
+    public_key: &PublicKey,
+    timeout: Option<Duration>,
+) -> Result<Vec<Url>> {
+    let filter = Filter::new()
+        .author(*public_key)
+        .kind(Kind::Custom(KIND_DM_RELAYS));
+    let events = client.get_events_of(vec![filter], timeout).await?;
+    let Some(event) = get_newest_event(&events) else {
+        return Ok(Vec::new());
+    };
+    Ok(event
+        .iter_tags()
+        .filter_map(|tag| match tag.as_vec() {
+            [kind, url, ..] if kind == "relay" => Url::parse(url).ok(),
+            _ => None,
+        })
+        .collect())
+}
+
+/// Relays `public_key` publishes to (its NIP-65 outbox)
+pub async fn get_write_relays(
+    client: &Client,
+    public_key: &PublicKey,
+    timeout: Option<Duration>,
+) -> Result<Vec<Url>> {
+    let relays = get_relay_list(client, public_key, timeout).await?;
+    Ok(relays
+        .into_iter()
+        .filter(|(_, metadata)| *metadata != Some(RelayMetadata::Read))
+        .map(|(url, _)| url)
+        .collect())
+}
+
+/// Outbox-model fetch of events written by `author`: the author's declared
+/// write relays are added to the pool and queried along with the client's
+/// relays, so notes published outside our relay set are still found.
+/// Falls back to the client's relays when the author has no relay list.
+pub async fn get_events_from_outbox(
+    client: &Client,
+    author: &PublicKey,
+    filters: Vec<Filter>,
+    timeout: Option<Duration>,
+) -> Result<Vec<Event>> {
+    let outbox = get_write_relays(client, author, timeout).await?;
+    for url in outbox.iter() {
+        if let Err(err) = client.add_relay(url.clone()).await {
+            tracing::warn!("Skipping outbox relay {}: {:?}", url, err);
+            continue;
+        }
+        client.connect_relay(url.clone()).await?;
+    }
+    let filters = filters.into_iter().map(|f| f.author(*author)).collect();
+    Ok(client.get_events_of(filters, timeout).await?)
+}
+
+pub async fn get_zap(
+    client: &Client,
+    event_id: &EventId,
+    timeout: Option<std::time::Duration>,
+) -> Result<Vec<Event>> {
+    let filter = Filter::new().kind(Kind::ZapReceipt).event(*event_id);
+    let events = client.get_events_of(vec![filter], timeout).await?;
+    Ok(events)
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ZapTotal {
+    /// Sum of every receipt, in millisats
+    pub total_msats: u64,
+    /// Millisats per zap sender; receipts without a known sender only count
+    /// towards the total
+    pub by_zapper: HashMap<PublicKey, u64>,
+}
+
+pub async fn get_zap_total(
+    client: &Client,
+    event_id: &EventId,
+    timeout: Option<std::time::Duration>,
+) -> Result<ZapTotal> {
+    let receipts = get_zap(client, event_id, timeout).await?;
+
+    let mut total = ZapTotal::default();
+    // Relays may return the same receipt more than once
+    let mut seen = HashSet::new();
+    for receipt in receipts.into_iter().filter(|event| seen.insert(event.id)) {
+        let zap = ZapReceipt::from(receipt);
+        let Some(amount) = zap.amount_msats else {
+            continue;
+        };
+        total.total_msats += amount;
+        if let Some(sender) = zap.sender {
+            *total.by_zapper.entry(sender).or_insert(0) += amount;
+        }
+    }
+    Ok(total)
+}
+
+/// A zap receipt (kind 9735) with the fields of its invoice and zap request
+/// already parsed
+#[derive(Debug, Clone)]
+pub struct ZapReceipt {
+    /// Millisats paid, when the invoice or the zap request states it
+    pub amount_msats: Option<u64>,
+    pub sender: Option<PublicKey>,
+    /// Zapped event, `None` for a profile zap
+    pub event_id: Option<EventId>,
+    /// Message the sender attached to the zap request
+    pub comment: Option<String>,
+    pub receipt: Event,
+}
+
+impl From<Event> for ZapReceipt {
+    fn from(receipt: Event) -> Self {
+        let comment = zap_request(&receipt)
+            .map(|request| request.content.clone())
+            .filter(|content| !content.is_empty());
+        Self {
+            amount_msats: zap_receipt_msats(&receipt),
+            sender: zap_sender(&receipt),
+            event_id: tag_value(&receipt, "e").and_then(|id| EventId::from_hex(id).ok()),
+            comment,
+            receipt,
+        }
+    }
+}
+
+fn tag_value<'a>(event: &'a Event, name: &str) -> Option<&'a str> {
+    event.tags.iter().find_map(|tag| match tag.as_vec() {
+        [kind, value, ..] if kind == name => Some(value.as_str()),
+        _ => None,
+    })
+}
+
+/// The zap request embedded in a receipt's `description` tag
+fn zap_request(receipt: &Event) -> Option<Event> {
+    Event::from_json(tag_value(receipt, "description")?).ok()
+}
+
+/// Amount paid, from the invoice or else the zap request's `amount` tag
+fn zap_receipt_msats(receipt: &Event) -> Option<u64> {
+    tag_value(receipt, "bolt11")
+        .and_then(parse_bolt11_msats)
+        .or_else(|| tag_value(&zap_request(receipt)?, "amount")?.parse().ok())
+}
+
+/// Who sent the zap: the zap request's author, or else the `P` tag
+fn zap_sender(receipt: &Event) -> Option<PublicKey> {
+    zap_request(receipt)
+        .map(|request| request.pubkey)
+        .or_else(|| PublicKey::from_hex(tag_value(receipt, "P")?).ok())
+}
+
+pub async fn get_repost(
+    client: &Client,
+    event_id: &EventId,
+    timeout: Option<std::time::Duration>,
+) -> Result<Vec<Event>> {
+    let filter = Filter::new().kind(Kind::Repost).event(*event_id);
+    let events = client.get_events_of(vec![filter], timeout).await?;
+    Ok(events)
+}
+
+/// Reaction counts per content. With `CacheThenRelay` the relays are only
+/// asked for reactions newer than the newest cached one.
+pub async fn get_reactions(
+    client: &Client,
+    event_id: &EventId,
+    timeout: Option<Duration>,
+    policy: FetchPolicy,
+) -> Result<HashMap<String, i32>> {
+    let mut reaction_map = HashMap::new();
+    let filter = Filter::new().kind(Kind::Reaction).event(*event_id);
+    let events = fetch_events(client, vec![filter], policy, timeout).await?;
+
+    // Assemble data
+    for event in events.iter() {
+        let content = event.content().to_string();
+        *reaction_map.entry(content).or_insert(0) += 1;
+    }
+
+    Ok(reaction_map)
+}
+
+/// Who reacted with one emoji
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReactionDetail {
+    /// Reacting public keys with the time of their reaction, oldest first
+    pub reactors: Vec<(PublicKey, Timestamp)>,
+    /// The current user's reaction event, to delete when toggling it off
+    pub own_reaction: Option<EventId>,
+    /// Image of a NIP-30 custom emoji reaction such as `:soapbox:`
+    pub emoji_url: Option<Url>,
+}
+
+impl ReactionDetail {
+    pub fn count(&self) -> usize {
+        self.reactors.len()
+    }
+
+    pub fn reacted_by_me(&self) -> bool {
+        self.own_reaction.is_some()
+    }
+}
+
+/// Reactions to `event_id` grouped by emoji, with `me`'s own reactions
+/// marked. An empty reaction counts as "+" (NIP-25), and a user reacting
+/// twice with the same emoji is counted once.
+pub async fn get_reaction_details(
+    client: &Client,
+    event_id: &EventId,
+    me: &PublicKey,
+    timeout: Option<Duration>,
+) -> Result<HashMap<String, ReactionDetail>> {
+    let filter = Filter::new().kind(Kind::Reaction).event(*event_id);
+    let events = client.get_events_of(vec![filter], timeout).await?;
+    Ok(group_reactions(events, me))
+}
+
+fn group_reactions(mut events: Vec<Event>, me: &PublicKey) -> HashMap<String, ReactionDetail> {
+    events.sort_by_key(|event| (event.created_at(), event.id));
+    let mut details: HashMap<String, ReactionDetail> = HashMap::new();
+    let mut counted = HashSet::new();
+    for event in events {
+        let emoji = match event.content.as_str() {
+            "" => "+".to_string(),
+            content => content.to_string(),
+        };
+        if !counted.insert((event.pubkey, emoji.clone())) {
+            continue;
+        }
+        let detail = details.entry(emoji).or_default();
+        if detail.emoji_url.is_none() {
+            detail.emoji_url = custom_emoji(&event).map(|emoji| emoji.url);
+        }
+        detail.reactors.push((event.pubkey, event.created_at));
+        if event.pubkey == *me {
+            detail.own_reaction = Some(event.id);
+        }
+    }
+    details
+}
+
+/// Votes of a poll per option
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PollResults {
+    /// `(id, label, votes)` of each option, in the poll's order
+    pub options: Vec<(String, String, usize)>,
+    /// Distinct public keys whose vote counted
+    pub voters: usize,
+}
+
+/// Tally of the NIP-88 responses to `poll`. Each voter counts once, with
+/// their latest vote cast before the poll ended.
+pub async fn get_poll_results(
+    client: &Client,
+    poll: &Poll,
+    timeout: Option<Duration>,
+) -> Result<PollResults> {
+    let mut filter = Filter::new().kind(Poll::RESPONSE_KIND).event(poll.inner.id);
+    if let Some(ends_at) = poll.ends_at {
+        filter = filter.until(ends_at);
     }
+    let responses = client.get_events_of(vec![filter], timeout).await?;
+    Ok(tally_votes(poll, &responses))
 }
 
-pub async fn get_zap() {
-    todo!()
+fn tally_votes(poll: &Poll, responses: &[Event]) -> PollResults {
+    let mut latest: HashMap<PublicKey, &Event> = HashMap::new();
+    for response in responses {
+        let counted = response.kind == Poll::RESPONSE_KIND
+            && response.event_ids().any(|id| *id == poll.inner.id)
+            && !poll.is_closed(response.created_at);
+        if !counted {
+            continue;
+        }
+        let newer = latest.get(&response.pubkey).is_none_or(|current| {
+            (response.created_at, response.id) > (current.created_at, current.id)
+        });
+        if newer {
+            latest.insert(response.pubkey, response);
+        }
+    }
+
+    let mut votes: HashMap<&str, usize> = HashMap::new();
+    let mut voters = 0;
+    for response in latest.values() {
+        let mut chosen: Vec<&str> = response
+            .iter_tags()
+            .filter_map(|tag| match tag.as_vec() {
+                [kind, id, ..] if kind == "response" && poll.has_option(id) => Some(id.as_str()),
+                _ => None,
+            })
+            .collect();
+        chosen.dedup();
+        if !poll.multiple_choice {
+            chosen.truncate(1);
+        }
+        if chosen.is_empty() {
+            continue;
+        }
+        voters += 1;
+        for id in chosen {
+            *votes.entry(id).or_default() += 1;
+        }
+    }
+
+    PollResults {
+        options: poll
+            .options
+            .iter()
+            .map(|(id, label)| {
+                let count = votes.get(id.as_str()).copied().unwrap_or_default();
+                (id.clone(), label.clone(), count)
+            })
+            .collect(),
+        voters,
+    }
 }
 
-pub async fn get_repost(
+/// Replies to `event_id` per NIP-10: notes naming it as their root or as
+/// the event they reply to. Notes that only mention or quote it are left
+/// out, see [`get_replies_and_quotes`].
+pub async fn get_replies(
     client: &Client,
     event_id: &EventId,
     timeout: Option<std::time::Duration>,
 ) -> Result<Vec<Event>> {
-    let filter = Filter::new().kind(Kind::Repost).event(*event_id);
-    let events = client.get_events_of(vec![filter], timeout).await?;
-    Ok(events)
+    Ok(get_replies_and_quotes(client, event_id, timeout)
+        .await?
+        .replies)
 }
 
-pub async fn get_reactions(
+/// Text notes referencing an event, split by how they reference it
+#[derive(Debug, Clone, Default)]
+pub struct References {
+    pub replies: Vec<Event>,
+    /// Notes quoting the event with a `q` tag or a `mention` marked `e` tag
+    pub quotes: Vec<Event>,
+}
+
+pub async fn get_replies_and_quotes(
     client: &Client,
     event_id: &EventId,
     timeout: Option<Duration>,
-    is_fetch: bool,
-) -> Result<HashMap<String, i32>> {
-    let mut reaction_map = HashMap::new();
-    let mut events: Vec<Event> = Vec::new();
-
-    let mut reaction_filter = Filter::new().kind(Kind::Reaction).event(*event_id);
+) -> Result<References> {
+    let tagged = Filter::new().kind(Kind::TextNote).event(*event_id);
+    let quoted = Filter::new()
+        .kind(Kind::TextNote)
+        .custom_tag(SingleLetterTag::lowercase(Alphabet::Q), [event_id.to_hex()]);
+    let events = client.get_events_of(vec![tagged, quoted], timeout).await?;
+    Ok(split_references(event_id, events))
+}
 
-    // Get reactions from db
-    let db_filter = reaction_filter.clone();
-    match client.database().query(vec![db_filter], Order::Desc).await {
-        Ok(db_events) => {
-            if !db_events.is_empty() {
-                events.extend(db_events);
-            }
+/// A note that both replies to and quotes the event counts as a reply;
+/// bare mentions are dropped
+fn split_references(event_id: &EventId, events: Vec<Event>) -> References {
+    let mut references = References::default();
+    let mut seen = HashSet::new();
+    for event in events {
+        if !seen.insert(event.id) {
+            continue;
         }
-        Err(_) => {
-            // is_fetch = true;
+        let is_reply = TextNote::try_from(event.clone()).is_ok_and(|note| {
+            note.get_root() == Some(*event_id) || note.get_reply_to() == Some(*event_id)
+        });
+        if is_reply {
+            references.replies.push(event);
+        } else if quotes_event(&event, event_id) {
+            references.quotes.push(event);
         }
     }
+    references
+}
 
-    let mut since = None;
-    if !events.is_empty() {
-        since = Some(events[0].created_at + 1);
-    }
+fn quotes_event(event: &Event, event_id: &EventId) -> bool {
+    let id = event_id.to_hex();
+    event.iter_tags().any(|tag| match tag.as_vec() {
+        [kind, value, ..] if kind == "q" => *value == id,
+        [kind, value, _, marker, ..] if kind == "e" => *value == id && marker == "mention",
+        _ => false,
+    })
+}
 
-    // Get reactions from relay if needed
-    if is_fetch {
-        if let Some(since) = since {
-            reaction_filter = reaction_filter.since(since);
-        }
+/// Ids per filter when fetching a thread, to keep each REQ small
+const THREAD_BATCH_SIZE: usize = 100;
 
-        let relay_events = client.get_events_of(vec![reaction_filter], timeout).await?;
-        events.extend(relay_events);
-    }
+/// The whole thread under `root_id`: the root, its replies, their replies
+/// and so on, up to `max_depth` levels below the root. Each round fetches
+/// the replies to every event found in the previous round at once. Events
+/// come back oldest first, ready for `ReplyTrees::accept`.
+pub async fn get_thread(
+    client: &Client,
+    root_id: &EventId,
+    max_depth: usize,
+    timeout: Option<Duration>,
+) -> Result<Vec<Event>> {
+    let mut thread: Vec<Event> = get_event_by_id(client, root_id, timeout)
+        .await?
+        .into_iter()
+        .collect();
+    let mut seen: HashSet<EventId> = HashSet::from([*root_id]);
+    let mut frontier = vec![*root_id];
 
-    // Assemble data
-    for event in events.iter() {
-        let content = event.content().to_string();
-        *reaction_map.entry(content).or_insert(0) += 1;
+    for _ in 0..max_depth {
+        if frontier.is_empty() {
+            break;
+        }
+        let filters: Vec<Filter> = frontier
+            .chunks(THREAD_BATCH_SIZE)
+            .map(|ids| {
+                Filter::new()
+                    .kind(Kind::TextNote)
+                    .events(ids.iter().copied())
+            })
+            .collect();
+        let replies: Vec<Event> = client
+            .get_events_of(filters, timeout)
+            .await?
+            .into_iter()
+            .filter(|event| seen.insert(event.id))
+            .collect();
+        frontier = replies.iter().map(|event| event.id).collect();
+        thread.extend(replies);
     }
 
-    Ok(reaction_map)
+    thread.sort_by_key(|event| (event.created_at(), event.id));
+    Ok(thread)
 }
 
-pub async fn get_replies(
+/// NIP-23 articles of `author`, newest revision of each, newest first
+pub async fn get_articles(
     client: &Client,
-    event_id: &EventId,
-    timeout: Option<std::time::Duration>,
-) -> Result<Vec<Event>> {
-    let filter = Filter::new().kind(Kind::TextNote).event(*event_id);
+    author: &PublicKey,
+    timeout: Option<Duration>,
+) -> Result<Vec<LongFormNote>> {
+    let filter = Filter::new().kind(Kind::LongFormTextNote).author(*author);
     let events = client.get_events_of(vec![filter], timeout).await?;
-    // TODO: filter out the mentions if necessary
-    Ok(events)
+    Ok(latest_articles(events))
+}
+
+/// Paginator over the article events of `author`. Pages hold raw revisions;
+/// turn them into [`LongFormNote`]s with `LongFormNote::try_from`.
+pub fn get_articles_paginator(
+    client: Arc<Client>,
+    author: &PublicKey,
+    timeout: Option<Duration>,
+    page_size: usize,
+    policy: FetchPolicy,
+) -> EventPaginator {
+    let filter = Filter::new().kind(Kind::LongFormTextNote).author(*author);
+    EventPaginator::new(client, vec![filter], timeout, page_size, policy)
+}
+
+/// Keep only the newest revision of every article (same `d` tag)
+fn latest_articles(events: Vec<Event>) -> Vec<LongFormNote> {
+    let mut latest: HashMap<String, LongFormNote> = HashMap::new();
+    for article in events
+        .into_iter()
+        .filter_map(|e| LongFormNote::try_from(e).ok())
+    {
+        match latest.get(&article.identifier) {
+            Some(existing) if existing.inner.created_at >= article.inner.created_at => {}
+            _ => {
+                latest.insert(article.identifier.clone(), article);
+            }
+        }
+    }
+    let mut articles: Vec<LongFormNote> = latest.into_values().collect();
+    articles.sort_by_key(|article| std::cmp::Reverse(article.published_at_or_created()));
+    articles
 }
 
 pub async fn get_following(
@@ -417,19 +2035,19 @@ pub async fn get_followers(
     client: Arc<Client>,
     public_key: &PublicKey,
     timeout: Option<std::time::Duration>,
-    from_db: bool,
+    policy: FetchPolicy,
 ) -> impl Stream<Item = String> {
     let filter = Filter::new().kind(Kind::ContactList).pubkey(*public_key);
 
     let (tx, rx) = mpsc::unbounded_channel();
 
-    spawn_local({
+    runtime::spawn({
         let paginator = Arc::new(Mutex::new(EventPaginator::new(
             client,
             vec![filter],
             timeout,
             500,
-            from_db,
+            policy,
         )));
         let exit_cond = Arc::new(AtomicBool::new(false));
         async move {
@@ -451,7 +2069,7 @@ pub async fn get_followers(
                 }
             }
 
-            TimeoutFuture::new(1_00).await;
+            runtime::sleep(Duration::from_millis(100)).await;
             exit_cond.store(true, Ordering::SeqCst);
         }
     });
@@ -459,17 +2077,544 @@ pub async fn get_followers(
     UnboundedReceiverStream::new(rx).filter_map(|res| async { Some(res) })
 }
 
+/// Contact lists fetched when no relay can count followers
+const FOLLOWER_SAMPLE_SIZE: usize = 1000;
+
+/// Follower count from [`get_follower_count`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FollowerCount {
+    /// Reported by a NIP-45 relay (the largest report when several answer),
+    /// or every contact list fit in the sample
+    Exact(usize),
+    /// The sample was full, so there are at least this many
+    AtLeast(usize),
+}
+
+impl FollowerCount {
+    pub fn value(self) -> usize {
+        match self {
+            FollowerCount::Exact(count) | FollowerCount::AtLeast(count) => count,
+        }
+    }
+}
+
+/// Number of contact lists following `public_key`, without paginating them
+/// all like [`get_followers`]. Relays supporting NIP-45 are asked to
+/// `COUNT`; without one, a bounded sample of contact lists is fetched.
+pub async fn get_follower_count(
+    client: &Client,
+    public_key: &PublicKey,
+    timeout: Option<Duration>,
+) -> Result<FollowerCount> {
+    let filter = Filter::new().kind(Kind::ContactList).pubkey(*public_key);
+
+    let relays = RelayCapabilitiesCache::global()
+        .relays_where(client, RelayCapabilities::supports_count)
+        .await;
+    let relay_timeout = timeout.unwrap_or(DEFAULT_RELAY_TIMEOUT);
+    let counts = futures::future::join_all(relays.into_iter().map(|(url, relay)| {
+        let filters = vec![filter.clone()];
+        async move {
+            let count = relay.count_events_of(filters, relay_timeout).await;
+            if let Err(err) = &count {
+                tracing::warn!("Relay {} count failed: {:?}", url, err);
+            }
+            count.ok()
+        }
+    }))
+    .await;
+    // Relays hold overlapping subsets, so the counts are not summed
+    if let Some(count) = counts.into_iter().flatten().max() {
+        return Ok(FollowerCount::Exact(count));
+    }
+
+    let sample = client
+        .get_events_of(vec![filter.limit(FOLLOWER_SAMPLE_SIZE)], timeout)
+        .await?;
+    Ok(count_sample(&sample, FOLLOWER_SAMPLE_SIZE))
+}
+
+/// Distinct authors of a sample of at most `limit` contact lists
+fn count_sample(events: &[Event], limit: usize) -> FollowerCount {
+    let authors: HashSet<PublicKey> = events.iter().map(|event| event.author()).collect();
+    if events.len() < limit {
+        FollowerCount::Exact(authors.len())
+    } else {
+        FollowerCount::AtLeast(authors.len())
+    }
+}
+
+/// Public keys followed in a kind 3 contact list
+fn contact_list_keys(event: &Event) -> HashSet<PublicKey> {
+    event
+        .tags()
+        .iter()
+        .filter_map(|tag| match tag.as_standardized() {
+            Some(TagStandard::PublicKey {
+                public_key,
+                uppercase: false,
+                ..
+            }) => Some(*public_key),
+            _ => None,
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone)]
+struct CachedContactList {
+    follows: Arc<HashSet<PublicKey>>,
+    /// `created_at` of the list, `None` when the author has none
+    created_at: Option<Timestamp>,
+}
+
+/// Parsed contact lists per public key, fetched once and reused by
+/// [`is_following`] and [`get_mutual_follows`]. Clones share the same
+/// entries.
+#[derive(Debug, Clone, Default)]
+pub struct ContactListCache {
+    entries: Arc<std::sync::RwLock<HashMap<PublicKey, CachedContactList>>>,
+}
+
+impl ContactListCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Process-wide cache used by the follow helpers
+    pub fn global() -> &'static ContactListCache {
+        static GLOBAL: std::sync::OnceLock<ContactListCache> = std::sync::OnceLock::new();
+        GLOBAL.get_or_init(ContactListCache::new)
+    }
+
+    /// Keys followed by `public_key`, empty when it has no contact list
+    pub async fn get(
+        &self,
+        client: &Client,
+        public_key: &PublicKey,
+        timeout: Option<Duration>,
+    ) -> Result<Arc<HashSet<PublicKey>>> {
+        if let Some(cached) = self.entries.read().unwrap().get(public_key) {
+            return Ok(Arc::clone(&cached.follows));
+        }
+        let filter = Filter::new().kind(Kind::ContactList).author(*public_key);
+        let events = client.get_events_of(vec![filter], timeout).await?;
+        match get_newest_event(&events) {
+            Some(event) => self.observe(event),
+            None => {
+                self.entries
+                    .write()
+                    .unwrap()
+                    .entry(*public_key)
+                    .or_insert_with(|| CachedContactList {
+                        follows: Arc::new(HashSet::new()),
+                        created_at: None,
+                    });
+            }
+        }
+        let entries = self.entries.read().unwrap();
+        Ok(Arc::clone(&entries[public_key].follows))
+    }
+
+    /// Feed a kind 3 event seen elsewhere. It replaces the cached list if it
+    /// is newer.
+    pub fn observe(&self, event: &Event) {
+        if event.kind != Kind::ContactList {
+            return;
+        }
+        let mut entries = self.entries.write().unwrap();
+        if entries
+            .get(&event.pubkey)
+            .and_then(|cached| cached.created_at)
+            .is_some_and(|created_at| created_at >= event.created_at)
+        {
+            return;
+        }
+        entries.insert(
+            event.pubkey,
+            CachedContactList {
+                follows: Arc::new(contact_list_keys(event)),
+                created_at: Some(event.created_at),
+            },
+        );
+    }
+
+    pub fn invalidate(&self, public_key: &PublicKey) {
+        self.entries.write().unwrap().remove(public_key);
+    }
+}
+
+/// Whether `follower` has `followed` in its contact list
+pub async fn is_following(
+    client: &Client,
+    follower: &PublicKey,
+    followed: &PublicKey,
+    timeout: Option<Duration>,
+) -> Result<bool> {
+    let follows = ContactListCache::global()
+        .get(client, follower, timeout)
+        .await?;
+    Ok(follows.contains(followed))
+}
+
+/// Keys followed by both `public_key_a` and `public_key_b`. Whether the two
+/// follow each other is two [`is_following`] calls.
+pub async fn get_mutual_follows(
+    client: &Client,
+    public_key_a: &PublicKey,
+    public_key_b: &PublicKey,
+    timeout: Option<Duration>,
+) -> Result<HashSet<PublicKey>> {
+    let cache = ContactListCache::global();
+    let (a, b) = futures::try_join!(
+        cache.get(client, public_key_a, timeout),
+        cache.get(client, public_key_b, timeout)
+    )?;
+    Ok(a.intersection(&b).copied().collect())
+}
+
+/// Subscribes to `filters` on every relay and yields matching events as they
+/// arrive, each event once. The subscription is re-sent to any relay that
+/// (re)connects, so a dropped connection never silently ends the feed. The
+/// stream ends when the client shuts down.
+pub async fn subscribe_stream(
+    client: Arc<Client>,
+    filters: Vec<Filter>,
+) -> impl Stream<Item = Event> {
+    let id = SubscriptionId::generate();
+    // Listen before subscribing so no early event is missed
+    let notifications = client.notifications();
+    client
+        .subscribe_with_id(id.clone(), filters.clone(), None)
+        .await;
+    live_events(client, id, filters, None, notifications)
+}
+
+/// Same as [`subscribe_stream`], limited to the given relays
+pub async fn subscribe_stream_to(
+    client: Arc<Client>,
+    urls: Vec<Url>,
+    filters: Vec<Filter>,
+) -> Result<impl Stream<Item = Event>> {
+    let id = SubscriptionId::generate();
+    let notifications = client.notifications();
+    client
+        .subscribe_with_id_to(urls.clone(), id.clone(), filters.clone(), None)
+        .await?;
+    Ok(live_events(client, id, filters, Some(urls), notifications))
+}
+
+struct LiveSubscription {
+    client: Arc<Client>,
+    id: SubscriptionId,
+    filters: Vec<Filter>,
+    /// Relays the subscription is limited to, all relays when `None`
+    relays: Option<Vec<Url>>,
+    notifications: broadcast::Receiver<RelayPoolNotification>,
+    seen: HashSet<EventId>,
+}
+
+impl LiveSubscription {
+    async fn resubscribe(&self, relay_url: Url) {
+        if self
+            .relays
+            .as_ref()
+            .is_some_and(|relays| !relays.contains(&relay_url))
+        {
+            return;
+        }
+        if let Err(err) = self
+            .client
+            .subscribe_with_id_to(
+                [relay_url.clone()],
+                self.id.clone(),
+                self.filters.clone(),
+                None,
+            )
+            .await
+        {
+            tracing::warn!("Failed to resubscribe on {}: {:?}", relay_url, err);
+        }
+    }
+}
+
+fn live_events(
+    client: Arc<Client>,
+    id: SubscriptionId,
+    filters: Vec<Filter>,
+    relays: Option<Vec<Url>>,
+    notifications: broadcast::Receiver<RelayPoolNotification>,
+) -> impl Stream<Item = Event> {
+    let subscription = LiveSubscription {
+        client,
+        id,
+        filters,
+        relays,
+        notifications,
+        seen: HashSet::new(),
+    };
+    futures::stream::unfold(subscription, |mut subscription| async move {
+        loop {
+            match subscription.notifications.recv().await {
+                Ok(RelayPoolNotification::Event {
+                    subscription_id,
+                    event,
+                    ..
+                }) => {
+                    // Several relays deliver the same event
+                    if subscription_id == subscription.id && subscription.seen.insert(event.id) {
+                        return Some((*event, subscription));
+                    }
+                }
+                Ok(RelayPoolNotification::RelayStatus {
+                    relay_url,
+                    status: RelayStatus::Connected,
+                }) => subscription.resubscribe(relay_url).await,
+                Ok(RelayPoolNotification::Shutdown) => return None,
+                Ok(_) => {}
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    tracing::warn!("Subscription lagged, {} notifications dropped", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    })
+}
+
 #[derive(Debug, Clone)]
 pub enum NotificationMsg {
+    /// Reaction; see [`NotificationMsg::custom_emoji`] for custom emojis
     Emoji(Event),
     Reply(Event),
     Repost(Event),
     Quote(Event),
-    ZapReceipt(Event),
+    ZapReceipt(ZapReceipt),
+    /// Note that tags us without replying to or quoting anything
+    Mention(Event),
+    /// NIP-04 direct message, still encrypted
+    Dm(Event),
+}
+
+impl NotificationMsg {
+    /// Image of a NIP-30 custom emoji reaction, to show instead of its
+    /// `:shortcode:`
+    pub fn custom_emoji(&self) -> Option<CustomEmoji> {
+        match self {
+            NotificationMsg::Emoji(event) => custom_emoji(event),
+            _ => None,
+        }
+    }
+}
+
+/// One entry of a NIP-51 list
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListEntry {
+    PublicKey(PublicKey),
+    Event(EventId),
+    /// `a` tag: `<kind>:<pubkey>:<d tag>` of a replaceable event
+    Address(String),
+    Hashtag(String),
+    Word(String),
+    Relay(String),
+    /// Any other tag, kept as is
+    Other(Vec<String>),
+}
+
+impl ListEntry {
+    pub fn from_tag(tag: &[String]) -> Option<Self> {
+        let entry = match tag {
+            [kind, value, ..] if kind == "p" => {
+                ListEntry::PublicKey(PublicKey::from_hex(value).ok()?)
+            }
+            [kind, value, ..] if kind == "e" => ListEntry::Event(EventId::from_hex(value).ok()?),
+            [kind, value, ..] if kind == "a" => ListEntry::Address(value.clone()),
+            [kind, value, ..] if kind == "t" => ListEntry::Hashtag(value.clone()),
+            [kind, value, ..] if kind == "word" => ListEntry::Word(value.clone()),
+            [kind, value, ..] if kind == "relay" || kind == "r" => ListEntry::Relay(value.clone()),
+            // List metadata, not entries
+            [kind, ..] if ["d", "title", "description", "image"].contains(&kind.as_str()) => {
+                return None
+            }
+            [_, ..] => ListEntry::Other(tag.to_vec()),
+            [] => return None,
+        };
+        Some(entry)
+    }
+}
+
+/// A NIP-51 list or set: mute list (10000), pins (10001), bookmarks (10003),
+/// follow sets (30000), bookmark sets (30003) and the rest of the series
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NostrList {
+    pub kind: Kind,
+    pub author: PublicKey,
+    /// `d` tag of a set, `None` for a standard list
+    pub identifier: Option<String>,
+    pub title: Option<String>,
+    pub public: Vec<ListEntry>,
+    /// Private entries, empty until [`NostrList::decrypt_private`] ran
+    pub private: Vec<ListEntry>,
+    /// Encrypted private entries, `None` if there are none or once decrypted
+    pub encrypted: Option<String>,
+    pub created_at: Timestamp,
+}
+
+impl NostrList {
+    pub fn from_event(event: &Event) -> Self {
+        let mut list = NostrList {
+            kind: event.kind,
+            author: event.pubkey,
+            identifier: None,
+            title: None,
+            public: Vec::new(),
+            private: Vec::new(),
+            encrypted: (!event.content.is_empty()).then(|| event.content.clone()),
+            created_at: event.created_at,
+        };
+        for tag in event.tags.iter() {
+            match tag.as_vec() {
+                [kind, value, ..] if kind == "d" => list.identifier = Some(value.clone()),
+                [kind, value, ..] if kind == "title" => list.title = Some(value.clone()),
+                tag => list.public.extend(ListEntry::from_tag(tag)),
+            }
+        }
+        list
+    }
+
+    /// Decrypt the private entries. Only the list's author can do this;
+    /// they are encrypted to themselves with NIP-44, or NIP-04 by older
+    /// clients.
+    pub async fn decrypt_private(&mut self, signer: &NostrSigner) -> Result<()> {
+        let Some(content) = &self.encrypted else {
+            return Ok(());
+        };
+        // NIP-04 payloads carry the IV after `?iv=`
+        let json = if content.contains("?iv=") {
+            if !is_nip04_payload(content) {
+                return Err(nostr_sdk::nips::nip04::Error::InvalidContentFormat.into());
+            }
+            signer.nip04_decrypt(self.author, content).await?
+        } else {
+            signer.nip44_decrypt(self.author, content).await?
+        };
+        let tags: Vec<Vec<String>> = serde_json::from_str(&json)?;
+        self.private = tags
+            .iter()
+            .filter_map(|tag| ListEntry::from_tag(tag))
+            .collect();
+        self.encrypted = None;
+        Ok(())
+    }
+
+    /// Public and decrypted private entries
+    pub fn entries(&self) -> impl Iterator<Item = &ListEntry> {
+        self.public.iter().chain(self.private.iter())
+    }
+}
+
+/// Lists of `list_kind` published by `public_key`: the newest one for a
+/// standard list, the newest of each set for the 30000 series
+pub async fn get_lists(
+    client: &Client,
+    public_key: &PublicKey,
+    list_kind: Kind,
+    timeout: Option<Duration>,
+) -> Result<Vec<NostrList>> {
+    let filter = Filter::new().author(*public_key).kind(list_kind);
+    let events = client.get_events_of(vec![filter], timeout).await?;
+
+    let mut latest: HashMap<Option<String>, NostrList> = HashMap::new();
+    for list in events.iter().map(NostrList::from_event) {
+        match latest.get(&list.identifier) {
+            Some(existing) if existing.created_at >= list.created_at => {}
+            _ => {
+                latest.insert(list.identifier.clone(), list);
+            }
+        }
+    }
+    let mut lists: Vec<NostrList> = latest.into_values().collect();
+    lists.sort_by(|a, b| a.identifier.cmp(&b.identifier));
+    Ok(lists)
+}
+
+/// NIP-51 mute list (kind 10000), public and private entries merged
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MuteList {
+    pub public_keys: HashSet<PublicKey>,
+    /// Muted threads
+    pub events: HashSet<EventId>,
+    pub hashtags: HashSet<String>,
+    /// Lowercase words, matched anywhere in the content
+    pub words: Vec<String>,
+}
+
+impl MuteList {
+    fn add_entry(&mut self, entry: &ListEntry) {
+        match entry {
+            ListEntry::PublicKey(public_key) => {
+                self.public_keys.insert(*public_key);
+            }
+            ListEntry::Event(event_id) => {
+                self.events.insert(*event_id);
+            }
+            ListEntry::Hashtag(hashtag) => {
+                self.hashtags.insert(hashtag.to_lowercase());
+            }
+            ListEntry::Word(word) => self.words.push(word.to_lowercase()),
+            _ => {}
+        }
+    }
+
+    pub fn is_muted(&self, event: &Event) -> bool {
+        if self.public_keys.contains(&event.pubkey) || self.events.contains(&event.id) {
+            return true;
+        }
+        let tagged = event.tags.iter().any(|tag| match tag.as_vec() {
+            [kind, value, ..] if kind == "e" => {
+                EventId::from_hex(value).is_ok_and(|event_id| self.events.contains(&event_id))
+            }
+            [kind, value, ..] if kind == "t" => self.hashtags.contains(&value.to_lowercase()),
+            _ => false,
+        });
+        if tagged || self.words.is_empty() {
+            return tagged;
+        }
+        let content = event.content.to_lowercase();
+        self.words
+            .iter()
+            .any(|word| content.contains(word.as_str()))
+    }
+
+    /// Drop muted events, e.g. from [`get_replies`] results
+    pub fn retain_unmuted(&self, events: &mut Vec<Event>) {
+        events.retain(|event| !self.is_muted(event));
+    }
+}
+
+/// Mute list of the signer, private entries included
+pub async fn get_mute_list(
+    client: &Client,
+    signer: &NostrSigner,
+    timeout: Option<Duration>,
+) -> Result<MuteList> {
+    let public_key = signer.public_key().await?;
+    let mut mute_list = MuteList::default();
+    let Some(mut list) = get_lists(client, &public_key, Kind::MuteList, timeout)
+        .await?
+        .pop()
+    else {
+        return Ok(mute_list);
+    };
+    list.decrypt_private(signer).await?;
+    for entry in list.entries() {
+        mute_list.add_entry(entry);
+    }
+    Ok(mute_list)
 }
 
 pub struct NotificationPaginator {
     paginator: EventPaginator,
+    mute_list: Option<MuteList>,
+    include_muted: bool,
 }
 
 impl NotificationPaginator {
@@ -478,20 +2623,35 @@ impl NotificationPaginator {
         public_key: PublicKey,
         timeout: Option<std::time::Duration>,
         page_size: usize,
-        from_db: bool,
+        policy: FetchPolicy,
     ) -> Self {
         let filters = create_notification_filters(&public_key);
 
         Self {
-            paginator: EventPaginator::new(client, filters, timeout, page_size, from_db),
+            paginator: EventPaginator::new(client, filters, timeout, page_size, policy),
+            mute_list: None,
+            include_muted: false,
         }
     }
 
+    /// Leave out notifications from muted users, threads, hashtags or words
+    pub fn with_mute_list(mut self, mute_list: MuteList) -> Self {
+        self.mute_list = Some(mute_list);
+        self
+    }
+
+    /// Keep muted notifications after all, e.g. for a "show hidden" toggle
+    pub fn include_muted(mut self, include: bool) -> Self {
+        self.include_muted = include;
+        self
+    }
+
     pub async fn next_page(&mut self) -> Option<Vec<NotificationMsg>> {
-        self.paginator
-            .next_page()
-            .await
-            .map(process_notification_events)
+        let mut events = self.paginator.next_page().await?;
+        if let (Some(mute_list), false) = (&self.mute_list, self.include_muted) {
+            mute_list.retain_unmuted(&mut events);
+        }
+        Some(process_notification_events(events))
     }
 }
 
@@ -501,7 +2661,8 @@ pub fn create_notification_filters(public_key: &PublicKey) -> Vec<Filter> {
         .kind(Kind::Reaction)
         .kind(Kind::TextNote)
         .kind(Kind::Repost)
-        .kind(Kind::ZapReceipt)]
+        .kind(Kind::ZapReceipt)
+        .kind(Kind::EncryptedDirectMessage)]
 }
 
 pub fn process_notification_events(events: Vec<Event>) -> Vec<NotificationMsg> {
@@ -510,40 +2671,52 @@ pub fn process_notification_events(events: Vec<Event>) -> Vec<NotificationMsg> {
         .filter_map(|event| match event.kind() {
             Kind::Reaction => Some(NotificationMsg::Emoji(event)),
             Kind::TextNote => {
-                if event.content.contains("nostr:") {
-                    Some(NotificationMsg::Quote(event))
-                } else {
-                    Some(NotificationMsg::Reply(event))
+                if is_quote(&event) {
+                    return Some(NotificationMsg::Quote(event));
+                }
+                match TextNote::try_from(event.clone()) {
+                    Ok(note) if note.is_reply() => Some(NotificationMsg::Reply(event)),
+                    _ => Some(NotificationMsg::Mention(event)),
                 }
             }
             Kind::Repost => Some(NotificationMsg::Repost(event)),
-            Kind::ZapReceipt => Some(NotificationMsg::ZapReceipt(event)),
+            Kind::ZapReceipt => Some(NotificationMsg::ZapReceipt(ZapReceipt::from(event))),
+            Kind::EncryptedDirectMessage => Some(NotificationMsg::Dm(event)),
             _ => None,
         })
         .collect()
 }
 
+/// NIP-18 `q` tag or an `e` tag with the NIP-10 `mention` marker
+fn is_quote(event: &Event) -> bool {
+    event.iter_tags().any(|tag| match tag.as_vec() {
+        [kind, ..] if kind == "q" => true,
+        [kind, _, _, marker, ..] if kind == "e" => marker == "mention",
+        _ => false,
+    })
+}
+
 #[cfg(test)]
 mod tests {
+    use nostr_sdk::database::Order;
+    use nostr_sdk::key::SecretKey;
+    use nostr_sdk::{Client, ClientBuilder, EventBuilder, FromBech32, Keys};
     use std::sync::atomic::{AtomicBool, Ordering};
     use std::sync::Arc;
     use std::time::Duration;
-    pub const NOSTR_DB_NAME: &str = "nostr-db";
-    use gloo_timers::future::sleep;
-    use nostr_indexeddb::database::Order;
-    use nostr_indexeddb::WebDatabase;
-    use nostr_sdk::key::SecretKey;
-    use nostr_sdk::{Client, ClientBuilder, EventBuilder, FromBech32, Keys};
-    use wasm_bindgen_futures::spawn_local;
     use wasm_bindgen_test::*;
 
     use super::*;
     use crate::nostr::note::{DisplayOrder, ReplyTrees};
-    use crate::testhelper::event_from;
-    use crate::testhelper::test_data::*;
+    use crate::nostr::runtime;
+    use crate::testhelper::console_log;
+    #[cfg(not(target_arch = "wasm32"))]
+    use crate::testhelper::MockRelay;
 
     wasm_bindgen_test::wasm_bindgen_test_configure!(run_in_browser);
 
+    #[cfg_attr(not(target_arch = "wasm32"), ignore = "needs public relays")]
+    #[wasm_bindgen_test(unsupported = tokio::test)]
     async fn test_get_event_by_id() {
         let timeout = Some(std::time::Duration::from_secs(5));
         let event_id =
@@ -556,7 +2729,8 @@ mod tests {
         assert!(event.is_some());
     }
 
-    #[wasm_bindgen_test]
+    #[cfg_attr(not(target_arch = "wasm32"), ignore = "needs public relays")]
+    #[wasm_bindgen_test(unsupported = tokio::test)]
     async fn test_get_replies() {
         let timeout = Some(std::time::Duration::from_secs(5));
         let event_id =
@@ -569,7 +2743,50 @@ mod tests {
         assert_eq!(replies.len(), 4);
     }
 
-    #[wasm_bindgen_test]
+    #[wasm_bindgen_test(unsupported = test)]
+    fn test_split_references() {
+        let keys = Keys::generate();
+        let parent = EventBuilder::text_note("parent", [])
+            .to_event(&keys)
+            .unwrap();
+        let other = EventBuilder::text_note("other", [])
+            .to_event(&keys)
+            .unwrap();
+        let (parent_hex, other_hex) = (parent.id.to_hex(), other.id.to_hex());
+        let note = |tags: Vec<Vec<&str>>| {
+            let tags = tags.into_iter().map(|tag| Tag::parse(&tag).unwrap());
+            EventBuilder::text_note("note", tags)
+                .to_event(&keys)
+                .unwrap()
+        };
+
+        let marked_reply = note(vec![
+            vec!["e", &other_hex, "", "root"],
+            vec!["e", &parent_hex, "", "reply"],
+        ]);
+        let positional_reply = note(vec![vec!["e", &parent_hex]]);
+        let mention = note(vec![
+            vec!["e", &other_hex, "", "root"],
+            vec!["e", &parent_hex, "", "mention"],
+        ]);
+        let quote = note(vec![vec!["q", &parent_hex]]);
+
+        let references = split_references(
+            &parent.id,
+            vec![
+                marked_reply.clone(),
+                positional_reply.clone(),
+                mention.clone(),
+                quote.clone(),
+                quote.clone(),
+            ],
+        );
+        assert_eq!(references.replies, vec![marked_reply, positional_reply]);
+        assert_eq!(references.quotes, vec![mention, quote]);
+    }
+
+    #[cfg_attr(not(target_arch = "wasm32"), ignore = "needs public relays")]
+    #[wasm_bindgen_test(unsupported = tokio::test)]
     async fn test_get_replies_into_tree() {
         let timeout = Some(std::time::Duration::from_secs(5));
         let event_id =
@@ -593,7 +2810,101 @@ mod tests {
         assert!(lv1_replies.len() == 3);
     }
 
-    #[wasm_bindgen_test]
+    #[cfg(not(target_arch = "wasm32"))]
+    #[tokio::test]
+    async fn test_get_thread() {
+        let relay = MockRelay::run().await.unwrap();
+        let keys = Keys::generate();
+        let note = |created_at: u64, parent: Option<&Event>| {
+            let tags = parent.map(|parent| Tag::event(parent.id));
+            let event = EventBuilder::text_note(format!("note {created_at}"), tags)
+                .custom_created_at(Timestamp::from(created_at))
+                .to_event(&keys)
+                .unwrap();
+            relay.add_event(event.clone());
+            event
+        };
+        // root <- a <- a1 <- a2 <- a3, and root <- b
+        let root = note(1, None);
+        let a = note(2, Some(&root));
+        let b = note(3, Some(&root));
+        let a1 = note(4, Some(&a));
+        let a2 = note(5, Some(&a1));
+        let a3 = note(6, Some(&a2));
+        note(7, None);
+
+        let client = mock_client(&[&relay]).await;
+        let timeout = Some(std::time::Duration::from_secs(5));
+        let ids = |thread: Vec<Event>| -> Vec<EventId> {
+            thread.into_iter().map(|event| event.id).collect()
+        };
+
+        // Three levels below the root, oldest first
+        let thread = get_thread(&client, &root.id, 3, timeout).await.unwrap();
+        let mut tree = ReplyTrees::default();
+        tree.accept(thread.clone());
+        assert_eq!(tree.get_replies(&root.id, None).len(), 2);
+        assert_eq!(ids(thread), vec![root.id, a.id, b.id, a1.id, a2.id]);
+
+        let thread = get_thread(&client, &root.id, 10, timeout).await.unwrap();
+        assert_eq!(ids(thread).last(), Some(&a3.id));
+        let thread = get_thread(&client, &root.id, 0, timeout).await.unwrap();
+        assert_eq!(ids(thread), vec![root.id]);
+    }
+
+    #[wasm_bindgen_test(unsupported = test)]
+    fn test_reaction_details() {
+        let me = Keys::generate();
+        let other = Keys::generate();
+        let target = EventBuilder::text_note("post", [])
+            .to_event(&other)
+            .unwrap();
+        let react = |keys: &Keys, content: &str, created_at: u64| {
+            EventBuilder::new(Kind::Reaction, content, [Tag::event(target.id)])
+                .custom_created_at(Timestamp::from(created_at))
+                .to_event(keys)
+                .unwrap()
+        };
+        let mine = react(&me, "+", 2);
+        let url = Url::parse("https://example.com/soapbox.png").unwrap();
+        let custom = EventBuilder::new(
+            Kind::Reaction,
+            ":soapbox:",
+            [
+                Tag::event(target.id),
+                Tag::from_standardized(TagStandard::Emoji {
+                    shortcode: "soapbox".to_string(),
+                    url: url.clone().into(),
+                }),
+            ],
+        )
+        .to_event(&other)
+        .unwrap();
+        let events = vec![
+            react(&other, "", 1),
+            mine.clone(),
+            react(&other, "🤙", 3),
+            // Repeated reaction only counts once
+            react(&me, "+", 4),
+            custom.clone(),
+        ];
+
+        let details = group_reactions(events, &me.public_key());
+        assert_eq!(details["+"].count(), 2);
+        assert_eq!(details["+"].own_reaction, Some(mine.id));
+        assert_eq!(details["+"].reactors[0].0, other.public_key());
+        assert_eq!(details["🤙"].count(), 1);
+        assert!(!details["🤙"].reacted_by_me());
+        assert_eq!(details["🤙"].emoji_url, None);
+        assert_eq!(details[":soapbox:"].emoji_url, Some(url.clone()));
+        assert_eq!(
+            NotificationMsg::Emoji(custom).custom_emoji(),
+            Some(CustomEmoji::new("soapbox", url))
+        );
+    }
+
+    #[cfg_attr(not(target_arch = "wasm32"), ignore = "needs public relays")]
+    #[wasm_bindgen_test(unsupported = tokio::test)]
     async fn test_get_reactions() {
         let timeout = Some(std::time::Duration::from_secs(5));
         let event_id =
@@ -602,7 +2913,7 @@ mod tests {
         let client = Client::default();
         client.add_relay("wss://relay.damus.io").await.unwrap();
         client.connect().await;
-        let reactions = get_reactions(&client, &event_id, timeout, true)
+        let reactions = get_reactions(&client, &event_id, timeout, FetchPolicy::CacheThenRelay)
             .await
             .unwrap();
         let length = reactions.len();
@@ -631,7 +2942,8 @@ mod tests {
     //     assert!(event_result[0].id == event.id);
     // }
 
-    #[wasm_bindgen_test]
+    #[cfg_attr(not(target_arch = "wasm32"), ignore = "needs public relays")]
+    #[wasm_bindgen_test(unsupported = tokio::test)]
     async fn test_event_page_iterator() {
         let client = Client::default();
         client.add_relay("wss://relay.damus.io").await.unwrap();
@@ -648,8 +2960,13 @@ mod tests {
 
         let page_size = 10;
         let timeout = Some(std::time::Duration::from_secs(5));
-        let mut paginator =
-            EventPaginator::new(Arc::new(client), vec![filter], timeout, page_size, false);
+        let mut paginator = EventPaginator::new(
+            Arc::new(client),
+            vec![filter],
+            timeout,
+            page_size,
+            FetchPolicy::RelayOnly,
+        );
 
         let mut count = 0;
         while let Some(result) = paginator.next_page().await {
@@ -662,7 +2979,600 @@ mod tests {
         assert!(count > 100);
     }
 
-    #[wasm_bindgen_test]
+    #[wasm_bindgen_test(unsupported = tokio::test)]
+    async fn test_forward_paginator() {
+        let opts = nostr_sdk::database::MemoryDatabaseOptions {
+            events: true,
+            ..Default::default()
+        };
+        let db = nostr_sdk::database::MemoryDatabase::with_opts(opts);
+        let client = Arc::new(ClientBuilder::new().database(db).build());
+        let keys = Keys::generate();
+        let save = |created_at: u64, content: &str| {
+            let event = EventBuilder::text_note(content, [])
+                .custom_created_at(Timestamp::from(created_at))
+                .to_event(&keys)
+                .unwrap();
+            let client = Arc::clone(&client);
+            async move { client.database().save_event(&event).await.unwrap() }
+        };
+        for created_at in 1..=5 {
+            save(created_at, "history").await;
+        }
+
+        let filter = Filter::new().kind(Kind::TextNote).author(keys.public_key());
+        let mut paginator = EventPaginator::new(
+            Arc::clone(&client),
+            vec![filter],
+            None,
+            2,
+            FetchPolicy::CacheOnly,
+        )
+        .forward(None);
+        let page = paginator.next_page().await.unwrap();
+        let timestamps: Vec<u64> = page.iter().map(|e| e.created_at.as_u64()).collect();
+        assert_eq!(timestamps, vec![1, 2, 3, 4, 5]);
+        assert!(paginator.next_page().await.is_none());
+
+        // Only events newer than the last page come through
+        save(5, "same second").await;
+        save(6, "live").await;
+        let page = paginator.next_page().await.unwrap();
+        let contents: Vec<&str> = page.iter().map(|e| e.content.as_str()).collect();
+        assert_eq!(contents, vec!["same second", "live"]);
+        assert!(!paginator.done);
+    }
+
+    #[wasm_bindgen_test(unsupported = tokio::test)]
+    async fn test_fetch_policy() {
+        let opts = nostr_sdk::database::MemoryDatabaseOptions {
+            events: true,
+            ..Default::default()
+        };
+        let db = nostr_sdk::database::MemoryDatabase::with_opts(opts);
+        let client = Arc::new(ClientBuilder::new().database(db).build());
+        let keys = Keys::generate();
+        for created_at in 1..=3 {
+            let event = EventBuilder::text_note("cached", [])
+                .custom_created_at(Timestamp::from(created_at))
+                .to_event(&keys)
+                .unwrap();
+            client.database().save_event(&event).await.unwrap();
+        }
+        let filter = Filter::new().kind(Kind::TextNote).author(keys.public_key());
+
+        let events = fetch_events(&client, vec![filter.clone()], FetchPolicy::CacheOnly, None)
+            .await
+            .unwrap();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].created_at, Timestamp::from(3));
+
+        // Without reachable relays the cached events are served
+        let timeout = Some(Duration::from_secs(1));
+        let events = fetch_events(
+            &client,
+            vec![filter.clone()],
+            FetchPolicy::CacheThenRelay,
+            timeout,
+        )
+        .await
+        .unwrap();
+        assert_eq!(events.len(), 3);
+        let mut paginator = EventPaginator::new(
+            client,
+            vec![filter],
+            timeout,
+            5,
+            FetchPolicy::CacheThenRelay,
+        );
+        assert_eq!(paginator.next_page().await.unwrap().len(), 3);
+    }
+
+    #[wasm_bindgen_test(unsupported = test)]
+    fn test_eose_tally() {
+        let keys = Keys::generate();
+        let event = EventBuilder::text_note("hello", [])
+            .to_event(&keys)
+            .unwrap();
+        let id = SubscriptionId::generate();
+        let relay = |url: &str| Url::parse(url).unwrap();
+        let message = |url: &str, message: RelayMessage| RelayPoolNotification::Message {
+            relay_url: relay(url),
+            message,
+        };
+        let event_message = |url: &str| {
+            message(
+                url,
+                RelayMessage::Event {
+                    subscription_id: id.clone(),
+                    event: Box::new(event.clone()),
+                },
+            )
+        };
+
+        assert_eq!(EoseQuorum::All.required(3), 3);
+        assert_eq!(EoseQuorum::AtLeast(2).required(3), 2);
+        assert_eq!(EoseQuorum::AtLeast(5).required(3), 3);
+
+        let mut tally = EoseTally::new(id.clone(), EoseQuorum::AtLeast(2).required(3));
+        assert!(!tally.observe(event_message("wss://a.example")));
+        assert!(!tally.observe(event_message("wss://b.example")));
+        assert!(!tally.observe(message(
+            "wss://a.example",
+            RelayMessage::EndOfStoredEvents(id.clone())
+        )));
+        // Other subscriptions do not count
+        assert!(!tally.observe(message(
+            "wss://b.example",
+            RelayMessage::EndOfStoredEvents(SubscriptionId::generate())
+        )));
+        assert!(tally.observe(message(
+            "wss://c.example",
+            RelayMessage::Closed {
+                subscription_id: id.clone(),
+                message: "error: shutting down".to_string(),
+            }
+        )));
+        assert_eq!(tally.events.len(), 1);
+    }
+
+    #[wasm_bindgen_test(unsupported = test)]
+    fn test_relay_stats() {
+        let mut stats = RelayStats::default();
+        assert_eq!(stats.success_rate(), 1.0);
+
+        stats.record_success(Duration::from_millis(100));
+        stats.record_success(Duration::from_millis(500));
+        assert_eq!(stats.avg_latency, Some(Duration::from_millis(200)));
+
+        stats.record_failure(true);
+        stats.record_failure(false);
+        assert!(!stats.is_quarantined());
+        stats.record_failure(false);
+        assert!(stats.is_quarantined());
+        assert_eq!(stats.timeouts, 1);
+        assert_eq!(stats.success_rate(), 0.4);
+
+        // One success lifts the quarantine
+        stats.record_success(Duration::from_millis(200));
+        assert!(!stats.is_quarantined());
+        assert_eq!(stats.consecutive_failures, 0);
+    }
+
+    #[wasm_bindgen_test(unsupported = tokio::test)]
+    async fn test_paginator_order() {
+        let opts = nostr_sdk::database::MemoryDatabaseOptions {
+            events: true,
+            ..Default::default()
+        };
+        let db = nostr_sdk::database::MemoryDatabase::with_opts(opts);
+        let client = Arc::new(ClientBuilder::new().database(db).build());
+        let keys = Keys::generate();
+        for created_at in 1..=5 {
+            let event = EventBuilder::text_note("history", [])
+                .custom_created_at(Timestamp::from(created_at))
+                .to_event(&keys)
+                .unwrap();
+            client.database().save_event(&event).await.unwrap();
+        }
+
+        // Chat view: walk back from a point in time, each page oldest first
+        let filter = Filter::new().kind(Kind::TextNote).author(keys.public_key());
+        let mut paginator =
+            EventPaginator::new(client, vec![filter], None, 2, FetchPolicy::CacheOnly)
+                .backward(Some(Timestamp::from(4)))
+                .order(Order::Asc);
+        let mut pages = Vec::new();
+        while let Some(page) = paginator.next_page().await {
+            pages.push(
+                page.iter()
+                    .map(|e| e.created_at.as_u64())
+                    .collect::<Vec<_>>(),
+            );
+        }
+        assert_eq!(pages, vec![vec![3, 4], vec![1, 2]]);
+    }
+
+    #[wasm_bindgen_test(unsupported = tokio::test)]
+    async fn test_paginator_stream() {
+        let opts = nostr_sdk::database::MemoryDatabaseOptions {
+            events: true,
+            ..Default::default()
+        };
+        let db = nostr_sdk::database::MemoryDatabase::with_opts(opts);
+        let client = Arc::new(ClientBuilder::new().database(db).build());
+        let keys = Keys::generate();
+        for created_at in 1..=5 {
+            let event = EventBuilder::text_note("history", [])
+                .custom_created_at(Timestamp::from(created_at))
+                .to_event(&keys)
+                .unwrap();
+            client.database().save_event(&event).await.unwrap();
+        }
+
+        let filter = Filter::new().kind(Kind::TextNote).author(keys.public_key());
+        let pages: Vec<Vec<Event>> =
+            EventPaginator::new(client, vec![filter], None, 2, FetchPolicy::CacheOnly)
+                .into_stream()
+                .collect()
+                .await;
+        let sizes: Vec<usize> = pages.iter().map(Vec::len).collect();
+        assert_eq!(sizes, vec![2, 2, 1]);
+    }
+
+    #[wasm_bindgen_test(unsupported = tokio::test)]
+    async fn test_timeline_paginator() {
+        let opts = nostr_sdk::database::MemoryDatabaseOptions {
+            events: true,
+            ..Default::default()
+        };
+        let db = nostr_sdk::database::MemoryDatabase::with_opts(opts);
+        let client = Arc::new(ClientBuilder::new().database(db).build());
+        let alice = Keys::generate();
+        let bob = Keys::generate();
+        for (keys, created_at) in [(&alice, 1), (&bob, 2), (&alice, 3), (&alice, 4), (&bob, 6)] {
+            let event = EventBuilder::text_note("feed", [])
+                .custom_created_at(Timestamp::from(created_at))
+                .to_event(keys)
+                .unwrap();
+            client.database().save_event(&event).await.unwrap();
+        }
+
+        // The second feed overlaps the first one
+        let filters = vec![
+            Filter::new()
+                .kind(Kind::TextNote)
+                .author(alice.public_key()),
+            Filter::new()
+                .kind(Kind::TextNote)
+                .authors([alice.public_key(), bob.public_key()]),
+        ];
+        let pages: Vec<Vec<Event>> =
+            TimelinePaginator::new(client, filters, None, 2, FetchPolicy::CacheOnly)
+                .into_stream()
+                .collect()
+                .await;
+        let timestamps: Vec<Vec<u64>> = pages
+            .iter()
+            .map(|page| page.iter().map(|e| e.created_at.as_u64()).collect())
+            .collect();
+        assert_eq!(timestamps, vec![vec![6, 4], vec![3, 2], vec![1]]);
+    }
+
+    #[wasm_bindgen_test(unsupported = tokio::test)]
+    async fn test_resume_from_cursor() {
+        let opts = nostr_sdk::database::MemoryDatabaseOptions {
+            events: true,
+            ..Default::default()
+        };
+        let db = nostr_sdk::database::MemoryDatabase::with_opts(opts);
+        let client = Arc::new(ClientBuilder::new().database(db).build());
+        let keys = Keys::generate();
+        for created_at in 1..=4 {
+            let event = EventBuilder::text_note("history", [])
+                .custom_created_at(Timestamp::from(created_at))
+                .to_event(&keys)
+                .unwrap();
+            client.database().save_event(&event).await.unwrap();
+        }
+
+        let filter = Filter::new().kind(Kind::TextNote).author(keys.public_key());
+        let paginator = || {
+            EventPaginator::new(
+                Arc::clone(&client),
+                vec![filter.clone()],
+                None,
+                2,
+                FetchPolicy::CacheOnly,
+            )
+        };
+        let mut first_session = paginator();
+        first_session.next_page().await.unwrap();
+        let saved = serde_json::to_string(&first_session.cursor()).unwrap();
+
+        let mut second_session = paginator().with_cursor(serde_json::from_str(&saved).unwrap());
+        let page = second_session.next_page().await.unwrap();
+        let mut timestamps: Vec<u64> = page.iter().map(|e| e.created_at.as_u64()).collect();
+        timestamps.sort();
+        assert_eq!(timestamps, vec![1, 2]);
+    }
+
+    // Client connected to each of `relays`
+    #[cfg(not(target_arch = "wasm32"))]
+    async fn mock_client(relays: &[&MockRelay]) -> Client {
+        let client = Client::default();
+        for relay in relays {
+            client.add_relay(relay.url()).await.unwrap();
+        }
+        client.connect().await;
+        client
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[tokio::test]
+    async fn test_per_relay_paginator() {
+        let first = MockRelay::run().await.unwrap();
+        let second = MockRelay::run().await.unwrap();
+        let keys = Keys::generate();
+        let note = |created_at: u64| {
+            EventBuilder::text_note(format!("note {created_at}"), [])
+                .custom_created_at(Timestamp::from(created_at))
+                .to_event(&keys)
+                .unwrap()
+        };
+        // Both relays hold the first notes, and each one a note of its own
+        for created_at in 1..=3 {
+            first.add_event(note(created_at));
+            second.add_event(note(created_at));
+        }
+        let only_first = note(4);
+        first.add_event(only_first.clone());
+        let only_second = note(5);
+        second.add_event(only_second.clone());
+
+        let client = mock_client(&[&first, &second]).await;
+        let filter = Filter::new().kind(Kind::TextNote).author(keys.public_key());
+        let timeout = Some(std::time::Duration::from_secs(5));
+        let mut paginator = EventPaginator::new(
+            Arc::new(client),
+            vec![filter],
+            timeout,
+            20,
+            FetchPolicy::RelayOnly,
+        )
+        .per_relay();
+
+        let page = paginator.next_page().await.unwrap();
+        let ids: HashSet<EventId> = page.iter().map(|event| event.id).collect();
+        assert_eq!(ids.len(), page.len());
+        assert_eq!(page.len(), 5);
+        let sources = paginator.page_sources();
+        assert_eq!(sources[&first.url()].len(), 4);
+        assert!(sources[&first.url()].contains(&only_first.id));
+        assert!(!sources[&first.url()].contains(&only_second.id));
+        assert!(sources[&second.url()].contains(&only_second.id));
+        assert!(sources.values().flatten().all(|id| ids.contains(id)));
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[tokio::test]
+    async fn test_subscribe_stream() {
+        let first = MockRelay::run().await.unwrap();
+        let second = MockRelay::run().await.unwrap();
+        let keys = Keys::generate();
+        let note = |content: &str| {
+            EventBuilder::text_note(content, [])
+                .to_event(&keys)
+                .unwrap()
+        };
+        let stored: Vec<Event> = (0..3).map(|i| note(&format!("stored {i}"))).collect();
+        for event in &stored {
+            first.add_event(event.clone());
+            second.add_event(event.clone());
+        }
+
+        let client = Arc::new(mock_client(&[&first, &second]).await);
+        let filter = Filter::new().kind(Kind::TextNote).author(keys.public_key());
+        let events = subscribe_stream(client, vec![filter]).await;
+        futures::pin_mut!(events);
+        let wait = Duration::from_secs(5);
+
+        // Stored events come once, though both relays send them
+        let mut ids = HashSet::new();
+        for _ in 0..stored.len() {
+            let event = tokio::time::timeout(wait, events.next()).await.unwrap();
+            ids.insert(event.unwrap().id);
+        }
+        assert_eq!(ids, stored.iter().map(|event| event.id).collect());
+
+        // Then new ones as they are published
+        let live = note("live");
+        second.add_event(live.clone());
+        first.add_event(live.clone());
+        let event = tokio::time::timeout(wait, events.next()).await.unwrap();
+        assert_eq!(event.unwrap().id, live.id);
+        // Also sent by the other relay, but not yielded again
+        assert!(
+            tokio::time::timeout(Duration::from_millis(300), events.next())
+                .await
+                .is_err()
+        );
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[tokio::test]
+    async fn test_search_events() {
+        let search = MockRelay::run().await.unwrap();
+        let plain = MockRelay::run().await.unwrap();
+        // The mock relays serve no NIP-11 document, say which one searches
+        RelayCapabilitiesCache::global().insert(
+            search.url(),
+            RelayCapabilities {
+                supported_nips: vec![1, 50],
+                ..Default::default()
+            },
+        );
+        let keys = Keys::generate();
+        let note = |created_at: u64, content: &str| {
+            EventBuilder::text_note(content, [])
+                .custom_created_at(Timestamp::from(created_at))
+                .to_event(&keys)
+                .unwrap()
+        };
+        let older = note(1, "Nostr and CRDTs");
+        let newer = note(2, "more about nostr");
+        for event in [&older, &newer, &note(3, "something else")] {
+            search.add_event(event.clone());
+        }
+        // Not asked, it does not support search
+        plain.add_event(note(4, "nostr elsewhere"));
+
+        let client = mock_client(&[&search, &plain]).await;
+        let timeout = Some(std::time::Duration::from_secs(5));
+        let filter = Filter::new().kind(Kind::TextNote).limit(10);
+        let events = search_events(&client, "nostr", vec![filter], timeout)
+            .await
+            .unwrap();
+        let ids: Vec<EventId> = events.iter().map(|event| event.id).collect();
+        assert_eq!(ids, vec![newer.id, older.id]);
+    }
+
+    #[wasm_bindgen_test(unsupported = tokio::test)]
+    async fn test_private_messages() {
+        let opts = nostr_sdk::database::MemoryDatabaseOptions {
+            events: true,
+            ..Default::default()
+        };
+        let db = nostr_sdk::database::MemoryDatabase::with_opts(opts);
+        let client = Arc::new(ClientBuilder::new().database(db).build());
+        let me = Keys::generate();
+        let peer = Keys::generate();
+        let stranger = Keys::generate();
+        let gift_wrap = |from: &Keys, content: &str, created_at: u64| {
+            let rumor = EventBuilder::private_msg_rumor(me.public_key(), content, None)
+                .custom_created_at(Timestamp::from(created_at))
+                .to_unsigned_event(from.public_key());
+            EventBuilder::gift_wrap(from, &me.public_key(), rumor, None).unwrap()
+        };
+        let legacy = EventBuilder::encrypted_direct_msg(&peer, me.public_key(), "legacy", None)
+            .unwrap()
+            .custom_created_at(Timestamp::from(10))
+            .to_event(&peer)
+            .unwrap();
+        for event in [
+            legacy,
+            gift_wrap(&peer, "private", 20),
+            gift_wrap(&stranger, "not for this chat", 30),
+        ] {
+            client.database().save_event(&event).await.unwrap();
+        }
+
+        let signer = NostrSigner::Keys(me.clone());
+        let mut paginator = DecryptedMsgPaginator::new(
+            &signer,
+            client,
+            peer.public_key(),
+            None,
+            10,
+            FetchPolicy::CacheOnly,
+        )
+        .await
+        .unwrap();
+        let page = paginator.next_page().await.unwrap();
+        let contents: Vec<&str> = page
+            .iter()
+            .map(|msg| msg.content.as_deref().unwrap())
+            .collect();
+        // Ordered by the rumor's timestamp, not the gift wrap's
+        assert_eq!(contents, vec!["private", "legacy"]);
+        assert_eq!(page[0].kind, Kind::PrivateDirectMessage);
+        assert_eq!(page[0].pubkey, peer.public_key());
+    }
+
+    #[wasm_bindgen_test(unsupported = tokio::test)]
+    async fn test_private_messages_decrypt_cache() {
+        let opts = nostr_sdk::database::MemoryDatabaseOptions {
+            events: true,
+            ..Default::default()
+        };
+        let db = nostr_sdk::database::MemoryDatabase::with_opts(opts);
+        let client = Arc::new(ClientBuilder::new().database(db).build());
+        let me = Keys::generate();
+        let peer = Keys::generate();
+        let legacy = EventBuilder::encrypted_direct_msg(&peer, me.public_key(), "legacy", None)
+            .unwrap()
+            .to_event(&peer)
+            .unwrap();
+        let rumor = EventBuilder::private_msg_rumor(me.public_key(), "private", None)
+            .to_unsigned_event(peer.public_key());
+        let gift_wrap = EventBuilder::gift_wrap(&peer, &me.public_key(), rumor, None).unwrap();
+        for event in [&legacy, &gift_wrap] {
+            client.database().save_event(event).await.unwrap();
+        }
+
+        let signer = NostrSigner::Keys(me.clone());
+        let cache = DecryptCache::default();
+        let mut contents = Vec::new();
+        for pass in 0..2 {
+            if pass == 1 {
+                // The second pass is served from the cache
+                cache.insert(legacy.id, "cached".to_string());
+            }
+            let mut paginator = DecryptedMsgPaginator::new(
+                &signer,
+                Arc::clone(&client),
+                peer.public_key(),
+                None,
+                10,
+                FetchPolicy::CacheOnly,
+            )
+            .await
+            .unwrap()
+            .with_decrypt_cache(cache.clone());
+            contents = paginator
+                .next_page()
+                .await
+                .unwrap()
+                .into_iter()
+                .filter_map(|msg| msg.content)
+                .collect();
+            contents.sort();
+            assert_eq!(cache.len(), 2);
+        }
+        assert_eq!(contents, ["cached", "private"]);
+    }
+
+    #[wasm_bindgen_test(unsupported = tokio::test)]
+    async fn test_get_conversations() {
+        let opts = nostr_sdk::database::MemoryDatabaseOptions {
+            events: true,
+            ..Default::default()
+        };
+        let db = nostr_sdk::database::MemoryDatabase::with_opts(opts);
+        let client = ClientBuilder::new().database(db).build();
+        let me = Keys::generate();
+        let alice = Keys::generate();
+        let bob = Keys::generate();
+        let dm = |from: &Keys, to: &Keys, content: &str, created_at: u64| {
+            EventBuilder::encrypted_direct_msg(from, to.public_key(), content, None)
+                .unwrap()
+                .custom_created_at(Timestamp::from(created_at))
+                .to_event(from)
+                .unwrap()
+        };
+        let rumor = EventBuilder::private_msg_rumor(me.public_key(), "from bob", None)
+            .custom_created_at(Timestamp::from(30))
+            .to_unsigned_event(bob.public_key());
+        for event in [
+            dm(&alice, &me, "hi", 10),
+            dm(&me, &alice, "hello alice", 20),
+            EventBuilder::gift_wrap(&bob, &me.public_key(), rumor, None).unwrap(),
+        ] {
+            client.database().save_event(&event).await.unwrap();
+        }
+
+        let filter = Filter::new().kinds([Kind::EncryptedDirectMessage, Kind::GiftWrap]);
+        let events = client
+            .database()
+            .query(vec![filter], Order::Desc)
+            .await
+            .unwrap();
+        let signer = NostrSigner::Keys(me.clone());
+        let conversations = summarize_conversations(&signer, me.public_key(), &events).await;
+        assert_eq!(conversations.len(), 2);
+        assert_eq!(conversations[0].peer, bob.public_key());
+        assert_eq!(conversations[0].last_message.as_deref(), Some("from bob"));
+        assert_eq!(conversations[1].peer, alice.public_key());
+        assert_eq!(
+            conversations[1].last_message.as_deref(),
+            Some("hello alice")
+        );
+        assert_eq!(conversations[1].last_received_at, Some(Timestamp::from(10)));
+        assert_eq!(conversations[1].message_count, 2);
+    }
+
+    #[cfg_attr(not(target_arch = "wasm32"), ignore = "needs public relays")]
+    #[wasm_bindgen_test(unsupported = tokio::test)]
     async fn test_encrypted_direct_message_filters_iterator() {
         let private_key = SecretKey::from_bech32(
             "nsec1qrypzwmxp8r54ctx2x7mhqzh5exca7xd8ssnlfup0js9l6pwku3qacq4u3",
@@ -685,7 +3595,7 @@ mod tests {
             target_pub_key,
             timeout,
             page_size,
-            false,
+            FetchPolicy::RelayOnly,
         )
         .await
         .unwrap();
@@ -700,7 +3610,145 @@ mod tests {
         assert!(count > 7);
     }
 
-    #[wasm_bindgen_test]
+    #[wasm_bindgen_test(unsupported = test)]
+    fn test_follower_count_sample() {
+        let followed = Keys::generate().public_key();
+        let contact_list = |author: &Keys| {
+            let contact = nostr_sdk::Contact::new(followed, None, None::<String>);
+            EventBuilder::contact_list([contact])
+                .to_event(author)
+                .unwrap()
+        };
+        let alice = Keys::generate();
+        let bob = Keys::generate();
+        // Relays may return an older version of the same list
+        let sample = vec![
+            contact_list(&alice),
+            contact_list(&alice),
+            contact_list(&bob),
+        ];
+
+        assert_eq!(count_sample(&sample, 10), FollowerCount::Exact(2));
+        let full = count_sample(&sample, 3);
+        assert_eq!(full, FollowerCount::AtLeast(2));
+        assert_eq!(full.value(), 2);
+    }
+
+    #[wasm_bindgen_test(unsupported = tokio::test)]
+    async fn test_decode_app_data() {
+        let keys = Keys::generate();
+        let signer = NostrSigner::Keys(keys.clone());
+        let data = serde_json::json!({ "theme": "dark", "columns": 3 });
+        let settings = |content: String| {
+            EventBuilder::new(
+                Kind::ApplicationSpecificData,
+                content,
+                [Tag::identifier("nostr-crdt")],
+            )
+            .to_event(&keys)
+            .unwrap()
+        };
+
+        let plain = settings(data.to_string());
+        assert_eq!(decode_app_data(&signer, &plain).await.unwrap(), data);
+
+        let payload = signer
+            .nip44_encrypt(keys.public_key(), data.to_string())
+            .await
+            .unwrap();
+        let encrypted = settings(payload);
+        assert_eq!(decode_app_data(&signer, &encrypted).await.unwrap(), data);
+
+        // Someone else's keys cannot read it
+        let other = NostrSigner::Keys(Keys::generate());
+        assert!(decode_app_data(&other, &encrypted).await.is_err());
+    }
+
+    #[wasm_bindgen_test(unsupported = test)]
+    fn test_tally_votes() {
+        let author = Keys::generate();
+        let poll = EventBuilder::new(
+            Poll::KIND,
+            "Lunch?",
+            [
+                Tag::parse(&["option", "a", "ramen"]).unwrap(),
+                Tag::parse(&["option", "b", "sushi"]).unwrap(),
+                Tag::parse(&["endsAt", "100"]).unwrap(),
+            ],
+        )
+        .to_event(&author)
+        .unwrap();
+        let poll = Poll::try_from(poll).unwrap();
+        let vote = |keys: &Keys, options: &[&str], created_at: u64| {
+            let mut tags = vec![Tag::event(poll.inner.id)];
+            tags.extend(
+                options
+                    .iter()
+                    .map(|id| Tag::parse(&["response", id]).unwrap()),
+            );
+            EventBuilder::new(Poll::RESPONSE_KIND, "", tags)
+                .custom_created_at(Timestamp::from(created_at))
+                .to_event(keys)
+                .unwrap()
+        };
+        let (alice, bob, carol) = (Keys::generate(), Keys::generate(), Keys::generate());
+        let responses = vec![
+            vote(&alice, &["a"], 10),
+            // Changed her mind
+            vote(&alice, &["b"], 20),
+            // Single choice: only the first option counts
+            vote(&bob, &["a", "b"], 30),
+            // Too late
+            vote(&carol, &["a"], 200),
+        ];
+
+        let results = tally_votes(&poll, &responses);
+        assert_eq!(results.voters, 2);
+        assert_eq!(
+            results.options,
+            vec![
+                ("a".to_string(), "ramen".to_string(), 1),
+                ("b".to_string(), "sushi".to_string(), 1),
+            ]
+        );
+    }
+
+    #[wasm_bindgen_test(unsupported = tokio::test)]
+    async fn test_mutual_follows() {
+        let client = Client::default();
+        let [carol, dave] = [(); 2].map(|_| Keys::generate().public_key());
+        let contact_list = |author: &Keys, follows: &[PublicKey], created_at: u64| {
+            let contacts = follows
+                .iter()
+                .map(|pk| nostr_sdk::Contact::new(*pk, None, None::<String>));
+            EventBuilder::contact_list(contacts)
+                .custom_created_at(Timestamp::from(created_at))
+                .to_event(author)
+                .unwrap()
+        };
+        let (alice, bob) = (Keys::generate(), Keys::generate());
+        let (alice_pk, bob_pk) = (alice.public_key(), bob.public_key());
+        let cache = ContactListCache::global();
+        cache.observe(&contact_list(&alice, &[bob_pk, carol, dave], 2));
+        cache.observe(&contact_list(&bob, &[alice_pk, carol], 1));
+        // An older list does not replace the cached one
+        cache.observe(&contact_list(&alice, &[dave], 1));
+
+        assert!(is_following(&client, &alice_pk, &bob_pk, None)
+            .await
+            .unwrap());
+        assert!(is_following(&client, &bob_pk, &alice_pk, None)
+            .await
+            .unwrap());
+        assert!(!is_following(&client, &bob_pk, &dave, None).await.unwrap());
+        let mutual = get_mutual_follows(&client, &alice_pk, &bob_pk, None)
+            .await
+            .unwrap();
+        assert_eq!(mutual, HashSet::from([carol]));
+    }
+
+    #[cfg_attr(not(target_arch = "wasm32"), ignore = "needs public relays")]
+    #[wasm_bindgen_test(unsupported = tokio::test)]
     async fn test_get_followers() {
         let client = &Client::default();
         let arc_client = Arc::new(client.clone());
@@ -717,12 +3765,12 @@ mod tests {
         let timeout = Some(std::time::Duration::from_secs(5));
         let exit_cond = Arc::new(AtomicBool::new(false));
 
-        let stream = get_followers(arc_client, &public_key, timeout, false).await;
+        let stream = get_followers(arc_client, &public_key, timeout, FetchPolicy::RelayOnly).await;
 
-        spawn_local({
+        runtime::spawn({
             let exit_cond_clone = Arc::clone(&exit_cond);
             async move {
-                sleep(Duration::from_secs(1)).await;
+                runtime::sleep(Duration::from_secs(1)).await;
                 console_log!("Setting exit condition");
                 exit_cond_clone.store(true, Ordering::SeqCst);
             }
@@ -744,7 +3792,135 @@ mod tests {
         assert!(!followers.lock().await.is_empty());
     }
 
-    #[wasm_bindgen_test]
+    #[cfg(not(target_arch = "wasm32"))]
+    #[tokio::test]
+    async fn test_metadata_cache() {
+        let relay = MockRelay::run().await.unwrap();
+        let keys = Keys::generate();
+        let metadata = |created_at: u64, name: &str| {
+            EventBuilder::metadata(&Metadata::new().name(name))
+                .custom_created_at(Timestamp::from(created_at))
+                .to_event(&keys)
+                .unwrap()
+        };
+        relay.add_event(metadata(1, "first"));
+
+        let client = Arc::new(mock_client(&[&relay]).await);
+        let timeout = Some(std::time::Duration::from_secs(5));
+        let cache = MetadataCache::new(client, Duration::from_secs(600), timeout);
+        let public_key = keys.public_key();
+        let name = |metadata: Metadata| metadata.name.unwrap();
+        assert_eq!(name(cache.get(&public_key).await.unwrap()), "first");
+
+        // Served from memory until the TTL runs out
+        relay.add_event(metadata(2, "second"));
+        assert_eq!(name(cache.get(&public_key).await.unwrap()), "first");
+        assert_eq!(name(cache.refresh(&public_key).await.unwrap()), "second");
+
+        // Fetched again once invalidated
+        relay.add_event(metadata(3, "third"));
+        cache.invalidate(&public_key);
+        assert_eq!(name(cache.get(&public_key).await.unwrap()), "third");
+    }
+
+    #[wasm_bindgen_test(unsupported = tokio::test)]
+    async fn test_metadata_cache_observe() {
+        // No relays: whatever `get` returns was observed
+        let client = Arc::new(Client::default());
+        let cache = MetadataCache::new(client, Duration::from_secs(600), None)
+            .policy(FetchPolicy::CacheOnly);
+
+        // A newer kind 0 event replaces the cached one, an older one does not
+        let keys = Keys::generate();
+        let old = EventBuilder::metadata(&Metadata::new().name("old"))
+            .custom_created_at(Timestamp::from(1))
+            .to_event(&keys)
+            .unwrap();
+        let new = EventBuilder::metadata(&Metadata::new().name("new"))
+            .to_event(&keys)
+            .unwrap();
+        cache.observe(&new);
+        cache.observe(&old);
+        let cached = cache.get(&keys.public_key()).await.unwrap();
+        assert_eq!(cached.name, Some("new".to_string()));
+
+        // Other kinds are ignored
+        let note = EventBuilder::text_note("not metadata", [])
+            .to_event(&keys)
+            .unwrap();
+        cache.invalidate(&keys.public_key());
+        cache.observe(&note);
+        assert!(cache.get(&keys.public_key()).await.is_err());
+    }
+
+    // Revision of the article `identifier` by `keys`
+    fn article(keys: &Keys, identifier: &str, created_at: u64, published_at: u64) -> Event {
+        EventBuilder::long_form_text_note(
+            format!("{identifier} at {created_at}"),
+            [
+                Tag::identifier(identifier),
+                Tag::from_standardized(TagStandard::PublishedAt(Timestamp::from(published_at))),
+            ],
+        )
+        .custom_created_at(Timestamp::from(created_at))
+        .to_event(keys)
+        .unwrap()
+    }
+
+    #[wasm_bindgen_test(unsupported = test)]
+    fn test_latest_articles() {
+        let keys = Keys::generate();
+        let first = article(&keys, "first", 10, 10);
+        let edited = article(&keys, "first", 30, 10);
+        let second = article(&keys, "second", 20, 20);
+        let note = EventBuilder::text_note("not an article", [])
+            .to_event(&keys)
+            .unwrap();
+
+        // The latest revision of each, last published first
+        let articles = latest_articles(vec![edited.clone(), second.clone(), first, note]);
+        let ids: Vec<EventId> = articles.iter().map(|article| article.inner.id).collect();
+        assert_eq!(ids, vec![second.id, edited.id]);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[tokio::test]
+    async fn test_get_articles() {
+        let relay = MockRelay::run().await.unwrap();
+        let keys = Keys::generate();
+        for (i, identifier) in ["a", "b", "c"].into_iter().enumerate() {
+            let at = i as u64 + 1;
+            relay.add_event(article(&keys, identifier, at, at));
+        }
+        relay.add_event(
+            EventBuilder::text_note("not an article", [])
+                .to_event(&keys)
+                .unwrap(),
+        );
+        let other = Keys::generate();
+        relay.add_event(article(&other, "a", 4, 4));
+
+        let client = Arc::new(mock_client(&[&relay]).await);
+        let public_key = keys.public_key();
+        let timeout = Some(std::time::Duration::from_secs(5));
+        let articles = get_articles(&client, &public_key, timeout).await.unwrap();
+        let identifiers: Vec<&str> = articles
+            .iter()
+            .map(|article| article.identifier.as_str())
+            .collect();
+        assert_eq!(identifiers, vec!["c", "b", "a"]);
+
+        let mut paginator =
+            get_articles_paginator(client, &public_key, timeout, 2, FetchPolicy::RelayOnly);
+        let page = paginator.next_page().await.unwrap();
+        assert_eq!(page.len(), 2);
+        assert!(page
+            .iter()
+            .all(|event| { event.kind == Kind::LongFormTextNote && event.pubkey == public_key }));
+    }
+
+    #[cfg_attr(not(target_arch = "wasm32"), ignore = "needs public relays")]
+    #[wasm_bindgen_test(unsupported = tokio::test)]
     async fn test_get_following() {
         let client = Client::default();
         let client = Arc::new(client);
@@ -762,7 +3938,66 @@ mod tests {
         assert!(!following.is_empty());
     }
 
-    #[wasm_bindgen_test]
+    #[cfg(not(target_arch = "wasm32"))]
+    #[tokio::test]
+    async fn test_get_relay_list() {
+        let home = MockRelay::run().await.unwrap();
+        let outbox = MockRelay::run().await.unwrap();
+        let inbox = MockRelay::run().await.unwrap();
+        let author = Keys::generate();
+        let relay_list = |created_at: u64, relays: Vec<(Url, Option<RelayMetadata>)>| {
+            EventBuilder::relay_list(relays)
+                .custom_created_at(Timestamp::from(created_at))
+                .to_event(&author)
+                .unwrap()
+        };
+        let relays = vec![
+            (outbox.url(), Some(RelayMetadata::Write)),
+            (inbox.url(), Some(RelayMetadata::Read)),
+        ];
+        home.add_event(relay_list(2, relays.clone()));
+        // Replaced by the list above
+        home.add_event(relay_list(1, vec![(home.url(), None)]));
+
+        // Only published to the outbox, next to a note by someone else
+        let note = EventBuilder::text_note("from the outbox", [])
+            .to_event(&author)
+            .unwrap();
+        outbox.add_event(note.clone());
+        outbox.add_event(
+            EventBuilder::text_note("not the author", [])
+                .to_event(&Keys::generate())
+                .unwrap(),
+        );
+
+        let client = mock_client(&[&home]).await;
+        let public_key = author.public_key();
+        let timeout = Some(std::time::Duration::from_secs(5));
+        assert_eq!(
+            get_relay_list(&client, &public_key, timeout).await.unwrap(),
+            relays
+        );
+        assert_eq!(
+            get_write_relays(&client, &public_key, timeout)
+                .await
+                .unwrap(),
+            vec![outbox.url()]
+        );
+
+        let filter = Filter::new().kind(Kind::TextNote);
+        let events = get_events_from_outbox(&client, &public_key, vec![filter], timeout)
+            .await
+            .unwrap();
+        let ids: Vec<EventId> = events.iter().map(|event| event.id).collect();
+        assert_eq!(ids, vec![note.id]);
+        // Outbox relays join the pool, read relays do not
+        let pool = client.relays().await;
+        assert!(pool.contains_key(&outbox.url()));
+        assert!(!pool.contains_key(&inbox.url()));
+    }
+
+    #[cfg_attr(not(target_arch = "wasm32"), ignore = "needs public relays")]
+    #[wasm_bindgen_test(unsupported = tokio::test)]
     async fn test_get_notification_paginator() {
         let client = Client::default();
         client.add_relay("wss://relay.damus.io").await.unwrap();
@@ -775,8 +4010,13 @@ mod tests {
         .unwrap();
 
         let timeout = Some(std::time::Duration::from_secs(5));
-        let mut paginator =
-            NotificationPaginator::new(Arc::new(client), public_key, timeout, 100, false);
+        let mut paginator = NotificationPaginator::new(
+            Arc::new(client),
+            public_key,
+            timeout,
+            100,
+            FetchPolicy::RelayOnly,
+        );
         let mut count = 0;
         loop {
             let result = paginator.next_page().await;
@@ -797,7 +4037,149 @@ mod tests {
         assert!(count > 0);
     }
 
-    #[wasm_bindgen_test]
+    #[wasm_bindgen_test(unsupported = test)]
+    fn test_notification_kinds() {
+        let keys = Keys::generate();
+        let me = Keys::generate().public_key();
+        let parent = EventBuilder::text_note("parent", [])
+            .to_event(&keys)
+            .unwrap();
+        let note = |tags: Vec<Tag>| {
+            let mut tags = tags;
+            tags.push(Tag::public_key(me));
+            EventBuilder::text_note("hi nostr:npub1...", tags)
+                .to_event(&keys)
+                .unwrap()
+        };
+        let reply = note(vec![
+            Tag::parse(&["e", &parent.id.to_hex(), "", "root"]).unwrap()
+        ]);
+        let quote = note(vec![Tag::parse(&["q", &parent.id.to_hex()]).unwrap()]);
+        // The `nostr:` text alone no longer makes a quote
+        let mention = note(vec![]);
+        let dm = EventBuilder::new(
+            Kind::EncryptedDirectMessage,
+            "secret",
+            [Tag::public_key(me)],
+        )
+        .to_event(&keys)
+        .unwrap();
+
+        let msgs = process_notification_events(vec![reply, quote, mention, dm]);
+        assert!(matches!(msgs[0], NotificationMsg::Reply(_)));
+        assert!(matches!(msgs[1], NotificationMsg::Quote(_)));
+        assert!(matches!(msgs[2], NotificationMsg::Mention(_)));
+        assert!(matches!(msgs[3], NotificationMsg::Dm(_)));
+    }
+
+    #[wasm_bindgen_test(unsupported = tokio::test)]
+    async fn test_list_private_entries() {
+        let keys = Keys::generate();
+        let signer = NostrSigner::Keys(keys.clone());
+        let bookmarked = EventId::all_zeros();
+        let private = serde_json::to_string(&vec![vec!["t", "secret"]]).unwrap();
+        let encrypted = signer
+            .nip44_encrypt(keys.public_key(), private)
+            .await
+            .unwrap();
+        let event = EventBuilder::new(
+            Kind::BookmarkSets,
+            encrypted,
+            [
+                Tag::identifier("reading"),
+                Tag::parse(&["title", "Reading list"]).unwrap(),
+                Tag::event(bookmarked),
+            ],
+        )
+        .to_event(&keys)
+        .unwrap();
+
+        let mut list = NostrList::from_event(&event);
+        assert_eq!(list.identifier.as_deref(), Some("reading"));
+        assert_eq!(list.title.as_deref(), Some("Reading list"));
+        assert_eq!(list.public, vec![ListEntry::Event(bookmarked)]);
+        assert!(list.encrypted.is_some());
+
+        list.decrypt_private(&signer).await.unwrap();
+        assert_eq!(list.private, vec![ListEntry::Hashtag("secret".to_string())]);
+        assert_eq!(list.entries().count(), 2);
+    }
+
+    #[wasm_bindgen_test(unsupported = test)]
+    fn test_mute_list() {
+        let keys = Keys::generate();
+        let muted = Keys::generate();
+        let thread = EventId::all_zeros();
+        let mut mute_list = MuteList::default();
+        for tag in [
+            vec!["p".to_string(), muted.public_key().to_hex()],
+            vec!["e".to_string(), thread.to_hex()],
+            vec!["t".to_string(), "Spam".to_string()],
+            vec!["word".to_string(), "Airdrop".to_string()],
+        ] {
+            mute_list.add_entry(&ListEntry::from_tag(&tag).unwrap());
+        }
+
+        let note = |keys: &Keys, content: &str, tags: Vec<Tag>| {
+            EventBuilder::text_note(content, tags)
+                .to_event(keys)
+                .unwrap()
+        };
+        let mut events = vec![
+            note(&muted, "hello", vec![]),
+            note(&keys, "in a muted thread", vec![Tag::event(thread)]),
+            note(&keys, "tagged", vec![Tag::hashtag("spam")]),
+            note(&keys, "free AIRDROP now", vec![]),
+            note(&keys, "hello", vec![]),
+        ];
+        mute_list.retain_unmuted(&mut events);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].pubkey, keys.public_key());
+    }
+
+    #[wasm_bindgen_test(unsupported = test)]
+    fn test_zap_receipt_parsing() {
+        let sender = Keys::generate();
+        let zapped = EventId::all_zeros();
+        let request = EventBuilder::new(
+            Kind::ZapRequest,
+            "great post",
+            [Tag::parse(&["amount", "21000"]).unwrap()],
+        )
+        .to_event(&sender)
+        .unwrap();
+        let receipt = |bolt11: &str| {
+            EventBuilder::new(
+                Kind::ZapReceipt,
+                "",
+                [
+                    Tag::parse(&["bolt11", bolt11]).unwrap(),
+                    Tag::parse(&["description", &request.as_json()]).unwrap(),
+                    Tag::event(zapped),
+                ],
+            )
+            .to_event(&Keys::generate())
+            .unwrap()
+        };
+
+        let paid = receipt("lnbc210n1pjexample");
+        assert_eq!(zap_receipt_msats(&paid), Some(21_000));
+        assert_eq!(zap_sender(&paid), Some(sender.public_key()));
+        // Falls back to the zap request's amount
+        assert_eq!(zap_receipt_msats(&receipt("garbage")), Some(21_000));
+
+        let msgs = process_notification_events(vec![paid]);
+        let NotificationMsg::ZapReceipt(zap) = &msgs[0] else {
+            panic!("expected a zap receipt");
+        };
+        assert_eq!(zap.amount_msats, Some(21_000));
+        assert_eq!(zap.sender, Some(sender.public_key()));
+        assert_eq!(zap.event_id, Some(zapped));
+        assert_eq!(zap.comment.as_deref(), Some("great post"));
+    }
+
+    #[cfg_attr(not(target_arch = "wasm32"), ignore = "needs public relays")]
+    #[wasm_bindgen_test(unsupported = tokio::test)]
     async fn test_get_repost() {
         let client = Client::default();
         client.add_relay("wss://relay.damus.io").await.unwrap();
@@ -1,27 +1,65 @@
+pub mod auth;
 pub mod crdt;
 pub mod fetch;
 
 pub mod note;
+pub mod outbox;
 pub mod publish;
 pub mod register;
+pub mod relay_info;
+pub mod remote_signer;
+mod runtime;
 pub mod utils;
+pub mod zap;
 
+pub use auth::{AuthPolicy, Authenticator};
 pub use fetch::{
-    create_notification_filters, get_event_by_id, get_events_by_ids, get_followers, get_following,
-    get_metadata, get_reactions, get_replies, get_repost, process_notification_events,
-    DecryptedMsg, DecryptedMsgPaginator, EventPaginator, NotificationMsg, NotificationPaginator,
+    create_notification_filters, fetch_events, get_app_data, get_articles, get_articles_paginator,
+    get_conversations, get_dm_relays, get_event_by_id, get_events_by_ids, get_events_from_outbox,
+    get_events_until_eose, get_follower_count, get_followers, get_following, get_lists,
+    get_metadata, get_mute_list, get_mutual_follows, get_poll_results, get_reaction_details,
+    get_reactions, get_relay_list, get_replies, get_replies_and_quotes, get_repost, get_thread,
+    get_write_relays, get_zap, get_zap_total, is_following, process_notification_events,
+    relay_stats, search_events, subscribe_stream, subscribe_stream_to, ContactListCache,
+    Conversation, DecryptCache, DecryptedMsg, DecryptedMsgPaginator, EoseQuorum, EventPaginator,
+    FetchPolicy, FollowerCount, ListEntry, MetadataCache, MuteList, NostrList, NotificationMsg,
+    NotificationPaginator, PaginationCursor, PollResults, ReactionDetail, References, RelayStats,
+    TimelinePaginator, ZapReceipt, ZapTotal,
 };
-pub use note::{DisplayOrder, ReplyTreeManager, ReplyTrees, TextNote};
+pub use note::{
+    ContentToken, DisplayOrder, LongFormNote, Poll, ReplyPage, ReplyTreeManager, ReplyTrees,
+    SubtreeStats, TextNote, ThreadItem, TreeChange,
+};
+#[cfg(native_backend)]
+pub use outbox::FileOutboxStore;
+#[cfg(wasm_backend)]
+pub use outbox::IndexedDbOutboxStore;
+pub use outbox::{Outbox, OutboxEntry, OutboxStatus, OutboxStore};
 pub use publish::{
-    delete_event, file_metadata, follow, new_channel, publish_text_note, reaction, repost,
-    send_channel_msg, send_private_msg, set_channel_metadata, set_contact_list, set_relay_list,
-    unfollow,
+    award_badge, create_live_event, create_poll, define_badge, delete_event, file_metadata, follow,
+    new_channel, publish_events, publish_long_form, publish_long_form_to, publish_text_note,
+    publish_text_note_to, quote_repost, quote_repost_to, reaction, reaction_to, repost, repost_to,
+    send_channel_msg, send_channel_msg_to, send_live_chat, send_private_msg, send_private_msg_to,
+    set_app_data, set_channel_metadata, set_contact_list, set_profile_badges, set_relay_list,
+    unfollow, update_contact_list, update_live_event, update_profile, vote_poll, ContactListDiff,
+    DeleteOptions, DmProtocol, EventReport, LiveEventUpdate, ProfilePatch, ProofOfWork,
+    PublishReport, Reaction,
 };
 
+pub use relay_info::{RelayCapabilities, RelayCapabilitiesCache};
+pub use remote_signer::{bunker_uri, connect_bunker, remote_public_key, Pairing};
+
 pub use utils::get_ancestors;
 pub use utils::get_children;
 pub use utils::get_newest_event;
 pub use utils::get_oldest_event;
 pub use utils::hash_filter;
 pub use utils::is_note_address;
+pub use utils::parse_bolt11_msats;
 pub use utils::AddressType;
+pub use utils::CustomEmoji;
+pub use utils::{
+    bucket_events_by_day, custom_emoji, decode_nostr_entity, events_in_range, filter_cache_key,
+    NostrEntity, FILTER_HASH_VERSION,
+};
+pub use zap::{create_zap_request, LnurlPay, ZapInvoice, ZapTarget};
@@ -1,9 +1,17 @@
 use std::cmp::min;
-use std::collections::{HashMap, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::OnceLock;
 
 use indextree::{Arena, NodeId};
+use nostr_sdk::nips::nip01::Coordinate;
 use nostr_sdk::nips::nip10::Marker;
-use nostr_sdk::{Alphabet, Event, EventId, Kind, SingleLetterTag, Tag, TagKind, TagStandard};
+use nostr_sdk::nips::nip19::{FromBech32, Nip19, Nip19Event};
+use nostr_sdk::{
+    Alphabet, Event, EventId, Kind, PublicKey, SingleLetterTag, Tag, TagKind, TagStandard,
+    Timestamp, Url,
+};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
 
 use super::utils::{self, get_children};
@@ -18,6 +26,8 @@ pub enum Error {
     NormalizationFailed,
     #[error("Node ID not found")]
     NodeIdNotFound,
+    #[error("Invalid serialized tree: {0}")]
+    InvalidJson(String),
 }
 
 type Result<T> = std::result::Result<T, Error>;
@@ -27,6 +37,8 @@ pub struct TextNote {
     pub inner: Event,
     root: Option<EventId>,
     reply_to: Option<EventId>,
+    /// Events cited but not replied to, never part of the ancestor chain
+    mentions: Vec<EventId>,
 }
 
 impl TextNote {
@@ -35,6 +47,7 @@ impl TextNote {
             inner: event,
             root: None,
             reply_to: None,
+            mentions: Vec::new(),
         }
     }
 
@@ -51,6 +64,15 @@ impl TextNote {
     pub fn get_reply_to(&self) -> Option<EventId> {
         self.reply_to
     }
+    pub fn get_mentions(&self) -> &[EventId] {
+        &self.mentions
+    }
+
+    /// Splits the content into text and the entities a client renders
+    /// specially, in the order they appear
+    pub fn content_tokens(&self) -> Vec<ContentToken> {
+        parse_content(&self.inner.content)
+    }
 
     fn process_tags(event: &Event, text_note: &mut TextNote) -> Result<()> {
         let mut no_marker_array: Vec<EventId> = vec![];
@@ -69,6 +91,7 @@ impl TextNote {
                     match marker {
                         Some(Marker::Root) => text_note.root = Some(event_id),
                         Some(Marker::Reply) => text_note.reply_to = Some(event_id),
+                        Some(Marker::Mention) => text_note.mentions.push(event_id),
                         None => no_marker_array.push(event_id),
                         _ => {}
                     }
@@ -82,19 +105,25 @@ impl TextNote {
             text_note.reply_to = Some(*root);
         }
 
-        if text_note.reply_to.is_none() {
-            match no_marker_array.len() {
-                1 => {
-                    text_note.root = no_marker_array.first().cloned();
-                    text_note.reply_to = no_marker_array.first().cloned();
-                }
-                2.. => {
-                    text_note.root = no_marker_array.first().cloned();
-                    text_note.reply_to = no_marker_array.get(1).cloned();
-                }
-                _ => {
-                    return Err(Error::NotEnoughElements);
-                }
+        if text_note.reply_to.is_some() {
+            // Next to marked tags, unmarked ones can only be mentions
+            text_note.mentions.extend(no_marker_array);
+            return Ok(());
+        }
+
+        // Deprecated positional style: root first, reply last, mentions between
+        match no_marker_array.as_slice() {
+            [only] => {
+                text_note.root = Some(*only);
+                text_note.reply_to = Some(*only);
+            }
+            [first, mentions @ .., last] => {
+                text_note.root = Some(*first);
+                text_note.reply_to = Some(*last);
+                text_note.mentions.extend_from_slice(mentions);
+            }
+            [] => {
+                return Err(Error::NotEnoughElements);
             }
         }
 
@@ -120,6 +149,99 @@ fn normalize_e_tag(t: &Tag) -> Result<Option<TagStandard>> {
     }
 }
 
+/// A piece of note content
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContentToken {
+    Text(String),
+    /// `nostr:npub…`, `nostr:nprofile…` or a bare npub
+    Mention(PublicKey),
+    /// `nostr:note…` or `nostr:nevent…`, with the relay hints if any
+    Event(Nip19Event),
+    /// `nostr:naddr…`
+    Address(Coordinate),
+    /// Without the `#`
+    Hashtag(String),
+    Url(Url),
+    /// A url pointing to an image file
+    Image(Url),
+}
+
+const IMAGE_EXTENSIONS: [&str; 7] = ["png", "jpg", "jpeg", "gif", "webp", "svg", "avif"];
+
+fn content_regex() -> &'static Regex {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    RE.get_or_init(|| {
+        Regex::new(
+            r"\b(?:nostr:)?(?:npub|nprofile|note|nevent|naddr)1[02-9ac-hj-np-z]+|https?://[^\s<>]+|#[\p{L}\p{N}_]+",
+        )
+        .expect("content regex is valid")
+    })
+}
+
+fn parse_content(content: &str) -> Vec<ContentToken> {
+    let mut tokens = Vec::new();
+    let mut text_start = 0;
+    for found in content_regex().find_iter(content) {
+        let Some((token, len)) = content_token(content, found.start(), found.as_str()) else {
+            continue;
+        };
+        if found.start() > text_start {
+            tokens.push(ContentToken::Text(
+                content[text_start..found.start()].to_string(),
+            ));
+        }
+        tokens.push(token);
+        text_start = found.start() + len;
+    }
+    if text_start < content.len() {
+        tokens.push(ContentToken::Text(content[text_start..].to_string()));
+    }
+    tokens
+}
+
+/// The token for a regex match and how many bytes of it it covers, or
+/// `None` when the match is plain text after all
+fn content_token(content: &str, start: usize, found: &str) -> Option<(ContentToken, usize)> {
+    if let Some(hashtag) = found.strip_prefix('#') {
+        // `a#b` is not a hashtag
+        let preceded_by_space = content[..start]
+            .chars()
+            .next_back()
+            .is_none_or(char::is_whitespace);
+        return preceded_by_space
+            .then(|| (ContentToken::Hashtag(hashtag.to_string()), found.len()));
+    }
+
+    if found.starts_with("http") {
+        // Punctuation closing a sentence is not part of the url
+        let trimmed = found.trim_end_matches(['.', ',', ';', ':', '!', '?', ')', '\'', '"']);
+        let url = Url::parse(trimmed).ok()?;
+        let is_image = url
+            .path()
+            .rsplit_once('.')
+            .is_some_and(|(_, ext)| IMAGE_EXTENSIONS.contains(&ext.to_lowercase().as_str()));
+        let token = if is_image {
+            ContentToken::Image(url)
+        } else {
+            ContentToken::Url(url)
+        };
+        return Some((token, trimmed.len()));
+    }
+
+    let bech32 = found.strip_prefix("nostr:").unwrap_or(found);
+    let token = match Nip19::from_bech32(bech32).ok()? {
+        Nip19::Pubkey(public_key) => ContentToken::Mention(public_key),
+        Nip19::Profile(profile) => ContentToken::Mention(profile.public_key),
+        Nip19::EventId(event_id) => {
+            ContentToken::Event(Nip19Event::new(event_id, Vec::<String>::new()))
+        }
+        Nip19::Event(event) => ContentToken::Event(event),
+        Nip19::Coordinate(coordinate) => ContentToken::Address(coordinate),
+        _ => return None,
+    };
+    Some((token, found.len()))
+}
+
 impl TryFrom<Event> for TextNote {
     type Error = Error;
 
@@ -134,11 +256,172 @@ impl TryFrom<Event> for TextNote {
         }
     }
 }
+/// NIP-23 long-form article (kind 30023)
+#[derive(Debug, Clone, PartialEq)]
+pub struct LongFormNote {
+    pub inner: Event,
+    /// `d` tag, stable across edits of the same article
+    pub identifier: String,
+    pub title: Option<String>,
+    pub summary: Option<String>,
+    pub image: Option<String>,
+    pub published_at: Option<Timestamp>,
+    pub hashtags: Vec<String>,
+}
+
+impl LongFormNote {
+    /// Markdown body
+    pub fn content(&self) -> &str {
+        &self.inner.content
+    }
+
+    /// First publication time, falling back to the time of this revision
+    pub fn published_at_or_created(&self) -> Timestamp {
+        self.published_at.unwrap_or(self.inner.created_at)
+    }
+}
+
+impl TryFrom<Event> for LongFormNote {
+    type Error = Error;
+
+    fn try_from(event: Event) -> Result<Self> {
+        if event.kind != Kind::LongFormTextNote {
+            return Err(Error::KindNotMatch);
+        }
+        let mut note = LongFormNote {
+            inner: event.clone(),
+            identifier: String::new(),
+            title: None,
+            summary: None,
+            image: None,
+            published_at: None,
+            hashtags: vec![],
+        };
+        for tag in event.iter_tags() {
+            match tag.as_standardized() {
+                Some(TagStandard::Identifier(identifier)) => note.identifier = identifier.clone(),
+                Some(TagStandard::Title(title)) => note.title = Some(title.clone()),
+                Some(TagStandard::Summary(summary)) => note.summary = Some(summary.clone()),
+                Some(TagStandard::Image(url, _)) => note.image = Some(url.to_string()),
+                Some(TagStandard::PublishedAt(timestamp)) => note.published_at = Some(*timestamp),
+                Some(TagStandard::Hashtag(hashtag)) => note.hashtags.push(hashtag.clone()),
+                _ => {}
+            }
+        }
+        Ok(note)
+    }
+}
+
+/// NIP-88 poll (kind 1068). Votes are kind 1018 responses naming the
+/// chosen option ids.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Poll {
+    pub inner: Event,
+    /// `(id, label)` of each option, in order
+    pub options: Vec<(String, String)>,
+    pub multiple_choice: bool,
+    /// Votes cast after this time are not counted
+    pub ends_at: Option<Timestamp>,
+}
+
+impl Poll {
+    pub const KIND: Kind = Kind::Custom(1068);
+    pub const RESPONSE_KIND: Kind = Kind::Custom(1018);
+
+    pub fn question(&self) -> &str {
+        &self.inner.content
+    }
+
+    pub fn has_option(&self, id: &str) -> bool {
+        self.options.iter().any(|(option, _)| option == id)
+    }
+
+    pub fn is_closed(&self, now: Timestamp) -> bool {
+        self.ends_at.is_some_and(|ends_at| now > ends_at)
+    }
+}
+
+impl TryFrom<Event> for Poll {
+    type Error = Error;
+
+    fn try_from(event: Event) -> Result<Self> {
+        if event.kind != Poll::KIND {
+            return Err(Error::KindNotMatch);
+        }
+        let mut poll = Poll {
+            inner: event.clone(),
+            options: vec![],
+            multiple_choice: false,
+            ends_at: None,
+        };
+        for tag in event.iter_tags() {
+            match tag.as_vec() {
+                [kind, id, label, ..] if kind == "option" => {
+                    poll.options.push((id.clone(), label.clone()))
+                }
+                [kind, value, ..] if kind == "polltype" => {
+                    poll.multiple_choice = value == "multiplechoice"
+                }
+                [kind, value, ..] if kind == "endsAt" => {
+                    poll.ends_at = value.parse::<u64>().ok().map(Timestamp::from)
+                }
+                _ => {}
+            }
+        }
+        Ok(poll)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct ReplyTrees {
     id2id: HashMap<EventId, NodeId>,
     arena: Arena<TextNote>,
     notes: Vec<TextNote>,
+    /// Replies whose parent has not been accepted yet, by parent id
+    orphans: HashMap<EventId, Vec<NodeId>>,
+}
+
+/// What a single insert changed, so a live thread view only has to
+/// re-render the affected subtree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeChange {
+    /// The inserted note
+    pub id: EventId,
+    /// Note it was attached under, `None` when its parent is not in the tree
+    pub parent: Option<EventId>,
+    /// Topmost known ancestor of the changed subtree
+    pub root: EventId,
+    /// Earlier replies that were waiting for this note and now hang under it
+    pub adopted: Vec<EventId>,
+}
+
+/// Serialized form of [`ReplyTrees`]: the events in insertion order, which
+/// replays into the same tree
+#[derive(Serialize, Deserialize)]
+struct SerializedTrees<E> {
+    events: Vec<E>,
+}
+
+/// One row of a flattened thread
+#[derive(Debug, Clone, PartialEq)]
+pub enum ThreadItem<'a> {
+    /// A note indented `depth` levels below the thread root
+    Note { note: &'a TextNote, depth: usize },
+    /// Stands for the `hidden` replies below `parent` that are deeper than
+    /// the maximum depth
+    Collapsed {
+        parent: EventId,
+        depth: usize,
+        hidden: usize,
+    },
+}
+
+/// A slice of the direct replies to a note
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplyPage<'a> {
+    pub replies: Vec<&'a TextNote>,
+    /// Direct replies in all pages
+    pub total: usize,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -153,28 +436,80 @@ impl Default for ReplyTrees {
             id2id: HashMap::new(),
             arena: Arena::new(),
             notes: Vec::new(),
+            orphans: HashMap::new(),
         }
     }
 }
 
 impl ReplyTrees {
     pub fn accept(&mut self, events: Vec<Event>) {
-        let mut text_notes = Vec::new();
         for event in events {
-            if let Ok(text_note) = TextNote::try_from(event) {
-                let node_id = self.arena.new_node(text_note.clone());
-                self.id2id.insert(text_note.inner.id, node_id);
-                text_notes.push((text_note, node_id));
-            }
+            self.accept_one(event);
         }
+    }
 
-        for (text_note, node_id) in text_notes {
-            if let Some(reply_to) = &text_note.reply_to {
-                if let Some(&parent_id) = self.id2id.get(reply_to) {
-                    parent_id.append(node_id, &mut self.arena);
-                }
+    /// Inserts one event without touching the rest of the tree. Replies
+    /// that arrived before it are re-parented under it. Returns `None` when
+    /// it is not a text note or is already in the tree.
+    pub fn accept_one(&mut self, event: Event) -> Option<TreeChange> {
+        if self.id2id.contains_key(&event.id) {
+            return None;
+        }
+        let text_note = TextNote::try_from(event).ok()?;
+        let id = text_note.inner.id;
+        let reply_to = text_note.reply_to;
+        let node_id = self.arena.new_node(text_note);
+        self.id2id.insert(id, node_id);
+
+        let parent = match reply_to.map(|reply_to| (reply_to, self.id2id.get(&reply_to))) {
+            Some((reply_to, Some(&parent_id))) => {
+                parent_id.append(node_id, &mut self.arena);
+                Some(reply_to)
             }
+            Some((reply_to, None)) => {
+                self.orphans.entry(reply_to).or_default().push(node_id);
+                None
+            }
+            None => None,
+        };
+
+        let mut adopted = Vec::new();
+        for orphan_id in self.orphans.remove(&id).unwrap_or_default() {
+            node_id.append(orphan_id, &mut self.arena);
+            adopted.push(self.arena[orphan_id].get().inner.id);
         }
+
+        let root = node_id
+            .ancestors(&self.arena)
+            .last()
+            .map_or(id, |root_id| self.arena[root_id].get().inner.id);
+        Some(TreeChange {
+            id,
+            parent,
+            root,
+            adopted,
+        })
+    }
+
+    /// Json snapshot of the tree, to cache a rendered thread and show it
+    /// again before fresh replies are fetched
+    pub fn to_json(&self) -> Result<String> {
+        let events = self
+            .arena
+            .iter()
+            .filter(|node| !node.is_removed())
+            .map(|node| &node.get().inner)
+            .collect();
+        serde_json::to_string(&SerializedTrees { events })
+            .map_err(|err| Error::InvalidJson(err.to_string()))
+    }
+
+    pub fn from_json(json: &str) -> Result<Self> {
+        let serialized: SerializedTrees<Event> =
+            serde_json::from_str(json).map_err(|err| Error::InvalidJson(err.to_string()))?;
+        let mut trees = Self::default();
+        trees.accept(serialized.events);
+        Ok(trees)
     }
 
     pub fn get_note_by_id(&self, id: &EventId) -> Option<&TextNote> {
@@ -188,7 +523,7 @@ impl ReplyTrees {
             let mut results = get_children(&self.arena, *node_id);
             match order {
                 Some(DisplayOrder::NewestFirst) => {
-                    results.sort_by(|b, a| a.inner.created_at.cmp(&b.inner.created_at));
+                    results.sort_by_key(|note| std::cmp::Reverse(note.inner.created_at));
                     results
                 }
                 _ => results,
@@ -198,6 +533,93 @@ impl ReplyTrees {
         }
     }
 
+    /// The thread below `root` as a display list, depth first, siblings in
+    /// `order`. Replies deeper than `max_depth` are folded into a
+    /// [`ThreadItem::Collapsed`] marker under their shallowest hidden parent.
+    pub fn flatten(
+        &self,
+        root: &EventId,
+        order: Option<DisplayOrder>,
+        max_depth: Option<usize>,
+    ) -> Vec<ThreadItem<'_>> {
+        let mut items = Vec::new();
+        let Some(&root_id) = self.id2id.get(root) else {
+            return items;
+        };
+        let mut stack = vec![(root_id, 0)];
+        while let Some((node_id, depth)) = stack.pop() {
+            let note = self.arena[node_id].get();
+            items.push(ThreadItem::Note { note, depth });
+
+            let mut children: Vec<NodeId> = node_id.children(&self.arena).collect();
+            if children.is_empty() {
+                continue;
+            }
+            if max_depth.is_some_and(|max_depth| depth >= max_depth) {
+                items.push(ThreadItem::Collapsed {
+                    parent: note.inner.id,
+                    depth: depth + 1,
+                    hidden: node_id.descendants(&self.arena).count() - 1,
+                });
+                continue;
+            }
+            self.sort_siblings(&mut children, order.as_ref());
+            // Reversed so the first child is popped first
+            stack.extend(children.into_iter().rev().map(|child| (child, depth + 1)));
+        }
+        items
+    }
+
+    /// Up to `limit` direct replies to `parent` starting at `offset`, with
+    /// the total count so a UI can render a large thread lazily
+    pub fn get_replies_page(
+        &self,
+        parent: &EventId,
+        offset: usize,
+        limit: usize,
+        order: Option<DisplayOrder>,
+    ) -> ReplyPage<'_> {
+        let Some(&node_id) = self.id2id.get(parent) else {
+            return ReplyPage {
+                replies: Vec::new(),
+                total: 0,
+            };
+        };
+        let page = |children: &mut dyn Iterator<Item = NodeId>| -> Vec<&TextNote> {
+            children
+                .skip(offset)
+                .take(limit)
+                .map(|child| self.arena[child].get())
+                .collect()
+        };
+        match order {
+            // Insertion order needs no sorting, so only the page is visited
+            None => ReplyPage {
+                replies: page(&mut node_id.children(&self.arena)),
+                total: node_id.children(&self.arena).count(),
+            },
+            Some(order) => {
+                let mut children: Vec<NodeId> = node_id.children(&self.arena).collect();
+                self.sort_siblings(&mut children, Some(&order));
+                ReplyPage {
+                    replies: page(&mut children.iter().copied()),
+                    total: children.len(),
+                }
+            }
+        }
+    }
+
+    fn sort_siblings(&self, children: &mut [NodeId], order: Option<&DisplayOrder>) {
+        match order {
+            Some(DisplayOrder::NewestFirst) => children
+                .sort_by_key(|child| std::cmp::Reverse(self.arena[*child].get().inner.created_at)),
+            Some(DisplayOrder::DeepestFirst) => {
+                children.sort_by_key(|child| std::cmp::Reverse(subtree_height(&self.arena, *child)))
+            }
+            None => {}
+        }
+    }
+
     pub fn get_ancestors(&self, id: &EventId) -> Vec<&TextNote> {
         if let Some(node_id) = self.id2id.get(id) {
             utils::get_ancestors(&self.arena, *node_id)
@@ -208,10 +630,15 @@ impl ReplyTrees {
     pub fn is_empty(&self) -> bool {
         self.notes.is_empty()
     }
+    /// Replies still waiting for their parent to be accepted
+    pub fn orphan_count(&self) -> usize {
+        self.orphans.values().map(Vec::len).sum()
+    }
     pub fn clear(&mut self) {
         self.id2id.clear();
         self.arena.clear();
         self.notes.clear();
+        self.orphans.clear();
     }
 }
 
@@ -219,9 +646,49 @@ impl ReplyTrees {
 // use std::time::{SystemTime, UNIX_EPOCH};
 // use nostr_sdk::{Event, EventId};
 
+/// Levels of replies below `node_id`
+fn subtree_height(arena: &Arena<TextNote>, node_id: NodeId) -> usize {
+    node_id
+        .children(arena)
+        .map(|child| subtree_height(arena, child) + 1)
+        .max()
+        .unwrap_or(0)
+}
+
+/// Aggregates over a note and every reply below it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubtreeStats {
+    /// Replies below the note, at any depth
+    pub descendants: usize,
+    /// Newest `created_at` in the subtree, the note's own included
+    pub last_activity: Timestamp,
+    /// Authors in the subtree, the note's own included
+    pub participants: HashSet<PublicKey>,
+}
+
+impl SubtreeStats {
+    fn of(note: &TextNote) -> Self {
+        Self {
+            descendants: 0,
+            last_activity: note.inner.created_at,
+            participants: HashSet::from([note.inner.author()]),
+        }
+    }
+
+    /// Adds a subtree hanging below this one
+    fn absorb(&mut self, subtree: &SubtreeStats) {
+        self.descendants += subtree.descendants + 1;
+        self.last_activity = self.last_activity.max(subtree.last_activity);
+        self.participants
+            .extend(subtree.participants.iter().copied());
+    }
+}
+
 #[derive(Debug)]
 pub struct ReplyTreeManager {
     trees: HashMap<EventId, ReplyTrees>,
+    /// Per tree, the stats of every note, kept up to date on insert
+    stats: HashMap<EventId, HashMap<EventId, SubtreeStats>>,
     order: VecDeque<EventId>,
     max_entries: usize,
 }
@@ -229,6 +696,7 @@ impl ReplyTreeManager {
     pub fn new(max_entries: usize) -> Self {
         ReplyTreeManager {
             trees: HashMap::new(),
+            stats: HashMap::new(),
             order: VecDeque::new(),
             max_entries,
         }
@@ -238,9 +706,11 @@ impl ReplyTreeManager {
         if self.order.len() >= self.max_entries {
             if let Some(oldest_id) = self.order.pop_front() {
                 self.trees.remove(&oldest_id);
+                self.stats.remove(&oldest_id);
             }
         }
 
+        self.stats.insert(root_id, tree_stats(&tree));
         self.trees.insert(root_id, tree);
         self.order.push_back(root_id);
     }
@@ -258,20 +728,47 @@ impl ReplyTreeManager {
 
     pub fn clear(&mut self) {
         self.trees.clear();
+        self.stats.clear();
         self.order.clear();
     }
 
     pub fn accept_event(&mut self, root_id: EventId, events: Vec<Event>) {
-        let tree = self.get_or_create_tree(root_id);
-        tree.accept(events);
+        for event in events {
+            self.accept_one(root_id, event);
+        }
+    }
+
+    /// Inserts one event into the tree of `root_id`, updating the stats of
+    /// the note and its ancestors only
+    pub fn accept_one(&mut self, root_id: EventId, event: Event) -> Option<TreeChange> {
+        let change = self.get_or_create_tree(root_id).accept_one(event)?;
+        let tree = &self.trees[&root_id];
+        let stats = self.stats.entry(root_id).or_default();
+
+        let mut own = SubtreeStats::of(tree.get_note_by_id(&change.id)?);
+        for adopted in &change.adopted {
+            if let Some(subtree) = stats.get(adopted) {
+                own.absorb(subtree);
+            }
+        }
+        for ancestor in tree.get_ancestors(&change.id) {
+            if let Some(ancestor_stats) = stats.get_mut(&ancestor.inner.id) {
+                ancestor_stats.absorb(&own);
+            }
+        }
+        stats.insert(change.id, own);
+        Some(change)
     }
 
+    /// The tree may change arbitrarily, so its stats are recomputed after
     pub fn modify_tree_with_event<F>(&mut self, root_id: &EventId, event: Event, modify: F)
     where
         F: FnOnce(&mut ReplyTrees, Event),
     {
         let tree = self.get_or_create_tree(*root_id);
         modify(tree, event);
+        let stats = tree_stats(tree);
+        self.stats.insert(*root_id, stats);
     }
 
     pub fn get_replies(&self, root_id: &EventId) -> Vec<&TextNote> {
@@ -281,6 +778,30 @@ impl ReplyTreeManager {
             None => vec![],
         }
     }
+
+    /// Cached stats of note `id` in the tree of `root_id`
+    pub fn subtree_stats(&self, root_id: &EventId, id: &EventId) -> Option<&SubtreeStats> {
+        self.stats.get(root_id)?.get(id)
+    }
+}
+
+/// Stats of every note in `tree`, from scratch
+fn tree_stats(tree: &ReplyTrees) -> HashMap<EventId, SubtreeStats> {
+    let mut stats: HashMap<EventId, SubtreeStats> = tree
+        .arena
+        .iter()
+        .filter(|node| !node.is_removed())
+        .map(|node| (node.get().inner.id, SubtreeStats::of(node.get())))
+        .collect();
+    for node in tree.arena.iter().filter(|node| !node.is_removed()) {
+        let own = SubtreeStats::of(node.get());
+        for ancestor in tree.get_ancestors(&node.get().inner.id) {
+            if let Some(ancestor_stats) = stats.get_mut(&ancestor.inner.id) {
+                ancestor_stats.absorb(&own);
+            }
+        }
+    }
+    stats
 }
 
 #[cfg(test)]
@@ -292,16 +813,45 @@ mod tests {
     use crate::testhelper::test_data::*;
     wasm_bindgen_test::wasm_bindgen_test_configure!(run_in_browser);
 
-    #[wasm_bindgen_test]
+    #[wasm_bindgen_test(unsupported = test)]
     fn test_no_note() {
-        let event = event_from(NOT_NOTE);
+        // `NOT_NOTE` is a kind 6 repost, which `TextNote` accepts
+        let event = nostr_sdk::EventBuilder::metadata(&nostr_sdk::Metadata::new())
+            .to_event(&nostr_sdk::Keys::generate())
+            .unwrap();
         assert!(
             TextNote::try_from(event).is_err(),
             "Expect an event with kind 1"
         );
     }
 
-    #[wasm_bindgen_test]
+    #[wasm_bindgen_test(unsupported = test)]
+    fn test_long_form_note() {
+        let keys = nostr_sdk::Keys::generate();
+        let event = nostr_sdk::EventBuilder::long_form_text_note(
+            "# Hello\n\nBody",
+            [
+                Tag::identifier("hello"),
+                Tag::from_standardized(TagStandard::Title("Hello".to_string())),
+                Tag::from_standardized(TagStandard::PublishedAt(Timestamp::from(1700000000))),
+                Tag::hashtag("rust"),
+            ],
+        )
+        .to_event(&keys)
+        .unwrap();
+        let article = LongFormNote::try_from(event).unwrap();
+        assert_eq!(article.identifier, "hello");
+        assert_eq!(article.title.as_deref(), Some("Hello"));
+        assert_eq!(article.summary, None);
+        assert_eq!(
+            article.published_at_or_created(),
+            Timestamp::from(1700000000)
+        );
+        assert_eq!(article.hashtags, vec!["rust".to_string()]);
+        assert!(LongFormNote::try_from(event_from(NOT_NOTE)).is_err());
+    }
+
+    #[wasm_bindgen_test(unsupported = test)]
     fn test_reply_with_marker() {
         let event = event_from(REPLY_WITH_MARKER);
         let text_note = TextNote::try_from(event).unwrap();
@@ -316,7 +866,7 @@ mod tests {
         );
     }
 
-    #[wasm_bindgen_test]
+    #[wasm_bindgen_test(unsupported = test)]
     fn test_reply_with_no_marker() {
         let event = event_from(REPLY_WITH_NO_MARKER);
         let text_note = TextNote::try_from(event).unwrap();
@@ -330,7 +880,7 @@ mod tests {
         );
     }
 
-    #[wasm_bindgen_test]
+    #[wasm_bindgen_test(unsupported = test)]
     fn test_reply_to_root_no_marker() {
         let event = event_from(REPLY_TO_ROOT_WITH_NO_MARKER);
         let text_note = TextNote::try_from(event).unwrap();
@@ -344,7 +894,7 @@ mod tests {
         );
     }
 
-    #[wasm_bindgen_test]
+    #[wasm_bindgen_test(unsupported = test)]
     fn test_reply_to_root_with_marker() {
         let event = event_from(REPLY_TO_ROOT_WITH_MARKER);
         let text_note = TextNote::try_from(event).unwrap();
@@ -358,14 +908,14 @@ mod tests {
         );
     }
 
-    #[wasm_bindgen_test]
+    #[wasm_bindgen_test(unsupported = test)]
     fn test_is_root() {
         let event = event_from(ROOT_NOTE);
         let text_note = TextNote::try_from(event).unwrap();
         assert!(text_note.is_root());
     }
 
-    #[wasm_bindgen_test]
+    #[wasm_bindgen_test(unsupported = test)]
     fn test_get_note() {
         let event = event_from(ROOT_NOTE);
         let mut reply_tree = ReplyTrees::default();
@@ -380,7 +930,7 @@ mod tests {
         assert_eq!(reply_tree.get_note_by_id(&event_id).unwrap().inner.content, *"If i do createElement and rhen appendChild for a lot of number of time, It took a lot of RAM compared to writting the entire HTML manually.");
     }
 
-    #[wasm_bindgen_test]
+    #[wasm_bindgen_test(unsupported = test)]
     fn test_get_replies_ordered() {
         let events: Vec<Event> = [R, R_A, R_A_B, R_X, R_Z, R_Z_O]
             .iter()
@@ -406,7 +956,7 @@ mod tests {
         assert_eq!(r_a_children.first().unwrap().inner.content, "R -> A -> B");
     }
 
-    #[wasm_bindgen_test]
+    #[wasm_bindgen_test(unsupported = test)]
     fn test_get_replies_with_orphan() {
         let events: Vec<Event> = [R, R_A, R_A_B, R_X, R_Z_O]
             .iter()
@@ -423,7 +973,7 @@ mod tests {
         assert_eq!(r_children.last().unwrap().inner.content, "R -> A");
     }
 
-    #[wasm_bindgen_test]
+    #[wasm_bindgen_test(unsupported = test)]
     fn test_get_ancestors() {
         let events: Vec<Event> = [R, R_A, R_A_B, R_X, R_Z, R_Z_O]
             .iter()
@@ -439,7 +989,7 @@ mod tests {
         assert_eq!(ancestors.last().unwrap().inner.content, "This is the Root!");
     }
 
-    #[wasm_bindgen_test]
+    #[wasm_bindgen_test(unsupported = test)]
     fn test_get_in_batch() {
         //assume we already have root
         let root: Vec<Event> = [R].iter().map(|raw: &&str| event_from(raw)).collect();
@@ -467,7 +1017,322 @@ mod tests {
         assert_eq!(r_a_children.first().unwrap().inner.content, "R -> A -> B");
     }
 
-    #[wasm_bindgen_test]
+    #[wasm_bindgen_test(unsupported = test)]
+    fn test_accept_one() {
+        let mut reply_tree = ReplyTrees::default();
+        let root = event_from(R);
+        let r_a = event_from(R_A);
+        let r_a_b = event_from(R_A_B);
+
+        let change = reply_tree.accept_one(root.clone()).unwrap();
+        assert_eq!(change.id, root.id);
+        assert_eq!(change.parent, None);
+        assert_eq!(change.root, root.id);
+
+        let change = reply_tree.accept_one(r_a.clone()).unwrap();
+        assert_eq!(change.parent, Some(root.id));
+        assert_eq!(change.root, root.id);
+
+        let change = reply_tree.accept_one(r_a_b.clone()).unwrap();
+        assert_eq!(change.id, r_a_b.id);
+        assert_eq!(change.parent, Some(r_a.id));
+        assert_eq!(change.root, root.id);
+
+        assert_eq!(reply_tree.accept_one(r_a.clone()), None);
+        let metadata = nostr_sdk::EventBuilder::metadata(&nostr_sdk::Metadata::new())
+            .to_event(&nostr_sdk::Keys::generate())
+            .unwrap();
+        assert_eq!(reply_tree.accept_one(metadata), None);
+        assert_eq!(reply_tree.get_replies(&root.id, None).len(), 1);
+        let ancestors = reply_tree.get_ancestors(&r_a_b.id);
+        assert_eq!(ancestors.last().unwrap().inner.id, root.id);
+    }
+
+    #[wasm_bindgen_test(unsupported = test)]
+    fn test_orphan_reparenting() {
+        let mut reply_tree = ReplyTrees::default();
+        let root = event_from(R);
+        let r_a = event_from(R_A);
+        let r_a_b = event_from(R_A_B);
+
+        // Deepest reply first: nothing to hang it under yet
+        reply_tree.accept(vec![r_a_b.clone()]);
+        assert_eq!(reply_tree.orphan_count(), 1);
+        assert!(reply_tree.get_ancestors(&r_a_b.id).is_empty());
+
+        let change = reply_tree.accept_one(r_a.clone()).unwrap();
+        assert_eq!(change.parent, None);
+        assert_eq!(change.adopted, vec![r_a_b.id]);
+        assert_eq!(reply_tree.orphan_count(), 1);
+
+        let change = reply_tree.accept_one(root.clone()).unwrap();
+        assert_eq!(change.adopted, vec![r_a.id]);
+        assert_eq!(change.root, root.id);
+        assert_eq!(reply_tree.orphan_count(), 0);
+
+        let ancestors = reply_tree.get_ancestors(&r_a_b.id);
+        assert_eq!(ancestors.first().unwrap().inner.id, r_a.id);
+        assert_eq!(ancestors.last().unwrap().inner.id, root.id);
+        assert_eq!(reply_tree.get_replies(&r_a.id, None).len(), 1);
+    }
+
+    #[wasm_bindgen_test(unsupported = test)]
+    fn test_content_tokens() {
+        use nostr_sdk::nips::nip19::ToBech32;
+
+        let keys = nostr_sdk::Keys::generate();
+        let npub = keys.public_key().to_bech32().unwrap();
+        let quoted = event_from(R);
+        let nevent = Nip19Event::new(quoted.id, ["wss://relay.example.com"]);
+        let content = format!(
+            "gm nostr:{npub}! see nostr:{} and https://example.com/a.PNG, \
+             or (https://example.com/page). #nostr a#b {npub}",
+            nevent.to_bech32().unwrap()
+        );
+        let note = TextNote::new(
+            nostr_sdk::EventBuilder::text_note(content, [])
+                .to_event(&keys)
+                .unwrap(),
+        );
+
+        let url = |s: &str| Url::parse(s).unwrap();
+        assert_eq!(
+            note.content_tokens(),
+            vec![
+                ContentToken::Text("gm ".to_string()),
+                ContentToken::Mention(keys.public_key()),
+                ContentToken::Text("! see ".to_string()),
+                ContentToken::Event(nevent),
+                ContentToken::Text(" and ".to_string()),
+                ContentToken::Image(url("https://example.com/a.PNG")),
+                ContentToken::Text(", or (".to_string()),
+                ContentToken::Url(url("https://example.com/page")),
+                ContentToken::Text("). ".to_string()),
+                ContentToken::Hashtag("nostr".to_string()),
+                ContentToken::Text(" a#b ".to_string()),
+                ContentToken::Mention(keys.public_key()),
+            ]
+        );
+        assert_eq!(
+            parse_content("npub1invalid"),
+            vec![ContentToken::Text("npub1invalid".to_string())]
+        );
+    }
+
+    #[wasm_bindgen_test(unsupported = test)]
+    fn test_subtree_stats() {
+        let [root, r_a, r_a_b, r_x] = [R, R_A, R_A_B, R_X].map(event_from);
+        let mut manager = ReplyTreeManager::new(10);
+
+        // Out of order, so R -> A adopts R -> A -> B when it arrives
+        manager.accept_event(root.id, vec![root.clone(), r_a_b.clone()]);
+        let stats = manager.subtree_stats(&root.id, &root.id).unwrap();
+        assert_eq!(stats.descendants, 0);
+        assert_eq!(stats.last_activity, root.created_at);
+
+        manager.accept_one(root.id, r_a.clone()).unwrap();
+        manager.accept_one(root.id, r_x.clone()).unwrap();
+        assert_eq!(manager.accept_one(root.id, r_x.clone()), None);
+
+        let stats = manager.subtree_stats(&root.id, &root.id).unwrap().clone();
+        assert_eq!(stats.descendants, 3);
+        let newest = [&root, &r_a, &r_a_b, &r_x]
+            .iter()
+            .map(|event| event.created_at)
+            .max()
+            .unwrap();
+        assert_eq!(stats.last_activity, newest);
+        let authors: HashSet<PublicKey> = [&root, &r_a, &r_a_b, &r_x]
+            .iter()
+            .map(|event| event.author())
+            .collect();
+        assert_eq!(stats.participants, authors);
+        assert_eq!(
+            manager
+                .subtree_stats(&root.id, &r_a.id)
+                .unwrap()
+                .descendants,
+            1
+        );
+
+        // A full recompute agrees with the incremental updates
+        manager.modify_tree_with_event(&root.id, r_x, |_, _| {});
+        assert_eq!(manager.subtree_stats(&root.id, &root.id), Some(&stats));
+    }
+
+    #[wasm_bindgen_test(unsupported = test)]
+    fn test_reply_trees_json() {
+        let events: Vec<Event> = [R_A_B, R, R_A, R_X, R_Z_O]
+            .iter()
+            .map(|raw: &&str| event_from(raw))
+            .collect();
+        let mut reply_tree = ReplyTrees::default();
+        reply_tree.accept(events);
+
+        let restored = ReplyTrees::from_json(&reply_tree.to_json().unwrap()).unwrap();
+        assert_eq!(restored, reply_tree);
+        assert_eq!(restored.orphan_count(), 1);
+        assert!(matches!(
+            ReplyTrees::from_json("{}"),
+            Err(Error::InvalidJson(_))
+        ));
+    }
+
+    #[wasm_bindgen_test(unsupported = test)]
+    fn test_flatten() {
+        let [root, r_a, r_a_b, r_x, r_z, r_z_o] = [R, R_A, R_A_B, R_X, R_Z, R_Z_O].map(event_from);
+        let mut reply_tree = ReplyTrees::default();
+        reply_tree.accept(vec![
+            root.clone(),
+            r_a.clone(),
+            r_a_b.clone(),
+            r_x.clone(),
+            r_z.clone(),
+            r_z_o.clone(),
+        ]);
+        let rows = |items: Vec<ThreadItem>| -> Vec<(EventId, usize)> {
+            items
+                .into_iter()
+                .map(|item| match item {
+                    ThreadItem::Note { note, depth } => (note.inner.id, depth),
+                    ThreadItem::Collapsed { parent, depth, .. } => (parent, depth),
+                })
+                .collect()
+        };
+
+        assert_eq!(
+            rows(reply_tree.flatten(&root.id, None, None)),
+            vec![
+                (root.id, 0),
+                (r_a.id, 1),
+                (r_a_b.id, 2),
+                (r_x.id, 1),
+                (r_z.id, 1),
+                (r_z_o.id, 2),
+            ]
+        );
+
+        let newest = reply_tree.flatten(&root.id, Some(DisplayOrder::NewestFirst), Some(1));
+        assert_eq!(
+            rows(newest.clone()),
+            vec![
+                (root.id, 0),
+                (r_z.id, 1),
+                (r_z.id, 2),
+                (r_x.id, 1),
+                (r_a.id, 1),
+                (r_a.id, 2),
+            ]
+        );
+        assert!(matches!(newest[2], ThreadItem::Collapsed { hidden: 1, .. }));
+
+        let collapsed = reply_tree.flatten(&root.id, None, Some(0));
+        assert_eq!(
+            collapsed[1],
+            ThreadItem::Collapsed {
+                parent: root.id,
+                depth: 1,
+                hidden: 5
+            }
+        );
+        assert!(reply_tree
+            .flatten(&EventId::all_zeros(), None, None)
+            .is_empty());
+    }
+
+    #[wasm_bindgen_test(unsupported = test)]
+    fn test_get_replies_page() {
+        let events: Vec<Event> = [R, R_A, R_A_B, R_X, R_Z, R_Z_O]
+            .iter()
+            .map(|raw: &&str| event_from(raw))
+            .collect();
+        let root = events[0].id;
+        let mut reply_tree = ReplyTrees::default();
+        reply_tree.accept(events);
+
+        let contents = |page: &ReplyPage| -> Vec<String> {
+            page.replies
+                .iter()
+                .map(|note| note.inner.content.clone())
+                .collect()
+        };
+        let page = reply_tree.get_replies_page(&root, 0, 2, Some(DisplayOrder::NewestFirst));
+        assert_eq!(page.total, 3);
+        assert_eq!(contents(&page), vec!["R -> Z", "R -> X"]);
+        let page = reply_tree.get_replies_page(&root, 2, 2, Some(DisplayOrder::NewestFirst));
+        assert_eq!(contents(&page), vec!["R -> A"]);
+        let page = reply_tree.get_replies_page(&root, 1, 1, None);
+        assert_eq!(page.total, 3);
+        assert_eq!(contents(&page), vec!["R -> X"]);
+        assert!(reply_tree
+            .get_replies_page(&root, 5, 2, None)
+            .replies
+            .is_empty());
+        assert_eq!(
+            reply_tree
+                .get_replies_page(&EventId::all_zeros(), 0, 2, None)
+                .total,
+            0
+        );
+    }
+
+    #[wasm_bindgen_test(unsupported = test)]
+    fn test_mentions_not_ancestors() {
+        use nostr_sdk::{EventBuilder, Keys};
+
+        let keys = Keys::generate();
+        let note = |content: &str, tags: Vec<Tag>| {
+            EventBuilder::text_note(content, tags)
+                .to_event(&keys)
+                .unwrap()
+        };
+        let e = |id: EventId, marker: Option<Marker>| {
+            Tag::from_standardized(TagStandard::Event {
+                event_id: id,
+                relay_url: None,
+                marker,
+                public_key: None,
+            })
+        };
+        let root = note("root", vec![]);
+        let mentioned = note("mentioned", vec![]);
+        let reply = note("reply", vec![e(root.id, Some(Marker::Root))]);
+        let marked = note(
+            "marked",
+            vec![
+                e(root.id, Some(Marker::Root)),
+                e(mentioned.id, Some(Marker::Mention)),
+                e(reply.id, Some(Marker::Reply)),
+            ],
+        );
+        let positional = note(
+            "positional",
+            vec![e(root.id, None), e(mentioned.id, None), e(reply.id, None)],
+        );
+
+        for event in [&marked, &positional] {
+            let text_note = TextNote::try_from(event.clone()).unwrap();
+            assert_eq!(text_note.get_root(), Some(root.id));
+            assert_eq!(text_note.get_reply_to(), Some(reply.id));
+            assert_eq!(text_note.get_mentions(), [mentioned.id]);
+        }
+
+        let mut reply_tree = ReplyTrees::default();
+        reply_tree.accept(vec![root.clone(), mentioned.clone(), reply.clone()]);
+        reply_tree.accept(vec![marked.clone(), positional.clone()]);
+        for event in [&marked, &positional] {
+            let ancestors: Vec<EventId> = reply_tree
+                .get_ancestors(&event.id)
+                .iter()
+                .map(|note| note.inner.id)
+                .collect();
+            assert_eq!(ancestors, vec![reply.id, root.id]);
+        }
+        assert!(reply_tree.get_replies(&mentioned.id, None).is_empty());
+        assert_eq!(reply_tree.get_replies(&reply.id, None).len(), 2);
+    }
+
+    #[wasm_bindgen_test(unsupported = test)]
     fn test_failed_process_tags() {
         let event = event_from(ERROR_EVENT);
         let mut text_note = TextNote::new(event);
@@ -0,0 +1,495 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+use nostr_sdk::database::async_trait;
+use nostr_sdk::{Client, Event, EventId, Timestamp, Url};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tokio::sync::{mpsc, OnceCell};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error("Outbox store error: {0}")]
+    Store(String),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Delay before the first retry, doubled after every failed attempt
+const RETRY_BASE: Duration = Duration::from_secs(5);
+const RETRY_MAX: Duration = Duration::from_secs(10 * 60);
+
+/// How often [`Outbox::run`] looks for due retries
+const RUN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A signed event waiting to be sent again
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OutboxEntry {
+    pub event: Event,
+    /// Relays the event was meant for, every relay of the client when empty
+    pub relays: Vec<Url>,
+    /// Failed sends so far
+    pub attempts: u32,
+    pub next_attempt: Timestamp,
+    pub last_error: String,
+}
+
+/// Progress of the events that went through the outbox
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutboxStatus {
+    /// The first send failed; the event is stored and will be retried
+    Queued { id: EventId, error: String },
+    /// A retry failed too
+    Retrying {
+        id: EventId,
+        attempts: u32,
+        next_attempt: Timestamp,
+        error: String,
+    },
+    /// A retry went through and the event left the outbox
+    Sent(EventId),
+    /// Dropped after the maximum number of attempts
+    GaveUp { id: EventId, error: String },
+}
+
+/// Durable storage of the pending events, so they survive a reload
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait OutboxStore: Send + Sync {
+    async fn load(&self) -> Result<Vec<OutboxEntry>>;
+    async fn put(&self, entry: &OutboxEntry) -> Result<()>;
+    async fn remove(&self, id: &EventId) -> Result<()>;
+}
+
+static GLOBAL: OnceLock<Outbox> = OnceLock::new();
+
+/// Events whose send failed, retried with exponential backoff until a
+/// relay takes them. Clones share the same queue and status subscribers.
+///
+/// Once [`installed`](Outbox::install), the publish helpers hand every
+/// event they fail to send to the outbox and report success: the status
+/// stream then tells whether it eventually got out.
+#[derive(Clone)]
+pub struct Outbox {
+    client: Arc<Client>,
+    store: Arc<dyn OutboxStore>,
+    entries: Arc<Mutex<HashMap<EventId, OutboxEntry>>>,
+    loaded: Arc<OnceCell<()>>,
+    subscribers: Arc<Mutex<Vec<mpsc::UnboundedSender<OutboxStatus>>>>,
+    retry_base: Duration,
+    retry_max: Duration,
+    max_attempts: Option<u32>,
+}
+
+impl Outbox {
+    pub fn new(client: Arc<Client>, store: Arc<dyn OutboxStore>) -> Self {
+        Self {
+            client,
+            store,
+            entries: Arc::new(Mutex::new(HashMap::new())),
+            loaded: Arc::new(OnceCell::new()),
+            subscribers: Arc::new(Mutex::new(Vec::new())),
+            retry_base: RETRY_BASE,
+            retry_max: RETRY_MAX,
+            max_attempts: None,
+        }
+    }
+
+    /// Delay before the first retry, doubling up to `max`
+    pub fn with_retry_delay(mut self, base: Duration, max: Duration) -> Self {
+        self.retry_base = base;
+        self.retry_max = max;
+        self
+    }
+
+    /// Give up on an event after `attempts` failed sends. Events are
+    /// retried forever by default.
+    pub fn with_max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = Some(attempts);
+        self
+    }
+
+    /// Makes this outbox the one the publish helpers fall back to. Only the
+    /// first call has an effect; returns whether it was this one.
+    pub fn install(self) -> bool {
+        GLOBAL.set(self).is_ok()
+    }
+
+    pub fn installed() -> Option<&'static Outbox> {
+        GLOBAL.get()
+    }
+
+    /// Live status updates, from now on
+    pub fn subscribe(&self) -> UnboundedReceiverStream<OutboxStatus> {
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.subscribers.lock().unwrap().push(tx);
+        UnboundedReceiverStream::new(rx)
+    }
+
+    /// Events still waiting, oldest first
+    pub async fn pending(&self) -> Result<Vec<OutboxEntry>> {
+        self.ensure_loaded().await?;
+        let mut entries: Vec<OutboxEntry> =
+            self.entries.lock().unwrap().values().cloned().collect();
+        entries.sort_by_key(|entry| (entry.event.created_at, entry.event.id));
+        Ok(entries)
+    }
+
+    /// Stores `event`, whose send to `relays` just failed with `error`, for
+    /// a later retry
+    pub async fn queue(&self, event: Event, relays: Vec<Url>, error: String) -> Result<()> {
+        self.ensure_loaded().await?;
+        let id = event.id;
+        let entry = OutboxEntry {
+            event,
+            relays,
+            attempts: 1,
+            next_attempt: Timestamp::now() + self.backoff(1),
+            last_error: error.clone(),
+        };
+        self.store.put(&entry).await?;
+        self.entries.lock().unwrap().insert(id, entry);
+        self.notify(OutboxStatus::Queued { id, error });
+        Ok(())
+    }
+
+    /// Sends `event` to `relays` (every relay when empty), queueing it when
+    /// that fails. Only store errors are returned.
+    pub async fn send(&self, event: Event, relays: Vec<Url>) -> Result<EventId> {
+        let id = event.id;
+        if let Err(err) = self.deliver(&event, &relays).await {
+            self.queue(event, relays, err.to_string()).await?;
+        }
+        Ok(id)
+    }
+
+    /// Retries every event that is due. Returns how many went through.
+    pub async fn flush(&self) -> Result<usize> {
+        self.ensure_loaded().await?;
+        let now = Timestamp::now();
+        let due: Vec<OutboxEntry> = self
+            .entries
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|entry| entry.next_attempt <= now)
+            .cloned()
+            .collect();
+
+        let mut sent = 0;
+        for mut entry in due {
+            let id = entry.event.id;
+            match self.deliver(&entry.event, &entry.relays).await {
+                Ok(()) => {
+                    self.forget(&id).await?;
+                    self.notify(OutboxStatus::Sent(id));
+                    sent += 1;
+                }
+                Err(err) => {
+                    let error = err.to_string();
+                    entry.attempts += 1;
+                    if self.max_attempts.is_some_and(|max| entry.attempts >= max) {
+                        self.forget(&id).await?;
+                        self.notify(OutboxStatus::GaveUp { id, error });
+                        continue;
+                    }
+                    entry.next_attempt = Timestamp::now() + self.backoff(entry.attempts);
+                    entry.last_error = error.clone();
+                    self.store.put(&entry).await?;
+                    let (attempts, next_attempt) = (entry.attempts, entry.next_attempt);
+                    self.entries.lock().unwrap().insert(id, entry);
+                    self.notify(OutboxStatus::Retrying {
+                        id,
+                        attempts,
+                        next_attempt,
+                        error,
+                    });
+                }
+            }
+        }
+        Ok(sent)
+    }
+
+    /// Retries due events forever, checking every second. Meant to be
+    /// spawned once, e.g. with `spawn_local` in the browser.
+    pub async fn run(self) {
+        loop {
+            if let Err(err) = self.flush().await {
+                tracing::warn!("Outbox retry failed: {}", err);
+            }
+            super::runtime::sleep(RUN_INTERVAL).await;
+        }
+    }
+
+    async fn deliver(
+        &self,
+        event: &Event,
+        relays: &[Url],
+    ) -> std::result::Result<(), nostr_sdk::client::Error> {
+        if relays.is_empty() {
+            self.client.send_event(event.clone()).await?;
+        } else {
+            self.client
+                .send_event_to(relays.to_vec(), event.clone())
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn forget(&self, id: &EventId) -> Result<()> {
+        self.store.remove(id).await?;
+        self.entries.lock().unwrap().remove(id);
+        Ok(())
+    }
+
+    fn backoff(&self, attempts: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempts.saturating_sub(1));
+        self.retry_base.saturating_mul(factor).min(self.retry_max)
+    }
+
+    fn notify(&self, status: OutboxStatus) {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .retain(|tx| tx.send(status.clone()).is_ok());
+    }
+
+    async fn ensure_loaded(&self) -> Result<()> {
+        self.loaded
+            .get_or_try_init(|| async {
+                let stored = self.store.load().await?;
+                let mut entries = self.entries.lock().unwrap();
+                for entry in stored {
+                    entries.entry(entry.event.id).or_insert(entry);
+                }
+                Ok::<(), Error>(())
+            })
+            .await?;
+        Ok(())
+    }
+}
+
+#[cfg(native_backend)]
+pub use file::FileOutboxStore;
+
+#[cfg(native_backend)]
+mod file {
+    use std::fs;
+    use std::io::ErrorKind;
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// One json file per pending event under `dir`
+    #[derive(Debug, Clone)]
+    pub struct FileOutboxStore {
+        dir: PathBuf,
+    }
+
+    impl FileOutboxStore {
+        pub fn new(dir: impl Into<PathBuf>) -> Result<Self> {
+            let dir = dir.into();
+            fs::create_dir_all(&dir).map_err(|err| Error::Store(err.to_string()))?;
+            Ok(Self { dir })
+        }
+
+        fn path(&self, id: &EventId) -> PathBuf {
+            self.dir.join(format!("{}.json", id.to_hex()))
+        }
+    }
+
+    #[async_trait]
+    impl OutboxStore for FileOutboxStore {
+        async fn load(&self) -> Result<Vec<OutboxEntry>> {
+            let dir = fs::read_dir(&self.dir).map_err(|err| Error::Store(err.to_string()))?;
+            let mut entries = Vec::new();
+            for file in dir {
+                let path = file.map_err(|err| Error::Store(err.to_string()))?.path();
+                if path.extension().is_none_or(|ext| ext != "json") {
+                    continue;
+                }
+                let json =
+                    fs::read_to_string(&path).map_err(|err| Error::Store(err.to_string()))?;
+                // A file torn by a crash is dropped rather than blocking the rest
+                match serde_json::from_str(&json) {
+                    Ok(entry) => entries.push(entry),
+                    Err(err) => tracing::warn!("Skipping outbox entry {:?}: {}", path, err),
+                }
+            }
+            Ok(entries)
+        }
+
+        async fn put(&self, entry: &OutboxEntry) -> Result<()> {
+            let json = serde_json::to_string(entry)?;
+            fs::write(self.path(&entry.event.id), json).map_err(|err| Error::Store(err.to_string()))
+        }
+
+        async fn remove(&self, id: &EventId) -> Result<()> {
+            match fs::remove_file(self.path(id)) {
+                Err(err) if err.kind() != ErrorKind::NotFound => Err(Error::Store(err.to_string())),
+                _ => Ok(()),
+            }
+        }
+    }
+}
+
+#[cfg(wasm_backend)]
+pub use indexed_db::IndexedDbOutboxStore;
+
+#[cfg(wasm_backend)]
+mod indexed_db {
+    use indexed_db_futures::prelude::*;
+    use wasm_bindgen::JsValue;
+
+    use super::*;
+
+    const STORE_NAME: &str = "outbox";
+
+    /// IndexedDB-backed store for the browser, keyed by event id. The
+    /// database is opened per call, so the store holds no JS handles.
+    #[derive(Debug, Clone)]
+    pub struct IndexedDbOutboxStore {
+        db_name: String,
+    }
+
+    impl IndexedDbOutboxStore {
+        pub fn new(db_name: &str) -> Self {
+            Self {
+                db_name: db_name.to_string(),
+            }
+        }
+
+        async fn open(&self) -> Result<IdbDatabase> {
+            let mut request = IdbDatabase::open_u32(&self.db_name, 1).map_err(dom_error)?;
+            request.set_on_upgrade_needed(Some(
+                |evt: &IdbVersionChangeEvent| -> std::result::Result<(), JsValue> {
+                    if !evt.db().object_store_names().any(|name| name == STORE_NAME) {
+                        evt.db().create_object_store(STORE_NAME)?;
+                    }
+                    Ok(())
+                },
+            ));
+            request.into_future().await.map_err(dom_error)
+        }
+    }
+
+    fn dom_error(err: web_sys::DomException) -> Error {
+        Error::Store(err.message())
+    }
+
+    #[async_trait(?Send)]
+    impl OutboxStore for IndexedDbOutboxStore {
+        async fn load(&self) -> Result<Vec<OutboxEntry>> {
+            let db = self.open().await?;
+            let tx = db
+                .transaction_on_one_with_mode(STORE_NAME, IdbTransactionMode::Readonly)
+                .map_err(dom_error)?;
+            let store = tx.object_store(STORE_NAME).map_err(dom_error)?;
+            let values = store
+                .get_all()
+                .map_err(dom_error)?
+                .await
+                .map_err(dom_error)?;
+            Ok(values
+                .iter()
+                .filter_map(|value| value.as_string())
+                .filter_map(|json| serde_json::from_str(&json).ok())
+                .collect())
+        }
+
+        async fn put(&self, entry: &OutboxEntry) -> Result<()> {
+            let json = serde_json::to_string(entry)?;
+            let db = self.open().await?;
+            let tx = db
+                .transaction_on_one_with_mode(STORE_NAME, IdbTransactionMode::Readwrite)
+                .map_err(dom_error)?;
+            let store = tx.object_store(STORE_NAME).map_err(dom_error)?;
+            store
+                .put_key_val_owned(entry.event.id.to_hex(), &JsValue::from_str(&json))
+                .map_err(dom_error)?;
+            tx.await.into_result().map_err(dom_error)
+        }
+
+        async fn remove(&self, id: &EventId) -> Result<()> {
+            let db = self.open().await?;
+            let tx = db
+                .transaction_on_one_with_mode(STORE_NAME, IdbTransactionMode::Readwrite)
+                .map_err(dom_error)?;
+            let store = tx.object_store(STORE_NAME).map_err(dom_error)?;
+            store.delete_owned(id.to_hex()).map_err(dom_error)?;
+            tx.await.into_result().map_err(dom_error)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::StreamExt;
+    use nostr_sdk::{EventBuilder, Keys};
+
+    #[tokio::test]
+    async fn test_outbox_retries_and_persists() {
+        let dir =
+            std::env::temp_dir().join(format!("nostr-outbox-{}", Keys::generate().public_key()));
+        let keys = Keys::generate();
+        // No relays, so every send fails
+        let client = Arc::new(Client::new(&keys));
+        let open = || {
+            let store = Arc::new(FileOutboxStore::new(&dir).unwrap());
+            Outbox::new(Arc::clone(&client), store)
+                .with_retry_delay(Duration::ZERO, Duration::ZERO)
+                .with_max_attempts(3)
+        };
+        let event = EventBuilder::text_note("gm", []).to_event(&keys).unwrap();
+
+        let outbox = open();
+        let mut status = outbox.subscribe();
+        assert_eq!(
+            outbox.send(event.clone(), Vec::new()).await.unwrap(),
+            event.id
+        );
+        assert!(
+            matches!(status.next().await, Some(OutboxStatus::Queued { id, .. }) if id == event.id)
+        );
+
+        // A restart finds the event again
+        let outbox = open();
+        let mut status = outbox.subscribe();
+        let pending = outbox.pending().await.unwrap();
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].event, event);
+        assert_eq!(pending[0].attempts, 1);
+
+        assert_eq!(outbox.flush().await.unwrap(), 0);
+        assert!(matches!(
+            status.next().await,
+            Some(OutboxStatus::Retrying { attempts: 2, .. })
+        ));
+        assert_eq!(outbox.flush().await.unwrap(), 0);
+        assert!(
+            matches!(status.next().await, Some(OutboxStatus::GaveUp { id, .. }) if id == event.id)
+        );
+        assert!(outbox.pending().await.unwrap().is_empty());
+        assert!(open().pending().await.unwrap().is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_outbox_backoff() {
+        let client = Arc::new(Client::default());
+        let dir =
+            std::env::temp_dir().join(format!("nostr-outbox-{}", Keys::generate().public_key()));
+        let outbox = Outbox::new(client, Arc::new(FileOutboxStore::new(&dir).unwrap()));
+        assert_eq!(outbox.backoff(1), RETRY_BASE);
+        assert_eq!(outbox.backoff(2), RETRY_BASE * 2);
+        assert_eq!(outbox.backoff(4), RETRY_BASE * 8);
+        assert_eq!(outbox.backoff(40), RETRY_MAX);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
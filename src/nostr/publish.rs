@@ -1,10 +1,26 @@
+use nostr_sdk::database::Order;
+use nostr_sdk::nips::nip01::Coordinate;
+use nostr_sdk::nips::nip19::Nip19Event;
+use nostr_sdk::nips::nip21::NostrURI;
+use nostr_sdk::nips::nip53::{LiveEvent, LiveEventStatus};
+use nostr_sdk::nips::nip59;
 use nostr_sdk::nips::nip65::RelayMetadata;
 use nostr_sdk::nips::nip94::FileMetadata;
 use nostr_sdk::{
-    Client, Contact, Event, EventBuilder, EventId, Filter, Kind, Metadata, NostrSigner, PublicKey,
-    Tag, TagStandard, Timestamp, UncheckedUrl, Url,
+    Alphabet, Client, Contact, Event, EventBuilder, EventId, Filter, JsonUtil, Kind, Metadata,
+    NostrSigner, PublicKey, RelaySendOptions, SingleLetterTag, Tag, TagKind, TagStandard,
+    Timestamp, UncheckedUrl, UnsignedEvent, Url,
 };
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
+
+use super::fetch::{get_dm_relays, get_metadata, ContactListCache, Error as FetchError};
+use super::note::{LongFormNote, Poll};
+use super::outbox::Outbox;
+use super::relay_info::RelayCapabilitiesCache;
+use super::utils::{get_newest_event, CustomEmoji};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
@@ -13,6 +29,28 @@ pub enum Error {
     Client(#[from] nostr_sdk::client::Error),
     #[error(transparent)]
     Signer(#[from] nostr_sdk::signer::Error),
+    #[error(transparent)]
+    Database(#[from] nostr_sdk::database::DatabaseError),
+    #[error(transparent)]
+    Fetch(#[from] super::fetch::Error),
+    #[error(transparent)]
+    Outbox(#[from] super::outbox::Error),
+    #[error("Event exceeds the size limits of every relay")]
+    EventTooLarge,
+    #[error("No relay to send the event to")]
+    NoRelays,
+    #[error(transparent)]
+    Builder(#[from] nostr_sdk::event::builder::Error),
+    #[error("Badge award {0} does not award this badge to us")]
+    InvalidBadgeAward(EventId),
+    #[error("Event {0} is not a live event")]
+    NotLiveEvent(EventId),
+    #[error("Invalid vote: {0}")]
+    InvalidVote(&'static str),
+    #[error("No contact list to update")]
+    EmptyContactList,
+    #[error("Proof of work cancelled")]
+    PowCancelled,
 }
 
 type Result<T> = std::result::Result<T, Error>;
@@ -20,19 +58,307 @@ type Result<T> = std::result::Result<T, Error>;
 macro_rules! sign_and_send_event {
     ($client:expr, $signer:expr, $builder:expr) => {{
         let event = $signer.sign_event_builder($builder).await?;
-        let eid = $client.send_event(event).await?;
+        let eid = send_event($client, event).await?;
+        Ok(eid)
+    }};
+    ($client:expr, $signer:expr, $builder:expr, $pow:expr) => {{
+        let event = sign_with_pow($signer, $builder, $pow).await?;
+        let eid = send_event($client, event).await?;
         Ok(eid)
     }};
 }
 
+/// Nonces tried between two checks for cancellation
+const POW_BATCH: u32 = 10_000;
+
+/// NIP-13 proof of work to mine before signing. Clones share the
+/// cancellation flag, so the UI can keep one to abort a long job.
+#[derive(Debug, Clone)]
+pub struct ProofOfWork {
+    difficulty: u8,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl ProofOfWork {
+    /// `difficulty` is the number of leading zero bits of the event id
+    pub fn new(difficulty: u8) -> Self {
+        Self {
+            difficulty,
+            cancelled: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn difficulty(&self) -> u8 {
+        self.difficulty
+    }
+
+    /// Stop mining; the publish call returns `Error::PowCancelled`
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+async fn sign_with_pow(
+    signer: &NostrSigner,
+    builder: EventBuilder,
+    pow: Option<&ProofOfWork>,
+) -> Result<Event> {
+    let Some(pow) = pow else {
+        return Ok(signer.sign_event_builder(builder).await?);
+    };
+    let public_key = signer.public_key().await?;
+    let unsigned = mine_pow(builder.to_unsigned_event(public_key), pow).await?;
+    Ok(signer.sign_event(unsigned).await?)
+}
+
+/// Adds the `nonce` tag giving `unsigned` an id with enough leading zero
+/// bits. Gives control back to the event loop between batches, so the page
+/// stays responsive and a cancellation is noticed.
+async fn mine_pow(mut unsigned: UnsignedEvent, pow: &ProofOfWork) -> Result<UnsignedEvent> {
+    let tags = unsigned.tags.clone();
+    let mut nonce: u128 = 0;
+    loop {
+        for _ in 0..POW_BATCH {
+            nonce += 1;
+            let mut candidate = tags.clone();
+            candidate.push(Tag::pow(nonce, pow.difficulty));
+            let id = EventId::new(
+                &unsigned.pubkey,
+                &unsigned.created_at,
+                &unsigned.kind,
+                &candidate,
+                &unsigned.content,
+            );
+            if id.check_pow(pow.difficulty) {
+                unsigned.id = Some(id);
+                unsigned.tags = candidate;
+                return Ok(unsigned);
+            }
+        }
+        yield_now().await;
+        if pow.is_cancelled() {
+            return Err(Error::PowCancelled);
+        }
+    }
+}
+
+async fn yield_now() {
+    // A macrotask, so input events such as a cancel click are handled
+    #[cfg(target_arch = "wasm32")]
+    gloo_timers::future::TimeoutFuture::new(0).await;
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let mut yielded = false;
+        futures::future::poll_fn(|cx| {
+            if yielded {
+                return std::task::Poll::Ready(());
+            }
+            yielded = true;
+            cx.waker().wake_by_ref();
+            std::task::Poll::Pending
+        })
+        .await
+    }
+}
+
+/// Send `event` to the relays whose NIP-11 limits allow it, instead of
+/// letting oversized events be rejected relay by relay
+async fn send_event(client: &Client, event: Event) -> Result<EventId> {
+    let all = client.relays().await.len();
+    let relays = RelayCapabilitiesCache::global()
+        .relays_where(client, |capabilities| capabilities.accepts_event(&event))
+        .await;
+    if relays.len() == all {
+        let retry = Outbox::installed().map(|outbox| (outbox, event.clone(), Vec::new()));
+        return queue_on_failure(client.send_event(event).await, retry).await;
+    }
+    if relays.is_empty() {
+        return Err(Error::EventTooLarge);
+    }
+    let relays: Vec<Url> = relays.into_keys().collect();
+    let retry = Outbox::installed().map(|outbox| (outbox, event.clone(), relays.clone()));
+    queue_on_failure(client.send_event_to(relays, event).await, retry).await
+}
+
+/// With an [`Outbox`] installed, a failed send is queued for a retry and
+/// reported as sent
+async fn queue_on_failure(
+    result: std::result::Result<EventId, nostr_sdk::client::Error>,
+    retry: Option<(&Outbox, Event, Vec<Url>)>,
+) -> Result<EventId> {
+    match (result, retry) {
+        (Ok(event_id), _) => Ok(event_id),
+        (Err(err), Some((outbox, event, relays))) => {
+            let event_id = event.id;
+            outbox.queue(event, relays, err.to_string()).await?;
+            Ok(event_id)
+        }
+        (Err(err), None) => Err(err.into()),
+    }
+}
+
+/// Same as [`send_event`], limited to `targets`. Targets must already be
+/// relays of the client.
+async fn send_event_to(client: &Client, targets: Vec<Url>, event: Event) -> Result<EventId> {
+    if targets.is_empty() {
+        return Err(Error::NoRelays);
+    }
+    let capabilities = RelayCapabilitiesCache::global();
+    let relays = client.relays().await;
+    let mut accepted = Vec::with_capacity(targets.len());
+    for url in targets {
+        // Unknown relays are left for the client to report
+        let fits = match relays.get(&url) {
+            Some(relay) => capabilities.get(&url, relay).await.accepts_event(&event),
+            None => true,
+        };
+        if fits {
+            accepted.push(url);
+        }
+    }
+    if accepted.is_empty() {
+        return Err(Error::EventTooLarge);
+    }
+    let retry = Outbox::installed().map(|outbox| (outbox, event.clone(), accepted.clone()));
+    queue_on_failure(client.send_event_to(accepted, event).await, retry).await
+}
+
+async fn sign_and_send_to(
+    client: &Client,
+    relays: Vec<Url>,
+    signer: &NostrSigner,
+    builder: EventBuilder,
+    pow: Option<&ProofOfWork>,
+) -> Result<EventId> {
+    let event = sign_with_pow(signer, builder, pow).await?;
+    send_event_to(client, relays, event).await
+}
+
+/// Outcome of one event of [`publish_events`]
+#[derive(Debug)]
+pub struct EventReport {
+    /// Position of the builder in the batch
+    pub index: usize,
+    /// `Err` when the event could not be signed, and was never sent
+    pub event_id: std::result::Result<EventId, Error>,
+    pub accepted: Vec<Url>,
+    /// Relays that refused the event or failed, with the reason
+    pub failed: Vec<(Url, String)>,
+}
+
+impl EventReport {
+    /// Accepted by at least one relay
+    pub fn is_published(&self) -> bool {
+        !self.accepted.is_empty()
+    }
+}
+
+/// Per-event, per-relay outcome of [`publish_events`]
+#[derive(Debug, Default)]
+pub struct PublishReport {
+    pub events: Vec<EventReport>,
+}
+
+impl PublishReport {
+    pub fn all_published(&self) -> bool {
+        self.events.iter().all(EventReport::is_published)
+    }
+
+    /// Events no relay accepted
+    pub fn unpublished(&self) -> impl Iterator<Item = &EventReport> {
+        self.events.iter().filter(|report| !report.is_published())
+    }
+}
+
+/// Signs and sends many events, each relay receiving them in order. Unlike
+/// the single event helpers, a failure never stops the batch: it is
+/// recorded in the report next to the relays that did accept.
+pub async fn publish_events(
+    client: &Client,
+    signer: &NostrSigner,
+    builders: Vec<EventBuilder>,
+) -> PublishReport {
+    let mut reports = Vec::with_capacity(builders.len());
+    let mut events = Vec::new();
+    for (index, builder) in builders.into_iter().enumerate() {
+        let event_id = match signer.sign_event_builder(builder).await {
+            Ok(event) => {
+                let event_id = event.id;
+                events.push((index, event));
+                Ok(event_id)
+            }
+            Err(err) => Err(err.into()),
+        };
+        reports.push(EventReport {
+            index,
+            event_id,
+            accepted: Vec::new(),
+            failed: Vec::new(),
+        });
+    }
+
+    let capabilities = RelayCapabilitiesCache::global();
+    let relays = client.relays().await;
+    let results = futures::future::join_all(relays.into_iter().map(|(url, relay)| {
+        let events = &events;
+        async move {
+            let limits = capabilities.get(&url, &relay).await;
+            let mut results = Vec::with_capacity(events.len());
+            for (index, event) in events.iter() {
+                let result = if limits.accepts_event(event) {
+                    relay
+                        .send_event(event.clone(), RelaySendOptions::default())
+                        .await
+                        .map(|_| ())
+                        .map_err(|err| err.to_string())
+                } else {
+                    Err(Error::EventTooLarge.to_string())
+                };
+                results.push((*index, result));
+            }
+            (url, results)
+        }
+    }))
+    .await;
+
+    for (url, results) in results {
+        for (index, result) in results {
+            let report = &mut reports[index];
+            match result {
+                Ok(()) => report.accepted.push(url.clone()),
+                Err(reason) => report.failed.push((url.clone(), reason)),
+            }
+        }
+    }
+    PublishReport { events: reports }
+}
+
 pub async fn publish_text_note(
     client: &Client,
     signer: &NostrSigner,
     content: &str,
     tags: Vec<Tag>,
+    pow: Option<&ProofOfWork>,
 ) -> Result<EventId> {
     let builder = EventBuilder::text_note(content, tags).custom_created_at(Timestamp::now());
-    sign_and_send_event!(client, signer, builder)
+    sign_and_send_event!(client, signer, builder, pow)
+}
+
+/// Same as [`publish_text_note`], sent only to `relays`
+pub async fn publish_text_note_to(
+    client: &Client,
+    relays: Vec<Url>,
+    signer: &NostrSigner,
+    content: &str,
+    tags: Vec<Tag>,
+    pow: Option<&ProofOfWork>,
+) -> Result<EventId> {
+    let builder = EventBuilder::text_note(content, tags).custom_created_at(Timestamp::now());
+    sign_and_send_to(client, relays, signer, builder, pow).await
 }
 
 pub async fn repost(
@@ -40,21 +366,288 @@ pub async fn repost(
     signer: &NostrSigner,
     event: &Event,
     url: Option<UncheckedUrl>,
+    pow: Option<&ProofOfWork>,
 ) -> Result<EventId> {
     let builder = EventBuilder::repost(event, url);
-    sign_and_send_event!(client, signer, builder)
+    sign_and_send_event!(client, signer, builder, pow)
+}
+
+/// Same as [`repost`], sent only to `relays`
+pub async fn repost_to(
+    client: &Client,
+    relays: Vec<Url>,
+    signer: &NostrSigner,
+    event: &Event,
+    url: Option<UncheckedUrl>,
+    pow: Option<&ProofOfWork>,
+) -> Result<EventId> {
+    let builder = EventBuilder::repost(event, url);
+    sign_and_send_to(client, relays, signer, builder, pow).await
+}
+
+/// NIP-18 quote: a text note with `comment`, followed by an `nevent`
+/// mention of `quoted` and a `q` tag pointing at it. Unlike [`repost`] it
+/// shows up as a note of its own.
+pub async fn quote_repost(
+    client: &Client,
+    signer: &NostrSigner,
+    quoted: &Event,
+    comment: &str,
+    pow: Option<&ProofOfWork>,
+) -> Result<EventId> {
+    let relay_hint = quote_relay_hint(client, quoted).await?;
+    let builder = quote_builder(quoted, comment, relay_hint);
+    sign_and_send_event!(client, signer, builder, pow)
+}
+
+/// Same as [`quote_repost`], sent only to `relays`
+pub async fn quote_repost_to(
+    client: &Client,
+    relays: Vec<Url>,
+    signer: &NostrSigner,
+    quoted: &Event,
+    comment: &str,
+    pow: Option<&ProofOfWork>,
+) -> Result<EventId> {
+    let relay_hint = quote_relay_hint(client, quoted).await?;
+    let builder = quote_builder(quoted, comment, relay_hint);
+    sign_and_send_to(client, relays, signer, builder, pow).await
+}
+
+/// A relay `quoted` was seen on, for readers to find it
+async fn quote_relay_hint(client: &Client, quoted: &Event) -> Result<Option<Url>> {
+    Ok(client
+        .database()
+        .event_seen_on_relays(quoted.id)
+        .await?
+        .and_then(|relays| relays.into_iter().next()))
+}
+
+fn quote_builder(quoted: &Event, comment: &str, relay_hint: Option<Url>) -> EventBuilder {
+    let relays: Vec<String> = relay_hint.iter().map(Url::to_string).collect();
+    let nevent = Nip19Event::new(quoted.id, relays.clone())
+        .author(quoted.author())
+        .kind(quoted.kind);
+    let mention = nevent.to_nostr_uri().unwrap_or_else(|_| quoted.id.to_hex());
+    let content = if comment.is_empty() {
+        mention
+    } else {
+        format!("{comment}\n\n{mention}")
+    };
+
+    let relay = relays.into_iter().next().unwrap_or_default();
+    let quote = Tag::custom(
+        TagKind::SingleLetter(SingleLetterTag::lowercase(Alphabet::Q)),
+        [quoted.id.to_hex(), relay, quoted.author().to_hex()],
+    );
+    let tags = vec![quote, Tag::public_key(quoted.author())];
+    EventBuilder::text_note(content, tags).custom_created_at(Timestamp::now())
+}
+
+/// Content of a reaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reaction {
+    /// "+", "-" or an emoji
+    Emoji(String),
+    /// NIP-30 custom emoji, sent as `:shortcode:` with its image url
+    Custom(CustomEmoji),
+}
+
+impl From<&str> for Reaction {
+    fn from(reaction: &str) -> Self {
+        Reaction::Emoji(reaction.to_string())
+    }
+}
+
+impl From<CustomEmoji> for Reaction {
+    fn from(emoji: CustomEmoji) -> Self {
+        Reaction::Custom(emoji)
+    }
+}
+
+fn reaction_builder(event: &Event, reaction: Reaction) -> EventBuilder {
+    match reaction {
+        Reaction::Emoji(content) => EventBuilder::reaction(event, content),
+        Reaction::Custom(emoji) => {
+            let content = format!(":{}:", emoji.shortcode);
+            EventBuilder::reaction(event, content).add_tags([Tag::from_standardized(
+                TagStandard::Emoji {
+                    shortcode: emoji.shortcode,
+                    url: emoji.url.into(),
+                },
+            )])
+        }
+    }
 }
 
 pub async fn reaction(
     client: &Client,
     signer: &NostrSigner,
     event: &Event,
-    reaction: &str,
+    reaction: impl Into<Reaction>,
+    pow: Option<&ProofOfWork>,
 ) -> Result<EventId> {
-    let builder = EventBuilder::reaction(event, reaction);
+    let builder = reaction_builder(event, reaction.into());
+    sign_and_send_event!(client, signer, builder, pow)
+}
+
+/// Same as [`reaction`], sent only to `relays`
+pub async fn reaction_to(
+    client: &Client,
+    relays: Vec<Url>,
+    signer: &NostrSigner,
+    event: &Event,
+    reaction: impl Into<Reaction>,
+    pow: Option<&ProofOfWork>,
+) -> Result<EventId> {
+    let builder = reaction_builder(event, reaction.into());
+    sign_and_send_to(client, relays, signer, builder, pow).await
+}
+
+/// Changes to a kind 0 profile. Fields left `None` keep their current
+/// value; an empty string removes the field.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProfilePatch {
+    pub name: Option<String>,
+    pub display_name: Option<String>,
+    pub about: Option<String>,
+    pub website: Option<String>,
+    pub picture: Option<String>,
+    pub banner: Option<String>,
+    pub nip05: Option<String>,
+    pub lud06: Option<String>,
+    pub lud16: Option<String>,
+    /// Non-standard fields; `Value::Null` removes one
+    pub custom: HashMap<String, serde_json::Value>,
+}
+
+impl ProfilePatch {
+    pub fn apply(self, mut metadata: Metadata) -> Metadata {
+        let set = |field: &mut Option<String>, value: Option<String>| {
+            if let Some(value) = value {
+                *field = (!value.is_empty()).then_some(value);
+            }
+        };
+        set(&mut metadata.name, self.name);
+        set(&mut metadata.display_name, self.display_name);
+        set(&mut metadata.about, self.about);
+        set(&mut metadata.website, self.website);
+        set(&mut metadata.picture, self.picture);
+        set(&mut metadata.banner, self.banner);
+        set(&mut metadata.nip05, self.nip05);
+        set(&mut metadata.lud06, self.lud06);
+        set(&mut metadata.lud16, self.lud16);
+        for (key, value) in self.custom {
+            if value.is_null() {
+                metadata.custom.remove(&key);
+            } else {
+                metadata.custom.insert(key, value);
+            }
+        }
+        metadata
+    }
+}
+
+/// Applies `patch` to our newest profile and publishes the result, so
+/// setting one field never wipes the others. Fails without publishing if
+/// the current profile cannot be fetched; having none is fine.
+pub async fn update_profile(
+    client: &Client,
+    signer: &NostrSigner,
+    patch: ProfilePatch,
+    timeout: Option<Duration>,
+) -> Result<(EventId, Metadata)> {
+    let public_key = signer.public_key().await?;
+    let current = match get_metadata(client, &public_key, timeout).await {
+        Ok(metadata) => metadata,
+        Err(FetchError::EventNotFound) => Metadata::new(),
+        Err(err) => return Err(err.into()),
+    };
+    let metadata = patch.apply(current);
+    let builder = EventBuilder::metadata(&metadata);
+    let event = signer.sign_event_builder(builder).await?;
+    let event_id = send_event(client, event).await?;
+    Ok((event_id, metadata))
+}
+
+/// Publishes a NIP-88 poll asking `question`. Options get their index as
+/// id. Votes after `ends_at` are not counted.
+pub async fn create_poll(
+    client: &Client,
+    signer: &NostrSigner,
+    question: &str,
+    options: &[&str],
+    multiple_choice: bool,
+    ends_at: Option<Timestamp>,
+) -> Result<EventId> {
+    let builder = poll_builder(question, options, multiple_choice, ends_at);
     sign_and_send_event!(client, signer, builder)
 }
 
+fn poll_builder(
+    question: &str,
+    options: &[&str],
+    multiple_choice: bool,
+    ends_at: Option<Timestamp>,
+) -> EventBuilder {
+    let mut tags: Vec<Tag> = options
+        .iter()
+        .enumerate()
+        .map(|(index, label)| {
+            Tag::custom(
+                TagKind::Custom("option".into()),
+                [index.to_string(), label.to_string()],
+            )
+        })
+        .collect();
+    let poll_type = if multiple_choice {
+        "multiplechoice"
+    } else {
+        "singlechoice"
+    };
+    tags.push(Tag::custom(TagKind::Custom("polltype".into()), [poll_type]));
+    if let Some(ends_at) = ends_at {
+        tags.push(Tag::custom(
+            TagKind::Custom("endsAt".into()),
+            [ends_at.to_string()],
+        ));
+    }
+    EventBuilder::new(Poll::KIND, question, tags)
+}
+
+/// Votes for `option_ids` of `poll`. A new vote replaces our previous one.
+pub async fn vote_poll(
+    client: &Client,
+    signer: &NostrSigner,
+    poll: &Poll,
+    option_ids: &[&str],
+) -> Result<EventId> {
+    let builder = vote_builder(poll, option_ids, Timestamp::now())?;
+    sign_and_send_event!(client, signer, builder)
+}
+
+fn vote_builder(poll: &Poll, option_ids: &[&str], now: Timestamp) -> Result<EventBuilder> {
+    if option_ids.is_empty() {
+        return Err(Error::InvalidVote("no option chosen"));
+    }
+    if !poll.multiple_choice && option_ids.len() > 1 {
+        return Err(Error::InvalidVote("poll allows a single choice"));
+    }
+    if !option_ids.iter().all(|id| poll.has_option(id)) {
+        return Err(Error::InvalidVote("unknown option"));
+    }
+    if poll.is_closed(now) {
+        return Err(Error::InvalidVote("poll has ended"));
+    }
+    let mut tags = vec![Tag::event(poll.inner.id)];
+    tags.extend(
+        option_ids
+            .iter()
+            .map(|id| Tag::custom(TagKind::Custom("response".into()), [*id])),
+    );
+    Ok(EventBuilder::new(Poll::RESPONSE_KIND, "", tags).custom_created_at(now))
+}
+
 pub async fn new_channel(
     client: &Client,
     signer: &NostrSigner,
@@ -86,6 +679,19 @@ pub async fn send_channel_msg(
     sign_and_send_event!(client, signer, builder)
 }
 
+/// Same as [`send_channel_msg`], sent only to `relays`
+pub async fn send_channel_msg_to(
+    client: &Client,
+    relays: Vec<Url>,
+    signer: &NostrSigner,
+    channel_id: EventId,
+    msg: &str,
+    relay_url: Url,
+) -> Result<EventId> {
+    let builder = EventBuilder::channel_msg(channel_id, relay_url, msg);
+    sign_and_send_to(client, relays, signer, builder, None).await
+}
+
 pub async fn file_metadata(
     client: &Client,
     signer: &NostrSigner,
@@ -96,24 +702,338 @@ pub async fn file_metadata(
     sign_and_send_event!(client, signer, builder)
 }
 
+/// Encryption of a direct message
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DmProtocol {
+    /// Kind 4, NIP-04 encrypted. Anyone can see who talks to whom.
+    Nip04,
+    /// Kind 14 rumor, sealed and gift wrapped (NIP-17 and NIP-59)
+    #[default]
+    Nip17,
+}
+
+/// Sends `message` to `receiver`. A NIP-17 message goes to the DM relays
+/// of the receiver (kind 10050), or to every relay when it has none, and a
+/// copy is wrapped for ourselves so other devices see the conversation.
+/// Returns the id of the event received by `receiver`.
 pub async fn send_private_msg(
     client: &Client,
     signer: &NostrSigner,
     receiver: PublicKey,
     message: &str,
     reply_to: Option<EventId>,
+    protocol: DmProtocol,
 ) -> Result<EventId> {
-    let builder = EventBuilder::private_msg_rumor(receiver, message, reply_to);
-    sign_and_send_event!(client, signer, builder)
+    let events = private_msg_events(signer, receiver, message, reply_to, protocol).await?;
+    let mut event_ids = Vec::with_capacity(events.len());
+    for (recipient, event) in events {
+        event_ids.push(event.id);
+        let relays = match protocol {
+            DmProtocol::Nip04 => Vec::new(),
+            DmProtocol::Nip17 => get_dm_relays(client, &recipient, Some(DM_RELAYS_TIMEOUT)).await?,
+        };
+        if relays.is_empty() {
+            send_event(client, event).await?;
+        } else {
+            for url in relays.iter() {
+                client.add_relay(url.clone()).await?;
+                client.connect_relay(url.clone()).await?;
+            }
+            send_event_to(client, relays, event).await?;
+        }
+    }
+    Ok(event_ids[0])
+}
+
+/// Same as [`send_private_msg`], sent only to `relays`, e.g. the
+/// receiver's inbox relays
+pub async fn send_private_msg_to(
+    client: &Client,
+    relays: Vec<Url>,
+    signer: &NostrSigner,
+    receiver: PublicKey,
+    message: &str,
+    reply_to: Option<EventId>,
+    protocol: DmProtocol,
+) -> Result<EventId> {
+    let events = private_msg_events(signer, receiver, message, reply_to, protocol).await?;
+    let mut event_ids = Vec::with_capacity(events.len());
+    for (_, event) in events {
+        event_ids.push(send_event_to(client, relays.clone(), event).await?);
+    }
+    Ok(event_ids[0])
+}
+
+/// How long to wait for a recipient's DM relay list
+const DM_RELAYS_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The events carrying a direct message, each with the key it is meant
+/// for: the receiver first, then for NIP-17 our own copy
+async fn private_msg_events(
+    signer: &NostrSigner,
+    receiver: PublicKey,
+    message: &str,
+    reply_to: Option<EventId>,
+    protocol: DmProtocol,
+) -> Result<Vec<(PublicKey, Event)>> {
+    if protocol == DmProtocol::Nip04 {
+        let content = signer.nip04_encrypt(receiver, message).await?;
+        let mut tags = vec![Tag::public_key(receiver)];
+        tags.extend(reply_to.map(Tag::event));
+        let builder = EventBuilder::new(Kind::EncryptedDirectMessage, content, tags);
+        let event = signer.sign_event_builder(builder).await?;
+        return Ok(vec![(receiver, event)]);
+    }
+
+    let sender = signer.public_key().await?;
+    let rumor = EventBuilder::private_msg_rumor(receiver, message, reply_to)
+        .to_unsigned_event(sender)
+        .as_json();
+    let mut recipients = vec![receiver];
+    if sender != receiver {
+        recipients.push(sender);
+    }
+    let mut events = Vec::with_capacity(recipients.len());
+    for recipient in recipients {
+        let content = signer.nip44_encrypt(recipient, &rumor).await?;
+        let seal = EventBuilder::new(Kind::Seal, content, [])
+            .custom_created_at(Timestamp::tweaked(nip59::RANGE_RANDOM_TIMESTAMP_TWEAK));
+        let seal = signer.sign_event_builder(seal).await?;
+        let gift_wrap = EventBuilder::gift_wrap_from_seal(&recipient, &seal, None)?;
+        events.push((recipient, gift_wrap));
+    }
+    Ok(events)
+}
+
+/// Options of a NIP-09 deletion request
+#[derive(Debug, Clone, Default)]
+pub struct DeleteOptions {
+    /// Shown to users by clients that honour the deletion
+    pub reason: Option<String>,
+    /// `k` tags for events missing from the database; kinds of stored
+    /// events are added automatically
+    pub kinds: Vec<Kind>,
+    /// Send the request only to the relays the events were seen on, or to
+    /// every relay when none is known
+    pub only_seen_relays: bool,
 }
 
 pub async fn delete_event(
     client: &Client,
     signer: &NostrSigner,
     event_ids: Vec<EventId>,
+    options: DeleteOptions,
 ) -> Result<EventId> {
-    let builder = EventBuilder::delete(event_ids);
-    sign_and_send_event!(client, signer, builder)
+    let database = client.database();
+    let stored = database
+        .query(vec![Filter::new().ids(event_ids.clone())], Order::Desc)
+        .await?;
+    let mut kinds = options.kinds;
+    kinds.extend(stored.iter().map(|event| event.kind));
+
+    let builder = deletion_builder(&event_ids, kinds, options.reason.as_deref());
+    let event = signer.sign_event_builder(builder).await?;
+    if !options.only_seen_relays {
+        return send_event(client, event).await;
+    }
+
+    let mut seen_on: HashSet<Url> = HashSet::new();
+    for event_id in event_ids.iter() {
+        seen_on.extend(
+            database
+                .event_seen_on_relays(*event_id)
+                .await?
+                .unwrap_or_default(),
+        );
+    }
+    let relays = client.relays().await;
+    seen_on.retain(|url| relays.contains_key(url));
+    if seen_on.is_empty() {
+        return send_event(client, event).await;
+    }
+    Ok(client.send_event_to(seen_on, event).await?)
+}
+
+fn deletion_builder(
+    event_ids: &[EventId],
+    mut kinds: Vec<Kind>,
+    reason: Option<&str>,
+) -> EventBuilder {
+    kinds.sort();
+    kinds.dedup();
+    let tags = event_ids
+        .iter()
+        .map(|event_id| Tag::event(*event_id))
+        .chain(
+            kinds
+                .into_iter()
+                .map(|kind| Tag::from_standardized(TagStandard::Kind(kind))),
+        );
+    EventBuilder::new(Kind::EventDeletion, reason.unwrap_or_default(), tags)
+}
+
+/// Longest `summary` tag derived from the article body, in characters
+const SUMMARY_MAX_CHARS: usize = 200;
+
+/// Publishes a NIP-23 article. Passing the `d_identifier` of an existing
+/// article replaces it and keeps its original `published_at`; without one a
+/// new identifier is derived from the title. `summary` and `image` tags are
+/// derived from the markdown unless given in `tags`. Returns the event id
+/// and the article's identifier.
+pub async fn publish_long_form(
+    client: &Client,
+    signer: &NostrSigner,
+    title: &str,
+    markdown: &str,
+    tags: Vec<Tag>,
+    d_identifier: Option<&str>,
+    timeout: Option<Duration>,
+) -> Result<(EventId, String)> {
+    let (event, identifier) =
+        long_form_event(client, signer, title, markdown, tags, d_identifier, timeout).await?;
+    let eid = send_event(client, event).await?;
+    Ok((eid, identifier))
+}
+
+/// Same as [`publish_long_form`], sent only to `relays`, e.g. to keep a
+/// draft on a private relay
+#[allow(clippy::too_many_arguments)]
+pub async fn publish_long_form_to(
+    client: &Client,
+    relays: Vec<Url>,
+    signer: &NostrSigner,
+    title: &str,
+    markdown: &str,
+    tags: Vec<Tag>,
+    d_identifier: Option<&str>,
+    timeout: Option<Duration>,
+) -> Result<(EventId, String)> {
+    let (event, identifier) =
+        long_form_event(client, signer, title, markdown, tags, d_identifier, timeout).await?;
+    let eid = send_event_to(client, relays, event).await?;
+    Ok((eid, identifier))
+}
+
+async fn long_form_event(
+    client: &Client,
+    signer: &NostrSigner,
+    title: &str,
+    markdown: &str,
+    tags: Vec<Tag>,
+    d_identifier: Option<&str>,
+    timeout: Option<Duration>,
+) -> Result<(Event, String)> {
+    let published_at = match d_identifier {
+        Some(identifier) => {
+            let public_key = signer.public_key().await?;
+            article_published_at(client, public_key, identifier, timeout).await?
+        }
+        None => None,
+    };
+    let identifier = d_identifier
+        .map(String::from)
+        .unwrap_or_else(|| new_article_identifier(title));
+    let builder = long_form_builder(
+        title,
+        markdown,
+        tags,
+        &identifier,
+        published_at.unwrap_or_else(Timestamp::now),
+    );
+    let event = signer.sign_event_builder(builder).await?;
+    Ok((event, identifier))
+}
+
+/// `published_at` of our current revision of an article, if any
+async fn article_published_at(
+    client: &Client,
+    public_key: PublicKey,
+    identifier: &str,
+    timeout: Option<Duration>,
+) -> Result<Option<Timestamp>> {
+    let filter = Filter::new()
+        .kind(Kind::LongFormTextNote)
+        .author(public_key)
+        .identifier(identifier);
+    let events = client.get_events_of(vec![filter], timeout).await?;
+    let newest = events.into_iter().max_by_key(|event| event.created_at);
+    Ok(newest
+        .and_then(|event| LongFormNote::try_from(event).ok())
+        .map(|article| article.published_at_or_created()))
+}
+
+fn long_form_builder(
+    title: &str,
+    markdown: &str,
+    mut tags: Vec<Tag>,
+    identifier: &str,
+    published_at: Timestamp,
+) -> EventBuilder {
+    let tag_name = |tag: &Tag| tag.as_vec().first().cloned().unwrap_or_default();
+    tags.retain(|tag| !matches!(tag_name(tag).as_str(), "d" | "title" | "published_at"));
+    let has_tag = |tags: &[Tag], name: &str| tags.iter().any(|tag| tag_name(tag) == name);
+
+    if !has_tag(&tags, "summary") {
+        if let Some(summary) = markdown_summary(markdown) {
+            tags.push(Tag::from_standardized(TagStandard::Summary(summary)));
+        }
+    }
+    if !has_tag(&tags, "image") {
+        if let Some(image) = first_markdown_image(markdown) {
+            tags.push(Tag::from_standardized(TagStandard::Image(
+                UncheckedUrl::from(image),
+                None,
+            )));
+        }
+    }
+    tags.push(Tag::identifier(identifier));
+    tags.push(Tag::from_standardized(TagStandard::Title(
+        title.to_string(),
+    )));
+    tags.push(Tag::from_standardized(TagStandard::PublishedAt(
+        published_at,
+    )));
+    EventBuilder::long_form_text_note(markdown, tags)
+}
+
+/// Title slug plus the current time, unique enough for one author
+fn new_article_identifier(title: &str) -> String {
+    let slug = title
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+    if slug.is_empty() {
+        Timestamp::now().to_string()
+    } else {
+        format!("{}-{}", slug, Timestamp::now())
+    }
+}
+
+/// First paragraph of plain text, skipping headings and images
+fn markdown_summary(markdown: &str) -> Option<String> {
+    let paragraph = markdown
+        .split("\n\n")
+        .map(str::trim)
+        .find(|block| !block.is_empty() && !block.starts_with('#') && !block.starts_with("!["))?;
+    let text = paragraph.split_whitespace().collect::<Vec<_>>().join(" ");
+    if text.chars().count() <= SUMMARY_MAX_CHARS {
+        return Some(text);
+    }
+    let mut summary: String = text.chars().take(SUMMARY_MAX_CHARS - 1).collect();
+    summary.push('…');
+    Some(summary)
+}
+
+/// Url of the first `![alt](url)` image
+fn first_markdown_image(markdown: &str) -> Option<&str> {
+    let start = markdown.find("![")?;
+    let rest = &markdown[start..];
+    let url_start = rest.find("](")? + 2;
+    let url = &rest[url_start..];
+    let url_end = url.find([')', ' '])?;
+    Some(&url[..url_end])
 }
 
 pub async fn set_relay_list(
@@ -125,6 +1045,194 @@ pub async fn set_relay_list(
     sign_and_send_event!(client, signer, builder)
 }
 
+/// Stores `data` as the NIP-78 settings of `app_name` (a kind 30078 event
+/// replacing the previous one). With `encrypt` the content is NIP-44
+/// encrypted to ourselves, so only our keys can read it back with
+/// `get_app_data`.
+pub async fn set_app_data(
+    client: &Client,
+    signer: &NostrSigner,
+    app_name: &str,
+    data: &serde_json::Value,
+    encrypt: bool,
+) -> Result<EventId> {
+    let mut content = data.to_string();
+    if encrypt {
+        let public_key = signer.public_key().await?;
+        content = signer.nip44_encrypt(public_key, content).await?;
+    }
+    let builder = EventBuilder::new(
+        Kind::ApplicationSpecificData,
+        content,
+        [Tag::identifier(app_name)],
+    );
+    sign_and_send_event!(client, signer, builder)
+}
+
+/// Defines a NIP-58 badge (kind 30009). `badge_id` is the badge's
+/// identifier among our badges; defining it again replaces it.
+pub async fn define_badge(
+    client: &Client,
+    signer: &NostrSigner,
+    badge_id: &str,
+    name: &str,
+    description: Option<&str>,
+    image: Option<Url>,
+) -> Result<EventId> {
+    let builder = EventBuilder::define_badge(
+        badge_id,
+        Some(name),
+        description,
+        image.map(UncheckedUrl::from),
+        None,
+        Vec::new(),
+    );
+    sign_and_send_event!(client, signer, builder)
+}
+
+/// Awards the badge of `badge_definition` to `recipients` (kind 8)
+pub async fn award_badge(
+    client: &Client,
+    signer: &NostrSigner,
+    badge_definition: &Event,
+    recipients: Vec<PublicKey>,
+) -> Result<EventId> {
+    let recipients = recipients.into_iter().map(Tag::public_key);
+    let builder = EventBuilder::award_badge(badge_definition, recipients)?;
+    sign_and_send_event!(client, signer, builder)
+}
+
+/// Shows badges on our profile (kind 30008), in the given order. Each
+/// badge is a definition and the award that gave it to us.
+pub async fn set_profile_badges(
+    client: &Client,
+    signer: &NostrSigner,
+    badges: Vec<(Event, Event)>,
+) -> Result<EventId> {
+    let public_key = signer.public_key().await?;
+    let builder = profile_badges_builder(&public_key, &badges)?;
+    sign_and_send_event!(client, signer, builder)
+}
+
+fn profile_badges_builder(
+    public_key: &PublicKey,
+    badges: &[(Event, Event)],
+) -> Result<EventBuilder> {
+    let mut tags = vec![Tag::identifier("profile_badges")];
+    for (definition, award) in badges {
+        let coordinate = Coordinate::new(Kind::BadgeDefinition, definition.author())
+            .identifier(definition.identifier().unwrap_or_default());
+        let awards_us = award.public_keys().any(|pk| pk == public_key);
+        let references = award.iter_tags().any(|tag| match tag.as_standardized() {
+            Some(TagStandard::Coordinate { coordinate: a, .. }) => *a == coordinate,
+            _ => false,
+        });
+        if definition.kind != Kind::BadgeDefinition
+            || award.kind != Kind::BadgeAward
+            || award.author() != definition.author()
+            || !awards_us
+            || !references
+        {
+            return Err(Error::InvalidBadgeAward(award.id));
+        }
+        tags.push(Tag::coordinate(coordinate));
+        tags.push(Tag::event(award.id));
+    }
+    Ok(EventBuilder::new(Kind::ProfileBadges, "", tags))
+}
+
+/// Announces a NIP-53 live activity (kind 30311), replacing ours with the
+/// same `id`
+pub async fn create_live_event(
+    client: &Client,
+    signer: &NostrSigner,
+    live_event: LiveEvent,
+) -> Result<EventId> {
+    let builder = EventBuilder::live_event(live_event);
+    sign_and_send_event!(client, signer, builder)
+}
+
+/// What changes while an activity runs. Fields left `None` keep their
+/// current value.
+#[derive(Debug, Clone, Default)]
+pub struct LiveEventUpdate {
+    pub status: Option<LiveEventStatus>,
+    pub current_participants: Option<u64>,
+    pub total_participants: Option<u64>,
+    pub recording: Option<Url>,
+}
+
+/// Replaces our live activity `current` with a copy carrying `update`.
+/// Ending an activity also sets its `ends` time if it has none.
+pub async fn update_live_event(
+    client: &Client,
+    signer: &NostrSigner,
+    current: &Event,
+    update: LiveEventUpdate,
+) -> Result<EventId> {
+    let builder = live_event_update_builder(current, update)?;
+    sign_and_send_event!(client, signer, builder)
+}
+
+fn live_event_update_builder(current: &Event, update: LiveEventUpdate) -> Result<EventBuilder> {
+    if current.kind != Kind::LiveEvent || current.identifier().is_none() {
+        return Err(Error::NotLiveEvent(current.id));
+    }
+    let mut replaced = Vec::new();
+    let mut tags = Vec::new();
+    if let Some(status) = update.status {
+        let ended = status == LiveEventStatus::Ended;
+        if ended && !current.iter_tags().any(|tag| tag.kind() == TagKind::Ends) {
+            tags.push(Tag::from_standardized(TagStandard::Ends(Timestamp::now())));
+        }
+        replaced.push(TagKind::Status);
+        tags.push(Tag::from_standardized(TagStandard::LiveEventStatus(status)));
+    }
+    if let Some(count) = update.current_participants {
+        replaced.push(TagKind::CurrentParticipants);
+        tags.push(Tag::from_standardized(TagStandard::CurrentParticipants(
+            count,
+        )));
+    }
+    if let Some(count) = update.total_participants {
+        replaced.push(TagKind::TotalParticipants);
+        tags.push(Tag::from_standardized(TagStandard::TotalParticipants(
+            count,
+        )));
+    }
+    if let Some(url) = update.recording {
+        replaced.push(TagKind::Recording);
+        tags.push(Tag::from_standardized(TagStandard::Recording(url.into())));
+    }
+    let kept = current
+        .iter_tags()
+        .filter(|tag| !replaced.contains(&tag.kind()))
+        .cloned();
+    let tags: Vec<Tag> = kept.chain(tags).collect();
+    Ok(EventBuilder::new(
+        Kind::LiveEvent,
+        current.content.clone(),
+        tags,
+    ))
+}
+
+/// Posts `message` to the chat of the live activity `live_event` (kind
+/// 1311)
+pub async fn send_live_chat(
+    client: &Client,
+    signer: &NostrSigner,
+    live_event: &Event,
+    message: &str,
+    relay_url: Option<Url>,
+) -> Result<EventId> {
+    let identifier = match live_event.identifier() {
+        Some(identifier) if live_event.kind == Kind::LiveEvent => identifier,
+        _ => return Err(Error::NotLiveEvent(live_event.id)),
+    };
+    let builder = EventBuilder::live_event_msg(identifier, live_event.author(), message, relay_url);
+    sign_and_send_event!(client, signer, builder)
+}
+
 pub async fn set_contact_list(
     client: &Client,
     signer: &NostrSigner,
@@ -140,13 +1248,8 @@ pub async fn unfollow(
     followee: PublicKey,
     timeout: Option<Duration>,
 ) -> Result<EventId> {
-    let contacts = get_contact_list(client, signer, timeout).await?;
-    let contacts: Vec<Contact> = contacts
-        .into_iter()
-        .filter(|contact| contact.public_key != followee)
-        .collect();
-    let builder = EventBuilder::contact_list(contacts);
-    sign_and_send_event!(client, signer, builder)
+    let diff = ContactListDiff::default().unfollow(followee);
+    update_contact_list(client, signer, diff, timeout).await
 }
 
 pub async fn follow(
@@ -157,51 +1260,130 @@ pub async fn follow(
     relay_url: Option<UncheckedUrl>,
     alias: Option<String>,
 ) -> Result<EventId> {
-    let contacts = get_contact_list(client, signer, timeout).await?;
-    let contacts: Vec<Contact> = contacts
-        .into_iter()
-        .filter(|contact| contact.public_key != followee)
-        .collect();
-    let contact = Contact::new(followee, relay_url, alias);
-    let mut contacts = contacts;
-    contacts.push(contact);
-    let builder = EventBuilder::contact_list(contacts);
-    sign_and_send_event!(client, signer, builder)
+    let diff = ContactListDiff::default().follow(Contact::new(followee, relay_url, alias));
+    update_contact_list(client, signer, diff, timeout).await
 }
 
-async fn get_contact_list_filters(signer: &NostrSigner) -> Result<Vec<Filter>> {
-    let public_key = signer.public_key().await?;
-    let filter: Filter = Filter::new()
-        .author(public_key)
-        .kind(Kind::ContactList)
-        .limit(1);
-    Ok(vec![filter])
+/// Follows and unfollows to apply to the newest contact list, instead of
+/// replacing it with a list that may be stale on this device
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ContactListDiff {
+    pub follow: Vec<Contact>,
+    pub unfollow: Vec<PublicKey>,
 }
 
-async fn get_contact_list(
-    client: &Client,
-    signer: &NostrSigner,
-    timeout: Option<Duration>,
-) -> Result<Vec<Contact>> {
-    let mut contact_list: Vec<Contact> = Vec::new();
-    let filters = get_contact_list_filters(signer).await?;
-    let events: Vec<Event> = client.get_events_of(filters, timeout).await?;
-
-    for event in events.into_iter() {
-        for tag in event.into_iter_tags() {
-            if let Some(TagStandard::PublicKey {
+impl ContactListDiff {
+    /// Follow `contact`. Its relay hint and petname, when `None`, keep the
+    /// ones already on the list.
+    pub fn follow(mut self, contact: Contact) -> Self {
+        self.unfollow
+            .retain(|public_key| *public_key != contact.public_key);
+        self.follow.push(contact);
+        self
+    }
+
+    pub fn unfollow(mut self, public_key: PublicKey) -> Self {
+        self.follow
+            .retain(|contact| contact.public_key != public_key);
+        self.unfollow.push(public_key);
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.follow.is_empty() && self.unfollow.is_empty()
+    }
+
+    /// `tags` of a contact list with the diff applied, or `None` when
+    /// nothing changes. Tags other than follows are kept in place.
+    fn apply(&self, tags: &[Tag]) -> Option<Vec<Tag>> {
+        let mut changed = false;
+        let mut followed = HashSet::new();
+        let mut result = Vec::with_capacity(tags.len() + self.follow.len());
+        for tag in tags {
+            let Some(TagStandard::PublicKey {
                 public_key,
                 relay_url,
                 alias,
                 uppercase: false,
-            }) = tag.to_standardized()
-            {
-                contact_list.push(Contact::new(public_key, relay_url, alias))
+            }) = tag.as_standardized()
+            else {
+                result.push(tag.clone());
+                continue;
+            };
+            if self.unfollow.contains(public_key) || !followed.insert(*public_key) {
+                changed = true;
+                continue;
+            }
+            match self.follow.iter().find(|c| c.public_key == *public_key) {
+                Some(contact) => {
+                    let merged = Contact::new(
+                        *public_key,
+                        contact.relay_url.clone().or_else(|| relay_url.clone()),
+                        contact.alias.clone().or_else(|| alias.clone()),
+                    );
+                    let tag_changed = merged.relay_url != *relay_url || merged.alias != *alias;
+                    changed |= tag_changed;
+                    result.push(contact_tag(merged));
+                }
+                None => result.push(tag.clone()),
+            }
+        }
+        for contact in &self.follow {
+            if followed.insert(contact.public_key) {
+                changed = true;
+                result.push(contact_tag(contact.clone()));
             }
         }
+        changed.then_some(result)
     }
+}
 
-    Ok(contact_list)
+pub(crate) fn contact_tag(contact: Contact) -> Tag {
+    Tag::from_standardized(TagStandard::PublicKey {
+        public_key: contact.public_key,
+        relay_url: contact.relay_url,
+        alias: contact.alias,
+        uppercase: false,
+    })
+}
+
+/// Applies `diff` to our newest contact list and publishes the result.
+/// The content (legacy relay list) and other tags are preserved. A diff
+/// that changes nothing publishes nothing and returns the current list.
+pub async fn update_contact_list(
+    client: &Client,
+    signer: &NostrSigner,
+    diff: ContactListDiff,
+    timeout: Option<Duration>,
+) -> Result<EventId> {
+    let public_key = signer.public_key().await?;
+    let filter = Filter::new().author(public_key).kind(Kind::ContactList);
+    let events = client.get_events_of(vec![filter], timeout).await?;
+    let latest = get_newest_event(&events);
+    let Some(builder) = contact_list_builder(latest, &diff) else {
+        return match latest {
+            Some(event) => Ok(event.id),
+            None => Err(Error::EmptyContactList),
+        };
+    };
+    let event = signer.sign_event_builder(builder).await?;
+    ContactListCache::global().observe(&event);
+    send_event(client, event).await
+}
+
+fn contact_list_builder(latest: Option<&Event>, diff: &ContactListDiff) -> Option<EventBuilder> {
+    let tags = latest.map(|event| event.tags()).unwrap_or_default();
+    let tags = diff.apply(tags)?;
+    let content = latest
+        .map(|event| event.content.clone())
+        .unwrap_or_default();
+    // Relays keep the newest list; a clock behind the last update must not
+    // make ours the older one
+    let created_at = match latest {
+        Some(event) if event.created_at >= Timestamp::now() => event.created_at + 1,
+        _ => Timestamp::now(),
+    };
+    Some(EventBuilder::new(Kind::ContactList, content, tags).custom_created_at(created_at))
 }
 
 #[cfg(test)]
@@ -213,9 +1395,11 @@ mod tests {
     use wasm_bindgen_test::*;
 
     use super::*;
+    use crate::testhelper::console_log;
     wasm_bindgen_test::wasm_bindgen_test_configure!(run_in_browser);
 
-    #[wasm_bindgen_test]
+    #[cfg_attr(not(target_arch = "wasm32"), ignore = "needs public relays")]
+    #[wasm_bindgen_test(unsupported = tokio::test)]
     async fn test_publish_text_note() {
         let private_key = SecretKey::from_bech32(
             "nsec19y0u0kgzwx4ygxpk04ktl6uc2daq2mts9w0rk2qrxnru5hhvpjeq20awgp",
@@ -226,11 +1410,53 @@ mod tests {
         let client = Client::default();
         client.add_relay("wss://relay.damus.io").await.unwrap();
         client.connect().await;
-        let result = publish_text_note(&client, signer, "Hello, world!", vec![]).await;
+        let result = publish_text_note(&client, signer, "Hello, world!", vec![], None).await;
         assert!(result.is_ok());
     }
 
-    #[wasm_bindgen_test]
+    #[wasm_bindgen_test(unsupported = tokio::test)]
+    async fn test_publish_events_report() {
+        let keys = Keys::generate();
+        let signer = keys.clone().into();
+        // No relay to accept anything, so every event is reported unpublished
+        let client = Client::new(&keys);
+        let builders = vec![
+            EventBuilder::text_note("first", []),
+            EventBuilder::text_note("second", []),
+        ];
+        let report = publish_events(&client, &signer, builders).await;
+
+        assert_eq!(report.events.len(), 2);
+        assert!(!report.all_published());
+        assert_eq!(report.unpublished().count(), 2);
+        let indexes: Vec<usize> = report.events.iter().map(|event| event.index).collect();
+        assert_eq!(indexes, vec![0, 1]);
+        assert!(report.events.iter().all(|event| event.event_id.is_ok()));
+    }
+
+    #[wasm_bindgen_test(unsupported = tokio::test)]
+    async fn test_mine_pow() {
+        let keys = Keys::generate();
+        let signer: NostrSigner = keys.clone().into();
+        let pow = ProofOfWork::new(8);
+        let event = sign_with_pow(&signer, EventBuilder::text_note("work", []), Some(&pow))
+            .await
+            .unwrap();
+        assert!(event.id.check_pow(8));
+        assert!(event.verify().is_ok());
+        assert!(event
+            .iter_tags()
+            .any(|tag| tag.as_vec().first().is_some_and(|name| name == "nonce")));
+
+        // Out of reach within one batch, so the cancellation is noticed
+        let pow = ProofOfWork::new(64);
+        pow.clone().cancel();
+        let result = sign_with_pow(&signer, EventBuilder::text_note("work", []), Some(&pow)).await;
+        assert!(matches!(result, Err(Error::PowCancelled)));
+    }
+
+    #[cfg_attr(not(target_arch = "wasm32"), ignore = "needs public relays")]
+    #[wasm_bindgen_test(unsupported = tokio::test)]
     async fn test_repost() {
         let private_key = SecretKey::from_bech32(
             "nsec1qrypzwmxp8r54ctx2x7mhqzh5exca7xd8ssnlfup0js9l6pwku3qacq4u3",
@@ -247,11 +1473,12 @@ mod tests {
         client.connect().await;
         let event = client.get_events_of(vec![f], None).await.unwrap();
         //let url = UncheckedUrl::from("wss://relay.damus.io");
-        let result = repost(&client, signer, &event[0], None).await;
+        let result = repost(&client, signer, &event[0], None, None).await;
         assert!(result.is_ok());
     }
 
-    #[wasm_bindgen_test]
+    #[cfg_attr(not(target_arch = "wasm32"), ignore = "needs public relays")]
+    #[wasm_bindgen_test(unsupported = tokio::test)]
     async fn test_reaction() {
         let private_key = SecretKey::from_bech32(
             "nsec1qrypzwmxp8r54ctx2x7mhqzh5exca7xd8ssnlfup0js9l6pwku3qacq4u3",
@@ -267,7 +1494,7 @@ mod tests {
         let f = Filter::new().event(eid);
         client.connect().await;
         let event = client.get_events_of(vec![f], None).await.unwrap();
-        let result = reaction(&client, signer, &event[0], "👍").await;
+        let result = reaction(&client, signer, &event[0], "👍", None).await;
         if let Ok(event_id) = &result {
             console_log!("Event ID: {:?}", event_id.to_bech32());
         } else if let Err(e) = &result {
@@ -276,7 +1503,8 @@ mod tests {
         assert!(result.is_ok());
     }
 
-    #[wasm_bindgen_test]
+    #[cfg_attr(not(target_arch = "wasm32"), ignore = "needs public relays")]
+    #[wasm_bindgen_test(unsupported = tokio::test)]
     async fn test_new_channel() {
         let private_key = SecretKey::from_bech32(
             "nsec1qrypzwmxp8r54ctx2x7mhqzh5exca7xd8ssnlfup0js9l6pwku3qacq4u3",
@@ -292,7 +1520,8 @@ mod tests {
         assert!(result.is_ok());
     }
 
-    #[wasm_bindgen_test]
+    #[cfg_attr(not(target_arch = "wasm32"), ignore = "needs public relays")]
+    #[wasm_bindgen_test(unsupported = tokio::test)]
     async fn test_set_channel_metadata() {
         let private_key = SecretKey::from_bech32(
             "nsec1qrypzwmxp8r54ctx2x7mhqzh5exca7xd8ssnlfup0js9l6pwku3qacq4u3",
@@ -311,7 +1540,8 @@ mod tests {
         assert!(result.is_ok());
     }
 
-    #[wasm_bindgen_test]
+    #[cfg_attr(not(target_arch = "wasm32"), ignore = "needs public relays")]
+    #[wasm_bindgen_test(unsupported = tokio::test)]
     async fn test_send_channel_msg() {
         let private_key = SecretKey::from_bech32(
             "nsec1qrypzwmxp8r54ctx2x7mhqzh5exca7xd8ssnlfup0js9l6pwku3qacq4u3",
@@ -330,7 +1560,8 @@ mod tests {
         assert!(result.is_ok());
     }
 
-    #[wasm_bindgen_test]
+    #[cfg_attr(not(target_arch = "wasm32"), ignore = "needs public relays")]
+    #[wasm_bindgen_test(unsupported = tokio::test)]
     async fn test_file_metadata() {
         const IMAGE_URL: &str = "https://image.nostr.build/99a95fcb4b7a2591ad32467032c52a62d90a204d3b176bc2459ad7427a3f2b89.jpg";
         const IMAGE_HASH: &str = "1aea8e98e0e5d969b7124f553b88dfae47d1f00472ea8c0dbf4ac4577d39ef02";
@@ -350,7 +1581,8 @@ mod tests {
         assert!(result.is_ok());
     }
 
-    #[wasm_bindgen_test]
+    #[cfg_attr(not(target_arch = "wasm32"), ignore = "needs public relays")]
+    #[wasm_bindgen_test(unsupported = tokio::test)]
     async fn test_send_private_msg() {
         let private_key = SecretKey::from_bech32(
             "nsec1qrypzwmxp8r54ctx2x7mhqzh5exca7xd8ssnlfup0js9l6pwku3qacq4u3",
@@ -365,11 +1597,67 @@ mod tests {
         )
         .unwrap();
         client.connect().await;
-        let result = send_private_msg(&client, signer, receiver, "Hello, world!", None).await;
+        let result = send_private_msg(
+            &client,
+            signer,
+            receiver,
+            "Hello, world!",
+            None,
+            DmProtocol::Nip17,
+        )
+        .await;
         assert!(result.is_ok());
     }
 
-    #[wasm_bindgen_test]
+    #[wasm_bindgen_test(unsupported = tokio::test)]
+    async fn test_private_msg_events() {
+        let sender = Keys::generate();
+        let receiver = Keys::generate();
+        let signer = NostrSigner::Keys(sender.clone());
+
+        let events = private_msg_events(
+            &signer,
+            receiver.public_key(),
+            "hi",
+            None,
+            DmProtocol::Nip17,
+        )
+        .await
+        .unwrap();
+        assert_eq!(events.len(), 2);
+        for ((recipient, wrap), keys) in events.iter().zip([&receiver, &sender]) {
+            assert_eq!(*recipient, keys.public_key());
+            assert_eq!(wrap.kind, Kind::GiftWrap);
+            // Wrapped by a throwaway key
+            assert_ne!(wrap.author(), sender.public_key());
+            let gift = nip59::extract_rumor(keys, wrap).unwrap();
+            assert_eq!(gift.sender, sender.public_key());
+            assert_eq!(gift.rumor.kind, Kind::PrivateDirectMessage);
+            assert_eq!(gift.rumor.content, "hi");
+        }
+
+        let events = private_msg_events(
+            &signer,
+            receiver.public_key(),
+            "hi",
+            None,
+            DmProtocol::Nip04,
+        )
+        .await
+        .unwrap();
+        let (_, dm) = &events[0];
+        assert_eq!(events.len(), 1);
+        assert_eq!(dm.kind, Kind::EncryptedDirectMessage);
+        let receiver_signer = NostrSigner::Keys(receiver);
+        let plaintext = receiver_signer
+            .nip04_decrypt(sender.public_key(), &dm.content)
+            .await
+            .unwrap();
+        assert_eq!(plaintext, "hi");
+    }
+
+    #[cfg_attr(not(target_arch = "wasm32"), ignore = "needs public relays")]
+    #[wasm_bindgen_test(unsupported = tokio::test)]
     async fn test_delete_event() {
         let private_key = SecretKey::from_bech32(
             "nsec1qrypzwmxp8r54ctx2x7mhqzh5exca7xd8ssnlfup0js9l6pwku3qacq4u3",
@@ -383,11 +1671,354 @@ mod tests {
             EventId::from_bech32("note1zlsz37aggmsc2nfzqjdsdw77qwyfqm3erxag5f75nz8tndkvs0uqllhywm")
                 .unwrap();
         client.connect().await;
-        let result = delete_event(&client, signer, vec![event_id]).await;
+        let options = DeleteOptions {
+            reason: Some("posted by mistake".to_string()),
+            ..Default::default()
+        };
+        let result = delete_event(&client, signer, vec![event_id], options).await;
         assert!(result.is_ok());
     }
 
-    #[wasm_bindgen_test]
+    #[wasm_bindgen_test(unsupported = test)]
+    fn test_long_form_builder() {
+        let keys = Keys::generate();
+        let markdown = "# Heading\n\n![cover](https://example.com/cover.png)\n\nFirst\nparagraph.\n\nSecond paragraph.";
+        let event = long_form_builder(
+            "Hello, Nostr!",
+            markdown,
+            vec![Tag::hashtag("nostr"), Tag::identifier("ignored")],
+            "hello-nostr",
+            Timestamp::from(1_700_000_000),
+        )
+        .to_event(&keys)
+        .unwrap();
+
+        let article = LongFormNote::try_from(event).unwrap();
+        assert_eq!(article.identifier, "hello-nostr");
+        assert_eq!(article.title.as_deref(), Some("Hello, Nostr!"));
+        assert_eq!(article.summary.as_deref(), Some("First paragraph."));
+        assert_eq!(
+            article.image.as_deref(),
+            Some("https://example.com/cover.png")
+        );
+        assert_eq!(article.published_at, Some(Timestamp::from(1_700_000_000)));
+        assert_eq!(article.hashtags, vec!["nostr".to_string()]);
+        assert!(new_article_identifier("Hello, Nostr!").starts_with("hello-nostr-"));
+    }
+
+    #[wasm_bindgen_test(unsupported = test)]
+    fn test_deletion_builder() {
+        let keys = Keys::generate();
+        let ids = [EventId::all_zeros(), EventId::from_slice(&[1; 32]).unwrap()];
+        let event = deletion_builder(
+            &ids,
+            vec![Kind::TextNote, Kind::Reaction, Kind::TextNote],
+            Some("posted by mistake"),
+        )
+        .to_event(&keys)
+        .unwrap();
+
+        assert_eq!(event.kind, Kind::EventDeletion);
+        assert_eq!(event.content, "posted by mistake");
+        assert_eq!(event.event_ids().copied().collect::<Vec<_>>(), ids);
+        let kinds: Vec<&str> = event
+            .iter_tags()
+            .filter_map(|tag| match tag.as_vec() {
+                [name, kind] if name == "k" => Some(kind.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(kinds, vec!["1", "7"]);
+    }
+
+    #[wasm_bindgen_test(unsupported = tokio::test)]
+    async fn test_publish_to_relays() {
+        let keys = Keys::generate();
+        let client = Client::new(&keys);
+        let signer = NostrSigner::Keys(keys);
+
+        let result = publish_text_note_to(&client, vec![], &signer, "hi", vec![], None).await;
+        assert!(matches!(result, Err(Error::NoRelays)));
+
+        // Relays outside the pool are refused instead of added
+        let unknown = Url::parse("wss://relay.example.com").unwrap();
+        let result =
+            publish_text_note_to(&client, vec![unknown], &signer, "hi", vec![], None).await;
+        assert!(matches!(result, Err(Error::Client(_))));
+    }
+
+    #[wasm_bindgen_test(unsupported = test)]
+    fn test_profile_badges_builder() {
+        let issuer = Keys::generate();
+        let us = Keys::generate();
+        let definition =
+            EventBuilder::define_badge("bravery", Some("Bravery"), None, None, None, Vec::new())
+                .to_event(&issuer)
+                .unwrap();
+        let award = EventBuilder::award_badge(&definition, [Tag::public_key(us.public_key())])
+            .unwrap()
+            .to_event(&issuer)
+            .unwrap();
+
+        let badges = vec![(definition.clone(), award.clone())];
+        let event = profile_badges_builder(&us.public_key(), &badges)
+            .unwrap()
+            .to_event(&us)
+            .unwrap();
+        assert_eq!(event.kind, Kind::ProfileBadges);
+        assert_eq!(event.identifier(), Some("profile_badges"));
+        let coordinate = format!("30009:{}:bravery", issuer.public_key());
+        let values: Vec<Vec<String>> = event
+            .iter_tags()
+            .skip(1)
+            .map(|tag| tag.as_vec().to_vec())
+            .collect();
+        assert_eq!(values[0], vec!["a".to_string(), coordinate]);
+        assert_eq!(values[1], vec!["e".to_string(), award.id.to_hex()]);
+
+        // Awarded to someone else
+        let stranger = Keys::generate().public_key();
+        assert!(matches!(
+            profile_badges_builder(&stranger, &badges),
+            Err(Error::InvalidBadgeAward(id)) if id == award.id
+        ));
+    }
+
+    #[wasm_bindgen_test(unsupported = test)]
+    fn test_live_event_update_builder() {
+        let keys = Keys::generate();
+        let live_event = LiveEvent {
+            id: "stream-1".to_string(),
+            title: Some("Morning jam".to_string()),
+            summary: None,
+            image: None,
+            hashtags: Vec::new(),
+            streaming: None,
+            recording: None,
+            starts: Some(Timestamp::now()),
+            ends: None,
+            status: Some(LiveEventStatus::Live),
+            current_participants: Some(10),
+            total_participants: None,
+            relays: Vec::new(),
+            host: None,
+            speakers: Vec::new(),
+            participants: Vec::new(),
+        };
+        let current = EventBuilder::live_event(live_event)
+            .to_event(&keys)
+            .unwrap();
+
+        let update = LiveEventUpdate {
+            status: Some(LiveEventStatus::Ended),
+            current_participants: Some(0),
+            ..Default::default()
+        };
+        let updated = live_event_update_builder(&current, update)
+            .unwrap()
+            .to_event(&keys)
+            .unwrap();
+        assert_eq!(updated.kind, Kind::LiveEvent);
+        assert_eq!(updated.identifier(), Some("stream-1"));
+        let values = |kind: TagKind| -> Vec<String> {
+            updated
+                .iter_tags()
+                .filter(|tag| tag.kind() == kind)
+                .filter_map(|tag| tag.content().map(str::to_string))
+                .collect()
+        };
+        assert_eq!(values(TagKind::Status), vec!["ended"]);
+        assert_eq!(values(TagKind::CurrentParticipants), vec!["0"]);
+        assert_eq!(values(TagKind::Title), vec!["Morning jam"]);
+        assert_eq!(values(TagKind::Ends).len(), 1);
+
+        let note = EventBuilder::text_note("not live", [])
+            .to_event(&keys)
+            .unwrap();
+        assert!(matches!(
+            live_event_update_builder(&note, LiveEventUpdate::default()),
+            Err(Error::NotLiveEvent(id)) if id == note.id
+        ));
+    }
+
+    #[wasm_bindgen_test(unsupported = test)]
+    fn test_reaction_builder() {
+        let keys = Keys::generate();
+        let note = EventBuilder::text_note("gm", []).to_event(&keys).unwrap();
+        let url = Url::parse("https://example.com/soapbox.png").unwrap();
+        let emoji = CustomEmoji::new(":soapbox:", url);
+
+        let custom = reaction_builder(&note, emoji.clone().into())
+            .to_event(&keys)
+            .unwrap();
+        assert_eq!(custom.content, ":soapbox:");
+        assert_eq!(crate::nostr::utils::custom_emoji(&custom), Some(emoji));
+
+        let plain = reaction_builder(&note, "🤙".into())
+            .to_event(&keys)
+            .unwrap();
+        assert_eq!(plain.content, "🤙");
+        assert_eq!(crate::nostr::utils::custom_emoji(&plain), None);
+    }
+
+    #[wasm_bindgen_test(unsupported = test)]
+    fn test_profile_patch() {
+        let current = Metadata::new()
+            .name("alice")
+            .about("old bio")
+            .lud16("alice@example.com")
+            .custom_field("pronouns", "she/her")
+            .custom_field("bot", false);
+
+        let patch = ProfilePatch {
+            about: Some("new bio".to_string()),
+            lud16: Some(String::new()),
+            custom: HashMap::from([
+                ("bot".to_string(), serde_json::Value::Null),
+                ("location".to_string(), serde_json::json!("Tokyo")),
+            ]),
+            ..Default::default()
+        };
+        let metadata = patch.apply(current);
+        assert_eq!(metadata.name.as_deref(), Some("alice"));
+        assert_eq!(metadata.about.as_deref(), Some("new bio"));
+        assert_eq!(metadata.lud16, None);
+        assert_eq!(metadata.custom["pronouns"], "she/her");
+        assert_eq!(metadata.custom["location"], "Tokyo");
+        assert!(!metadata.custom.contains_key("bot"));
+    }
+
+    #[wasm_bindgen_test(unsupported = test)]
+    fn test_poll_builders() {
+        let keys = Keys::generate();
+        let ends_at = Timestamp::from(2_000);
+        let event = poll_builder("Tabs or spaces?", &["tabs", "spaces"], false, Some(ends_at))
+            .to_event(&keys)
+            .unwrap();
+        let poll = Poll::try_from(event).unwrap();
+        assert_eq!(poll.question(), "Tabs or spaces?");
+        assert_eq!(
+            poll.options,
+            vec![
+                ("0".to_string(), "tabs".to_string()),
+                ("1".to_string(), "spaces".to_string())
+            ]
+        );
+        assert!(!poll.multiple_choice);
+        assert_eq!(poll.ends_at, Some(ends_at));
+
+        let before = Timestamp::from(1_000);
+        let vote = vote_builder(&poll, &["1"], before)
+            .unwrap()
+            .to_event(&keys)
+            .unwrap();
+        assert_eq!(vote.kind, Poll::RESPONSE_KIND);
+        assert_eq!(vote.event_ids().next(), Some(&poll.inner.id));
+        assert!(vote
+            .iter_tags()
+            .any(|tag| tag.as_vec() == ["response", "1"]));
+
+        for (options, after) in [
+            (&["0", "1"][..], before),
+            (&["2"][..], before),
+            (&[][..], before),
+            (&["0"][..], Timestamp::from(3_000)),
+        ] {
+            assert!(matches!(
+                vote_builder(&poll, options, after),
+                Err(Error::InvalidVote(_))
+            ));
+        }
+    }
+
+    #[wasm_bindgen_test(unsupported = test)]
+    fn test_contact_list_diff() {
+        let keys = Keys::generate();
+        let (alice, bob, carol) = (
+            Keys::generate().public_key(),
+            Keys::generate().public_key(),
+            Keys::generate().public_key(),
+        );
+        let relay = UncheckedUrl::from("wss://relay.example.com");
+        let hashtag = Tag::hashtag("nostr");
+        let latest = EventBuilder::new(
+            Kind::ContactList,
+            r#"{"wss://relay.example.com":{"read":true,"write":true}}"#,
+            [
+                contact_tag(Contact::new(alice, Some(relay.clone()), Some("al"))),
+                hashtag.clone(),
+                contact_tag(Contact::new(bob, None, None::<String>)),
+            ],
+        )
+        .custom_created_at(Timestamp::now() + 60)
+        .to_event(&keys)
+        .unwrap();
+
+        let diff = ContactListDiff::default()
+            .follow(Contact::new(alice, None, None::<String>))
+            .follow(Contact::new(carol, None, Some("carol")))
+            .unfollow(bob);
+        let event = contact_list_builder(Some(&latest), &diff)
+            .unwrap()
+            .to_event(&keys)
+            .unwrap();
+        assert_eq!(event.content, latest.content);
+        assert!(event.created_at > latest.created_at);
+        assert_eq!(
+            event.tags().to_vec(),
+            vec![
+                contact_tag(Contact::new(alice, Some(relay), Some("al"))),
+                hashtag,
+                contact_tag(Contact::new(carol, None, Some("carol"))),
+            ]
+        );
+
+        // Following someone already followed changes nothing
+        let noop = ContactListDiff::default().follow(Contact::new(alice, None, None::<String>));
+        assert!(contact_list_builder(Some(&latest), &noop).is_none());
+        assert!(contact_list_builder(None, &ContactListDiff::default()).is_none());
+    }
+
+    #[wasm_bindgen_test(unsupported = test)]
+    fn test_quote_builder() {
+        let author = Keys::generate();
+        let quoted = EventBuilder::text_note("original", [])
+            .to_event(&author)
+            .unwrap();
+        let relay = Url::parse("wss://relay.example.com").unwrap();
+        let quote = quote_builder(&quoted, "so true", Some(relay.clone()))
+            .to_event(&Keys::generate())
+            .unwrap();
+
+        assert_eq!(quote.kind, Kind::TextNote);
+        let (comment, mention) = quote.content.split_once("\n\n").unwrap();
+        assert_eq!(comment, "so true");
+        let nevent = Nip19Event::from_nostr_uri(mention).unwrap();
+        assert_eq!(nevent.event_id, quoted.id);
+        assert_eq!(nevent.author, Some(author.public_key()));
+        assert_eq!(nevent.relays, vec![relay.to_string()]);
+
+        let q = quote
+            .iter_tags()
+            .find(|tag| {
+                tag.kind() == TagKind::SingleLetter(SingleLetterTag::lowercase(Alphabet::Q))
+            })
+            .unwrap();
+        assert_eq!(
+            q.as_vec()[1..],
+            [
+                quoted.id.to_hex(),
+                relay.to_string(),
+                author.public_key().to_hex()
+            ]
+        );
+        assert_eq!(quote.public_keys().next(), Some(&author.public_key()));
+        // Not a NIP-10 reply to the quoted note
+        assert_eq!(quote.event_ids().next(), None);
+    }
+
+    #[cfg_attr(not(target_arch = "wasm32"), ignore = "needs public relays")]
+    #[wasm_bindgen_test(unsupported = tokio::test)]
     async fn test_set_relay_list() {
         let private_key = SecretKey::from_bech32(
             "nsec1qrypzwmxp8r54ctx2x7mhqzh5exca7xd8ssnlfup0js9l6pwku3qacq4u3",
@@ -412,7 +2043,8 @@ mod tests {
         assert!(result.is_ok());
     }
 
-    #[wasm_bindgen_test]
+    #[cfg_attr(not(target_arch = "wasm32"), ignore = "needs public relays")]
+    #[wasm_bindgen_test(unsupported = tokio::test)]
     async fn test_set_contact_list() {
         let private_key = SecretKey::from_bech32(
             "nsec1qrypzwmxp8r54ctx2x7mhqzh5exca7xd8ssnlfup0js9l6pwku3qacq4u3",
@@ -435,7 +2067,8 @@ mod tests {
         assert!(result.is_ok());
     }
 
-    #[wasm_bindgen_test]
+    #[cfg_attr(not(target_arch = "wasm32"), ignore = "needs public relays")]
+    #[wasm_bindgen_test(unsupported = tokio::test)]
     async fn test_follow() {
         let private_key = SecretKey::from_bech32(
             "nsec1qt5ptz2rx83j5d758p72tqm2kh8w0gvq4l7ca9etk8v3n5zsxw5qw8f4y4",
@@ -455,7 +2088,8 @@ mod tests {
         assert!(result.is_ok());
     }
 
-    #[wasm_bindgen_test]
+    #[cfg_attr(not(target_arch = "wasm32"), ignore = "needs public relays")]
+    #[wasm_bindgen_test(unsupported = tokio::test)]
     async fn test_unfollow() {
         let private_key = SecretKey::from_bech32(
             "nsec1qt5ptz2rx83j5d758p72tqm2kh8w0gvq4l7ca9etk8v3n5zsxw5qw8f4y4",
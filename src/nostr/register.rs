@@ -1,18 +1,49 @@
 use dashmap::mapref::entry::Entry;
 use dashmap::DashMap;
+use nostr_sdk::database::async_trait;
+use nostr_sdk::nips::nip19::{FromBech32, ToBech32};
+use nostr_sdk::nips::nip49::{self, EncryptedSecretKey, KeySecurity};
 use nostr_sdk::{
-    Client, Filter, RelayMessage, RelayPoolNotification, SubscribeAutoCloseOptions, SubscriptionId,
+    Client, Filter, Keys, NostrSigner, PublicKey, RelayMessage, RelayPoolNotification,
+    SubscribeAutoCloseOptions, SubscriptionId, Url,
 };
+use serde::{Deserialize, Serialize};
+#[cfg(native_backend)]
+use std::path::PathBuf;
 use std::sync::Arc;
 use thiserror::Error;
 use tokio::sync::RwLock;
 
+use super::crdt::{CrdtManager, ProcessedStore};
+
 #[derive(Error, Debug)]
 pub enum RegisterError {
     #[error("Subscription not found")]
     SubscriptionNotFound,
     #[error(transparent)]
     Client(#[from] nostr_sdk::client::Error),
+    #[error(transparent)]
+    Keys(#[from] nostr_sdk::key::Error),
+    #[error(transparent)]
+    Encryption(#[from] nip49::Error),
+    #[error(transparent)]
+    Nip19(#[from] nostr_sdk::nips::nip19::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error("Wrong passphrase")]
+    WrongPassphrase,
+    #[error("No stored key for {0}")]
+    KeyNotFound(PublicKey),
+    #[error("Key store error: {0}")]
+    KeyStore(String),
+    #[error(transparent)]
+    Signer(#[from] nostr_sdk::signer::Error),
+    #[error(transparent)]
+    Crdt(#[from] super::crdt::Error),
+    #[error("Account {0} is locked")]
+    AccountLocked(PublicKey),
+    #[error("Account storage error: {0}")]
+    Storage(String),
 }
 
 type Result<T> = std::result::Result<T, RegisterError>;
@@ -116,20 +147,767 @@ impl Register {
     }
 }
 
+/// scrypt cost (log2 of N) of newly sealed keys, the NIP-49 recommendation
+const KEY_LOG_N: u8 = 16;
+
+/// A secret key sealed with a passphrase: scrypt for the key derivation,
+/// XChaCha20-Poly1305 for the encryption (the NIP-49 scheme). This is what
+/// gets persisted, never the raw key.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoredKey {
+    pub public_key: PublicKey,
+    pub encrypted: EncryptedSecretKey,
+}
+
+impl StoredKey {
+    pub fn seal(keys: &Keys, passphrase: &str) -> Result<Self> {
+        Self::seal_with_cost(keys, passphrase, KEY_LOG_N)
+    }
+
+    fn seal_with_cost(keys: &Keys, passphrase: &str, log_n: u8) -> Result<Self> {
+        let encrypted =
+            EncryptedSecretKey::new(keys.secret_key()?, passphrase, log_n, KeySecurity::Unknown)?;
+        Ok(Self {
+            public_key: keys.public_key(),
+            encrypted,
+        })
+    }
+
+    /// Takes an `ncryptsec` from another client. The passphrase is needed
+    /// once to learn whose key it is; the stored copy stays encrypted.
+    pub fn from_ncryptsec(ncryptsec: &str, passphrase: &str) -> Result<Self> {
+        Ok(Self::open_ncryptsec(ncryptsec, passphrase)?.0)
+    }
+
+    /// `from_ncryptsec` together with the keys it decrypted on the way
+    fn open_ncryptsec(ncryptsec: &str, passphrase: &str) -> Result<(Self, Keys)> {
+        let encrypted = EncryptedSecretKey::from_bech32(ncryptsec.trim())?;
+        let keys = decrypt(&encrypted, passphrase)?;
+        let stored = Self {
+            public_key: keys.public_key(),
+            encrypted,
+        };
+        Ok((stored, keys))
+    }
+
+    /// The stored key in NIP-49 form, still sealed with its passphrase
+    pub fn to_ncryptsec(&self) -> Result<String> {
+        Ok(self.encrypted.to_bech32()?)
+    }
+
+    pub fn unlock(&self, passphrase: &str) -> Result<Keys> {
+        let keys = decrypt(&self.encrypted, passphrase)?;
+        // A tampered record must not unlock into someone else's identity
+        if keys.public_key() != self.public_key {
+            return Err(RegisterError::WrongPassphrase);
+        }
+        Ok(keys)
+    }
+}
+
+/// Exports the secret key as a NIP-49 `ncryptsec` any client can import
+pub fn export_ncryptsec(keys: &Keys, passphrase: &str) -> Result<String> {
+    StoredKey::seal(keys, passphrase)?.to_ncryptsec()
+}
+
+/// Imports a NIP-49 `ncryptsec` exported by any client
+pub fn import_ncryptsec(ncryptsec: &str, passphrase: &str) -> Result<Keys> {
+    decrypt(
+        &EncryptedSecretKey::from_bech32(ncryptsec.trim())?,
+        passphrase,
+    )
+}
+
+/// Runs the scrypt key derivation, the slow part of every NIP-49 operation
+fn decrypt(encrypted: &EncryptedSecretKey, passphrase: &str) -> Result<Keys> {
+    let secret_key = encrypted
+        .to_secret_key(passphrase)
+        .map_err(|err| match err {
+            nip49::Error::ChaCha20Poly1305(_) => RegisterError::WrongPassphrase,
+            err => err.into(),
+        })?;
+    Ok(Keys::new(secret_key))
+}
+
+/// Where sealed keys are persisted
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+pub trait KeyStore: Send + Sync {
+    async fn load(&self) -> Result<Vec<StoredKey>>;
+    async fn put(&self, key: &StoredKey) -> Result<()>;
+    async fn remove(&self, public_key: &PublicKey) -> Result<()>;
+}
+
+/// Saves and unlocks passphrase-protected secret keys
+#[derive(Clone)]
+pub struct KeyVault {
+    store: Arc<dyn KeyStore>,
+    log_n: u8,
+}
+
+impl KeyVault {
+    pub fn new(store: Arc<dyn KeyStore>) -> Self {
+        Self {
+            store,
+            log_n: KEY_LOG_N,
+        }
+    }
+
+    /// scrypt cost of newly sealed keys. Lower is faster and weaker.
+    pub fn with_log_n(mut self, log_n: u8) -> Self {
+        self.log_n = log_n;
+        self
+    }
+
+    /// Seals `keys` with `passphrase` and stores them, replacing any earlier
+    /// copy of the same identity
+    pub async fn save(&self, keys: &Keys, passphrase: &str) -> Result<PublicKey> {
+        let stored = StoredKey::seal_with_cost(keys, passphrase, self.log_n)?;
+        self.store.put(&stored).await?;
+        Ok(stored.public_key)
+    }
+
+    /// Identities with a stored key
+    pub async fn list(&self) -> Result<Vec<PublicKey>> {
+        let mut public_keys: Vec<PublicKey> = self
+            .store
+            .load()
+            .await?
+            .into_iter()
+            .map(|stored| stored.public_key)
+            .collect();
+        public_keys.sort();
+        Ok(public_keys)
+    }
+
+    pub async fn unlock(&self, public_key: &PublicKey, passphrase: &str) -> Result<Keys> {
+        self.store
+            .load()
+            .await?
+            .into_iter()
+            .find(|stored| stored.public_key == *public_key)
+            .ok_or(RegisterError::KeyNotFound(*public_key))?
+            .unlock(passphrase)
+    }
+
+    pub async fn remove(&self, public_key: &PublicKey) -> Result<()> {
+        self.store.remove(public_key).await
+    }
+
+    /// Stores an `ncryptsec` from another client as is, keeping its
+    /// passphrase
+    pub async fn import_ncryptsec(&self, ncryptsec: &str, passphrase: &str) -> Result<PublicKey> {
+        Ok(self.import_keys(ncryptsec, passphrase).await?.public_key())
+    }
+
+    /// `import_ncryptsec`, handing back the keys it had to decrypt anyway
+    async fn import_keys(&self, ncryptsec: &str, passphrase: &str) -> Result<Keys> {
+        let (stored, keys) = StoredKey::open_ncryptsec(ncryptsec, passphrase)?;
+        self.store.put(&stored).await?;
+        Ok(keys)
+    }
+
+    /// The stored key of `public_key` as an `ncryptsec`, sealed with the
+    /// passphrase it was saved with
+    pub async fn export_ncryptsec(&self, public_key: &PublicKey) -> Result<String> {
+        self.store
+            .load()
+            .await?
+            .into_iter()
+            .find(|stored| stored.public_key == *public_key)
+            .ok_or(RegisterError::KeyNotFound(*public_key))?
+            .to_ncryptsec()
+    }
+}
+
+/// Prefix of everything persisted for `public_key`, so that accounts never
+/// see each other's data
+pub fn account_namespace(public_key: &PublicKey) -> String {
+    format!("nostr-crdt-{}", public_key.to_hex())
+}
+
+/// Client and CRDT state of the active account
+pub struct Account {
+    pub public_key: PublicKey,
+    pub namespace: String,
+    pub client: Arc<Client>,
+    pub crdt: Arc<CrdtManager>,
+}
+
+/// Several identities on one device, one of them active at a time.
+/// Switching tears down the previous account's client and builds a fresh
+/// client and `CrdtManager` whose storage lives under the new account's
+/// [namespace](account_namespace).
+pub struct AccountManager {
+    vault: KeyVault,
+    relays: Vec<Url>,
+    /// Accounts usable this session: unlocked local keys and remote signers
+    signers: DashMap<PublicKey, NostrSigner>,
+    active: RwLock<Option<Arc<Account>>>,
+    #[cfg(native_backend)]
+    data_dir: Option<PathBuf>,
+}
+
+impl AccountManager {
+    pub fn new(vault: KeyVault, relays: Vec<Url>) -> Self {
+        Self {
+            vault,
+            relays,
+            signers: DashMap::new(),
+            active: RwLock::new(None),
+            #[cfg(native_backend)]
+            data_dir: None,
+        }
+    }
+
+    /// Keep each account's processed CRDT events under `dir/<namespace>`.
+    /// Without it native builds persist nothing; the browser always uses
+    /// IndexedDB databases named after the namespace.
+    #[cfg(native_backend)]
+    pub fn with_data_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.data_dir = Some(dir.into());
+        self
+    }
+
+    /// Stores `keys` sealed with `passphrase`. The account stays unlocked
+    /// for this session.
+    pub async fn add_account(&self, keys: &Keys, passphrase: &str) -> Result<PublicKey> {
+        let public_key = self.vault.save(keys, passphrase).await?;
+        self.signers
+            .insert(public_key, NostrSigner::Keys(keys.clone()));
+        Ok(public_key)
+    }
+
+    /// Adds an identity exported by another client as an `ncryptsec`. The
+    /// account stays unlocked for this session.
+    pub async fn import_account(&self, ncryptsec: &str, passphrase: &str) -> Result<PublicKey> {
+        let keys = self.vault.import_keys(ncryptsec, passphrase).await?;
+        let public_key = keys.public_key();
+        self.signers.insert(public_key, NostrSigner::Keys(keys));
+        Ok(public_key)
+    }
+
+    /// Adds an account backed by a remote signer, for this session only
+    pub async fn add_remote_account(&self, signer: NostrSigner) -> Result<PublicKey> {
+        let public_key = signer.public_key().await?;
+        self.signers.insert(public_key, signer);
+        Ok(public_key)
+    }
+
+    /// Stored and session accounts
+    pub async fn accounts(&self) -> Result<Vec<PublicKey>> {
+        let mut accounts = self.vault.list().await?;
+        accounts.extend(self.signers.iter().map(|entry| *entry.key()));
+        accounts.sort();
+        accounts.dedup();
+        Ok(accounts)
+    }
+
+    pub async fn unlock(&self, public_key: &PublicKey, passphrase: &str) -> Result<()> {
+        let keys = self.vault.unlock(public_key, passphrase).await?;
+        self.signers.insert(*public_key, NostrSigner::Keys(keys));
+        Ok(())
+    }
+
+    /// Makes `public_key` the active account. It must have been added or
+    /// unlocked this session.
+    pub async fn switch_account(&self, public_key: &PublicKey) -> Result<Arc<Account>> {
+        let signer = self
+            .signers
+            .get(public_key)
+            .map(|entry| entry.value().clone())
+            .ok_or(RegisterError::AccountLocked(*public_key))?;
+
+        let mut active = self.active.write().await;
+        if let Some(account) = active.as_ref().filter(|a| a.public_key == *public_key) {
+            return Ok(Arc::clone(account));
+        }
+        let account = Arc::new(self.build_account(*public_key, signer).await?);
+        if let Some(previous) = active.replace(Arc::clone(&account)) {
+            shutdown(&previous).await;
+        }
+        Ok(account)
+    }
+
+    pub async fn active(&self) -> Option<Arc<Account>> {
+        self.active.read().await.clone()
+    }
+
+    pub async fn sign_out(&self) {
+        if let Some(previous) = self.active.write().await.take() {
+            shutdown(&previous).await;
+        }
+    }
+
+    /// Forgets the account and its stored key, signing out if it is active
+    pub async fn remove_account(&self, public_key: &PublicKey) -> Result<()> {
+        self.vault.remove(public_key).await?;
+        self.signers.remove(public_key);
+        let is_active = self
+            .active
+            .read()
+            .await
+            .as_ref()
+            .is_some_and(|account| account.public_key == *public_key);
+        if is_active {
+            self.sign_out().await;
+        }
+        Ok(())
+    }
+
+    async fn build_account(&self, public_key: PublicKey, signer: NostrSigner) -> Result<Account> {
+        let namespace = account_namespace(&public_key);
+        let client = self.account_client(&namespace, signer.clone()).await?;
+        for relay in &self.relays {
+            client.add_relay(relay.clone()).await?;
+        }
+        client.connect().await;
+        let client = Arc::new(client);
+
+        let mut crdt = CrdtManager::new(Arc::clone(&client), signer);
+        if let Some(store) = self.processed_store(&namespace)? {
+            crdt = crdt.with_processed_store(store);
+        }
+        Ok(Account {
+            public_key,
+            namespace,
+            client,
+            crdt: Arc::new(crdt),
+        })
+    }
+
+    #[cfg(not(wasm_backend))]
+    async fn account_client(&self, _namespace: &str, signer: NostrSigner) -> Result<Client> {
+        Ok(Client::new(signer))
+    }
+
+    #[cfg(wasm_backend)]
+    async fn account_client(&self, namespace: &str, signer: NostrSigner) -> Result<Client> {
+        let database = nostr_sdk::WebDatabase::open(format!("{namespace}-events"))
+            .await
+            .map_err(|err| RegisterError::Storage(err.to_string()))?;
+        Ok(nostr_sdk::ClientBuilder::new()
+            .signer(signer)
+            .database(database)
+            .build())
+    }
+
+    #[cfg(native_backend)]
+    fn processed_store(&self, namespace: &str) -> Result<Option<Arc<dyn ProcessedStore>>> {
+        let Some(dir) = &self.data_dir else {
+            return Ok(None);
+        };
+        let store = super::crdt::FileProcessedStore::new(dir.join(namespace))?;
+        Ok(Some(Arc::new(store)))
+    }
+
+    #[cfg(wasm_backend)]
+    fn processed_store(&self, namespace: &str) -> Result<Option<Arc<dyn ProcessedStore>>> {
+        let store = super::crdt::IndexedDbProcessedStore::new(&format!("{namespace}-processed"));
+        Ok(Some(Arc::new(store)))
+    }
+
+    // No backend to persist to, accounts start from relays each session
+    #[cfg(not(any(native_backend, wasm_backend)))]
+    fn processed_store(&self, _namespace: &str) -> Result<Option<Arc<dyn ProcessedStore>>> {
+        Ok(None)
+    }
+}
+
+async fn shutdown(account: &Account) {
+    if let Err(err) = Client::clone(&account.client).shutdown().await {
+        tracing::warn!(
+            "Failed to shut down client of {}: {}",
+            account.public_key,
+            err
+        );
+    }
+}
+
+#[cfg(native_backend)]
+pub use file::FileKeyStore;
+
+#[cfg(native_backend)]
+mod file {
+    use std::fs;
+    use std::io::ErrorKind;
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// One json file per identity under `dir`
+    #[derive(Debug, Clone)]
+    pub struct FileKeyStore {
+        dir: PathBuf,
+    }
+
+    impl FileKeyStore {
+        pub fn new(dir: impl Into<PathBuf>) -> Result<Self> {
+            let dir = dir.into();
+            fs::create_dir_all(&dir).map_err(|err| RegisterError::KeyStore(err.to_string()))?;
+            Ok(Self { dir })
+        }
+
+        fn path(&self, public_key: &PublicKey) -> PathBuf {
+            self.dir.join(format!("{}.json", public_key.to_hex()))
+        }
+    }
+
+    #[async_trait]
+    impl KeyStore for FileKeyStore {
+        async fn load(&self) -> Result<Vec<StoredKey>> {
+            let store_error = |err: std::io::Error| RegisterError::KeyStore(err.to_string());
+            let mut keys = Vec::new();
+            for file in fs::read_dir(&self.dir).map_err(store_error)? {
+                let path = file.map_err(store_error)?.path();
+                if path.extension().is_none_or(|ext| ext != "json") {
+                    continue;
+                }
+                let json = fs::read_to_string(&path).map_err(store_error)?;
+                keys.push(serde_json::from_str(&json)?);
+            }
+            Ok(keys)
+        }
+
+        async fn put(&self, key: &StoredKey) -> Result<()> {
+            let json = serde_json::to_string(key)?;
+            fs::write(self.path(&key.public_key), json)
+                .map_err(|err| RegisterError::KeyStore(err.to_string()))
+        }
+
+        async fn remove(&self, public_key: &PublicKey) -> Result<()> {
+            match fs::remove_file(self.path(public_key)) {
+                Err(err) if err.kind() != ErrorKind::NotFound => {
+                    Err(RegisterError::KeyStore(err.to_string()))
+                }
+                _ => Ok(()),
+            }
+        }
+    }
+}
+
+#[cfg(wasm_backend)]
+pub use indexed_db::IndexedDbKeyStore;
+
+#[cfg(wasm_backend)]
+mod indexed_db {
+    use indexed_db_futures::prelude::*;
+    use wasm_bindgen::JsValue;
+
+    use super::*;
+
+    const STORE_NAME: &str = "keys";
+
+    /// IndexedDB-backed store for the browser, keyed by public key. The
+    /// database is opened per call, so the store holds no JS handles.
+    #[derive(Debug, Clone)]
+    pub struct IndexedDbKeyStore {
+        db_name: String,
+    }
+
+    impl IndexedDbKeyStore {
+        pub fn new(db_name: &str) -> Self {
+            Self {
+                db_name: db_name.to_string(),
+            }
+        }
+
+        async fn open(&self) -> Result<IdbDatabase> {
+            let mut request = IdbDatabase::open_u32(&self.db_name, 1).map_err(dom_error)?;
+            request.set_on_upgrade_needed(Some(
+                |evt: &IdbVersionChangeEvent| -> std::result::Result<(), JsValue> {
+                    if !evt.db().object_store_names().any(|name| name == STORE_NAME) {
+                        evt.db().create_object_store(STORE_NAME)?;
+                    }
+                    Ok(())
+                },
+            ));
+            request.into_future().await.map_err(dom_error)
+        }
+    }
+
+    fn dom_error(err: web_sys::DomException) -> RegisterError {
+        RegisterError::KeyStore(err.message())
+    }
+
+    #[async_trait(?Send)]
+    impl KeyStore for IndexedDbKeyStore {
+        async fn load(&self) -> Result<Vec<StoredKey>> {
+            let db = self.open().await?;
+            let tx = db
+                .transaction_on_one_with_mode(STORE_NAME, IdbTransactionMode::Readonly)
+                .map_err(dom_error)?;
+            let store = tx.object_store(STORE_NAME).map_err(dom_error)?;
+            let values = store
+                .get_all()
+                .map_err(dom_error)?
+                .await
+                .map_err(dom_error)?;
+            values
+                .iter()
+                .filter_map(|value| value.as_string())
+                .map(|json| Ok(serde_json::from_str(&json)?))
+                .collect()
+        }
+
+        async fn put(&self, key: &StoredKey) -> Result<()> {
+            let json = serde_json::to_string(key)?;
+            let db = self.open().await?;
+            let tx = db
+                .transaction_on_one_with_mode(STORE_NAME, IdbTransactionMode::Readwrite)
+                .map_err(dom_error)?;
+            let store = tx.object_store(STORE_NAME).map_err(dom_error)?;
+            store
+                .put_key_val_owned(key.public_key.to_hex(), &JsValue::from_str(&json))
+                .map_err(dom_error)?;
+            tx.await.into_result().map_err(dom_error)
+        }
+
+        async fn remove(&self, public_key: &PublicKey) -> Result<()> {
+            let db = self.open().await?;
+            let tx = db
+                .transaction_on_one_with_mode(STORE_NAME, IdbTransactionMode::Readwrite)
+                .map_err(dom_error)?;
+            let store = tx.object_store(STORE_NAME).map_err(dom_error)?;
+            store.delete_owned(public_key.to_hex()).map_err(dom_error)?;
+            tx.await.into_result().map_err(dom_error)
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::testhelper::{sleep, test_hander::create_console_log_handler};
+    use crate::nostr::runtime::spawn;
+    use crate::testhelper::{console_log, sleep, test_hander::create_console_log_handler};
     use nostr_sdk::FromBech32;
     use nostr_sdk::{EventId, Filter, PublicKey, SubscriptionId};
-    use std::rc::Rc;
     use std::sync::Mutex;
-    use wasm_bindgen_futures::spawn_local;
     use wasm_bindgen_test::*;
 
     wasm_bindgen_test_configure!(run_in_browser);
 
-    #[wasm_bindgen_test(async)]
+    #[wasm_bindgen_test(unsupported = test)]
+    fn test_stored_key() {
+        let keys = Keys::generate();
+        // Cheap scrypt so the test stays fast
+        let stored = StoredKey::seal_with_cost(&keys, "correct horse", 4).unwrap();
+        let json = serde_json::to_string(&stored).unwrap();
+        assert!(json.contains("ncryptsec1"));
+        assert!(!json.contains(&keys.secret_key().unwrap().to_secret_hex()));
+
+        let stored: StoredKey = serde_json::from_str(&json).unwrap();
+        let unlocked = stored.unlock("correct horse").unwrap();
+        assert_eq!(unlocked.public_key(), keys.public_key());
+        assert_eq!(unlocked.secret_key().unwrap(), keys.secret_key().unwrap());
+        assert!(matches!(
+            stored.unlock("wrong"),
+            Err(RegisterError::WrongPassphrase)
+        ));
+
+        let tampered = StoredKey {
+            public_key: Keys::generate().public_key(),
+            ..stored
+        };
+        assert!(matches!(
+            tampered.unlock("correct horse"),
+            Err(RegisterError::WrongPassphrase)
+        ));
+    }
+
+    #[derive(Default)]
+    struct MemoryKeyStore(Mutex<std::collections::HashMap<PublicKey, StoredKey>>);
+
+    #[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+    #[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+    impl KeyStore for MemoryKeyStore {
+        async fn load(&self) -> Result<Vec<StoredKey>> {
+            Ok(self.0.lock().unwrap().values().cloned().collect())
+        }
+        async fn put(&self, key: &StoredKey) -> Result<()> {
+            self.0.lock().unwrap().insert(key.public_key, key.clone());
+            Ok(())
+        }
+        async fn remove(&self, public_key: &PublicKey) -> Result<()> {
+            self.0.lock().unwrap().remove(public_key);
+            Ok(())
+        }
+    }
+
+    #[wasm_bindgen_test(unsupported = tokio::test)]
+    async fn test_key_vault() {
+        let vault = KeyVault::new(Arc::new(MemoryKeyStore::default())).with_log_n(4);
+        let (alice, bob) = (Keys::generate(), Keys::generate());
+        vault.save(&alice, "alice pass").await.unwrap();
+        vault.save(&bob, "bob pass").await.unwrap();
+        let mut expected = vec![alice.public_key(), bob.public_key()];
+        expected.sort();
+        assert_eq!(vault.list().await.unwrap(), expected);
+
+        let unlocked = vault
+            .unlock(&alice.public_key(), "alice pass")
+            .await
+            .unwrap();
+        assert_eq!(unlocked.secret_key().unwrap(), alice.secret_key().unwrap());
+        assert!(matches!(
+            vault.unlock(&alice.public_key(), "bob pass").await,
+            Err(RegisterError::WrongPassphrase)
+        ));
+
+        vault.remove(&bob.public_key()).await.unwrap();
+        assert_eq!(vault.list().await.unwrap(), vec![alice.public_key()]);
+        assert!(matches!(
+            vault.unlock(&bob.public_key(), "bob pass").await,
+            Err(RegisterError::KeyNotFound(_))
+        ));
+    }
+
+    #[wasm_bindgen_test(unsupported = tokio::test)]
+    async fn test_account_manager() {
+        let store = Arc::new(MemoryKeyStore::default());
+        let vault = || KeyVault::new(store.clone()).with_log_n(4);
+        let (alice, bob) = (Keys::generate(), Keys::generate());
+        let manager = AccountManager::new(vault(), Vec::new());
+        manager.add_account(&alice, "alice pass").await.unwrap();
+        manager.add_account(&bob, "bob pass").await.unwrap();
+
+        // A new session has to unlock before switching
+        let manager = AccountManager::new(vault(), Vec::new());
+        let mut expected = vec![alice.public_key(), bob.public_key()];
+        expected.sort();
+        assert_eq!(manager.accounts().await.unwrap(), expected);
+        assert!(matches!(
+            manager.switch_account(&alice.public_key()).await,
+            Err(RegisterError::AccountLocked(_))
+        ));
+        assert!(matches!(
+            manager.unlock(&alice.public_key(), "bob pass").await,
+            Err(RegisterError::WrongPassphrase)
+        ));
+        manager
+            .unlock(&alice.public_key(), "alice pass")
+            .await
+            .unwrap();
+        manager.unlock(&bob.public_key(), "bob pass").await.unwrap();
+
+        let account = manager.switch_account(&alice.public_key()).await.unwrap();
+        assert_eq!(account.public_key, alice.public_key());
+        let signer = account.client.signer().await.unwrap();
+        assert_eq!(signer.public_key().await.unwrap(), alice.public_key());
+        let again = manager.switch_account(&alice.public_key()).await.unwrap();
+        assert!(Arc::ptr_eq(&account, &again));
+
+        let other = manager.switch_account(&bob.public_key()).await.unwrap();
+        assert_ne!(other.namespace, account.namespace);
+        assert_eq!(manager.active().await.unwrap().public_key, bob.public_key());
+
+        manager.remove_account(&bob.public_key()).await.unwrap();
+        assert!(manager.active().await.is_none());
+        assert_eq!(manager.accounts().await.unwrap(), vec![alice.public_key()]);
+    }
+
+    #[cfg(native_backend)]
+    #[tokio::test]
+    async fn test_accounts_persist_in_files() {
+        let dir =
+            std::env::temp_dir().join(format!("nostr-accounts-{}", Keys::generate().public_key()));
+        let session = || {
+            let store = Arc::new(FileKeyStore::new(dir.join("keys")).unwrap());
+            AccountManager::new(KeyVault::new(store).with_log_n(4), Vec::new())
+                .with_data_dir(dir.join("data"))
+        };
+        let (alice, bob) = (Keys::generate(), Keys::generate());
+        let event = |keys: &Keys| {
+            let op = crate::nostr::crdt::CrdtOperation::GCounter {
+                key: "visitors".to_string(),
+                increment: 1,
+            };
+            nostr_sdk::EventBuilder::new(
+                nostr_sdk::Kind::TextNote,
+                serde_json::to_string(&op).unwrap(),
+                [nostr_sdk::Tag::hashtag("nostr-crdt")],
+            )
+            .to_event(keys)
+            .unwrap()
+        };
+
+        let seen = event(&alice);
+
+        let manager = session();
+        manager.add_account(&alice, "alice pass").await.unwrap();
+        manager.add_account(&bob, "bob pass").await.unwrap();
+        let account = manager.switch_account(&alice.public_key()).await.unwrap();
+        account.crdt.process_event(&seen).await.unwrap();
+        assert_eq!(account.crdt.metrics().ops_applied, 1);
+
+        // Bob's account shares neither state nor processed events with Alice's
+        let other = manager.switch_account(&bob.public_key()).await.unwrap();
+        assert_eq!(other.crdt.get_counter_value("visitors"), None);
+        other.crdt.process_event(&seen).await.unwrap();
+        assert_eq!(other.crdt.metrics().ops_applied, 1);
+        drop((manager, account, other));
+
+        // A new session finds the sealed keys and Alice's processed events
+        let manager = session();
+        let mut expected = vec![alice.public_key(), bob.public_key()];
+        expected.sort();
+        assert_eq!(manager.accounts().await.unwrap(), expected);
+        manager
+            .unlock(&alice.public_key(), "alice pass")
+            .await
+            .unwrap();
+        let account = manager.switch_account(&alice.public_key()).await.unwrap();
+        account.crdt.process_event(&seen).await.unwrap();
+        account.crdt.process_event(&event(&bob)).await.unwrap();
+        assert_eq!(account.crdt.metrics().ops_applied, 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[wasm_bindgen_test(unsupported = tokio::test)]
+    async fn test_ncryptsec() {
+        // Test vector from NIP-49, decrypted once as its scrypt cost is high
+        let ncryptsec = "ncryptsec1qgg9947rlpvqu76pj5ecreduf9jxhselq2nae2kghhvd5g7dgjtcxfqtd67p9m0w57lspw8gsq6yphnm8623nsl8xn9j4jdzz84zm3frztj3z7s35vpzmqf6ksu8r89qk5z2zxfmu5gv8th8wclt0h4p";
+        let keys = import_ncryptsec(ncryptsec, "nostr").unwrap();
+        assert_eq!(
+            keys.secret_key().unwrap().to_secret_hex(),
+            "3501454135014541350145413501453fefb02227e449e57cf4d3a3ce05378683"
+        );
+        assert!(matches!(
+            import_ncryptsec("nsec1garbage", "nostr"),
+            Err(RegisterError::Nip19(_))
+        ));
+
+        let exported = StoredKey::seal_with_cost(&keys, "other", 4)
+            .unwrap()
+            .to_ncryptsec()
+            .unwrap();
+        assert!(exported.starts_with("ncryptsec1"));
+        assert_eq!(
+            import_ncryptsec(&exported, "other").unwrap().public_key(),
+            keys.public_key()
+        );
+        assert!(matches!(
+            import_ncryptsec(&exported, "wrong"),
+            Err(RegisterError::WrongPassphrase)
+        ));
+
+        let vault = KeyVault::new(Arc::new(MemoryKeyStore::default()));
+        let public_key = vault.import_ncryptsec(&exported, "other").await.unwrap();
+        assert_eq!(public_key, keys.public_key());
+        assert_eq!(vault.export_ncryptsec(&public_key).await.unwrap(), exported);
+        assert_eq!(
+            vault
+                .unlock(&public_key, "other")
+                .await
+                .unwrap()
+                .public_key(),
+            keys.public_key()
+        );
+    }
+
+    #[cfg_attr(not(target_arch = "wasm32"), ignore = "needs public relays")]
+    #[wasm_bindgen_test(unsupported = tokio::test)]
     async fn test_sub_for_two_clients() {
         let _timeout = Some(std::time::Duration::from_secs(5));
         let event_id1 =
@@ -165,13 +943,13 @@ mod tests {
 
         // Handle notifications for test1
         let r = register.clone();
-        spawn_local(async move {
+        spawn(async move {
             r.handle_notifications(&client1).await.unwrap();
         });
 
         let r = register.clone();
         // Stop handling notifications for test1 after some time
-        spawn_local(async move {
+        spawn(async move {
             sleep(2000).await.unwrap();
             r.set_stop_flag(&SubscriptionId::new("test1"), true).await;
         });
@@ -191,19 +969,20 @@ mod tests {
 
         // Handle notifications for test2
         let r = register.clone();
-        spawn_local(async move {
+        spawn(async move {
             r.handle_notifications(&client2).await.unwrap();
         });
 
         sleep(5000).await.unwrap();
     }
 
-    #[wasm_bindgen_test(async)]
+    #[cfg_attr(not(target_arch = "wasm32"), ignore = "needs public relays")]
+    #[wasm_bindgen_test(unsupported = tokio::test)]
     async fn test_seen_on_relays() {
         let event_id =
             EventId::from_hex("770e3b604de378c67570ce3c521e2fd51c1a59aa85c22ef9aeab7b5f5e2f5e1b")
                 .unwrap();
-        let client = Rc::new(Client::default());
+        let client = Arc::new(Client::default());
         client.add_relay("wss://nos.lol").await.unwrap();
         client.add_relay("wss://relay.damus.io").await.unwrap();
         client.add_relay("wss://nostr.oxtr.dev").await.unwrap();
@@ -224,8 +1003,8 @@ mod tests {
             .await
             .unwrap();
 
-        let cc = Rc::clone(&client);
-        spawn_local(async move {
+        let cc = Arc::clone(&client);
+        spawn(async move {
             register.handle_notifications(&cc).await.unwrap();
         });
 
@@ -242,7 +1021,8 @@ mod tests {
         assert!(!relays.is_empty());
     }
 
-    #[wasm_bindgen_test(async)]
+    #[cfg_attr(not(target_arch = "wasm32"), ignore = "needs public relays")]
+    #[wasm_bindgen_test(unsupported = tokio::test)]
     async fn test_update_subscription() {
         let brian_search = Filter::new().author(
             PublicKey::from_bech32(
@@ -250,7 +1030,7 @@ mod tests {
             )
             .unwrap(),
         );
-        let filter1 = Filter::new()
+        let _filter1 = Filter::new()
             .author(
                 PublicKey::from_bech32(
                     "npub1awsnqr5338h497yam5m9hrgh9535yadj9zxglwk55xpsdtsn2c4syjruew",
@@ -296,7 +1076,8 @@ mod tests {
         // register.handle_notifications(&client).await.unwrap();
     }
 
-    #[wasm_bindgen_test(async)]
+    #[cfg_attr(not(target_arch = "wasm32"), ignore = "needs public relays")]
+    #[wasm_bindgen_test(unsupported = tokio::test)]
     async fn test_handler_as_closure() {
         let brian_search = Filter::new().author(
             PublicKey::from_bech32(
@@ -321,7 +1102,7 @@ mod tests {
                 vec![brian_search],
                 Arc::new({
                     let counter = Arc::clone(&counter);
-                    move |notification| {
+                    move |_notification| {
                         let counter = Arc::clone(&counter);
                         Box::pin(async move {
                             // console_log!("Received notification: {:?}", notification);
@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock, RwLock};
+
+use nostr_sdk::nips::nip11::RelayInformationDocument;
+use nostr_sdk::{Client, Event, JsonUtil, Relay, Url};
+
+/// NIP numbers the fetch and publish helpers route on
+const NIP_AUTH: u16 = 42;
+const NIP_COUNT: u16 = 45;
+const NIP_SEARCH: u16 = 50;
+const NIP_NEGENTROPY: u16 = 77;
+
+/// What a relay declares about itself in its NIP-11 information document
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RelayCapabilities {
+    pub supported_nips: Vec<u16>,
+    /// Largest websocket message the relay accepts, in bytes
+    pub max_message_length: Option<usize>,
+    /// Largest event `content` the relay accepts, in characters
+    pub max_content_length: Option<usize>,
+    pub auth_required: bool,
+    pub payment_required: bool,
+}
+
+impl RelayCapabilities {
+    pub fn from_document(document: &RelayInformationDocument) -> Self {
+        let limitation = document.limitation.as_ref();
+        let limit = |value: Option<i32>| value.and_then(|value| usize::try_from(value).ok());
+        Self {
+            supported_nips: document.supported_nips.clone().unwrap_or_default(),
+            max_message_length: limit(limitation.and_then(|l| l.max_message_length)),
+            max_content_length: limit(limitation.and_then(|l| l.max_content_length)),
+            auth_required: limitation.and_then(|l| l.auth_required).unwrap_or(false),
+            payment_required: limitation.and_then(|l| l.payment_required).unwrap_or(false),
+        }
+    }
+
+    pub fn supports_nip(&self, nip: u16) -> bool {
+        self.supported_nips.contains(&nip)
+    }
+
+    /// NIP-50 full-text search
+    pub fn supports_search(&self) -> bool {
+        self.supports_nip(NIP_SEARCH)
+    }
+
+    /// NIP-45 `COUNT` requests
+    pub fn supports_count(&self) -> bool {
+        self.supports_nip(NIP_COUNT)
+    }
+
+    /// Negentropy set reconciliation (NIP-77)
+    pub fn supports_negentropy(&self) -> bool {
+        self.supports_nip(NIP_NEGENTROPY)
+    }
+
+    /// NIP-42 authentication, either advertised or required
+    pub fn supports_auth(&self) -> bool {
+        self.auth_required || self.supports_nip(NIP_AUTH)
+    }
+
+    /// Whether `event` fits the declared size limits. Relays without limits
+    /// accept everything.
+    pub fn accepts_event(&self, event: &Event) -> bool {
+        let content_fits = self
+            .max_content_length
+            .is_none_or(|max| event.content.chars().count() <= max);
+        // The relay receives `["EVENT", <event>]`
+        let message_fits = self
+            .max_message_length
+            .is_none_or(|max| event.as_json().len() + 12 <= max);
+        content_fits && message_fits
+    }
+}
+
+/// Capabilities per relay url. Clones share the same entries.
+///
+/// Entries come from the document the relay fetched when it connected; a
+/// relay whose document is not known yet is not cached, so it is looked up
+/// again next time.
+#[derive(Debug, Clone, Default)]
+pub struct RelayCapabilitiesCache {
+    entries: Arc<RwLock<HashMap<Url, RelayCapabilities>>>,
+}
+
+impl RelayCapabilitiesCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Process-wide cache used by the fetch and publish helpers
+    pub fn global() -> &'static RelayCapabilitiesCache {
+        static GLOBAL: OnceLock<RelayCapabilitiesCache> = OnceLock::new();
+        GLOBAL.get_or_init(RelayCapabilitiesCache::new)
+    }
+
+    pub async fn get(&self, url: &Url, relay: &Relay) -> RelayCapabilities {
+        if let Some(capabilities) = self.entries.read().unwrap().get(url) {
+            return capabilities.clone();
+        }
+        let document = relay.document().await;
+        let capabilities = RelayCapabilities::from_document(&document);
+        if document != RelayInformationDocument::default() {
+            self.insert(url.clone(), capabilities.clone());
+        }
+        capabilities
+    }
+
+    pub fn insert(&self, url: Url, capabilities: RelayCapabilities) {
+        self.entries.write().unwrap().insert(url, capabilities);
+    }
+
+    /// Forget a relay, e.g. after it changed its policy
+    pub fn invalidate(&self, url: &Url) {
+        self.entries.write().unwrap().remove(url);
+    }
+
+    /// Relays of `client` whose capabilities satisfy `predicate`
+    pub async fn relays_where<F>(&self, client: &Client, predicate: F) -> HashMap<Url, Relay>
+    where
+        F: Fn(&RelayCapabilities) -> bool,
+    {
+        let mut relays = client.relays().await;
+        let mut rejected = Vec::new();
+        for (url, relay) in relays.iter() {
+            if !predicate(&self.get(url, relay).await) {
+                rejected.push(url.clone());
+            }
+        }
+        for url in rejected {
+            relays.remove(&url);
+        }
+        relays
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr_sdk::nips::nip11::Limitation;
+    use nostr_sdk::{EventBuilder, Keys};
+
+    #[test]
+    fn test_capabilities_from_document() {
+        let document = RelayInformationDocument {
+            supported_nips: Some(vec![1, 11, 42, 50]),
+            limitation: Some(Limitation {
+                max_content_length: Some(10),
+                auth_required: Some(true),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        let capabilities = RelayCapabilities::from_document(&document);
+        assert!(capabilities.supports_search());
+        assert!(capabilities.supports_auth());
+        assert!(!capabilities.supports_count());
+        assert!(!capabilities.supports_negentropy());
+
+        let keys = Keys::generate();
+        let short = EventBuilder::text_note("hello", [])
+            .to_event(&keys)
+            .unwrap();
+        let long = EventBuilder::text_note("hello, world", [])
+            .to_event(&keys)
+            .unwrap();
+        assert!(capabilities.accepts_event(&short));
+        assert!(!capabilities.accepts_event(&long));
+        assert!(RelayCapabilities::default().accepts_event(&long));
+    }
+}
@@ -0,0 +1,121 @@
+use std::time::Duration;
+
+use nostr_sdk::nips::nip46::NostrConnectURI;
+use nostr_sdk::signer::Nip46Signer;
+use nostr_sdk::{Keys, NostrSigner, PublicKey, Url};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Uri(#[from] nostr_sdk::nips::nip46::Error),
+    #[error(transparent)]
+    Signer(#[from] nostr_sdk::signer::nip46::Error),
+    #[error("Expected a bunker:// URI")]
+    NotBunkerUri,
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// Connects to a NIP-46 remote signer from the `bunker://` URI it handed
+/// out. `app_keys` identify this app to the bunker: keep them (e.g. in a
+/// [`KeyVault`](super::register::KeyVault)) to reconnect later without
+/// pairing again.
+///
+/// The returned signer works everywhere a local one does: in
+/// `Client::new`, the publish and fetch helpers and `CrdtManager::new`.
+pub async fn connect_bunker(uri: &str, app_keys: Keys, timeout: Duration) -> Result<NostrSigner> {
+    let uri = NostrConnectURI::parse(uri)?;
+    if !uri.is_bunker() {
+        return Err(Error::NotBunkerUri);
+    }
+    let signer = Nip46Signer::new(uri, app_keys, timeout, None).await?;
+    Ok(NostrSigner::nip46(signer))
+}
+
+/// Pairing started by this app: show [`uri`](Pairing::uri) as a link or QR
+/// code, then [`wait`](Pairing::wait) for the user to approve it in their
+/// signer.
+#[derive(Debug, Clone)]
+pub struct Pairing {
+    app_keys: Keys,
+    uri: NostrConnectURI,
+}
+
+impl Pairing {
+    pub fn new(app_keys: Keys, relays: Vec<Url>, app_name: &str) -> Self {
+        let uri = NostrConnectURI::client(app_keys.public_key(), relays, app_name);
+        Self { app_keys, uri }
+    }
+
+    /// The `nostrconnect://` URI for the signer
+    pub fn uri(&self) -> String {
+        self.uri.to_string()
+    }
+
+    pub fn app_keys(&self) -> &Keys {
+        &self.app_keys
+    }
+
+    /// Waits up to `timeout` for the signer to connect
+    pub async fn wait(self, timeout: Duration) -> Result<NostrSigner> {
+        let signer = Nip46Signer::new(self.uri, self.app_keys, timeout, None).await?;
+        Ok(NostrSigner::nip46(signer))
+    }
+}
+
+/// `bunker://` URI to reconnect to the same remote signer, `None` for a
+/// local signer
+pub async fn bunker_uri(signer: &NostrSigner) -> Option<String> {
+    match signer {
+        NostrSigner::NIP46(signer) => Some(signer.nostr_connect_uri().await.to_string()),
+        _ => None,
+    }
+}
+
+/// The user's key behind a remote signer, not the app keys it talks with
+pub fn remote_public_key(signer: &NostrSigner) -> Option<PublicKey> {
+    match signer {
+        NostrSigner::NIP46(signer) => Some(signer.signer_public_key()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pairing_uri() {
+        let app_keys = Keys::generate();
+        let relay = Url::parse("wss://relay.example.com").unwrap();
+        let pairing = Pairing::new(app_keys.clone(), vec![relay.clone()], "nostr-crdt");
+
+        let uri = pairing.uri();
+        assert!(uri.starts_with("nostrconnect://"));
+        match NostrConnectURI::parse(&uri).unwrap() {
+            NostrConnectURI::Client {
+                public_key, relays, ..
+            } => {
+                assert_eq!(public_key, app_keys.public_key());
+                assert_eq!(relays, vec![relay]);
+            }
+            other => panic!("unexpected uri {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_connect_bunker_rejects_client_uri() {
+        let app_keys = Keys::generate();
+        let relay = Url::parse("wss://relay.example.com").unwrap();
+        let uri = Pairing::new(app_keys.clone(), vec![relay], "nostr-crdt").uri();
+        assert!(matches!(
+            connect_bunker(&uri, app_keys, Duration::from_secs(1)).await,
+            Err(Error::NotBunkerUri)
+        ));
+
+        let local = NostrSigner::Keys(Keys::generate());
+        assert_eq!(bunker_uri(&local).await, None);
+        assert_eq!(remote_public_key(&local), None);
+    }
+}
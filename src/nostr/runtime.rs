@@ -0,0 +1,77 @@
+// Timers and background tasks on the executor of the target: Tokio on
+// native targets, the browser event loop on wasm32
+use std::future::Future;
+use std::time::Duration;
+
+pub(crate) async fn sleep(duration: Duration) {
+    #[cfg(target_arch = "wasm32")]
+    gloo_timers::future::sleep(duration).await;
+    #[cfg(not(target_arch = "wasm32"))]
+    tokio::time::sleep(duration).await;
+}
+
+// Run `future` in the background. Native tasks may move between threads,
+// so they must be `Send`.
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn spawn<F>(future: F)
+where
+    F: Future<Output = ()> + 'static,
+{
+    wasm_bindgen_futures::spawn_local(future);
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn spawn<F>(future: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    tokio::spawn(future);
+}
+
+// Run `future` in the background where there is an executor to run it on,
+// otherwise to completion in place, e.g. when called from synchronous code
+// outside a Tokio runtime
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn detach<F>(future: F)
+where
+    F: Future<Output = ()> + 'static,
+{
+    spawn(future);
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn detach<F>(future: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    match tokio::runtime::Handle::try_current() {
+        Ok(handle) => drop(handle.spawn(future)),
+        Err(_) => futures::executor::block_on(future),
+    }
+}
+
+// Run `future` as a task of its own where the executor allows, so CPU-bound
+// work spreads over worker threads, and wait for its output. Outside a
+// Tokio runtime, and in the browser, it runs in place.
+#[cfg(target_arch = "wasm32")]
+pub(crate) async fn run<F>(future: F) -> F::Output
+where
+    F: Future + 'static,
+{
+    future.await
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) async fn run<F>(future: F) -> F::Output
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    if tokio::runtime::Handle::try_current().is_err() {
+        return future.await;
+    }
+    match tokio::spawn(future).await {
+        Ok(output) => output,
+        Err(err) => std::panic::resume_unwind(err.into_panic()),
+    }
+}
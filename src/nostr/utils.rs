@@ -1,8 +1,17 @@
-use std::hash::{DefaultHasher, Hash, Hasher};
+use std::collections::BTreeMap;
 
+use chrono::{DateTime, NaiveDate};
 use indextree::{Arena, NodeId};
-use nostr_sdk::{Event, EventId, FromBech32, PublicKey};
+use nostr_sdk::base64::engine::{general_purpose, Engine};
+use nostr_sdk::hashes::sha256::Hash as Sha256Hash;
+use nostr_sdk::hashes::Hash;
+use nostr_sdk::nips::nip01::Coordinate;
+use nostr_sdk::nips::nip19::Nip19;
+use nostr_sdk::{
+    Event, EventId, FromBech32, Kind, PublicKey, SecretKey, TagStandard, Timestamp, Url,
+};
 use serde::Serialize;
+use serde_json::Value;
 
 /// Utility function to get all children of a specified node in an Arena.
 ///
@@ -46,33 +55,146 @@ pub fn get_ancestors<T>(arena: &Arena<T>, node_id: NodeId) -> Vec<&T> {
     ancestors
 }
 
+/// Bumped whenever the normalization below changes, so cache keys from an
+/// older version never match
+pub const FILTER_HASH_VERSION: u32 = 1;
+
+/// Hash of a filter (or list of filters) that ignores the order of authors,
+/// ids, kinds, tag values and filters, so equivalent filters hash equally.
+/// Stable across sessions and builds.
 pub fn hash_filter<T: Serialize>(filter: &T) -> u64 {
-    let serialized = serde_json::to_string(filter).unwrap();
-    let mut hasher = DefaultHasher::new();
-    serialized.hash(&mut hasher);
-    hasher.finish()
+    let digest = filter_digest(filter);
+    let bytes = digest.as_byte_array();
+    u64::from_be_bytes(bytes[..8].try_into().unwrap())
+}
+
+/// Versioned hex digest of the normalized filter, for use as a persistent
+/// cache key
+pub fn filter_cache_key<T: Serialize>(filter: &T) -> String {
+    format!("v{FILTER_HASH_VERSION}:{}", filter_digest(filter))
+}
+
+fn filter_digest<T: Serialize>(filter: &T) -> Sha256Hash {
+    let value = serde_json::to_value(filter).expect("filter serializes to json");
+    // Object keys come out sorted, as serde_json's map is ordered
+    let normalized = normalize_filter_json(value).to_string();
+    Sha256Hash::hash(normalized.as_bytes())
+}
+
+/// Every array in a filter is a set, so sort and dedup them all
+fn normalize_filter_json(value: Value) -> Value {
+    match value {
+        Value::Array(values) => {
+            let mut values: Vec<Value> = values.into_iter().map(normalize_filter_json).collect();
+            values.sort_by_cached_key(Value::to_string);
+            values.dedup();
+            Value::Array(values)
+        }
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .map(|(key, value)| (key, normalize_filter_json(value)))
+                .collect(),
+        ),
+        value => value,
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum AddressType {
+    /// note
     Note,
+    /// npub
     Mention,
+    /// nprofile
+    Profile,
+    /// nevent
+    Event,
+    /// naddr
+    Address,
+    /// nrelay
+    Relay,
     Nostr, // unknown address type
 }
 
+/// What a NIP-19 entity or `nostr:` URI points to, for routing. Secret keys
+/// are not addresses and come back as [`AddressType::Nostr`].
 pub fn is_note_address(address: &str) -> AddressType {
-    let is_start_nostr = address.starts_with("nostr:");
-    if is_start_nostr {
-        let id = address.strip_prefix("nostr:").unwrap();
-        let is_note = id.starts_with("note") && EventId::from_bech32(id).is_ok();
-        let is_mention = id.starts_with("npub") && PublicKey::from_bech32(id).is_ok();
-        if is_note {
-            return AddressType::Note;
-        } else if is_mention {
-            return AddressType::Mention;
-        }
+    match decode_nostr_entity(address) {
+        Some(NostrEntity::Note(_)) => AddressType::Note,
+        Some(NostrEntity::Pubkey(_)) => AddressType::Mention,
+        Some(NostrEntity::Profile { .. }) => AddressType::Profile,
+        Some(NostrEntity::Event { .. }) => AddressType::Event,
+        Some(NostrEntity::Address { .. }) => AddressType::Address,
+        Some(NostrEntity::Relay(_)) => AddressType::Relay,
+        Some(NostrEntity::SecretKey(_)) | None => AddressType::Nostr,
+    }
+}
+
+/// A decoded NIP-19 entity, with the relay hints it carries
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NostrEntity {
+    /// npub
+    Pubkey(PublicKey),
+    /// nsec
+    SecretKey(SecretKey),
+    /// nprofile
+    Profile {
+        public_key: PublicKey,
+        relays: Vec<Url>,
+    },
+    /// note
+    Note(EventId),
+    /// nevent
+    Event {
+        id: EventId,
+        author: Option<PublicKey>,
+        kind: Option<Kind>,
+        relays: Vec<Url>,
+    },
+    /// naddr
+    Address {
+        coordinate: Coordinate,
+        relays: Vec<Url>,
+    },
+    /// nrelay
+    Relay(Url),
+}
+
+/// Decodes a bech32 NIP-19 entity, bare or as a `nostr:` URI. Relay hints
+/// that are not valid urls are dropped. Secret keys are only accepted bare,
+/// as NIP-21 forbids them in URIs.
+pub fn decode_nostr_entity(entity: &str) -> Option<NostrEntity> {
+    let (bech32, is_uri) = match entity.strip_prefix("nostr:") {
+        Some(bech32) => (bech32, true),
+        None => (entity, false),
+    };
+    let parse_relays = |relays: &[String]| -> Vec<Url> {
+        relays
+            .iter()
+            .filter_map(|relay| Url::parse(relay).ok())
+            .collect()
+    };
+    match Nip19::from_bech32(bech32).ok()? {
+        Nip19::Pubkey(public_key) => Some(NostrEntity::Pubkey(public_key)),
+        Nip19::Secret(secret_key) if !is_uri => Some(NostrEntity::SecretKey(secret_key)),
+        Nip19::Profile(profile) => Some(NostrEntity::Profile {
+            public_key: profile.public_key,
+            relays: profile.relays,
+        }),
+        Nip19::EventId(id) => Some(NostrEntity::Note(id)),
+        Nip19::Event(event) => Some(NostrEntity::Event {
+            id: event.event_id,
+            author: event.author,
+            kind: event.kind,
+            relays: parse_relays(&event.relays),
+        }),
+        Nip19::Coordinate(coordinate) => Some(NostrEntity::Address {
+            relays: parse_relays(&coordinate.relays),
+            coordinate,
+        }),
+        Nip19::Relay(relay) => Some(NostrEntity::Relay(relay.url)),
+        _ => None,
     }
-    AddressType::Nostr
 }
 
 pub fn get_newest_event(events: &[Event]) -> Option<&Event> {
@@ -82,6 +204,329 @@ pub fn get_newest_event(events: &[Event]) -> Option<&Event> {
 pub fn get_oldest_event(events: &[Event]) -> Option<&Event> {
     events.iter().min_by_key(|event| event.created_at())
 }
+
+/// Events created within `since..=until`, like a relay filter; a missing
+/// bound is open. Input order is kept.
+pub fn events_in_range(
+    events: &[Event],
+    since: Option<Timestamp>,
+    until: Option<Timestamp>,
+) -> Vec<&Event> {
+    events
+        .iter()
+        .filter(|event| since.is_none_or(|since| event.created_at >= since))
+        .filter(|event| until.is_none_or(|until| event.created_at <= until))
+        .collect()
+}
+
+/// Events grouped by the UTC day they were created on, oldest day first,
+/// each day oldest event first
+pub fn bucket_events_by_day(events: &[Event]) -> BTreeMap<NaiveDate, Vec<&Event>> {
+    let mut buckets: BTreeMap<NaiveDate, Vec<&Event>> = BTreeMap::new();
+    for event in events {
+        let Some(created_at) = DateTime::from_timestamp(event.created_at.as_u64() as i64, 0) else {
+            continue;
+        };
+        buckets
+            .entry(created_at.date_naive())
+            .or_default()
+            .push(event);
+    }
+    for day in buckets.values_mut() {
+        day.sort_by_key(|event| (event.created_at, event.id));
+    }
+    buckets
+}
+
+/// NIP-30 custom emoji, written `:shortcode:` in content
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CustomEmoji {
+    pub shortcode: String,
+    pub url: Url,
+}
+
+impl CustomEmoji {
+    pub fn new(shortcode: &str, url: Url) -> Self {
+        Self {
+            shortcode: shortcode.trim_matches(':').to_string(),
+            url,
+        }
+    }
+}
+
+/// The custom emoji an event's whole content stands for, such as a
+/// `:shortcode:` reaction, resolved through its `emoji` tags
+pub fn custom_emoji(event: &Event) -> Option<CustomEmoji> {
+    let shortcode = event
+        .content
+        .strip_prefix(':')?
+        .strip_suffix(':')
+        .filter(|shortcode| !shortcode.is_empty())?;
+    event
+        .iter_tags()
+        .find_map(|tag| match tag.as_standardized() {
+            Some(TagStandard::Emoji {
+                shortcode: name,
+                url,
+            }) if name == shortcode => {
+                Some(CustomEmoji::new(name, Url::parse(&url.to_string()).ok()?))
+            }
+            _ => None,
+        })
+}
+
+/// Reads the amount encoded in a BOLT11 invoice's human-readable part.
+///
+/// # Returns
+/// The amount in millisatoshis, or `None` for malformed or amountless invoices.
+pub fn parse_bolt11_msats(invoice: &str) -> Option<u64> {
+    let invoice = invoice.to_lowercase();
+    let hrp = &invoice[..invoice.rfind('1')?];
+    let rest = hrp.strip_prefix("ln")?;
+    // Skip the currency prefix (bc, tb, bcrt, ...)
+    let amount = rest.trim_start_matches(|c: char| c.is_ascii_alphabetic());
+    if amount.is_empty() {
+        return None;
+    }
+
+    let (digits, multiplier) = match amount.chars().last()? {
+        'm' | 'u' | 'n' | 'p' => amount.split_at(amount.len() - 1),
+        _ => (amount, ""),
+    };
+    let value: u64 = digits.parse().ok()?;
+    match multiplier {
+        "" => value.checked_mul(100_000_000_000),
+        "m" => value.checked_mul(100_000_000),
+        "u" => value.checked_mul(100_000),
+        "n" => value.checked_mul(100),
+        // Pico-bitcoin amounts must be whole millisatoshis
+        "p" => value.is_multiple_of(10).then_some(value / 10),
+        _ => None,
+    }
+}
+
+/// Whether `content` has the shape of a NIP-04 payload,
+/// `<base64 ciphertext>?iv=<base64 16-byte IV>`. nostr's NIP-04 decryption
+/// panics on any other IV length, so relay data is checked first.
+pub fn is_nip04_payload(content: &str) -> bool {
+    let Some((ciphertext, iv)) = content.split_once("?iv=") else {
+        return false;
+    };
+    let decode = |part: &str| general_purpose::STANDARD.decode(part).ok();
+    decode(ciphertext).is_some() && decode(iv).is_some_and(|iv| iv.len() == 16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr_sdk::nips::nip19::{Nip19Event, Nip19Profile, Nip19Relay, ToBech32};
+    use nostr_sdk::{EventBuilder, Filter, Keys};
+
+    #[test]
+    fn test_decode_nostr_entity() {
+        let keys = Keys::generate();
+        let public_key = keys.public_key();
+        let relay = Url::parse("wss://relay.example.com").unwrap();
+        let id = EventId::all_zeros();
+
+        let npub = public_key.to_bech32().unwrap();
+        assert_eq!(
+            decode_nostr_entity(&npub),
+            Some(NostrEntity::Pubkey(public_key))
+        );
+        assert_eq!(
+            decode_nostr_entity(&format!("nostr:{npub}")),
+            Some(NostrEntity::Pubkey(public_key))
+        );
+
+        let nsec = keys.secret_key().unwrap().to_bech32().unwrap();
+        assert!(matches!(
+            decode_nostr_entity(&nsec),
+            Some(NostrEntity::SecretKey(_))
+        ));
+        assert_eq!(decode_nostr_entity(&format!("nostr:{nsec}")), None);
+
+        let nprofile = Nip19Profile::new(public_key, [relay.clone()])
+            .unwrap()
+            .to_bech32()
+            .unwrap();
+        assert_eq!(
+            decode_nostr_entity(&nprofile),
+            Some(NostrEntity::Profile {
+                public_key,
+                relays: vec![relay.clone()],
+            })
+        );
+
+        let note = id.to_bech32().unwrap();
+        assert_eq!(decode_nostr_entity(&note), Some(NostrEntity::Note(id)));
+
+        let nevent = Nip19Event::new(id, [relay.to_string(), "not a url".to_string()])
+            .author(public_key)
+            .kind(Kind::TextNote)
+            .to_bech32()
+            .unwrap();
+        assert_eq!(
+            decode_nostr_entity(&nevent),
+            Some(NostrEntity::Event {
+                id,
+                author: Some(public_key),
+                kind: Some(Kind::TextNote),
+                relays: vec![relay.clone()],
+            })
+        );
+
+        let mut coordinate =
+            Coordinate::new(Kind::LongFormTextNote, public_key).identifier("article");
+        coordinate.relays = vec![relay.to_string()];
+        let naddr = coordinate.to_bech32().unwrap();
+        assert_eq!(
+            decode_nostr_entity(&naddr),
+            Some(NostrEntity::Address {
+                coordinate,
+                relays: vec![relay],
+            })
+        );
+
+        assert_eq!(decode_nostr_entity("npub1invalid"), None);
+    }
+
+    #[test]
+    fn test_hash_filter() {
+        let a = Keys::generate().public_key();
+        let b = Keys::generate().public_key();
+        let filter = Filter::new()
+            .authors([a, b])
+            .kinds([Kind::TextNote, Kind::Repost])
+            .hashtags(["nostr", "rust"]);
+        let reordered = Filter::new()
+            .hashtags(["rust", "nostr"])
+            .kinds([Kind::Repost, Kind::TextNote])
+            .authors([b, a]);
+        assert_eq!(hash_filter(&filter), hash_filter(&reordered));
+        assert_eq!(
+            hash_filter(&vec![filter.clone(), Filter::new().author(a)]),
+            hash_filter(&vec![Filter::new().author(a), reordered])
+        );
+        assert_ne!(hash_filter(&filter), hash_filter(&filter.clone().limit(10)));
+
+        // Pinned so a change to the normalization is caught and versioned
+        let fixed = Filter::new()
+            .kinds([Kind::Metadata, Kind::TextNote])
+            .limit(10);
+        let key = filter_cache_key(&fixed);
+        assert!(key.starts_with("v1:"));
+        assert_eq!(
+            key,
+            format!("v1:{}", Sha256Hash::hash(br#"{"kinds":[0,1],"limit":10}"#))
+        );
+    }
+
+    #[test]
+    fn test_events_by_time() {
+        let keys = Keys::generate();
+        let at = |secs: u64| {
+            EventBuilder::text_note(secs.to_string(), [])
+                .custom_created_at(Timestamp::from(secs))
+                .to_event(&keys)
+                .unwrap()
+        };
+        // 2024-01-01 23:00, 2024-01-02 00:00 and 12:00, 2024-01-03 00:00 UTC
+        let events = vec![
+            at(1_704_153_600),
+            at(1_704_150_000),
+            at(1_704_196_800),
+            at(1_704_240_000),
+        ];
+
+        let selected = |since: Option<u64>, until: Option<u64>| -> Vec<&str> {
+            events_in_range(
+                &events,
+                since.map(Timestamp::from),
+                until.map(Timestamp::from),
+            )
+            .iter()
+            .map(|event| event.content.as_str())
+            .collect()
+        };
+        assert_eq!(
+            selected(Some(1_704_153_600), Some(1_704_196_800)),
+            vec!["1704153600", "1704196800"]
+        );
+        assert_eq!(selected(None, Some(1_704_150_000)), vec!["1704150000"]);
+        assert_eq!(selected(Some(1_704_240_000), None), vec!["1704240000"]);
+        assert_eq!(selected(None, None).len(), 4);
+
+        let buckets = bucket_events_by_day(&events);
+        let day = |d: u32| NaiveDate::from_ymd_opt(2024, 1, d).unwrap();
+        assert_eq!(
+            buckets.keys().copied().collect::<Vec<_>>(),
+            vec![day(1), day(2), day(3)]
+        );
+        assert_eq!(
+            buckets[&day(2)]
+                .iter()
+                .map(|event| event.content.as_str())
+                .collect::<Vec<_>>(),
+            vec!["1704153600", "1704196800"]
+        );
+    }
+
+    #[test]
+    fn test_is_note_address() {
+        let keys = Keys::generate();
+        let relay = Url::parse("wss://relay.example.com").unwrap();
+        let note = EventId::all_zeros().to_bech32().unwrap();
+        let npub = keys.public_key().to_bech32().unwrap();
+        let nprofile = Nip19Profile::new(keys.public_key(), [relay.clone()])
+            .unwrap()
+            .to_bech32()
+            .unwrap();
+        let nevent = Nip19Event::new(EventId::all_zeros(), Vec::<String>::new())
+            .to_bech32()
+            .unwrap();
+        let naddr = Coordinate::new(Kind::LongFormTextNote, keys.public_key())
+            .identifier("article")
+            .to_bech32()
+            .unwrap();
+        let nrelay = Nip19Relay::new(relay).to_bech32().unwrap();
+
+        for (address, expected) in [
+            (note, AddressType::Note),
+            (npub, AddressType::Mention),
+            (nprofile, AddressType::Profile),
+            (nevent, AddressType::Event),
+            (naddr, AddressType::Address),
+            (nrelay, AddressType::Relay),
+        ] {
+            assert_eq!(is_note_address(&address), expected, "{address}");
+            assert_eq!(is_note_address(&format!("nostr:{address}")), expected);
+        }
+        let nsec = keys.secret_key().unwrap().to_bech32().unwrap();
+        assert_eq!(is_note_address(&nsec), AddressType::Nostr);
+        assert_eq!(is_note_address("nostr:garbage"), AddressType::Nostr);
+        assert_eq!(is_note_address("https://example.com"), AddressType::Nostr);
+    }
+
+    #[test]
+    fn test_is_nip04_payload() {
+        let keys = Keys::generate();
+        let payload = nostr_sdk::nips::nip04::encrypt(
+            keys.secret_key().unwrap(),
+            &keys.public_key(),
+            "hello",
+        )
+        .unwrap();
+        assert!(is_nip04_payload(&payload));
+
+        assert!(!is_nip04_payload("plain text"));
+        assert!(!is_nip04_payload("x?iv="));
+        assert!(!is_nip04_payload("AAAA?iv=AAAA"));
+        let (ciphertext, iv) = payload.split_once("?iv=").unwrap();
+        assert!(!is_nip04_payload(&format!("{ciphertext}?iv={iv}?iv={iv}")));
+    }
+}
+
 /*
 pub async fn query_events_from_db(
     client: &Client,
@@ -0,0 +1,328 @@
+use nostr_sdk::bech32::{self, Bech32, Hrp};
+use nostr_sdk::nips::nip01::Coordinate;
+use nostr_sdk::nips::nip57::ZapRequestData;
+use nostr_sdk::{
+    Client, Event, EventBuilder, JsonUtil, Metadata, NostrSigner, PublicKey, UncheckedUrl, Url,
+};
+use serde::Deserialize;
+use std::time::Duration;
+use thiserror::Error;
+
+use super::fetch::get_metadata;
+
+const LNURL_HRP: &str = "lnurl";
+
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error(transparent)]
+    Fetch(#[from] super::fetch::Error),
+    #[error(transparent)]
+    Signer(#[from] nostr_sdk::signer::Error),
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Url(#[from] nostr_sdk::types::url::ParseError),
+    #[error("Invalid LNURL: {0}")]
+    InvalidLnurl(String),
+    #[error("Recipient has no lightning address")]
+    NoLightningAddress,
+    #[error("Recipient's LNURL endpoint does not accept zaps")]
+    ZapsNotSupported,
+    #[error("Amount must be between {min} and {max} msats")]
+    AmountOutOfRange { min: u64, max: u64 },
+    #[error("LNURL request failed: {0}")]
+    Http(String),
+}
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// What a zap pays for
+#[derive(Debug, Clone)]
+pub enum ZapTarget {
+    Profile(PublicKey),
+    /// Zaps the author of the event; addressable events are also zapped by
+    /// their coordinate
+    Event(Box<Event>),
+}
+
+impl ZapTarget {
+    pub fn recipient(&self) -> PublicKey {
+        match self {
+            ZapTarget::Profile(public_key) => *public_key,
+            ZapTarget::Event(event) => event.author(),
+        }
+    }
+}
+
+/// LNURL pay parameters of a lightning address (LUD-06)
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LnurlPay {
+    pub callback: String,
+    pub min_sendable: u64,
+    pub max_sendable: u64,
+    #[serde(default)]
+    pub allows_nostr: bool,
+    /// Key the LNURL server signs zap receipts with
+    #[serde(default)]
+    pub nostr_pubkey: Option<PublicKey>,
+}
+
+/// A signed zap request and the invoice that carries it
+#[derive(Debug, Clone)]
+pub struct ZapInvoice {
+    /// BOLT-11 invoice to pay
+    pub invoice: String,
+    pub zap_request: Event,
+    /// Key the zap receipt must be signed with
+    pub zapper: Option<PublicKey>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum LnurlResponse<T> {
+    /// `{"status": "ERROR", "reason": ...}`
+    Error {
+        #[allow(dead_code)]
+        status: String,
+        reason: String,
+    },
+    Ok(T),
+}
+
+#[derive(Debug, Deserialize)]
+struct CallbackResponse {
+    pr: String,
+}
+
+/// Build and sign a kind 9734 zap request for `amount_msat` and fetch the
+/// invoice for it from the recipient's LNURL pay endpoint. Receipts are
+/// requested on `relays`.
+pub async fn create_zap_request(
+    client: &Client,
+    signer: &NostrSigner,
+    target: ZapTarget,
+    amount_msat: u64,
+    relays: Vec<Url>,
+    comment: Option<&str>,
+    timeout: Option<Duration>,
+) -> Result<ZapInvoice> {
+    let metadata = get_metadata(client, &target.recipient(), timeout).await?;
+    let lnurl = metadata_lnurl(&metadata)?;
+    let pay: LnurlPay = http_get_json(lnurl_pay_url(&lnurl)?.as_str()).await?;
+    if !pay.allows_nostr || pay.nostr_pubkey.is_none() {
+        return Err(Error::ZapsNotSupported);
+    }
+    if amount_msat < pay.min_sendable || amount_msat > pay.max_sendable {
+        return Err(Error::AmountOutOfRange {
+            min: pay.min_sendable,
+            max: pay.max_sendable,
+        });
+    }
+
+    let builder = zap_request_builder(&target, amount_msat, relays, comment, &lnurl);
+    let zap_request = signer.sign_event_builder(builder).await?;
+
+    let callback = zap_callback_url(&pay.callback, amount_msat, &zap_request, &lnurl)?;
+    let response: CallbackResponse = http_get_json(callback.as_str()).await?;
+    Ok(ZapInvoice {
+        invoice: response.pr,
+        zap_request,
+        zapper: pay.nostr_pubkey,
+    })
+}
+
+/// bech32 LNURL of the recipient, preferring the lightning address (lud16)
+fn metadata_lnurl(metadata: &Metadata) -> Result<String> {
+    let address = metadata.lud16.as_deref().filter(|s| !s.is_empty());
+    if let Some(address) = address {
+        return encode_lnurl(&lightning_address_url(address)?);
+    }
+    match metadata.lud06.as_deref().filter(|s| !s.is_empty()) {
+        Some(lnurl) => Ok(lnurl.to_lowercase()),
+        None => Err(Error::NoLightningAddress),
+    }
+}
+
+/// `name@domain` resolves to `https://domain/.well-known/lnurlp/name`
+fn lightning_address_url(address: &str) -> Result<Url> {
+    let (name, domain) = address
+        .split_once('@')
+        .filter(|(name, domain)| !name.is_empty() && !domain.is_empty())
+        .ok_or_else(|| Error::InvalidLnurl(address.to_string()))?;
+    Ok(Url::parse(&format!(
+        "https://{domain}/.well-known/lnurlp/{name}"
+    ))?)
+}
+
+fn encode_lnurl(url: &Url) -> Result<String> {
+    let hrp = Hrp::parse_unchecked(LNURL_HRP);
+    bech32::encode::<Bech32>(hrp, url.as_str().as_bytes())
+        .map_err(|e| Error::InvalidLnurl(e.to_string()))
+}
+
+/// Url a bech32 LNURL points to
+fn lnurl_pay_url(lnurl: &str) -> Result<Url> {
+    let invalid = || Error::InvalidLnurl(lnurl.to_string());
+    let (hrp, data) = bech32::decode(lnurl).map_err(|_| invalid())?;
+    if hrp.to_lowercase() != LNURL_HRP {
+        return Err(invalid());
+    }
+    let url = String::from_utf8(data).map_err(|_| invalid())?;
+    Ok(Url::parse(&url)?)
+}
+
+fn zap_request_builder(
+    target: &ZapTarget,
+    amount_msat: u64,
+    relays: Vec<Url>,
+    comment: Option<&str>,
+    lnurl: &str,
+) -> EventBuilder {
+    let relays = relays.into_iter().map(UncheckedUrl::from);
+    let mut data = ZapRequestData::new(target.recipient(), relays)
+        .message(comment.unwrap_or_default())
+        .amount(amount_msat)
+        .lnurl(lnurl);
+    if let ZapTarget::Event(event) = target {
+        data = data.event_id(event.id);
+        if event.kind.is_parameterized_replaceable() {
+            if let Some(identifier) = event.identifier() {
+                let coordinate = Coordinate::new(event.kind, event.author()).identifier(identifier);
+                data = data.event_coordinate(coordinate);
+            }
+        }
+    }
+    EventBuilder::public_zap_request(data)
+}
+
+fn zap_callback_url(
+    callback: &str,
+    amount_msat: u64,
+    zap_request: &Event,
+    lnurl: &str,
+) -> Result<Url> {
+    let mut url = Url::parse(callback)?;
+    url.query_pairs_mut()
+        .append_pair("amount", &amount_msat.to_string())
+        .append_pair("nostr", &zap_request.as_json())
+        .append_pair("lnurl", lnurl);
+    Ok(url)
+}
+
+/// GET `url` and parse the LNURL json response
+async fn http_get_json<T>(url: &str) -> Result<T>
+where
+    T: for<'de> Deserialize<'de>,
+{
+    let text = http_get(url).await?;
+    match serde_json::from_str(&text)? {
+        LnurlResponse::Error { reason, .. } => Err(Error::Http(reason)),
+        LnurlResponse::Ok(value) => Ok(value),
+    }
+}
+
+// Body of a GET through the browser's fetch
+#[cfg(wasm_backend)]
+async fn http_get(url: &str) -> Result<String> {
+    use wasm_bindgen::JsCast;
+    use wasm_bindgen_futures::JsFuture;
+
+    let js_error = |e: wasm_bindgen::JsValue| Error::Http(format!("{e:?}"));
+    let window = web_sys::window().ok_or_else(|| Error::Http("no window".to_string()))?;
+    let response = JsFuture::from(window.fetch_with_str(url))
+        .await
+        .map_err(js_error)?;
+    let response: web_sys::Response = response.dyn_into().map_err(js_error)?;
+    if !response.ok() {
+        return Err(Error::Http(format!("status {}", response.status())));
+    }
+    Ok(JsFuture::from(response.text().map_err(js_error)?)
+        .await
+        .map_err(js_error)?
+        .as_string()
+        .unwrap_or_default())
+}
+
+// Body of a GET through reqwest
+#[cfg(native_backend)]
+async fn http_get(url: &str) -> Result<String> {
+    let http_error = |e: reqwest::Error| Error::Http(e.to_string());
+    let response = reqwest::get(url).await.map_err(http_error)?;
+    if !response.status().is_success() {
+        return Err(Error::Http(format!(
+            "status {}",
+            response.status().as_u16()
+        )));
+    }
+    response.text().await.map_err(http_error)
+}
+
+#[cfg(not(any(native_backend, wasm_backend)))]
+async fn http_get(_url: &str) -> Result<String> {
+    Err(Error::Http(
+        "no HTTP backend, enable the `native` or `wasm` feature".to_string(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nostr_sdk::{Keys, Kind, TagKind};
+
+    #[test]
+    fn test_lnurl_resolution() {
+        let url = lightning_address_url("satoshi@example.com").unwrap();
+        assert_eq!(
+            url.as_str(),
+            "https://example.com/.well-known/lnurlp/satoshi"
+        );
+        assert!(lightning_address_url("example.com").is_err());
+
+        let lnurl = encode_lnurl(&url).unwrap();
+        assert!(lnurl.starts_with("lnurl1"));
+        assert_eq!(lnurl_pay_url(&lnurl).unwrap(), url);
+        assert_eq!(lnurl_pay_url(&lnurl.to_uppercase()).unwrap(), url);
+
+        let metadata = Metadata::new().lud06(lnurl.to_uppercase());
+        assert_eq!(metadata_lnurl(&metadata).unwrap(), lnurl);
+        let metadata = metadata.lud16("satoshi@example.com");
+        assert_eq!(metadata_lnurl(&metadata).unwrap(), lnurl);
+        assert!(matches!(
+            metadata_lnurl(&Metadata::new()),
+            Err(Error::NoLightningAddress)
+        ));
+    }
+
+    #[test]
+    fn test_zap_request_builder() {
+        let keys = Keys::generate();
+        let note = EventBuilder::text_note("gm", []).to_event(&keys).unwrap();
+        let relay = Url::parse("wss://relay.example.com").unwrap();
+        let target = ZapTarget::Event(Box::new(note.clone()));
+
+        let request = zap_request_builder(&target, 21_000, vec![relay], Some("nice"), "lnurl1x")
+            .to_event(&Keys::generate())
+            .unwrap();
+        assert_eq!(request.kind, Kind::ZapRequest);
+        assert_eq!(request.content, "nice");
+        assert_eq!(request.event_ids().next(), Some(&note.id));
+        assert_eq!(request.public_keys().next(), Some(&keys.public_key()));
+        let tag = |kind: &str| {
+            request
+                .tags
+                .iter()
+                .find(|t| t.kind() == TagKind::from(kind))
+                .and_then(|t| t.content().map(str::to_string))
+        };
+        assert_eq!(tag("amount").as_deref(), Some("21000"));
+        assert_eq!(tag("lnurl").as_deref(), Some("lnurl1x"));
+
+        let callback =
+            zap_callback_url("https://example.com/cb?k=v", 21_000, &request, "lnurl1x").unwrap();
+        let pairs: Vec<(String, String)> = callback.query_pairs().into_owned().collect();
+        assert_eq!(pairs[0], ("k".to_string(), "v".to_string()));
+        assert_eq!(pairs[1], ("amount".to_string(), "21000".to_string()));
+        assert_eq!(Event::from_json(&pairs[2].1).unwrap(), request);
+    }
+}
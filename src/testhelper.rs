@@ -1,8 +1,61 @@
+#[cfg(all(test, target_arch = "wasm32"))]
 use js_sys::Promise;
+#[cfg(test)]
 use nostr_sdk::{Event, JsonUtil};
+#[cfg(all(test, target_arch = "wasm32"))]
 use wasm_bindgen::prelude::*;
+#[cfg(all(test, target_arch = "wasm32"))]
 use wasm_bindgen_futures::JsFuture;
-use wasm_bindgen_test::*;
+#[cfg(all(feature = "testkit", not(target_arch = "wasm32")))]
+mod mock_relay;
+#[cfg(all(feature = "testkit", not(target_arch = "wasm32")))]
+pub use mock_relay::MockRelay;
+/// Entry points of the cargo-fuzz targets in `fuzz/`
+#[cfg(feature = "testkit")]
+pub mod fuzz;
+/// Proptest strategies for `CrdtOperation` sequences and the convergence
+/// laws every `CrdtState` should obey, for this crate's CRDTs and for
+/// custom ones:
+///
+/// ```ignore
+/// proptest! {
+///     #[test]
+///     fn my_crdt_converges((ops, permuted) in permutations(0..32)) {
+///         assert_commutative::<MyCrdt>(&ops, &permuted)?;
+///     }
+/// }
+/// ```
+#[cfg(feature = "testkit")]
+pub mod properties;
+#[cfg(feature = "testkit")]
+mod rng;
+#[cfg(feature = "testkit")]
+pub(crate) use rng::Rng;
+#[cfg(feature = "testkit")]
+mod simulation;
+#[cfg(feature = "testkit")]
+pub use simulation::{NetworkConfig, Simulation, SimulationStats};
+#[cfg(test)]
+pub fn event_from(raw: &str) -> Event {
+    Event::from_json(raw).unwrap()
+}
+
+// Manager signing with `keys` over a client connected to `relay` alone, as
+// one device of a user in the CRDT document tests
+#[cfg(all(test, feature = "testkit", not(target_arch = "wasm32")))]
+pub(crate) async fn connect(
+    relay: &MockRelay,
+    keys: &nostr_sdk::Keys,
+) -> crate::nostr::crdt::CrdtManager {
+    let client = nostr_sdk::Client::new(keys);
+    client.add_relay(relay.url()).await.unwrap();
+    client.connect().await;
+    crate::nostr::crdt::CrdtManager::new(
+        std::sync::Arc::new(client),
+        nostr_sdk::NostrSigner::Keys(keys.clone()),
+    )
+}
+
 #[cfg(test)]
 pub mod test_data {
     //basic test notes
@@ -23,14 +76,27 @@ pub mod test_data {
 
     //events from relay
     pub const R_EVENT_770: &str = r#"{"id":"770e3b604de378c67570ce3c521e2fd51c1a59aa85c22ef9aeab7b5f5e2f5e1b","tags":[],"content":"How it started 🤖.......... How it's going 🥜\n\nhttps://m.primal.net/IHQz.png ","created_at":1715871171,"sig":"90a8abf718b28c51e24bce9f95f92250379e6c612937b9f113d2b24dc43492aacdd6c43a220c02e480300a6d84139bfdeb70e2fdd08330f81ef9683b627baf56","pubkey":"50d94fc2d8580c682b071a542f8b1e31a200b0508bab95a33bef0855df281d63","kind":1}"#;
-    pub const R_EVENT_70c: &str = r#"{"content":"Not gonna lie the throw back is sexy","created_at":1715871480,"id":"70cfdf05fa80ce6b4a54668788eef31ff7d5a23b74f54943ec9e5a91cb5806f1","kind":1,"pubkey":"3b7fc823611f1aeaea63ee3bf69b25b8aa16ec6e81d1afc39026808fe194354f","sig":"76a90208faa44bbf95d6d9f1100f9667a7f038c32a9b19ac60abe5c4d75a5ba13f74e74fd0830c28920815bd2ac5f2b8ee0bf6b47b32d57b660ab8a7847d5690","tags":[["e","770e3b604de378c67570ce3c521e2fd51c1a59aa85c22ef9aeab7b5f5e2f5e1b","","root"],["p","50d94fc2d8580c682b071a542f8b1e31a200b0508bab95a33bef0855df281d63"]]}"#;
+    pub const R_EVENT_70C: &str = r#"{"content":"Not gonna lie the throw back is sexy","created_at":1715871480,"id":"70cfdf05fa80ce6b4a54668788eef31ff7d5a23b74f54943ec9e5a91cb5806f1","kind":1,"pubkey":"3b7fc823611f1aeaea63ee3bf69b25b8aa16ec6e81d1afc39026808fe194354f","sig":"76a90208faa44bbf95d6d9f1100f9667a7f038c32a9b19ac60abe5c4d75a5ba13f74e74fd0830c28920815bd2ac5f2b8ee0bf6b47b32d57b660ab8a7847d5690","tags":[["e","770e3b604de378c67570ce3c521e2fd51c1a59aa85c22ef9aeab7b5f5e2f5e1b","","root"],["p","50d94fc2d8580c682b071a542f8b1e31a200b0508bab95a33bef0855df281d63"]]}"#;
 }
+// `console_log!` of wasm-bindgen-test in the browser, stdout on native
+// targets, where the browser console is not there to log to
+#[cfg(test)]
+macro_rules! console_log {
+    ($($arg:tt)*) => {{
+        #[cfg(target_arch = "wasm32")]
+        wasm_bindgen_test::console_log!($($arg)*);
+        #[cfg(not(target_arch = "wasm32"))]
+        println!($($arg)*);
+    }};
+}
+#[cfg(test)]
+pub(crate) use console_log;
+
 #[cfg(test)]
 pub mod test_hander {
     use std::sync::Arc;
 
     use nostr_sdk::prelude::*;
-    use wasm_bindgen_test::*;
 
     use crate::nostr::register::NotificationHandler;
 
@@ -58,19 +124,14 @@ pub mod test_hander {
     }
 }
 
-#[cfg(test)]
-pub fn event_from(raw: &str) -> Event {
-    Event::from_json(raw).unwrap()
-}
-
-#[cfg(test)]
+#[cfg(all(test, target_arch = "wasm32"))]
 #[wasm_bindgen]
 extern "C" {
     #[wasm_bindgen(js_namespace = window, js_name = setTimeout)]
     fn set_timeout(closure: &Closure<dyn FnMut()>, time: u32) -> i32;
 }
 
-#[cfg(test)]
+#[cfg(all(test, target_arch = "wasm32"))]
 pub async fn sleep(ms: u32) -> Result<(), JsValue> {
     let promise = Promise::new(&mut |resolve, _| {
         let closure = Closure::once(move || {
@@ -83,3 +144,9 @@ pub async fn sleep(ms: u32) -> Result<(), JsValue> {
     JsFuture::from(promise).await?;
     Ok(())
 }
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+pub async fn sleep(ms: u32) -> Result<(), std::convert::Infallible> {
+    tokio::time::sleep(std::time::Duration::from_millis(ms.into())).await;
+    Ok(())
+}
@@ -0,0 +1,151 @@
+use std::sync::{Arc, OnceLock};
+
+use nostr_sdk::nips::nip04;
+use nostr_sdk::{Client, EventBuilder, Keys, Kind, NostrSigner, SecretKey, Tag};
+
+use crate::nostr::crdt::{
+    CrdtManager, CrdtOperation, CrdtOperationRef, CrdtState, CrdtType, GCounter, GSet, LWWRegister,
+};
+
+// Fixed keys, so inputs found by the fuzzer replay identically
+fn keys() -> &'static Keys {
+    static KEYS: OnceLock<Keys> = OnceLock::new();
+    KEYS.get_or_init(|| Keys::new(SecretKey::from_slice(&[0x42; 32]).expect("valid secret key")))
+}
+
+/// Deserializes `data` as a `CrdtOperation` the way `process_event` does
+/// with decrypted content. Anything that parses must survive a round trip
+/// and be applied by every CRDT type without panicking.
+pub fn parse_operation(data: &[u8]) {
+    let Ok(op) = serde_json::from_slice::<CrdtOperation>(data) else {
+        return;
+    };
+    let json = serde_json::to_string(&op).expect("parsed operations serialize");
+    let reparsed: CrdtOperation = serde_json::from_str(&json).expect("serialized operations parse");
+    assert_eq!(
+        serde_json::to_string(&reparsed).expect("parsed operations serialize"),
+        json
+    );
+
+    // Received operations are parsed in place; both forms must agree
+    let borrowed: CrdtOperationRef =
+        serde_json::from_slice(data).expect("operations parse borrowed too");
+    assert_eq!(
+        serde_json::to_string(&borrowed).expect("parsed operations serialize"),
+        json
+    );
+
+    let _ = LWWRegister::default().apply_operation(&op);
+    let _ = GCounter::default().apply_operation(&op);
+    let _ = GSet::default().apply_operation(&op);
+    let _ = LWWRegister::default().apply_borrowed(&borrowed);
+    let _ = GCounter::default().apply_borrowed(&borrowed);
+    let _ = GSet::default().apply_borrowed(&borrowed);
+}
+
+/// Feeds a CRDT event built from `data` to a fresh `CrdtManager`, as if a
+/// hostile relay had sent it. The first byte picks the payload:
+///
+/// - 0: the rest as plaintext content
+/// - 1: the rest, NIP-04 encrypted to the manager's own key
+/// - 2: an encrypted valid operation, truncated and with bytes flipped
+///   according to the rest
+///
+/// Errors are expected; panics are bugs.
+pub fn process_event(data: &[u8]) {
+    let Some((mode, rest)) = data.split_first() else {
+        return;
+    };
+    let keys = keys();
+    let content = match mode % 3 {
+        0 => String::from_utf8_lossy(rest).into_owned(),
+        1 => encrypt(keys, &String::from_utf8_lossy(rest)),
+        _ => corrupt(
+            &encrypt(keys, r#"{"GCounter":{"key":"views","increment":1}}"#),
+            rest,
+        ),
+    };
+    let event = EventBuilder::new(Kind::TextNote, content, [Tag::hashtag("nostr-crdt")])
+        .to_event(keys)
+        .expect("events sign with local keys");
+
+    // Never connected, nothing leaves the process
+    let client = Arc::new(Client::new(keys));
+    let manager = CrdtManager::new(client, NostrSigner::Keys(keys.clone()));
+    // Existing state for the event to collide with
+    manager
+        .apply(&CrdtOperation::GCounter {
+            key: "views".to_string(),
+            increment: u64::MAX - 1,
+        })
+        .expect("counters accept increments");
+    let _ = futures::executor::block_on(manager.process_event(&event));
+    // Whatever was applied must still read back
+    for (key, crdt_type, value) in manager.iter_state() {
+        let read = match crdt_type {
+            CrdtType::LWWRegister => manager.get_register_value(&key),
+            CrdtType::GCounter => manager.get_counter_value(&key),
+            CrdtType::GSet => manager.get_set_value(&key),
+        };
+        assert_eq!(read, Some(value));
+    }
+}
+
+fn encrypt(keys: &Keys, content: &str) -> String {
+    let secret_key = keys.secret_key().expect("local keys hold a secret key");
+    nip04::encrypt(secret_key, &keys.public_key(), content).expect("NIP-04 encryption")
+}
+
+// Cut the payload at a position chosen by `mutation` and flip bytes of
+// what is left, keeping it valid UTF-8
+fn corrupt(payload: &str, mutation: &[u8]) -> String {
+    let mut bytes = payload.as_bytes().to_vec();
+    if let Some((cut, flips)) = mutation.split_first() {
+        bytes.truncate(*cut as usize * bytes.len() / 255);
+        for pair in flips.chunks(2) {
+            if let [position, mask] = pair {
+                if !bytes.is_empty() {
+                    let index = *position as usize % bytes.len();
+                    bytes[index] ^= mask & 0x7f;
+                }
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_fuzz_seeds() {
+        for seed in [
+            &br#"{"GCounter":{"key":"views","increment":18446744073709551615}}"#[..],
+            br#"{"LWWRegister":{"key":"","value":"\u0000","timestamp":0}}"#,
+            br#"{"GSet":{"key":"tags","value":"a","action":"Remove"}}"#,
+            br#"{"GCounter":{"key":"views"}}"#,
+            br#"{"GCounter":{"key":"views","increment":2}}"#,
+            br#"{"Unknown":{}}"#,
+            b"[]",
+            b"",
+        ] {
+            parse_operation(seed);
+            process_event(&[&[0], seed].concat());
+            process_event(&[&[1], seed].concat());
+        }
+        process_event(&[2]);
+        process_event(&[2, 128, 3, 0x41]);
+        process_event(&[2, 255]);
+        process_event(&[0, b'?', b'i', b'v', b'=']);
+    }
+
+    proptest! {
+        #[test]
+        fn test_fuzz_random_inputs(data in proptest::collection::vec(any::<u8>(), 0..256)) {
+            parse_operation(&data);
+            process_event(&data);
+        }
+    }
+}
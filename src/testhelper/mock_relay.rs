@@ -0,0 +1,424 @@
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+use futures::{SinkExt, StreamExt};
+use nostr_sdk::{
+    ClientMessage, Event, EventId, Filter, JsonUtil, RelayMessage, SubscriptionId, Url,
+};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::Message;
+
+// Live events buffered per connection before a slow client starts missing some
+const EVENT_BUFFER: usize = 1024;
+
+// Events held by the relay, in arrival order
+#[derive(Debug, Default)]
+struct Store {
+    events: Vec<Event>,
+    // Ids of `events`, so long runs are not slowed down by duplicate checks
+    ids: HashSet<EventId>,
+}
+
+impl Store {
+    // Whether the event was new; replaceable events evict older versions
+    // and are ignored when a newer version is already stored
+    fn insert(&mut self, event: &Event) -> bool {
+        if self.ids.contains(&event.id) {
+            return false;
+        }
+        if event.kind.is_replaceable() || event.kind.is_parameterized_replaceable() {
+            let replaces = |stored: &Event| {
+                stored.kind == event.kind
+                    && stored.pubkey == event.pubkey
+                    && stored.identifier() == event.identifier()
+            };
+            if self
+                .events
+                .iter()
+                .any(|stored| replaces(stored) && stored.created_at > event.created_at)
+            {
+                return false;
+            }
+            let ids = &mut self.ids;
+            self.events.retain(|stored| {
+                let keep = !replaces(stored);
+                if !keep {
+                    ids.remove(&stored.id);
+                }
+                keep
+            });
+        }
+        self.ids.insert(event.id);
+        self.events.push(event.clone());
+        true
+    }
+
+    // Stored events matching any of `filters`, newest first, each filter
+    // capped at its own limit like a real relay
+    fn query(&self, filters: &[Filter]) -> Vec<Event> {
+        let mut events: Vec<Event> = Vec::new();
+        for filter in filters {
+            let mut matching: Vec<&Event> = self
+                .events
+                .iter()
+                .filter(|event| matches(filter, event))
+                .collect();
+            matching.sort_by_key(|event| std::cmp::Reverse((event.created_at, event.id)));
+            if let Some(limit) = filter.limit {
+                matching.truncate(limit);
+            }
+            for event in matching {
+                if !events.iter().any(|seen| seen.id == event.id) {
+                    events.push(event.clone());
+                }
+            }
+        }
+        events.sort_by_key(|event| std::cmp::Reverse((event.created_at, event.id)));
+        events
+    }
+}
+
+// NIP-01 matching plus NIP-50 search, taken as a case-insensitive
+// substring of the content
+fn matches(filter: &Filter, event: &Event) -> bool {
+    filter.match_event(event)
+        && filter
+            .search
+            .as_ref()
+            .is_none_or(|query| event.content.to_lowercase().contains(&query.to_lowercase()))
+}
+
+/// In-process relay speaking enough of NIP-01 (EVENT, REQ, EOSE, CLOSE) to
+/// test code against a `Client` without reaching public relays.
+///
+/// Events live in memory for the lifetime of the relay: REQ answers with
+/// the stored matches followed by EOSE, then streams new matches until
+/// CLOSE. Signatures are checked, replaceable kinds keep their newest
+/// version and a NIP-50 `search` matches content containing it; everything
+/// else (auth, NIP-11, rate limits) is left out.
+/// Dropping the relay closes every connection.
+///
+/// Listens on a loopback port, so it needs a native target.
+pub struct MockRelay {
+    addr: SocketAddr,
+    store: Arc<Mutex<Store>>,
+    events: broadcast::Sender<Event>,
+    tasks: Arc<Mutex<Vec<JoinHandle<()>>>>,
+}
+
+impl MockRelay {
+    /// Starts a relay on a free loopback port
+    pub async fn run() -> std::io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let store = Arc::new(Mutex::new(Store::default()));
+        let (events, _) = broadcast::channel(EVENT_BUFFER);
+        let tasks = Arc::new(Mutex::new(Vec::new()));
+
+        let accept = tokio::spawn(accept_loop(
+            listener,
+            Arc::clone(&store),
+            events.clone(),
+            Arc::clone(&tasks),
+        ));
+        tasks.lock().unwrap().push(accept);
+
+        Ok(Self {
+            addr,
+            store,
+            events,
+            tasks,
+        })
+    }
+
+    /// `ws://` URL to hand to `Client::add_relay`
+    pub fn url(&self) -> Url {
+        Url::parse(&format!("ws://{}", self.addr)).expect("valid relay url")
+    }
+
+    /// Stores `event` as if a client had published it, e.g. to seed
+    /// history before the code under test connects
+    pub fn add_event(&self, event: Event) {
+        if self.store.lock().unwrap().insert(&event) {
+            // No receivers just means nobody is subscribed
+            let _ = self.events.send(event);
+        }
+    }
+
+    /// Every stored event, newest first
+    pub fn events(&self) -> Vec<Event> {
+        self.store.lock().unwrap().query(&[Filter::new()])
+    }
+
+    /// Stored events matching `filters`, newest first
+    pub fn query(&self, filters: &[Filter]) -> Vec<Event> {
+        self.store.lock().unwrap().query(filters)
+    }
+
+    pub fn contains(&self, id: &EventId) -> bool {
+        self.store
+            .lock()
+            .unwrap()
+            .events
+            .iter()
+            .any(|event| event.id == *id)
+    }
+}
+
+impl Drop for MockRelay {
+    fn drop(&mut self) {
+        for task in self.tasks.lock().unwrap().drain(..) {
+            task.abort();
+        }
+    }
+}
+
+async fn accept_loop(
+    listener: TcpListener,
+    store: Arc<Mutex<Store>>,
+    events: broadcast::Sender<Event>,
+    tasks: Arc<Mutex<Vec<JoinHandle<()>>>>,
+) {
+    while let Ok((stream, peer)) = listener.accept().await {
+        // Replies are small; without this, Nagle's algorithm holds them back
+        // waiting for acks whenever traffic flows both ways
+        let _ = stream.set_nodelay(true);
+        let connection = tokio::spawn({
+            let store = Arc::clone(&store);
+            let events = events.clone();
+            async move {
+                if let Err(err) = handle_connection(stream, store, events).await {
+                    tracing::debug!("Mock relay connection {} closed: {}", peer, err);
+                }
+            }
+        });
+        tasks.lock().unwrap().push(connection);
+    }
+}
+
+async fn handle_connection(
+    stream: TcpStream,
+    store: Arc<Mutex<Store>>,
+    events: broadcast::Sender<Event>,
+) -> Result<(), tokio_tungstenite::tungstenite::Error> {
+    let mut socket = tokio_tungstenite::accept_async(stream).await?;
+    let mut live = events.subscribe();
+    let mut subscriptions: HashMap<SubscriptionId, Vec<Filter>> = HashMap::new();
+
+    loop {
+        tokio::select! {
+            message = socket.next() => {
+                let text = match message {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Close(_))) | None => return Ok(()),
+                    Some(Ok(_)) => continue,
+                    Some(Err(err)) => return Err(err),
+                };
+                let replies = handle_message(&text, &store, &events, &mut subscriptions);
+                for reply in replies {
+                    socket.send(Message::Text(reply.as_json())).await?;
+                }
+            }
+            event = live.recv() => {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(broadcast::error::RecvError::Lagged(missed)) => {
+                        tracing::warn!("Mock relay client missed {} events", missed);
+                        continue;
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return Ok(()),
+                };
+                for (id, filters) in &subscriptions {
+                    if filters.iter().any(|filter| matches(filter, &event)) {
+                        let reply = RelayMessage::event(id.clone(), event.clone());
+                        socket.send(Message::Text(reply.as_json())).await?;
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Replies to one client message
+fn handle_message(
+    text: &str,
+    store: &Mutex<Store>,
+    events: &broadcast::Sender<Event>,
+    subscriptions: &mut HashMap<SubscriptionId, Vec<Filter>>,
+) -> Vec<RelayMessage> {
+    let message = match ClientMessage::from_json(text) {
+        Ok(message) => message,
+        Err(err) => return vec![RelayMessage::notice(format!("error: {err}"))],
+    };
+    match message {
+        ClientMessage::Event(event) => {
+            if let Err(err) = event.verify() {
+                return vec![RelayMessage::ok(event.id, false, format!("invalid: {err}"))];
+            }
+            // Ephemeral events are only forwarded
+            let fresh = event.kind.is_ephemeral() || store.lock().unwrap().insert(&event);
+            if fresh {
+                let _ = events.send(*event.clone());
+            }
+            let message = if fresh {
+                ""
+            } else {
+                "duplicate: already have this event"
+            };
+            vec![RelayMessage::ok(event.id, true, message)]
+        }
+        ClientMessage::Req {
+            subscription_id,
+            filters,
+        } => {
+            let mut replies: Vec<RelayMessage> = store
+                .lock()
+                .unwrap()
+                .query(&filters)
+                .into_iter()
+                .map(|event| RelayMessage::event(subscription_id.clone(), event))
+                .collect();
+            replies.push(RelayMessage::eose(subscription_id.clone()));
+            subscriptions.insert(subscription_id, filters);
+            replies
+        }
+        ClientMessage::Close(subscription_id) => {
+            subscriptions.remove(&subscription_id);
+            Vec::new()
+        }
+        _ => vec![RelayMessage::notice("error: unsupported message")],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nostr::crdt::CrdtManager;
+    use crate::nostr::fetch::{EventPaginator, FetchPolicy};
+    use nostr_sdk::{Client, EventBuilder, Keys, Kind, NostrSigner, Tag, Timestamp};
+    use std::time::Duration;
+
+    const TIMEOUT: Option<Duration> = Some(Duration::from_secs(5));
+
+    async fn client(relay: &MockRelay, keys: &Keys) -> Arc<Client> {
+        let client = Client::new(keys);
+        client.add_relay(relay.url()).await.unwrap();
+        client.connect().await;
+        Arc::new(client)
+    }
+
+    #[tokio::test]
+    async fn test_mock_relay_crdt_round_trip() {
+        let relay = MockRelay::run().await.unwrap();
+        let keys = Keys::generate();
+
+        let writer = CrdtManager::new(client(&relay, &keys).await, NostrSigner::Keys(keys.clone()));
+        let id = writer.update_lww_register("title", "hello").await.unwrap();
+        writer.increment_counter("views", 3).await.unwrap();
+        assert!(relay.contains(&id));
+        assert_eq!(relay.events().len(), 2);
+
+        // A second device with the same keys replays the published history
+        let reader = CrdtManager::new(client(&relay, &keys).await, NostrSigner::Keys(keys));
+        reader.load_checkpoint(TIMEOUT).await.unwrap();
+        assert_eq!(
+            reader.get_register_value("title"),
+            Some("hello".to_string())
+        );
+        assert_eq!(reader.get_counter_value("views"), Some("3".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_mock_relay_paginator() {
+        let relay = MockRelay::run().await.unwrap();
+        let keys = Keys::generate();
+        for i in 0..5 {
+            let event = EventBuilder::text_note(format!("note {i}"), [])
+                .custom_created_at(Timestamp::from(1_000 + i))
+                .to_event(&keys)
+                .unwrap();
+            relay.add_event(event);
+        }
+
+        let filter = Filter::new().kind(Kind::TextNote).author(keys.public_key());
+        let mut paginator = EventPaginator::new(
+            client(&relay, &keys).await,
+            vec![filter],
+            TIMEOUT,
+            2,
+            FetchPolicy::RelayOnly,
+        );
+        let mut contents = Vec::new();
+        while let Some(page) = paginator.next_page().await {
+            contents.extend(page.iter().map(|event| event.content.clone()));
+        }
+        assert_eq!(contents, ["note 4", "note 3", "note 2", "note 1", "note 0"]);
+    }
+
+    #[tokio::test]
+    async fn test_mock_relay_protocol() {
+        let relay = MockRelay::run().await.unwrap();
+        let keys = Keys::generate();
+        let (mut socket, _) = tokio_tungstenite::connect_async(relay.url().as_str())
+            .await
+            .unwrap();
+
+        // Newer metadata replaces the older version
+        let old = EventBuilder::metadata(&Default::default())
+            .custom_created_at(Timestamp::from(1))
+            .to_event(&keys)
+            .unwrap();
+        let new = EventBuilder::metadata(&Default::default())
+            .custom_created_at(Timestamp::from(2))
+            .to_event(&keys)
+            .unwrap();
+        socket
+            .send(Message::Text(ClientMessage::event(new.clone()).as_json()))
+            .await
+            .unwrap();
+        socket
+            .send(Message::Text(ClientMessage::event(old.clone()).as_json()))
+            .await
+            .unwrap();
+        let subscription = SubscriptionId::new("sub");
+        let req = ClientMessage::req(
+            subscription.clone(),
+            vec![Filter::new().author(keys.public_key())],
+        );
+        socket.send(Message::Text(req.as_json())).await.unwrap();
+
+        let mut replies = Vec::new();
+        while let Some(Ok(Message::Text(text))) = socket.next().await {
+            let reply = RelayMessage::from_json(text).unwrap();
+            let eose = matches!(reply, RelayMessage::EndOfStoredEvents(_));
+            replies.push(reply);
+            if eose {
+                break;
+            }
+        }
+        assert_eq!(
+            replies,
+            vec![
+                RelayMessage::ok(new.id, true, ""),
+                RelayMessage::ok(old.id, true, "duplicate: already have this event"),
+                RelayMessage::event(subscription.clone(), new.clone()),
+                RelayMessage::eose(subscription.clone()),
+            ]
+        );
+
+        // Live events reach open subscriptions
+        let note = EventBuilder::new(Kind::TextNote, "live", [Tag::hashtag("mock")])
+            .to_event(&keys)
+            .unwrap();
+        relay.add_event(note.clone());
+        let reply = socket.next().await.unwrap().unwrap().into_text().unwrap();
+        assert_eq!(
+            RelayMessage::from_json(reply).unwrap(),
+            RelayMessage::event(subscription, note)
+        );
+        assert_eq!(relay.events().len(), 2);
+    }
+}
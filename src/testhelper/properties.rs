@@ -0,0 +1,220 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Debug;
+use std::ops::Range;
+
+use proptest::prelude::*;
+use proptest::test_runner::TestCaseError;
+
+use crate::nostr::crdt::{CrdtOperation, CrdtState, GCounter, GSet, GSetAction, LWWRegister};
+
+// Few keys and values, so generated operations keep colliding
+const KEYS: usize = 4;
+const VALUES: usize = 6;
+
+/// State as seen by readers, compared by the assertions below
+pub trait Observe {
+    type View: Debug + PartialEq;
+
+    fn observe(&self) -> Self::View;
+}
+
+// Every key with its value
+fn values<S: CrdtState>(state: &S) -> BTreeMap<String, String> {
+    state
+        .keys()
+        .into_iter()
+        .filter_map(|key| state.get_value(&key).map(|value| (key, value)))
+        .collect()
+}
+
+impl Observe for LWWRegister {
+    type View = BTreeMap<String, String>;
+
+    fn observe(&self) -> Self::View {
+        values(self)
+    }
+}
+
+impl Observe for GCounter {
+    type View = BTreeMap<String, String>;
+
+    fn observe(&self) -> Self::View {
+        values(self)
+    }
+}
+
+impl Observe for GSet {
+    // Values come back in arrival order; the set is what replicas agree on
+    type View = BTreeMap<String, BTreeSet<String>>;
+
+    fn observe(&self) -> Self::View {
+        values(self)
+            .into_iter()
+            .map(|(key, json)| (key, serde_json::from_str(&json).unwrap_or_default()))
+            .collect()
+    }
+}
+
+/// A single operation of any CRDT type, over a handful of keys
+pub fn operation() -> impl Strategy<Value = CrdtOperation> {
+    let key = (0..KEYS).prop_map(|key| format!("key-{key}"));
+    let value = (0..VALUES).prop_map(|value| format!("value-{value}"));
+    prop_oneof![
+        (key.clone(), value.clone(), 0..1_000u64).prop_map(|(key, value, timestamp)| {
+            CrdtOperation::LWWRegister {
+                key,
+                value,
+                timestamp,
+            }
+        }),
+        (key.clone(), 1..100u64)
+            .prop_map(|(key, increment)| CrdtOperation::GCounter { key, increment }),
+        (key, value).prop_map(|(key, value)| CrdtOperation::GSet {
+            key,
+            value,
+            action: GSetAction::Add,
+        }),
+    ]
+}
+
+/// Operation sequences with a length in `len`. Register timestamps are
+/// unique within a sequence: equal timestamps are settled by arrival order,
+/// so no delivery order could be expected to converge on them.
+pub fn operations(len: Range<usize>) -> impl Strategy<Value = Vec<CrdtOperation>> {
+    proptest::collection::vec(operation(), len).prop_map(|mut ops| {
+        for (index, op) in ops.iter_mut().enumerate() {
+            if let CrdtOperation::LWWRegister { timestamp, .. } = op {
+                *timestamp = *timestamp * 1_000 + index as u64;
+            }
+        }
+        ops
+    })
+}
+
+/// An operation sequence together with a shuffled copy of it
+pub fn permutations(
+    len: Range<usize>,
+) -> impl Strategy<Value = (Vec<CrdtOperation>, Vec<CrdtOperation>)> {
+    operations(len).prop_flat_map(|ops| (Just(ops.clone()), Just(ops).prop_shuffle()))
+}
+
+/// An operation sequence split into three batches, as seen by three replicas
+#[allow(clippy::type_complexity)]
+pub fn batches(
+    len: Range<usize>,
+) -> impl Strategy<Value = (Vec<CrdtOperation>, Vec<CrdtOperation>, Vec<CrdtOperation>)> {
+    operations(len).prop_flat_map(|ops| {
+        let len = ops.len();
+        (Just(ops), 0..=len, 0..=len).prop_map(|(mut ops, first, second)| {
+            let (first, second) = (first.min(second), first.max(second));
+            let c = ops.split_off(second);
+            let b = ops.split_off(first);
+            (ops, b, c)
+        })
+    })
+}
+
+/// State after applying `ops` in order to a fresh `S`. Operations for other
+/// CRDT types are rejected by `S` and skipped.
+pub fn apply_all<S: CrdtState + Default>(ops: &[CrdtOperation]) -> S {
+    let mut state = S::default();
+    apply(&mut state, ops);
+    state
+}
+
+fn apply<S: CrdtState>(state: &mut S, ops: &[CrdtOperation]) {
+    for op in ops {
+        let _ = state.apply_operation(op);
+    }
+}
+
+/// Replicas receiving the same operations in any order agree
+pub fn assert_commutative<S>(
+    ops: &[CrdtOperation],
+    permuted: &[CrdtOperation],
+) -> Result<(), TestCaseError>
+where
+    S: CrdtState + Default + Observe,
+{
+    prop_assert_eq!(
+        apply_all::<S>(ops).observe(),
+        apply_all::<S>(permuted).observe()
+    );
+    Ok(())
+}
+
+/// Applying operations a replica already applied changes nothing.
+///
+/// Holds for registers and sets. Counter increments are not idempotent
+/// themselves; `CrdtManager` skips events it already processed instead,
+/// which `Simulation` covers.
+pub fn assert_idempotent<S>(ops: &[CrdtOperation]) -> Result<(), TestCaseError>
+where
+    S: CrdtState + Default + Observe,
+{
+    let once = apply_all::<S>(ops);
+    let mut twice = apply_all::<S>(ops);
+    apply(&mut twice, ops);
+    prop_assert_eq!(once.observe(), twice.observe());
+    Ok(())
+}
+
+/// Replicas that saw `a`, `b` and `c` agree however they are merged:
+/// `(a ⊔ b) ⊔ c` merged into the replica holding `c` and `a ⊔ (b ⊔ c)`
+/// merged into the one holding `a`, where merging delivers the operations
+/// of one replica to another.
+pub fn assert_associative<S>(
+    a: &[CrdtOperation],
+    b: &[CrdtOperation],
+    c: &[CrdtOperation],
+) -> Result<(), TestCaseError>
+where
+    S: CrdtState + Default + Observe,
+{
+    let mut left = apply_all::<S>(c);
+    apply(&mut left, a);
+    apply(&mut left, b);
+
+    let mut right = apply_all::<S>(a);
+    apply(&mut right, b);
+    apply(&mut right, c);
+
+    prop_assert_eq!(left.observe(), right.observe());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    proptest! {
+        #[test]
+        fn test_operations_commute((ops, permuted) in permutations(0..40)) {
+            assert_commutative::<LWWRegister>(&ops, &permuted)?;
+            assert_commutative::<GCounter>(&ops, &permuted)?;
+            assert_commutative::<GSet>(&ops, &permuted)?;
+        }
+
+        #[test]
+        fn test_operations_idempotent(ops in operations(0..40)) {
+            assert_idempotent::<LWWRegister>(&ops)?;
+            assert_idempotent::<GSet>(&ops)?;
+        }
+
+        #[test]
+        fn test_merges_associate((a, b, c) in batches(0..40)) {
+            assert_associative::<LWWRegister>(&a, &b, &c)?;
+            assert_associative::<GCounter>(&a, &b, &c)?;
+            assert_associative::<GSet>(&a, &b, &c)?;
+        }
+    }
+
+    #[test]
+    fn test_counter_is_not_idempotent() {
+        let ops = [CrdtOperation::GCounter {
+            key: "views".to_string(),
+            increment: 1,
+        }];
+        assert!(assert_idempotent::<GCounter>(&ops).is_err());
+    }
+}
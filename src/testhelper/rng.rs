@@ -0,0 +1,24 @@
+use std::ops::RangeInclusive;
+
+// splitmix64, so a seed always replays the same run without a rand dependency
+#[derive(Debug, Clone)]
+pub(crate) struct Rng(pub(crate) u64);
+
+impl Rng {
+    pub(crate) fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    pub(crate) fn range(&mut self, range: &RangeInclusive<u64>) -> u64 {
+        let span = range.end().saturating_sub(*range.start()).saturating_add(1);
+        range.start() + self.next() % span
+    }
+
+    pub(crate) fn chance(&mut self, probability: f64) -> bool {
+        ((self.next() >> 11) as f64 / (1u64 << 53) as f64) < probability
+    }
+}
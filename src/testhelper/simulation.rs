@@ -0,0 +1,365 @@
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+
+use nostr_sdk::{Client, Event, EventId, Keys, NostrSigner, SecretKey};
+
+use super::Rng;
+use crate::nostr::crdt::{CrdtManager, CrdtOperation, Error, StateBackup};
+
+/// Faults injected by a [`Simulation`] network
+#[derive(Debug, Clone, PartialEq)]
+pub struct NetworkConfig {
+    /// Ticks a message spends in flight, drawn uniformly from the range.
+    /// A wide range reorders messages.
+    pub delay: RangeInclusive<u64>,
+    /// Chance (0 to 1) of a message being delivered a second time
+    pub duplicate: f64,
+    /// Chance (0 to 1) of a message being lost
+    pub drop: f64,
+}
+
+impl Default for NetworkConfig {
+    // Reliable, in-order delivery on the next tick
+    fn default() -> Self {
+        Self {
+            delay: 1..=1,
+            duplicate: 0.0,
+            drop: 0.0,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct InFlight {
+    deliver_at: u64,
+    // Send order, breaks ties between messages due on the same tick
+    seq: u64,
+    from: usize,
+    to: usize,
+    event: Event,
+}
+
+/// Delivery counters of a simulation run
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SimulationStats {
+    pub sent: u64,
+    pub delivered: u64,
+    pub duplicated: u64,
+    pub dropped: u64,
+    // Lost because sender and receiver were in different partitions
+    pub partitioned: u64,
+}
+
+/// Deterministic network of `CrdtManager` replicas for convergence tests.
+///
+/// Replicas are devices of one user: they share keys, so each decrypts the
+/// others' operations. Operations are signed exactly as for publishing, then
+/// routed between replicas instead of through relays, with the delays,
+/// reordering, duplicates, drops and partitions of [`NetworkConfig`]. Time
+/// is counted in ticks and every random choice comes from the seed, so a
+/// failing seed replays the same run.
+///
+/// ```ignore
+/// let mut sim = Simulation::new(3, seed).with_network(NetworkConfig {
+///     delay: 0..=5,
+///     duplicate: 0.1,
+///     drop: 0.2,
+/// });
+/// sim.partition(&[&[0], &[1, 2]]);
+/// sim.apply(0, op).await?;
+/// sim.run().await;
+/// sim.heal().await;
+/// sim.assert_converged();
+/// ```
+pub struct Simulation {
+    replicas: Vec<CrdtManager>,
+    network: NetworkConfig,
+    rng: Rng,
+    now: u64,
+    next_seq: u64,
+    in_flight: Vec<InFlight>,
+    // Every operation event, replayed to all replicas on healing the way a
+    // reconnecting device catches up from relay history
+    history: Vec<Event>,
+    // Partition of each replica; messages only cross within a partition
+    groups: Vec<usize>,
+    stats: SimulationStats,
+}
+
+impl Simulation {
+    pub fn new(replicas: usize, seed: u64) -> Self {
+        let mut rng = Rng(seed);
+        let keys = loop {
+            let mut secret = [0u8; 32];
+            for chunk in secret.chunks_mut(8) {
+                chunk.copy_from_slice(&rng.next().to_le_bytes());
+            }
+            if let Ok(secret_key) = SecretKey::from_slice(&secret) {
+                break Keys::new(secret_key);
+            }
+        };
+        let replicas = (0..replicas)
+            .map(|_| {
+                // Never connected, events only travel through the simulation
+                let client = Arc::new(Client::new(&keys));
+                CrdtManager::new(client, NostrSigner::Keys(keys.clone()))
+            })
+            .collect::<Vec<_>>();
+        Self {
+            groups: vec![0; replicas.len()],
+            replicas,
+            network: NetworkConfig::default(),
+            rng,
+            now: 0,
+            next_seq: 0,
+            in_flight: Vec::new(),
+            history: Vec::new(),
+            stats: SimulationStats::default(),
+        }
+    }
+
+    pub fn with_network(mut self, network: NetworkConfig) -> Self {
+        self.network = network;
+        self
+    }
+
+    pub fn replica(&self, index: usize) -> &CrdtManager {
+        &self.replicas[index]
+    }
+
+    pub fn replicas(&self) -> usize {
+        self.replicas.len()
+    }
+
+    /// Current tick
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    pub fn stats(&self) -> SimulationStats {
+        self.stats
+    }
+
+    /// Applies `op` on `replica` and sends it to every other replica
+    pub async fn apply(&mut self, replica: usize, op: CrdtOperation) -> Result<EventId, Error> {
+        let manager = &self.replicas[replica];
+        manager.apply(&op)?;
+        let event = manager.sign_operation(&op).await?;
+        let id = event.id;
+        for to in (0..self.replicas.len()).filter(|to| *to != replica) {
+            self.send(replica, to, event.clone());
+        }
+        self.history.push(event);
+        Ok(id)
+    }
+
+    fn send(&mut self, from: usize, to: usize, event: Event) {
+        self.stats.sent += 1;
+        if self.rng.chance(self.network.drop) {
+            self.stats.dropped += 1;
+            return;
+        }
+        let copies = if self.rng.chance(self.network.duplicate) {
+            self.stats.duplicated += 1;
+            2
+        } else {
+            1
+        };
+        for _ in 0..copies {
+            let deliver_at = self.now + self.rng.range(&self.network.delay);
+            self.in_flight.push(InFlight {
+                deliver_at,
+                seq: self.next_seq,
+                from,
+                to,
+                event: event.clone(),
+            });
+            self.next_seq += 1;
+        }
+    }
+
+    /// Splits the replicas into `groups`; replicas left out form one more
+    /// group together. Messages between groups are lost, including those
+    /// already in flight, until [`Simulation::heal`].
+    pub fn partition(&mut self, groups: &[&[usize]]) {
+        self.groups = vec![0; self.replicas.len()];
+        for (group, members) in groups.iter().enumerate() {
+            for replica in members.iter() {
+                self.groups[*replica] = group + 1;
+            }
+        }
+    }
+
+    /// Delivers the messages due on the current tick and moves to the next
+    /// one. Returns whether messages are still in flight.
+    pub async fn step(&mut self) -> bool {
+        let (mut due, pending): (Vec<InFlight>, Vec<InFlight>) =
+            std::mem::take(&mut self.in_flight)
+                .into_iter()
+                .partition(|message| message.deliver_at <= self.now);
+        self.in_flight = pending;
+        due.sort_by_key(|message| (message.deliver_at, message.seq));
+
+        for message in due {
+            if self.groups[message.from] != self.groups[message.to] {
+                self.stats.partitioned += 1;
+                continue;
+            }
+            self.stats.delivered += 1;
+            // Same handling as a live relay subscription: failures are logged
+            if let Err(err) = self.replicas[message.to]
+                .process_event(&message.event)
+                .await
+            {
+                tracing::warn!(
+                    "Replica {} failed to apply {}: {}",
+                    message.to,
+                    message.event.id,
+                    err
+                );
+            }
+        }
+        self.now += 1;
+        !self.in_flight.is_empty()
+    }
+
+    /// Steps until nothing is in flight
+    pub async fn run(&mut self) {
+        while self.step().await {}
+    }
+
+    /// Rejoins all partitions, lets in-flight messages land, then has every
+    /// replica replay the full operation history, as devices do from relays
+    /// once they reconnect. Lost messages are recovered this way.
+    pub async fn heal(&mut self) {
+        self.groups = vec![0; self.replicas.len()];
+        self.run().await;
+        for replica in &self.replicas {
+            replica.process_events(self.history.clone()).await;
+        }
+    }
+
+    /// Whether every replica holds the same document
+    pub fn converged(&self) -> bool {
+        let first = self.replicas.first().map(snapshot);
+        self.replicas
+            .iter()
+            .all(|replica| Some(snapshot(replica)) == first)
+    }
+
+    /// Panics with the diverging states unless every replica holds the
+    /// same document
+    pub fn assert_converged(&self) {
+        let states: Vec<StateBackup> = self.replicas.iter().map(snapshot).collect();
+        for (index, state) in states.iter().enumerate().skip(1) {
+            assert_eq!(
+                &states[0], state,
+                "replica {index} diverged from replica 0 at tick {}",
+                self.now
+            );
+        }
+    }
+}
+
+// Document state compared for convergence. Sets hold the same values in
+// arrival order, which legitimately differs, so they are sorted; the
+// processed ids are bookkeeping rather than document state.
+fn snapshot(replica: &CrdtManager) -> StateBackup {
+    let mut state = replica.export_state();
+    for values in state.sets.values_mut() {
+        values.sort();
+    }
+    state.processed_events.clear();
+    state
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nostr::crdt::GSetAction;
+
+    // Operation number `i` of a scripted workload. Register timestamps are
+    // unique, equal timestamps are resolved by arrival order.
+    fn op(i: u64) -> CrdtOperation {
+        match i % 3 {
+            0 => CrdtOperation::LWWRegister {
+                key: format!("register-{}", i % 4),
+                value: format!("value-{i}"),
+                timestamp: i,
+            },
+            1 => CrdtOperation::GCounter {
+                key: format!("counter-{}", i % 2),
+                increment: i,
+            },
+            _ => CrdtOperation::GSet {
+                key: "set".to_string(),
+                value: format!("item-{}", i % 7),
+                action: GSetAction::Add,
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_simulation_converges_after_healing() {
+        let network = NetworkConfig {
+            delay: 0..=6,
+            duplicate: 0.3,
+            drop: 0.3,
+        };
+        for seed in 0..10 {
+            let mut sim = Simulation::new(4, seed).with_network(network.clone());
+            let mut total = 0;
+            for i in 0..60 {
+                if i == 20 {
+                    sim.partition(&[&[0, 1], &[2]]);
+                }
+                if let CrdtOperation::GCounter { increment, .. } = op(i) {
+                    total += increment;
+                }
+                sim.apply(i as usize % sim.replicas(), op(i)).await.unwrap();
+                sim.step().await;
+            }
+            sim.run().await;
+            assert!(!sim.converged(), "seed {seed} converged without healing");
+
+            sim.heal().await;
+            sim.assert_converged();
+            // Duplicates are never counted twice
+            let counted: u64 = ["counter-0", "counter-1"]
+                .iter()
+                .filter_map(|key| sim.replica(0).get_counter_value(key))
+                .map(|count| count.parse::<u64>().unwrap())
+                .sum();
+            assert_eq!(counted, total, "seed {seed}");
+            let stats = sim.stats();
+            assert!(stats.dropped > 0 && stats.duplicated > 0 && stats.partitioned > 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_simulation_is_deterministic() {
+        async fn run(seed: u64) -> (SimulationStats, u64) {
+            let mut sim = Simulation::new(3, seed).with_network(NetworkConfig {
+                delay: 0..=4,
+                duplicate: 0.2,
+                drop: 0.2,
+            });
+            for i in 0..30 {
+                sim.apply(i as usize % 3, op(i)).await.unwrap();
+                sim.step().await;
+            }
+            sim.run().await;
+            (sim.stats(), sim.now())
+        }
+        assert_eq!(run(7).await, run(7).await);
+        assert_ne!(run(7).await, run(8).await);
+
+        // A reliable network converges without healing
+        let mut sim = Simulation::new(3, 1);
+        for i in 0..30 {
+            sim.apply(i as usize % 3, op(i)).await.unwrap();
+        }
+        sim.run().await;
+        sim.assert_converged();
+    }
+}